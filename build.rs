@@ -25,6 +25,8 @@ fn write_git_version() {
 
     let mut file = File::create(&dest_path).unwrap();
     write!(file, "({} {})", git_hash, build_date).unwrap();
+
+    println!("cargo:rustc-env=CRATESFYI_GIT_COMMIT={}", git_hash);
 }
 
 fn get_git_hash() -> Option<String> {
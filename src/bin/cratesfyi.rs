@@ -2,9 +2,12 @@ use std::env;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use chrono::Utc;
 use cratesfyi::db::{self, add_path_into_database, Pool};
 use cratesfyi::utils::{remove_crate_priority, set_crate_priority};
-use cratesfyi::{BuildQueue, Config, DocBuilder, DocBuilderOptions, RustwideBuilder, Server};
+use cratesfyi::{
+    BuildQueue, Config, CrateNameFilter, DocBuilder, DocBuilderOptions, RustwideBuilder, Server,
+};
 use failure::Error;
 use once_cell::sync::OnceCell;
 use structopt::StructOpt;
@@ -52,6 +55,7 @@ enum CommandLine {
 
     /// Starts web server
     StartWebServer {
+        /// Address to listen on, or `unix:/path/to/socket` to listen on a Unix domain socket
         #[structopt(name = "SOCKET_ADDR", default_value = "0.0.0.0:3000")]
         socket_addr: String,
 
@@ -147,9 +151,14 @@ impl QueueSubcommand {
                 crate_name,
                 crate_version,
                 build_priority,
-            } => ctx
-                .build_queue()?
-                .add_crate(&crate_name, &crate_version, build_priority)?,
+            } => {
+                if !ctx
+                    .build_queue()?
+                    .add_crate(&crate_name, &crate_version, build_priority)?
+                {
+                    println!("{}-{} is already queued", crate_name, crate_version);
+                }
+            }
 
             Self::DefaultPriority { subcommand } => subcommand.handle_args(ctx)?,
         }
@@ -234,6 +243,29 @@ struct Build {
     )]
     keep_build_directory: bool,
 
+    /// Number of threads reading the registry index when building every crate, 0 uses one
+    /// thread per CPU
+    #[structopt(
+        name = "INDEX_WALK_THREADS",
+        long = "index-walk-threads",
+        default_value = "1"
+    )]
+    index_walk_threads: usize,
+
+    /// Reads the registry index in the same order on every machine when building every crate,
+    /// with a single thread
+    #[structopt(name = "SORTED_INDEX_WALK", long = "sorted-index-walk")]
+    sorted_index_walk: bool,
+
+    /// Only builds the crates matching one of these patterns when building every crate, `*`
+    /// matches any sequence of characters
+    #[structopt(name = "ALLOW_CRATE", long = "allow-crate")]
+    allow_crates: Vec<String>,
+
+    /// Skips the crates matching one of these patterns when building every crate
+    #[structopt(name = "DENY_CRATE", long = "deny-crate")]
+    deny_crates: Vec<String>,
+
     #[structopt(subcommand)]
     subcommand: BuildSubcommand,
 }
@@ -250,6 +282,21 @@ impl Build {
             doc_options.skip_if_exists = self.skip_if_exists;
             doc_options.skip_if_log_exists = self.skip_if_log_exists;
             doc_options.keep_build_directory = self.keep_build_directory;
+            doc_options.index_walk_threads = self.index_walk_threads;
+            doc_options.sorted_index_walk = self.sorted_index_walk;
+            if !self.allow_crates.is_empty() || !self.deny_crates.is_empty() {
+                let filter = self
+                    .allow_crates
+                    .iter()
+                    .fold(CrateNameFilter::new(), |filter, name| {
+                        filter.allow(name.as_str())
+                    });
+                doc_options.crate_name_filter = Some(
+                    self.deny_crates
+                        .iter()
+                        .fold(filter, |filter, name| filter.deny(name.as_str())),
+                );
+            }
 
             doc_options
                 .check_paths()
@@ -265,7 +312,11 @@ impl Build {
 #[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
 enum BuildSubcommand {
     /// Builds documentation of every crate
-    World,
+    World {
+        /// Only build the versions added to the index since the previous incremental run
+        #[structopt(name = "INCREMENTAL", long = "incremental")]
+        incremental: bool,
+    },
 
     /// Builds documentation for a crate
     Crate {
@@ -286,6 +337,13 @@ enum BuildSubcommand {
         local: Option<PathBuf>,
     },
 
+    /// Builds documentation for every version of a crate listed in the index
+    Versions {
+        /// Crate name
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+    },
+
     /// update the currently installed rustup toolchain
     UpdateToolchain {
         /// Update the toolchain only if no toolchain is currently installed
@@ -308,13 +366,19 @@ enum BuildSubcommand {
 impl BuildSubcommand {
     pub fn handle_args(self, ctx: Context, mut docbuilder: DocBuilder) -> Result<(), Error> {
         match self {
-            Self::World => {
+            Self::World { incremental } => {
                 docbuilder.load_cache().expect("Failed to load cache");
 
-                let mut builder = RustwideBuilder::init(ctx.pool()?).unwrap();
-                builder
-                    .build_world(&mut docbuilder)
-                    .expect("Failed to build world");
+                let mut builder = RustwideBuilder::init(&*ctx.config()?, ctx.pool()?).unwrap();
+                if incremental {
+                    builder
+                        .build_world_incremental(&mut docbuilder)
+                        .expect("Failed to build new versions");
+                } else {
+                    builder
+                        .build_world(&mut docbuilder)
+                        .expect("Failed to build world");
+                }
 
                 docbuilder.save_cache().expect("Failed to save cache");
             }
@@ -325,8 +389,8 @@ impl BuildSubcommand {
                 local,
             } => {
                 docbuilder.load_cache().expect("Failed to load cache");
-                let mut builder =
-                    RustwideBuilder::init(ctx.pool()?).expect("failed to initialize rustwide");
+                let mut builder = RustwideBuilder::init(&*ctx.config()?, ctx.pool()?)
+                    .expect("failed to initialize rustwide");
 
                 if let Some(path) = local {
                     builder
@@ -346,6 +410,18 @@ impl BuildSubcommand {
                 docbuilder.save_cache().expect("Failed to save cache");
             }
 
+            Self::Versions { crate_name } => {
+                docbuilder.load_cache().expect("Failed to load cache");
+                let mut builder = RustwideBuilder::init(&*ctx.config()?, ctx.pool()?)
+                    .expect("failed to initialize rustwide");
+
+                builder
+                    .build_crate_versions(&mut docbuilder, &crate_name)
+                    .expect("Building documentation failed");
+
+                docbuilder.save_cache().expect("Failed to save cache");
+            }
+
             Self::UpdateToolchain { only_first_time } => {
                 if only_first_time {
                     let conn = ctx
@@ -362,14 +438,14 @@ impl BuildSubcommand {
                     }
                 }
 
-                let mut builder = RustwideBuilder::init(ctx.pool()?).unwrap();
+                let mut builder = RustwideBuilder::init(&*ctx.config()?, ctx.pool()?).unwrap();
                 builder
                     .update_toolchain()
                     .expect("failed to update toolchain");
             }
 
             Self::AddEssentialFiles => {
-                let mut builder = RustwideBuilder::init(ctx.pool()?).unwrap();
+                let mut builder = RustwideBuilder::init(&*ctx.config()?, ctx.pool()?).unwrap();
                 builder
                     .add_essential_files()
                     .expect("failed to add essential files");
@@ -391,6 +467,10 @@ enum DatabaseSubcommand {
         /// The database version to migrate to
         #[structopt(name = "VERSION")]
         version: Option<i64>,
+
+        /// Print the migrations that would run without applying them
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
     },
 
     /// Updates github stats for crates.
@@ -415,18 +495,47 @@ enum DatabaseSubcommand {
         crate_name: String,
     },
 
+    /// Removes the documentation of the old releases, keeping the releases selected by the
+    /// DOCSRS_DOCS_RETENTION_VERSIONS and DOCSRS_DOCS_RETENTION_DAYS variables
+    RemoveOldDocs {
+        /// Print the documentation that would be removed and its size without removing it
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+    },
+
     /// Blacklist operations
     Blacklist {
         #[structopt(subcommand)]
         command: BlacklistSubcommand,
     },
+
+    /// Runs a data backfill until it completes, resuming it if it was interrupted. The available
+    /// backfills are listed when no name is given.
+    Backfill {
+        /// Name of the backfill to run
+        #[structopt(name = "NAME")]
+        name: Option<String>,
+
+        /// Number of rows processed between two progress checkpoints
+        #[structopt(long = "batch-size", default_value = "100")]
+        batch_size: i64,
+    },
 }
 
 impl DatabaseSubcommand {
     pub fn handle_args(self, ctx: Context) -> Result<(), Error> {
         match self {
-            Self::Migrate { version } => {
-                db::migrate(version, &*ctx.conn()?).expect("Failed to run database migrations");
+            Self::Migrate { version, dry_run } => {
+                let conn = ctx.conn()?;
+                if dry_run {
+                    let current = db::current_version(&conn)?;
+                    println!("current database version: {}", current);
+                    for (version, description, direction) in db::migrate_dry_run(version, &conn)? {
+                        println!("{:?} {}: {}", direction, version, description);
+                    }
+                } else {
+                    db::migrate(version, &conn).expect("Failed to run database migrations");
+                }
             }
 
             Self::UpdateGithubFields => {
@@ -435,8 +544,15 @@ impl DatabaseSubcommand {
             }
 
             Self::AddDirectory { directory, prefix } => {
-                add_path_into_database(&*ctx.conn()?, &prefix, directory)
-                    .expect("Failed to add directory into database");
+                let config = ctx.config()?;
+                add_path_into_database(
+                    &*ctx.conn()?,
+                    config.storage_backend(),
+                    &prefix,
+                    directory,
+                    config.storage_compression(),
+                )
+                .expect("Failed to add directory into database");
             }
 
             // FIXME: This is actually util command not database
@@ -444,10 +560,54 @@ impl DatabaseSubcommand {
                 .expect("Failed to update release activity"),
 
             Self::DeleteCrate { crate_name } => {
-                db::delete_crate(&*ctx.conn()?, &crate_name).expect("failed to delete the crate");
+                db::delete_crate(&*ctx.conn()?, ctx.config()?.storage_backend(), &crate_name)
+                    .expect("failed to delete the crate");
+            }
+
+            Self::RemoveOldDocs { dry_run } => {
+                let config = ctx.config()?;
+                let conn = ctx.conn()?;
+                let policy = db::RetentionPolicy::from_config(&config, Utc::now());
+                if dry_run {
+                    let plan = db::gc_plan(&conn, config.storage_backend(), &policy)?;
+                    for (name, version, size) in &plan {
+                        println!("{}-{}: {} bytes", name, version, size);
+                    }
+                    let total = plan.iter().map(|(_, _, size)| size).sum::<u64>();
+                    println!(
+                        "would remove the documentation of {} releases ({} bytes)",
+                        plan.len(),
+                        total
+                    );
+                } else {
+                    let removed = db::remove_old_docs(&conn, config.storage_backend(), &policy)
+                        .expect("failed to remove the old documentation");
+                    println!("removed the documentation of {} releases", removed);
+                }
             }
 
             Self::Blacklist { command } => command.handle_args(ctx)?,
+
+            Self::Backfill { name, batch_size } => {
+                let conn = ctx.conn()?;
+                match name {
+                    Some(name) => {
+                        let batches = db::run_backfill(
+                            &conn,
+                            ctx.config()?.storage_backend(),
+                            &name,
+                            batch_size,
+                        )?;
+                        println!("backfill {} completed after {} batches", name, batches);
+                    }
+                    None => {
+                        for (name, description, completed) in db::list_backfills(&conn)? {
+                            let status = if completed { "completed" } else { "pending" };
+                            println!("{} ({}): {}", name, status, description);
+                        }
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -521,7 +681,17 @@ impl Context {
     fn config(&self) -> Result<Arc<Config>, Error> {
         Ok(self
             .config
-            .get_or_try_init::<_, Error>(|| Ok(Arc::new(Config::from_env()?)))?
+            .get_or_try_init::<_, Error>(|| {
+                let config = Config::from_env()?;
+                if let Err(errors) = config.validate() {
+                    let errors = errors
+                        .iter()
+                        .map(|err| format!("\n  - {}", err))
+                        .collect::<String>();
+                    failure::bail!("the configuration is not valid:{}", errors);
+                }
+                Ok(Arc::new(config))
+            })?
             .clone())
     }
 
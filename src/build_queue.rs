@@ -1,7 +1,14 @@
 use crate::config::Config;
 use crate::db::Pool;
+use crate::docbuilder::CrateNameFilter;
 use crate::error::Result;
-use log::error;
+use log::{error, info};
+use postgres::Connection;
+use serde_json::Value;
+use std::{
+    sync::{Condvar, Mutex},
+    time::Instant,
+};
 
 #[derive(Debug, Eq, PartialEq, serde::Serialize)]
 pub(crate) struct QueuedCrate {
@@ -10,33 +17,145 @@ pub(crate) struct QueuedCrate {
     pub(crate) name: String,
     pub(crate) version: String,
     pub(crate) priority: i32,
+    /// Number of previous attempts at building this crate
+    #[serde(skip)]
+    attempt: i32,
+}
+
+/// Priority of the crates matching `Config::priority_crates`, the highest one used when queueing
+/// crates.
+const PRIORITY_CRATES_PRIORITY: i32 = -1000;
+
+/// Longest delay in seconds before retrying a failed build, however many times it failed.
+const MAX_RETRY_BACKOFF: u64 = 6 * 60 * 60;
+
+/// Seconds during which a crate picked by a builder is hidden from the other ones. Builds are
+/// stopped by their timeout well before, so this only matters if the builder crashed while
+/// building the crate: it's then retried once the claim expired.
+const BUILD_CLAIM_DURATION: u64 = 6 * 60 * 60;
+
+/// Returns how many seconds to wait before retrying a build after its failed attempt number
+/// `attempt`, starting from 1: `base` after the first failure, doubled after each of the next
+/// ones, up to `MAX_RETRY_BACKOFF`.
+fn retry_backoff(base: u64, attempt: i32) -> u64 {
+    if base == 0 {
+        return 0;
+    }
+    let doublings = (attempt.max(1) - 1) as u32;
+    2u64.checked_pow(doublings)
+        .and_then(|factor| base.checked_mul(factor))
+        .map_or(MAX_RETRY_BACKOFF, |backoff| backoff.min(MAX_RETRY_BACKOFF))
 }
 
 #[derive(Debug)]
 pub struct BuildQueue {
     db: Pool,
     max_attempts: i32,
+    /// Seconds to wait before retrying a build after its first failure, see `retry_backoff`
+    retry_backoff: u64,
+    /// Crates always queued with `PRIORITY_CRATES_PRIORITY`, if any
+    priority_crates: Option<CrateNameFilter>,
+    /// Limits the builds running at the same time to `Config::max_concurrent_builds`
+    build_permits: BuildPermits,
+}
+
+/// Counting semaphore handing out a fixed number of permits.
+#[derive(Debug)]
+struct BuildPermits {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl BuildPermits {
+    fn new(permits: usize) -> Self {
+        Self {
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits until a permit is available, which is given back when the returned guard is dropped.
+    fn acquire(&self) -> BuildPermit<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        BuildPermit { permits: self }
+    }
+}
+
+struct BuildPermit<'a> {
+    permits: &'a BuildPermits,
+}
+
+impl Drop for BuildPermit<'_> {
+    fn drop(&mut self) {
+        *self.permits.available.lock().unwrap() += 1;
+        self.permits.released.notify_one();
+    }
 }
 
 impl BuildQueue {
     pub fn new(db: Pool, config: &Config) -> Self {
+        let priority_crates = if config.priority_crates.is_empty() {
+            None
+        } else {
+            Some(
+                config
+                    .priority_crates
+                    .iter()
+                    .fold(CrateNameFilter::new(), |filter, pattern| {
+                        filter.allow(pattern.as_str())
+                    }),
+            )
+        };
+
         BuildQueue {
             db,
             max_attempts: config.build_attempts.into(),
+            retry_backoff: config.build_retry_backoff,
+            priority_crates,
+            build_permits: BuildPermits::new(config.max_concurrent_builds),
         }
     }
 
-    pub fn add_crate(&self, name: &str, version: &str, priority: i32) -> Result<()> {
-        self.db.get()?.execute(
-            "INSERT INTO queue (name, version, priority) VALUES ($1, $2, $3);",
-            &[&name, &version, &priority],
+    /// Adds a crate to the queue with `priority`, where lower values are built first. The
+    /// crates configured in `Config::priority_crates` are always given the highest priority.
+    ///
+    /// Nothing is done if the crate is already pending, so that it's not built twice, and `false`
+    /// is returned. Crates that failed `max_attempts` times are queued again from scratch, and can
+    /// be built right away.
+    pub fn add_crate(&self, name: &str, version: &str, priority: i32) -> Result<bool> {
+        let priority = match &self.priority_crates {
+            Some(filter) if filter.allows(name) => priority.min(PRIORITY_CRATES_PRIORITY),
+            _ => priority,
+        };
+        let rows = self.db.get()?.query(
+            "INSERT INTO queue (name, version, priority) VALUES ($1, $2, $3)
+             ON CONFLICT (name, version) DO UPDATE
+                SET priority = EXCLUDED.priority, attempt = 0, next_attempt_at = NULL
+                WHERE queue.attempt >= $4
+             RETURNING id;",
+            &[&name, &version, &priority, &self.max_attempts],
         )?;
-        Ok(())
+        let id: i32 = match rows.iter().next() {
+            Some(row) => row.get(0),
+            None => return Ok(false),
+        };
+        info!(
+            "queue_id={} crate={} version={} attempt=0 state=pending",
+            id, name, version,
+        );
+        crate::web::metrics::QUEUED_CRATES_TOTAL.inc();
+        Ok(true)
     }
 
     pub(crate) fn pending_count(&self) -> Result<usize> {
         let res = self.db.get()?.query(
-            "SELECT COUNT(*) FROM queue WHERE attempt < $1;",
+            "SELECT COUNT(*)
+             FROM queue
+             WHERE attempt < $1 AND (next_attempt_at IS NULL OR next_attempt_at <= NOW());",
             &[&self.max_attempts],
         )?;
         Ok(res.get(0).get::<_, i64>(0) as usize)
@@ -44,7 +163,11 @@ impl BuildQueue {
 
     pub(crate) fn prioritized_count(&self) -> Result<usize> {
         let res = self.db.get()?.query(
-            "SELECT COUNT(*) FROM queue WHERE attempt < $1 AND priority <= 0;",
+            "SELECT COUNT(*)
+             FROM queue
+             WHERE attempt < $1
+               AND priority <= 0
+               AND (next_attempt_at IS NULL OR next_attempt_at <= NOW());",
             &[&self.max_attempts],
         )?;
         Ok(res.get(0).get::<_, i64>(0) as usize)
@@ -60,7 +183,7 @@ impl BuildQueue {
 
     pub(crate) fn queued_crates(&self) -> Result<Vec<QueuedCrate>> {
         let query = self.db.get()?.query(
-            "SELECT id, name, version, priority
+            "SELECT id, name, version, priority, attempt
              FROM queue
              WHERE attempt < $1
              ORDER BY priority ASC, attempt ASC, id ASC",
@@ -74,38 +197,187 @@ impl BuildQueue {
                 name: row.get("name"),
                 version: row.get("version"),
                 priority: row.get("priority"),
+                attempt: row.get("attempt"),
+            })
+            .collect())
+    }
+
+    /// Claims the crate `process_next_crate` builds next: the first one of `queued_crates` which
+    /// isn't waiting for the backoff of its last failure to elapse, nor being built by another
+    /// builder. The claim hides it from the other builders for `BUILD_CLAIM_DURATION`.
+    fn claim_next_crate(&self, conn: &Connection) -> Result<Option<QueuedCrate>> {
+        let query = conn.query(
+            "UPDATE queue
+             SET next_attempt_at = NOW() + make_interval(secs => $2)
+             WHERE id = (
+                SELECT id
+                FROM queue
+                WHERE attempt < $1 AND (next_attempt_at IS NULL OR next_attempt_at <= NOW())
+                ORDER BY priority ASC, attempt ASC, id ASC
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, name, version, priority, attempt;",
+            &[&self.max_attempts, &(BUILD_CLAIM_DURATION as f64)],
+        )?;
+
+        Ok(query.into_iter().next().map(|row| QueuedCrate {
+            id: row.get("id"),
+            name: row.get("name"),
+            version: row.get("version"),
+            priority: row.get("priority"),
+            attempt: row.get("attempt"),
+        }))
+    }
+
+    /// Returns whether any version of a crate is waiting in the build queue.
+    pub(crate) fn is_queued(&self, name: &str) -> Result<bool> {
+        let query = self.db.get()?.query(
+            "SELECT 1 FROM queue WHERE name = $1 AND attempt < $2 LIMIT 1;",
+            &[&name, &self.max_attempts],
+        )?;
+        Ok(!query.is_empty())
+    }
+
+    /// Returns the position of a crate in the build queue, starting from 1 for the next crate to
+    /// be built, or `None` if the crate isn't queued.
+    pub(crate) fn position(&self, name: &str, version: &str) -> Result<Option<usize>> {
+        Ok(self
+            .queued_crates()?
+            .iter()
+            .position(|krate| krate.name == name && krate.version == version)
+            .map(|idx| idx + 1))
+    }
+
+    /// Removes a crate from the build queue, returning whether it was queued.
+    ///
+    /// If the crate is currently being built, the build is not interrupted: only the pending row
+    /// is deleted, so the crate won't be retried if the build fails.
+    pub fn remove(&self, name: &str, version: &str) -> Result<bool> {
+        let deleted = self.db.get()?.execute(
+            "DELETE FROM queue WHERE name = $1 AND version = $2;",
+            &[&name, &version],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /// Returns the crates that failed to build at least `max_attempts` times, and are no longer
+    /// handed out by `process_next_crate`.
+    pub(crate) fn failed_crates(&self) -> Result<Vec<QueuedCrate>> {
+        let query = self.db.get()?.query(
+            "SELECT id, name, version, priority, attempt
+             FROM queue
+             WHERE attempt >= $1
+             ORDER BY id ASC",
+            &[&self.max_attempts],
+        )?;
+
+        Ok(query
+            .into_iter()
+            .map(|row| QueuedCrate {
+                id: row.get("id"),
+                name: row.get("name"),
+                version: row.get("version"),
+                priority: row.get("priority"),
+                attempt: row.get("attempt"),
             })
             .collect())
     }
 
+    /// Pauses or resumes the build queue. While the queue is paused `process_next_crate` doesn't
+    /// hand out any crate. The flag is stored in the database, so it survives restarts.
+    pub fn set_paused(&self, paused: bool) -> Result<()> {
+        self.db.get()?.execute(
+            "INSERT INTO config (name, value) VALUES ('queue_paused', $1)
+             ON CONFLICT (name) DO UPDATE SET value = $1;",
+            &[&Value::Bool(paused)],
+        )?;
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> Result<bool> {
+        let rows = self
+            .db
+            .get()?
+            .query("SELECT value FROM config WHERE name = 'queue_paused';", &[])?;
+        Ok(rows.iter().next().map(|row| row.get::<_, Value>(0)) == Some(Value::Bool(true)))
+    }
+
+    /// Builds the next crate in the queue with `f`, removing it from the queue if it succeeded.
+    ///
+    /// Every state transition of the crate is logged at the `info` level in the logfmt format,
+    /// with the number of the current attempt and, once it's over, the duration of the build:
+    ///
+    /// ```text
+    /// queue_id=42 crate=rand version=0.7.3 attempt=1 state=building
+    /// queue_id=42 crate=rand version=0.7.3 attempt=1 state=failed duration_ms=1234.567
+    /// ```
+    ///
+    /// Crates are `pending` when queued, then `building`, and finally either `done`, `failed` if
+    /// they will be retried, or `abandoned` once they failed `max_attempts` times. Failed crates
+    /// are only retried once their backoff elapsed, see `retry_backoff`.
+    ///
+    /// At most `Config::max_concurrent_builds` crates are built at the same time, additional
+    /// callers wait for one of the builds to finish before picking the next crate. Concurrent
+    /// builds never pick the same crate.
     pub(crate) fn process_next_crate(
         &self,
         f: impl FnOnce(&QueuedCrate) -> Result<()>,
     ) -> Result<()> {
+        if self.is_paused()? {
+            return Ok(());
+        }
+
+        let _permit = self.build_permits.acquire();
         let conn = self.db.get()?;
 
-        let queued = self.queued_crates()?;
-        let to_process = match queued.get(0) {
+        let to_process = match self.claim_next_crate(&conn)? {
             Some(krate) => krate,
             None => return Ok(()),
         };
 
+        let attempt = to_process.attempt + 1;
+        let log_transition = |state: &str, duration: Option<f64>| {
+            let duration = duration
+                .map(|duration| format!(" duration_ms={:.3}", duration * 1000.0))
+                .unwrap_or_default();
+            info!(
+                "queue_id={} crate={} version={} attempt={} state={}{}",
+                to_process.id, to_process.name, to_process.version, attempt, state, duration,
+            );
+        };
+
+        log_transition("building", None);
+        let start = Instant::now();
         let res = f(&to_process);
+        let duration = Some(start.elapsed().as_secs_f64());
         crate::web::metrics::TOTAL_BUILDS.inc();
         match res {
             Ok(()) => {
                 conn.execute("DELETE FROM queue WHERE id = $1;", &[&to_process.id])?;
+                log_transition("done", duration);
             }
             Err(e) => {
-                // Increase attempt count
+                // Increase attempt count, and delay the next one
+                let backoff = retry_backoff(self.retry_backoff, attempt) as f64;
                 let rows = conn.query(
-                    "UPDATE queue SET attempt = attempt + 1 WHERE id = $1 RETURNING attempt;",
-                    &[&to_process.id],
+                    "UPDATE queue
+                     SET attempt = attempt + 1,
+                         next_attempt_at = NOW() + make_interval(secs => $2)
+                     WHERE id = $1
+                     RETURNING attempt;",
+                    &[&to_process.id, &backoff],
                 )?;
-                let attempt: i32 = rows.get(0).get(0);
 
-                if attempt >= self.max_attempts {
-                    crate::web::metrics::FAILED_BUILDS.inc();
+                // The crate might have been removed from the queue while it was being built.
+                if let Some(row) = rows.iter().next() {
+                    let attempt: i32 = row.get(0);
+                    if attempt >= self.max_attempts {
+                        crate::web::metrics::FAILED_BUILDS.inc();
+                        log_transition("abandoned", duration);
+                    } else {
+                        log_transition("failed", duration);
+                    }
                 }
 
                 error!(
@@ -125,6 +397,292 @@ impl BuildQueue {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test::captured_logs;
+
+    #[test]
+    fn test_max_concurrent_builds() {
+        use std::{sync::mpsc, thread, time::Duration};
+
+        crate::test::wrapper(|env| {
+            env.override_config(|config| config.max_concurrent_builds = 1);
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.add_crate("bar", "1.0.0", 0)?;
+
+            let spawn_build = |release: Option<mpsc::Receiver<()>>| {
+                let queue = queue.clone();
+                let (started_tx, started) = mpsc::channel();
+                let handle = thread::spawn(move || {
+                    queue
+                        .process_next_crate(|krate| {
+                            started_tx.send(krate.name.clone()).unwrap();
+                            if let Some(release) = release {
+                                release.recv().unwrap();
+                            }
+                            Ok(())
+                        })
+                        .unwrap();
+                });
+                (started, handle)
+            };
+
+            let (release_tx, release_rx) = mpsc::channel();
+            let (first_started, first) = spawn_build(Some(release_rx));
+            assert_eq!(first_started.recv()?, "foo");
+
+            // The second build waits for the permit held by the first one
+            let (second_started, second) = spawn_build(None);
+            assert!(second_started
+                .recv_timeout(Duration::from_millis(500))
+                .is_err());
+
+            release_tx.send(())?;
+            first.join().unwrap();
+            assert_eq!(second_started.recv_timeout(Duration::from_secs(10))?, "bar");
+            second.join().unwrap();
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_concurrent_builds_pick_different_crates() {
+        use std::{sync::mpsc, thread, time::Duration};
+
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.max_concurrent_builds = 2;
+                // Each build holds a connection
+                config.max_pool_size = 4;
+            });
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.add_crate("bar", "1.0.0", 0)?;
+
+            let (started_tx, started) = mpsc::channel();
+            let (release_tx, release_rx) = mpsc::channel::<()>();
+            let release_rx = std::sync::Arc::new(Mutex::new(release_rx));
+            let builds = (0..2)
+                .map(|_| {
+                    let queue = queue.clone();
+                    let started_tx = started_tx.clone();
+                    let release_rx = release_rx.clone();
+                    thread::spawn(move || {
+                        queue
+                            .process_next_crate(|krate| {
+                                started_tx.send(krate.name.clone()).unwrap();
+                                release_rx.lock().unwrap().recv().unwrap();
+                                Ok(())
+                            })
+                            .unwrap();
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            // Both builds run at the same time, each on its own crate
+            let mut names = vec![
+                started.recv_timeout(Duration::from_secs(10))?,
+                started.recv_timeout(Duration::from_secs(10))?,
+            ];
+            names.sort();
+            assert_eq!(names, vec!["bar", "foo"]);
+
+            // The crates being built aren't handed out again
+            assert_eq!(queue.claim_next_crate(&env.db().conn())?, None);
+
+            release_tx.send(())?;
+            release_tx.send(())?;
+            for build in builds {
+                build.join().unwrap();
+            }
+            assert!(queue.queued_crates()?.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_backoff(60, 1), 60);
+        assert_eq!(retry_backoff(60, 2), 120);
+        assert_eq!(retry_backoff(60, 4), 480);
+        assert_eq!(retry_backoff(60, 100), MAX_RETRY_BACKOFF);
+        assert_eq!(retry_backoff(0, 3), 0);
+    }
+
+    #[test]
+    fn test_failed_builds_are_retried_after_backoff() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.build_attempts = 3;
+                config.build_retry_backoff = 60;
+            });
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.process_next_crate(|_| failure::bail!("transient failure"))?;
+
+            // The crate is still queued, but isn't handed out before its backoff elapses
+            assert_eq!(queue.queued_crates()?.len(), 1);
+            assert_eq!(queue.pending_count()?, 0);
+            queue.process_next_crate(|_| panic!("the crate was retried during its backoff"))?;
+
+            let rows = env.db().conn().query(
+                "SELECT next_attempt_at > NOW() + INTERVAL '50 seconds' FROM queue",
+                &[],
+            )?;
+            assert!(rows.get(0).get::<_, bool>(0));
+
+            // Simulate the backoff elapsing
+            env.db().conn().execute(
+                "UPDATE queue SET next_attempt_at = NOW() - INTERVAL '1 second'",
+                &[],
+            )?;
+            let mut retried = false;
+            queue.process_next_crate(|krate| {
+                assert_eq!(krate.name, "foo");
+                retried = true;
+                Ok(())
+            })?;
+            assert!(retried, "the crate wasn't retried after its backoff");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_backed_off_crates_are_not_pending() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.build_attempts = 3;
+                config.build_retry_backoff = 60;
+            });
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", -10)?;
+            assert_eq!(queue.pending_count()?, 1);
+            assert_eq!(queue.prioritized_count()?, 1);
+
+            // The daemon would spin on the queue if the only entry left was counted while it
+            // can't be built yet
+            queue.process_next_crate(|_| failure::bail!("transient failure"))?;
+            assert_eq!(queue.pending_count()?, 0);
+            assert_eq!(queue.prioritized_count()?, 0);
+
+            env.db().conn().execute(
+                "UPDATE queue SET next_attempt_at = NOW() - INTERVAL '1 second'",
+                &[],
+            )?;
+            assert_eq!(queue.pending_count()?, 1);
+            assert_eq!(queue.prioritized_count()?, 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_transitions_are_logged() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| config.build_attempts = 2);
+            let queue = env.build_queue();
+
+            queue.add_crate("logged_ok", "1.0.0", 0)?;
+            queue.process_next_crate(|_| Ok(()))?;
+            queue.add_crate("logged_err", "1.0.0", 0)?;
+            for _ in 0..2 {
+                queue.process_next_crate(|_| failure::bail!("build failed"))?;
+            }
+
+            let logged = captured_logs("cratesfyi::build_queue");
+            let transitions = |name: &str| {
+                logged
+                    .iter()
+                    .filter(|line| line.contains(&format!(" crate={} ", name)))
+                    .map(|line| {
+                        let attempt_state = line.split(" version=1.0.0 ").nth(1).unwrap();
+                        assert!(line.starts_with("queue_id="));
+                        assert_eq!(
+                            line.contains(" duration_ms="),
+                            !attempt_state.ends_with("state=pending")
+                                && !attempt_state.ends_with("state=building")
+                        );
+                        attempt_state
+                            .split(" duration_ms=")
+                            .next()
+                            .unwrap()
+                            .to_string()
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            assert_eq!(
+                transitions("logged_ok"),
+                vec![
+                    "attempt=0 state=pending",
+                    "attempt=1 state=building",
+                    "attempt=1 state=done"
+                ]
+            );
+            assert_eq!(
+                transitions("logged_err"),
+                vec![
+                    "attempt=0 state=pending",
+                    "attempt=1 state=building",
+                    "attempt=1 state=failed",
+                    "attempt=2 state=building",
+                    "attempt=2 state=abandoned",
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_add_pending_crate_again() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| config.build_attempts = 1);
+            let queue = env.build_queue();
+
+            assert!(queue.add_crate("foo", "1.0.0", 0)?);
+            assert!(!queue.add_crate("foo", "1.0.0", -10)?);
+            let queued = queue.queued_crates()?;
+            assert_eq!(queued.len(), 1);
+            assert_eq!(queued[0].priority, 0);
+
+            // Abandoned crates are queued again
+            queue.process_next_crate(|_| failure::bail!("build failed"))?;
+            assert_eq!(queue.pending_count()?, 0);
+            assert!(queue.add_crate("foo", "1.0.0", 0)?);
+            assert_eq!(queue.pending_count()?, 1);
+            assert_eq!(queue.failed_count()?, 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_priority_crates() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.priority_crates = vec!["serde*".to_string()].into_iter().collect();
+            });
+            let queue = env.build_queue();
+
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.add_crate("serde_json", "1.0.0", 0)?;
+            queue.add_crate("bar", "1.0.0", -10)?;
+
+            let mut processed = Vec::new();
+            for _ in 0..3 {
+                queue.process_next_crate(|krate| {
+                    processed.push(krate.name.clone());
+                    Ok(())
+                })?;
+            }
+            assert_eq!(processed, vec!["serde_json", "bar", "foo"]);
+
+            Ok(())
+        });
+    }
 
     #[test]
     fn test_add_and_process_crates() {
@@ -277,6 +835,125 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_failed_crates() {
+        const MAX_ATTEMPTS: u16 = 3;
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.build_attempts = MAX_ATTEMPTS;
+            });
+            let queue = env.build_queue();
+
+            queue.add_crate("foo", "1.0.0", 0)?;
+            assert!(queue.failed_crates()?.is_empty());
+
+            for _ in 0..MAX_ATTEMPTS {
+                let mut called = false;
+                queue.process_next_crate(|krate| {
+                    called = true;
+                    assert_eq!("foo", krate.name);
+                    failure::bail!("this failed");
+                })?;
+                assert!(
+                    called,
+                    "the crate was not handed out before reaching the cap"
+                );
+            }
+
+            // Once the cap is reached the crate is not handed out anymore.
+            let mut called = false;
+            queue.process_next_crate(|_| {
+                called = true;
+                Ok(())
+            })?;
+            assert!(!called, "the failed crate was handed out again");
+
+            let failed = queue.failed_crates()?;
+            assert_eq!(1, failed.len());
+            assert_eq!("foo", failed[0].name);
+            assert_eq!("1.0.0", failed[0].version);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+
+            assert!(!queue.is_paused()?);
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.add_crate("bar", "1.0.0", 0)?;
+
+            queue.set_paused(true)?;
+            assert!(queue.is_paused()?);
+
+            let mut called = false;
+            queue.process_next_crate(|_| {
+                called = true;
+                Ok(())
+            })?;
+            assert!(!called, "a crate was handed out while the queue was paused");
+            assert_eq!(queue.pending_count()?, 2);
+
+            queue.set_paused(false)?;
+            assert!(!queue.is_paused()?);
+
+            queue.process_next_crate(|krate| {
+                assert_eq!("foo", krate.name);
+                Ok(())
+            })?;
+            assert_eq!(queue.pending_count()?, 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_remove() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.add_crate("bar", "1.0.0", 0)?;
+            assert_eq!(queue.pending_count()?, 2);
+
+            assert!(queue.remove("foo", "1.0.0")?);
+            assert_eq!(queue.pending_count()?, 1);
+            assert!(!queue.remove("foo", "1.0.0")?);
+            assert!(!queue.remove("bar", "2.0.0")?);
+            assert_eq!(queue.pending_count()?, 1);
+
+            // Removing a crate while it's being built doesn't break the queue.
+            queue.process_next_crate(|krate| {
+                assert_eq!("bar", krate.name);
+                assert!(queue.remove("bar", "1.0.0")?);
+                failure::bail!("this failed");
+            })?;
+            assert_eq!(queue.pending_count()?, 0);
+            assert_eq!(queue.failed_count()?, 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_is_queued() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+
+            queue.add_crate("foo", "1.0.0", 0)?;
+            assert!(queue.is_queued("foo")?);
+            assert!(!queue.is_queued("bar")?);
+
+            queue.remove("foo", "1.0.0")?;
+            assert!(!queue.is_queued("foo")?);
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_queued_crates() {
         crate::test::wrapper(|env| {
@@ -298,18 +975,21 @@ mod tests {
                         name: "foo".into(),
                         version: "1.0.0".into(),
                         priority: -10,
+                        attempt: 0,
                     },
                     QueuedCrate {
                         id: 2,
                         name: "bar".into(),
                         version: "1.0.0".into(),
                         priority: 0,
+                        attempt: 0,
                     },
                     QueuedCrate {
                         id: 3,
                         name: "baz".into(),
                         version: "1.0.0".into(),
                         priority: 10,
+                        attempt: 0,
                     },
                 ],
                 queue.queued_crates()?
@@ -8,7 +8,7 @@ use crate::{
     docbuilder::BuildResult,
     error::Result,
     index::api::{CrateOwner, RegistryCrateData},
-    storage::CompressionAlgorithm,
+    storage::{CompressionAlgorithm, Storage, StorageKind},
     utils::MetadataPackage,
 };
 use log::debug;
@@ -17,6 +17,9 @@ use regex::Regex;
 use serde_json::Value;
 use slug::slugify;
 
+/// Maximum length of the build error recorded for a release, in characters.
+const MAX_BUILD_ERROR_LENGTH: usize = 1000;
+
 /// Adds a package into database.
 ///
 /// Package must be built first.
@@ -82,7 +85,8 @@ pub(crate) fn add_package_into_database(
                 is_library = $22,
                 doc_rustc_version = $23,
                 documentation_url = $24,
-                default_target = $25
+                default_target = $25,
+                docs_removed = FALSE
          RETURNING id",
         &[
             &crate_id,
@@ -123,9 +127,10 @@ pub(crate) fn add_package_into_database(
     // Update the crates table with the new release
     conn.execute(
         "UPDATE crates
-         SET latest_version_id = $2
+         SET latest_version_id = $2,
+             content = crate_search_document(name, $3)
          WHERE id = $1",
-        &[&crate_id, &release_id],
+        &[&crate_id, &release_id, &metadata_pkg.description],
     )?;
 
     Ok(release_id)
@@ -141,8 +146,9 @@ pub(crate) fn add_build_into_database(
     let rows = conn.query(
         "INSERT INTO builds (rid, rustc_version,
                                                     cratesfyi_version,
-                                                    build_status, output)
-                                VALUES ($1, $2, $3, $4, $5)
+                                                    build_status, output,
+                                                    features, cargo_args)
+                                VALUES ($1, $2, $3, $4, $5, $6, $7)
                                 RETURNING id",
         &[
             &release_id,
@@ -150,9 +156,83 @@ pub(crate) fn add_build_into_database(
             &res.docsrs_version,
             &res.successful,
             &res.build_log,
+            &serde_json::to_value(&res.features)?,
+            &serde_json::to_value(&res.cargo_args)?,
         ],
     )?;
-    Ok(rows.get(0).get(0))
+    let build_id: i32 = rows.get(0).get(0);
+
+    let error = if res.successful {
+        None
+    } else {
+        Some(build_error(&res.build_log))
+    };
+    conn.execute(
+        "UPDATE releases
+         SET last_build_attempt = builds.build_time, last_build_error = $3
+         FROM builds
+         WHERE releases.id = $1 AND builds.id = $2",
+        &[&release_id, &build_id, &error],
+    )?;
+
+    Ok(build_id)
+}
+
+/// Extracts why a build failed from its log, starting at the first error reported by cargo or
+/// rustc, and truncated to `MAX_BUILD_ERROR_LENGTH` characters.
+fn build_error(log: &str) -> String {
+    let start = Regex::new(r"(?m)^\s*error")
+        .unwrap()
+        .find(log)
+        .map(|m| m.start())
+        .unwrap_or(0);
+    let error = log[start..].trim();
+
+    match error.char_indices().nth(MAX_BUILD_ERROR_LENGTH) {
+        Some((end, _)) => format!("{}...", &error[..end]),
+        None => error.to_string(),
+    }
+}
+
+/// Records the total size of the documentation stored for a release.
+///
+/// The documentation must be stored first.
+pub(crate) fn update_doc_size(
+    conn: &Connection,
+    storage: &StorageKind,
+    release_id: i32,
+    name: &str,
+    version: &str,
+) -> Result<()> {
+    let size =
+        Storage::new(conn, storage).prefix_size(&format!("rustdoc/{}/{}/", name, version))?;
+    conn.execute(
+        "UPDATE releases SET doc_size_bytes = $2 WHERE id = $1",
+        &[&release_id, &(size as i64)],
+    )?;
+    Ok(())
+}
+
+/// Records the targets whose documentation was built for a release, replacing the ones recorded
+/// by previous builds.
+pub(crate) fn add_targets_into_database(
+    conn: &Connection,
+    release_id: i32,
+    targets: &[(&str, bool)],
+) -> Result<()> {
+    conn.execute(
+        "DELETE FROM release_targets WHERE release = $1",
+        &[&release_id],
+    )?;
+    let prepared = conn.prepare_cached(
+        "INSERT INTO release_targets (release, target, build_status)
+         VALUES ($1, $2, $3)
+         ON CONFLICT DO NOTHING",
+    )?;
+    for (target, build_status) in targets {
+        prepared.execute(&[&release_id, target, build_status])?;
+    }
+    Ok(())
 }
 
 fn initialize_package_in_database(conn: &Connection, pkg: &MetadataPackage) -> Result<i32> {
@@ -328,31 +408,39 @@ fn add_authors_into_database(
     Ok(())
 }
 
-/// Adds owners into database
+/// Stores the current owners of a crate, replacing the ones stored with the previous releases.
+///
+/// Crates always have at least one owner, so an empty list means the owners couldn't be loaded
+/// from the registry: the stored owners are kept in that case.
 fn add_owners_into_database(conn: &Connection, owners: &[CrateOwner], crate_id: i32) -> Result<()> {
+    if owners.is_empty() {
+        return Ok(());
+    }
+
+    let mut owner_ids = Vec::with_capacity(owners.len());
     for owner in owners {
-        let owner_id: i32 = {
-            let rows = conn.query("SELECT id FROM owners WHERE login = $1", &[&owner.login])?;
-            if !rows.is_empty() {
-                rows.get(0).get(0)
-            } else {
-                conn.query(
-                    "INSERT INTO owners (login, avatar, name, email)
-                                 VALUES ($1, $2, $3, $4)
-                                 RETURNING id",
-                    &[&owner.login, &owner.avatar, &owner.name, &owner.email],
-                )?
-                .get(0)
-                .get(0)
-            }
-        };
+        let rows = conn.query(
+            "INSERT INTO owners (login, avatar, name, email)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (login) DO UPDATE
+                 SET avatar = EXCLUDED.avatar, name = EXCLUDED.name, email = EXCLUDED.email
+             RETURNING id",
+            &[&owner.login, &owner.avatar, &owner.name, &owner.email],
+        )?;
+        owner_ids.push(rows.get(0).get::<_, i32>(0));
+    }
 
-        // add relationship
-        let _ = conn.query(
-            "INSERT INTO owner_rels (cid, oid) VALUES ($1, $2)",
+    conn.execute(
+        "DELETE FROM owner_rels WHERE cid = $1 AND NOT (oid = ANY($2))",
+        &[&crate_id, &owner_ids],
+    )?;
+    for owner_id in owner_ids {
+        conn.execute(
+            "INSERT INTO owner_rels (cid, oid) VALUES ($1, $2) ON CONFLICT DO NOTHING",
             &[&crate_id, &owner_id],
-        );
+        )?;
     }
+
     Ok(())
 }
 
@@ -0,0 +1,220 @@
+//! Resumable backfills of the stored data, for the changes the schema migrations can't do on
+//! their own, like computing a new column from the storage.
+//!
+//! Each backfill walks the rows of a table in batches ordered by id, and records the id of the
+//! last processed row in the `backfills` table after every batch. An interrupted backfill resumes
+//! after the last recorded batch, which might have been partially processed: batches must be
+//! idempotent.
+
+use crate::db::update_doc_size;
+use crate::storage::StorageKind;
+use chrono::NaiveDateTime;
+use failure::{format_err, Error};
+use log::info;
+use postgres::Connection;
+
+/// Processes up to `batch_size` rows with an id greater than `after`, returning the id of the
+/// last processed row, or `None` once there is nothing left to process.
+type BatchFn = fn(
+    conn: &Connection,
+    storage: &StorageKind,
+    after: i32,
+    batch_size: i64,
+) -> Result<Option<i32>, Error>;
+
+/// A named backfill, see the module documentation.
+struct Backfill {
+    name: &'static str,
+    description: &'static str,
+    run_batch: BatchFn,
+}
+
+/// The registered backfills, in the order they were added.
+fn backfills() -> Vec<Backfill> {
+    vec![Backfill {
+        name: "doc-size",
+        description: "Record the size of the documentation stored before the sizes were tracked",
+        run_batch: backfill_doc_size,
+    }]
+}
+
+fn backfill_doc_size(
+    conn: &Connection,
+    storage: &StorageKind,
+    after: i32,
+    batch_size: i64,
+) -> Result<Option<i32>, Error> {
+    let rows = conn.query(
+        "SELECT releases.id, crates.name, releases.version
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE releases.id > $1 AND releases.doc_size_bytes IS NULL
+         ORDER BY releases.id
+         LIMIT $2",
+        &[&after, &batch_size],
+    )?;
+
+    let mut last_id = None;
+    for row in &rows {
+        let release_id: i32 = row.get(0);
+        let name: String = row.get(1);
+        let version: String = row.get(2);
+        update_doc_size(conn, storage, release_id, &name, &version)?;
+        last_id = Some(release_id);
+    }
+    Ok(last_id)
+}
+
+/// Returns the name, description and whether it completed of every registered backfill.
+pub fn list_backfills(conn: &Connection) -> Result<Vec<(&'static str, &'static str, bool)>, Error> {
+    backfills()
+        .into_iter()
+        .map(|backfill| {
+            let rows = conn.query(
+                "SELECT completed_at FROM backfills WHERE name = $1",
+                &[&backfill.name],
+            )?;
+            let completed = rows
+                .iter()
+                .next()
+                .and_then(|row| row.get::<_, Option<NaiveDateTime>>(0))
+                .is_some();
+            Ok((backfill.name, backfill.description, completed))
+        })
+        .collect()
+}
+
+/// Runs the registered backfill called `name` until it completes, resuming from where the
+/// previous runs stopped. Returns the number of batches processed by this run.
+pub fn run_backfill(
+    conn: &Connection,
+    storage: &StorageKind,
+    name: &str,
+    batch_size: i64,
+) -> Result<usize, Error> {
+    let backfill = backfills()
+        .into_iter()
+        .find(|backfill| backfill.name == name)
+        .ok_or_else(|| format_err!("unknown backfill: {}", name))?;
+    run(conn, storage, &backfill, batch_size)
+}
+
+fn run(
+    conn: &Connection,
+    storage: &StorageKind,
+    backfill: &Backfill,
+    batch_size: i64,
+) -> Result<usize, Error> {
+    conn.execute(
+        "INSERT INTO backfills (name) VALUES ($1) ON CONFLICT (name) DO NOTHING",
+        &[&backfill.name],
+    )?;
+    let rows = conn.query(
+        "SELECT last_id, completed_at FROM backfills WHERE name = $1",
+        &[&backfill.name],
+    )?;
+    let mut last_id: i32 = rows.get(0).get(0);
+    if rows.get(0).get::<_, Option<NaiveDateTime>>(1).is_some() {
+        info!("backfill {} already completed", backfill.name);
+        return Ok(0);
+    }
+
+    let mut batches = 0;
+    while let Some(batch_last_id) = (backfill.run_batch)(conn, storage, last_id, batch_size)? {
+        last_id = batch_last_id;
+        batches += 1;
+        conn.execute(
+            "UPDATE backfills SET last_id = $2 WHERE name = $1",
+            &[&backfill.name, &last_id],
+        )?;
+        info!(
+            "backfill {} processed the rows up to {}",
+            backfill.name, last_id
+        );
+    }
+
+    conn.execute(
+        "UPDATE backfills SET completed_at = CURRENT_TIMESTAMP WHERE name = $1",
+        &[&backfill.name],
+    )?;
+    info!("backfill {} completed", backfill.name);
+
+    Ok(batches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    fn mark_descriptions(
+        conn: &Connection,
+        _storage: &StorageKind,
+        after: i32,
+        batch_size: i64,
+    ) -> Result<Option<i32>, Error> {
+        let rows = conn.query(
+            "UPDATE releases SET description = 'backfilled'
+             WHERE id IN (SELECT id FROM releases WHERE id > $1 ORDER BY id LIMIT $2)
+             RETURNING id",
+            &[&after, &batch_size],
+        )?;
+        Ok(rows.iter().map(|row| row.get::<_, i32>(0)).max())
+    }
+
+    #[test]
+    fn test_backfill_resumes_and_completes() {
+        wrapper(|env| {
+            let db = env.db();
+            let mut release_ids = Vec::new();
+            for version in &["0.1.0", "0.2.0", "0.3.0", "0.4.0", "0.5.0"] {
+                release_ids.push(db.fake_release().name("foo").version(version).create()?);
+            }
+            let backfill = Backfill {
+                name: "mark-descriptions",
+                description: "Mark the descriptions of all the releases",
+                run_batch: mark_descriptions,
+            };
+
+            // Pretend a previous run was interrupted after processing the first two releases
+            let conn = db.conn();
+            conn.execute(
+                "INSERT INTO backfills (name, last_id) VALUES ($1, $2)",
+                &[&backfill.name, &release_ids[1]],
+            )?;
+
+            assert_eq!(run(&conn, db.storage_backend(), &backfill, 2)?, 2);
+            let descriptions = conn
+                .query("SELECT description FROM releases ORDER BY id", &[])?
+                .iter()
+                .map(|row| row.get(0))
+                .collect::<Vec<String>>();
+            assert_eq!(
+                descriptions,
+                vec![
+                    "Fake package",
+                    "Fake package",
+                    "backfilled",
+                    "backfilled",
+                    "backfilled"
+                ]
+            );
+
+            let completed: Option<NaiveDateTime> = conn
+                .query(
+                    "SELECT completed_at FROM backfills WHERE name = $1",
+                    &[&backfill.name],
+                )?
+                .get(0)
+                .get(0);
+            assert!(completed.is_some());
+
+            // Completed backfills are not run again
+            assert_eq!(run(&conn, db.storage_backend(), &backfill, 2)?, 0);
+
+            assert!(run_backfill(&conn, db.storage_backend(), "missing", 2).is_err());
+
+            Ok(())
+        });
+    }
+}
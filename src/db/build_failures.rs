@@ -0,0 +1,42 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use postgres::Connection;
+use serde::Serialize;
+
+/// A release whose last build failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct BuildFailure {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) time: DateTime<Utc>,
+    /// Summary of the build error, already truncated when the build was recorded
+    pub(crate) error: String,
+}
+
+/// Loads the releases whose last build failed, most recent failure first.
+pub(crate) fn recent_build_failures(
+    conn: &Connection,
+    limit: i64,
+) -> Result<Vec<BuildFailure>, postgres::Error> {
+    let rows = conn.query(
+        "SELECT crates.name,
+                releases.version,
+                releases.last_build_attempt,
+                releases.last_build_error
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE releases.last_build_error IS NOT NULL
+         ORDER BY releases.last_build_attempt DESC, releases.id DESC
+         LIMIT $1",
+        &[&limit],
+    )?;
+
+    Ok(rows
+        .iter()
+        .map(|row| BuildFailure {
+            name: row.get(0),
+            version: row.get(1),
+            time: DateTime::from_utc(row.get::<_, NaiveDateTime>(2), Utc),
+            error: row.get(3),
+        })
+        .collect())
+}
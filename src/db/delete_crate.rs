@@ -1,11 +1,10 @@
-use crate::storage::s3::{s3_client, S3_BUCKET_NAME};
+use crate::storage::{Storage, StorageKind};
 use failure::{Error, Fail};
 use postgres::Connection;
-use rusoto_s3::{DeleteObjectsRequest, ListObjectsV2Request, ObjectIdentifier, S3Client, S3};
 
-/// List of directories in docs.rs's underlying storage (either the database or S3) containing a
-/// subdirectory named after the crate. Those subdirectories will be deleted.
-static STORAGE_PATHS_TO_DELETE: &[&str] = &["rustdoc", "sources"];
+/// List of directories in docs.rs's underlying storage (the database, S3 or the filesystem)
+/// containing a subdirectory named after the crate. Those subdirectories will be deleted.
+static STORAGE_PATHS_TO_DELETE: &[&str] = &["rustdoc", "rustdoc-json", "sources"];
 
 #[derive(Debug, Fail)]
 enum CrateDeletionError {
@@ -13,7 +12,7 @@ enum CrateDeletionError {
     MissingCrate(String),
 }
 
-pub fn delete_crate(conn: &Connection, name: &str) -> Result<(), Error> {
+pub fn delete_crate(conn: &Connection, storage: &StorageKind, name: &str) -> Result<(), Error> {
     let crate_id_res = conn.query("SELECT id FROM crates WHERE name = $1", &[&name])?;
     let crate_id = if crate_id_res.is_empty() {
         return Err(CrateDeletionError::MissingCrate(name.into()).into());
@@ -22,8 +21,12 @@ pub fn delete_crate(conn: &Connection, name: &str) -> Result<(), Error> {
     };
 
     delete_from_database(conn, name, crate_id)?;
-    if let Some(s3) = s3_client() {
-        delete_from_s3(&s3, name)?;
+    // Files in the database were already deleted as part of the transaction
+    if *storage != StorageKind::Database {
+        let mut storage = Storage::new(conn, storage);
+        for prefix in STORAGE_PATHS_TO_DELETE {
+            storage.delete_prefix(&format!("{}/{}/", prefix, name))?;
+        }
     }
 
     Ok(())
@@ -40,16 +43,18 @@ fn delete_from_database(conn: &Connection, name: &str, crate_id: i32) -> Result<
         "DELETE FROM author_rels WHERE rid IN (SELECT id FROM releases WHERE crate_id = $1);",
         &[&crate_id],
     )?;
+    transaction.execute("DELETE FROM owner_rels WHERE cid = $1;", &[&crate_id])?;
     transaction.execute(
-        "DELETE FROM owner_rels WHERE cid IN (SELECT id FROM releases WHERE crate_id = $1);",
+        "DELETE FROM keyword_rels WHERE rid IN (SELECT id FROM releases WHERE crate_id = $1);",
         &[&crate_id],
     )?;
     transaction.execute(
-        "DELETE FROM keyword_rels WHERE rid IN (SELECT id FROM releases WHERE crate_id = $1);",
+        "DELETE FROM builds WHERE rid IN (SELECT id FROM releases WHERE crate_id = $1);",
         &[&crate_id],
     )?;
     transaction.execute(
-        "DELETE FROM builds WHERE rid IN (SELECT id FROM releases WHERE crate_id = $1);",
+        "DELETE FROM release_targets
+         WHERE release IN (SELECT id FROM releases WHERE crate_id = $1);",
         &[&crate_id],
     )?;
     transaction.execute("DELETE FROM releases WHERE crate_id = $1;", &[&crate_id])?;
@@ -68,59 +73,6 @@ fn delete_from_database(conn: &Connection, name: &str, crate_id: i32) -> Result<
     Ok(())
 }
 
-fn delete_from_s3(s3: &S3Client, name: &str) -> Result<(), Error> {
-    for prefix in STORAGE_PATHS_TO_DELETE {
-        delete_prefix_from_s3(s3, &format!("{}/{}/", prefix, name))?;
-    }
-    Ok(())
-}
-
-fn delete_prefix_from_s3(s3: &S3Client, name: &str) -> Result<(), Error> {
-    let mut continuation_token = None;
-    loop {
-        let list = s3
-            .list_objects_v2(ListObjectsV2Request {
-                bucket: S3_BUCKET_NAME.into(),
-                prefix: Some(name.into()),
-                continuation_token,
-                ..ListObjectsV2Request::default()
-            })
-            .sync()?;
-
-        let to_delete = list
-            .contents
-            .unwrap_or_else(Vec::new)
-            .into_iter()
-            .filter_map(|o| o.key)
-            .map(|key| ObjectIdentifier {
-                key,
-                version_id: None,
-            })
-            .collect::<Vec<_>>();
-        let resp = s3
-            .delete_objects(DeleteObjectsRequest {
-                bucket: S3_BUCKET_NAME.into(),
-                delete: rusoto_s3::Delete {
-                    objects: to_delete,
-                    quiet: None,
-                },
-                ..DeleteObjectsRequest::default()
-            })
-            .sync()?;
-        if let Some(errs) = resp.errors {
-            for err in &errs {
-                log::error!("error deleting file from s3: {:?}", err);
-            }
-            failure::bail!("uploading to s3 failed");
-        }
-
-        continuation_token = list.continuation_token;
-        if continuation_token.is_none() {
-            return Ok(());
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,410 @@
+//! Removal of the documentation of old releases, to keep the size of the storage in check.
+//!
+//! Only the rustdoc output is removed: the releases, their sources and their builds are kept, and
+//! the releases are marked with `docs_removed` so that they can be told apart from failed builds.
+
+use crate::storage::{Storage, StorageKind};
+use crate::Config;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use failure::Error;
+use log::info;
+use postgres::Connection;
+use std::cmp::Reverse;
+
+/// Which documentation to keep when removing the old documentation. A release is kept if any of
+/// the rules selects it, and the latest documented release of each crate is always kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Keeps the documentation of the newest versions of each crate
+    pub(crate) keep_versions: Option<u32>,
+    /// Keeps the documentation of the releases published after this time
+    pub(crate) keep_newer_than: Option<DateTime<Utc>>,
+}
+
+impl RetentionPolicy {
+    pub fn from_config(config: &Config, now: DateTime<Utc>) -> Self {
+        Self {
+            keep_versions: config.docs_retention_versions,
+            keep_newer_than: config
+                .docs_retention_days
+                .map(|days| now - Duration::days(days.into())),
+        }
+    }
+
+    /// Returns the releases of a crate whose documentation can be removed.
+    fn releases_to_remove<'a>(&self, releases: &'a [StoredRelease]) -> Vec<&'a StoredRelease> {
+        if self.keep_versions.is_none() && self.keep_newer_than.is_none() {
+            return Vec::new();
+        }
+
+        let mut newest_first = releases
+            .iter()
+            .filter(|release| release.has_docs)
+            .collect::<Vec<_>>();
+        newest_first
+            .sort_by_cached_key(|release| Reverse(semver::Version::parse(&release.version).ok()));
+        // Like everywhere else, the latest release is the newest one that wasn't yanked, but only
+        // the releases with documentation count so that a failed build doesn't leave the crate
+        // without any documentation
+        let latest = newest_first
+            .iter()
+            .find(|release| !release.yanked)
+            .or_else(|| newest_first.first())
+            .map(|release| release.id);
+
+        newest_first
+            .into_iter()
+            .enumerate()
+            .filter(|(position, release)| {
+                let is_kept = Some(release.id) == latest
+                    || matches!(self.keep_versions, Some(versions) if *position < versions as usize)
+                    || matches!(self.keep_newer_than, Some(cutoff) if release.release_time >= cutoff);
+                !is_kept
+            })
+            .map(|(_, release)| release)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StoredRelease {
+    id: i32,
+    version: String,
+    release_time: DateTime<Utc>,
+    yanked: bool,
+    has_docs: bool,
+}
+
+/// A release whose documentation isn't kept by a retention policy.
+struct Removal {
+    release_id: i32,
+    name: String,
+    version: String,
+}
+
+impl Removal {
+    fn docs_prefix(&self) -> String {
+        format!("rustdoc/{}/{}/", self.name, self.version)
+    }
+}
+
+/// Returns the releases of all the crates whose documentation isn't kept by `policy`.
+fn planned_removals(conn: &Connection, policy: &RetentionPolicy) -> Result<Vec<Removal>, Error> {
+    let mut removals = Vec::new();
+
+    for krate in &conn.query("SELECT id, name FROM crates ORDER BY name", &[])? {
+        let crate_id: i32 = krate.get(0);
+        let name: String = krate.get(1);
+
+        let releases = conn
+            .query(
+                "SELECT id, version, release_time, yanked, rustdoc_status
+                 FROM releases
+                 WHERE crate_id = $1",
+                &[&crate_id],
+            )?
+            .iter()
+            .map(|row| StoredRelease {
+                id: row.get(0),
+                version: row.get(1),
+                release_time: DateTime::from_utc(row.get::<_, NaiveDateTime>(2), Utc),
+                yanked: row.get(3),
+                has_docs: row.get(4),
+            })
+            .collect::<Vec<_>>();
+
+        removals.extend(
+            policy
+                .releases_to_remove(&releases)
+                .into_iter()
+                .map(|release| Removal {
+                    release_id: release.id,
+                    name: name.clone(),
+                    version: release.version.clone(),
+                }),
+        );
+    }
+
+    Ok(removals)
+}
+
+/// Returns the name, version and stored size in bytes of the documentation [`remove_old_docs`]
+/// would remove, without removing anything.
+pub fn gc_plan(
+    conn: &Connection,
+    storage: &StorageKind,
+    policy: &RetentionPolicy,
+) -> Result<Vec<(String, String, u64)>, Error> {
+    let storage = Storage::new(conn, storage);
+
+    planned_removals(conn, policy)?
+        .into_iter()
+        .map(|removal| {
+            let size = storage.prefix_size(&removal.docs_prefix())?;
+            Ok((removal.name, removal.version, size))
+        })
+        .collect()
+}
+
+/// Removes the documentation of the releases not kept by `policy`, returning how many releases
+/// had their documentation removed.
+pub fn remove_old_docs(
+    conn: &Connection,
+    storage: &StorageKind,
+    policy: &RetentionPolicy,
+) -> Result<usize, Error> {
+    let mut storage = Storage::new(conn, storage);
+
+    let removals = planned_removals(conn, policy)?;
+    for removal in &removals {
+        // The files are removed first, so that the release is picked up again by the next run
+        // if it fails
+        storage.delete_prefix(&removal.docs_prefix())?;
+        conn.execute(
+            "UPDATE releases
+             SET rustdoc_status = FALSE, docs_removed = TRUE, doc_size_bytes = 0
+             WHERE id = $1",
+            &[&removal.release_id],
+        )?;
+        info!(
+            "removed the documentation of {}-{}",
+            removal.name, removal.version
+        );
+    }
+
+    Ok(removals.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use chrono::TimeZone;
+
+    fn release(id: i32, version: &str, day: u32) -> StoredRelease {
+        StoredRelease {
+            id,
+            version: version.into(),
+            release_time: Utc.ymd(2020, 1, day).and_hms(0, 0, 0),
+            yanked: false,
+            has_docs: true,
+        }
+    }
+
+    fn removed_versions(policy: &RetentionPolicy, releases: &[StoredRelease]) -> Vec<String> {
+        let mut versions = policy
+            .releases_to_remove(releases)
+            .into_iter()
+            .map(|release| release.version.clone())
+            .collect::<Vec<_>>();
+        versions.sort();
+        versions
+    }
+
+    #[test]
+    fn test_count_based_policy() {
+        let mut releases = vec![
+            release(1, "0.1.0", 1),
+            release(2, "0.3.0", 3),
+            release(3, "0.2.0", 2),
+            release(4, "0.4.0", 4),
+        ];
+        let policy = RetentionPolicy {
+            keep_versions: Some(2),
+            keep_newer_than: None,
+        };
+        assert_eq!(removed_versions(&policy, &releases), vec!["0.1.0", "0.2.0"]);
+
+        // The versions without documentation don't count
+        releases[1].has_docs = false;
+        assert_eq!(removed_versions(&policy, &releases), vec!["0.1.0"]);
+
+        // The latest version is kept even if it's not one of the newest ones
+        releases[1].has_docs = true;
+        releases[1].yanked = true;
+        releases[3].yanked = true;
+        let policy = RetentionPolicy {
+            keep_versions: Some(1),
+            keep_newer_than: None,
+        };
+        assert_eq!(removed_versions(&policy, &releases), vec!["0.1.0", "0.3.0"]);
+    }
+
+    #[test]
+    fn test_latest_documented_release_is_kept() {
+        let mut releases = vec![
+            release(1, "0.1.0", 1),
+            release(2, "0.2.0", 2),
+            release(3, "0.3.0", 3),
+        ];
+        // The build of the latest release failed
+        releases[2].has_docs = false;
+
+        let policy = RetentionPolicy {
+            keep_versions: None,
+            keep_newer_than: Some(Utc.ymd(2021, 1, 1).and_hms(0, 0, 0)),
+        };
+        assert_eq!(removed_versions(&policy, &releases), vec!["0.1.0"]);
+
+        // The yanked releases are only kept when there's nothing else
+        releases[1].yanked = true;
+        assert_eq!(removed_versions(&policy, &releases), vec!["0.2.0"]);
+        releases[0].yanked = true;
+        assert_eq!(removed_versions(&policy, &releases), vec!["0.1.0"]);
+    }
+
+    #[test]
+    fn test_age_based_policy() {
+        let releases = vec![
+            release(1, "0.1.0", 1),
+            release(2, "0.2.0", 10),
+            release(3, "0.3.0", 20),
+        ];
+        let policy = RetentionPolicy {
+            keep_versions: None,
+            keep_newer_than: Some(Utc.ymd(2020, 1, 10).and_hms(0, 0, 0)),
+        };
+        assert_eq!(removed_versions(&policy, &releases), vec!["0.1.0"]);
+
+        // The latest version is kept even if it's too old
+        let policy = RetentionPolicy {
+            keep_versions: None,
+            keep_newer_than: Some(Utc.ymd(2021, 1, 1).and_hms(0, 0, 0)),
+        };
+        assert_eq!(removed_versions(&policy, &releases), vec!["0.1.0", "0.2.0"]);
+
+        // Releases selected by either rule are kept
+        let policy = RetentionPolicy {
+            keep_versions: Some(2),
+            keep_newer_than: Some(Utc.ymd(2021, 1, 1).and_hms(0, 0, 0)),
+        };
+        assert_eq!(removed_versions(&policy, &releases), vec!["0.1.0"]);
+
+        assert!(removed_versions(&RetentionPolicy::default(), &releases).is_empty());
+    }
+
+    #[test]
+    fn test_gc_plan() {
+        wrapper(|env| {
+            let db = env.db();
+            for version in &["0.1.0", "0.2.0", "0.3.0"] {
+                db.fake_release()
+                    .name("foo")
+                    .version(version)
+                    .rustdoc_file("foo/index.html", b"docs")
+                    .create()?;
+            }
+            db.fake_release().name("bar").version("0.1.0").create()?;
+            db.fake_release().name("bar").version("0.2.0").create()?;
+
+            let policy = RetentionPolicy {
+                keep_versions: Some(1),
+                keep_newer_than: None,
+            };
+            let plan = gc_plan(&db.conn(), db.storage_backend(), &policy)?;
+            let planned = plan
+                .iter()
+                .map(|(name, version, _)| format!("{}-{}", name, version))
+                .collect::<Vec<_>>();
+            assert_eq!(planned, vec!["bar-0.1.0", "foo-0.2.0", "foo-0.1.0"]);
+            assert!(plan.iter().all(|(_, _, size)| *size > 0));
+
+            // The plan didn't remove anything
+            let conn = db.conn();
+            let storage = Storage::new(&conn, db.storage_backend());
+            assert!(storage.prefix_size("rustdoc/foo/0.1.0/")? > 0);
+
+            assert_eq!(
+                remove_old_docs(&db.conn(), db.storage_backend(), &policy)?,
+                plan.len()
+            );
+            let mut removed = db
+                .conn()
+                .query(
+                    "SELECT crates.name || '-' || releases.version
+                     FROM releases
+                     INNER JOIN crates ON crates.id = releases.crate_id
+                     WHERE docs_removed",
+                    &[],
+                )?
+                .iter()
+                .map(|row| row.get(0))
+                .collect::<Vec<String>>();
+            removed.sort();
+            let mut planned = planned;
+            planned.sort();
+            assert_eq!(removed, planned);
+            for (name, version, _) in &plan {
+                let prefix = format!("rustdoc/{}/{}/", name, version);
+                assert_eq!(storage.prefix_size(&prefix)?, 0);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_remove_old_docs() {
+        wrapper(|env| {
+            let db = env.db();
+            for version in &["0.1.0", "0.2.0", "0.3.0"] {
+                db.fake_release()
+                    .name("foo")
+                    .version(version)
+                    .rustdoc_file("foo/index.html", b"docs")
+                    .create()?;
+            }
+            db.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .rustdoc_file("bar/index.html", b"docs")
+                .create()?;
+
+            let policy = RetentionPolicy {
+                keep_versions: Some(2),
+                keep_newer_than: None,
+            };
+            assert_eq!(
+                remove_old_docs(&db.conn(), db.storage_backend(), &policy)?,
+                1
+            );
+            // Nothing is left to remove
+            assert_eq!(
+                remove_old_docs(&db.conn(), db.storage_backend(), &policy)?,
+                0
+            );
+
+            let existing = crate::db::exists_many(
+                &db.conn(),
+                db.storage_backend(),
+                &[
+                    "rustdoc/foo/0.1.0/foo/index.html",
+                    "rustdoc/foo/0.2.0/foo/index.html",
+                    "rustdoc/foo/0.3.0/foo/index.html",
+                    "rustdoc/bar/0.1.0/bar/index.html",
+                ],
+            )?;
+            assert!(!existing["rustdoc/foo/0.1.0/foo/index.html"]);
+            assert!(existing["rustdoc/foo/0.2.0/foo/index.html"]);
+            assert!(existing["rustdoc/foo/0.3.0/foo/index.html"]);
+            assert!(existing["rustdoc/bar/0.1.0/bar/index.html"]);
+
+            let removed = db
+                .conn()
+                .query(
+                    "SELECT version FROM releases WHERE docs_removed AND NOT rustdoc_status",
+                    &[],
+                )?
+                .iter()
+                .map(|row| row.get(0))
+                .collect::<Vec<String>>();
+            assert_eq!(removed, vec!["0.1.0"]);
+
+            // The crate page is still available for the removed docs
+            let web = env.frontend();
+            assert!(web.get("/crate/foo/0.1.0").send()?.status().is_success());
+
+            Ok(())
+        });
+    }
+}
@@ -5,34 +5,56 @@
 //! filesystem. This module is adding files into database and retrieving them.
 
 use crate::error::Result;
-use crate::storage::{CompressionAlgorithms, Storage};
+use crate::storage::{CompressionAlgorithm, CompressionAlgorithms, Storage, StorageKind};
 use postgres::Connection;
 
 use serde_json::Value;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 pub(crate) use crate::storage::Blob;
 
-pub(crate) fn get_path(conn: &Connection, path: &str, max_size: usize) -> Result<Blob> {
-    Storage::new(conn).get(path, max_size)
+pub(crate) fn get_path(
+    conn: &Connection,
+    storage: &StorageKind,
+    path: &str,
+    max_size: usize,
+) -> Result<Blob> {
+    Storage::new(conn, storage).get(path, max_size)
+}
+
+/// Checks which of the paths are present in the storage, returning a map from each path to
+/// whether it exists.
+///
+/// This is cheaper than fetching the files one by one, as the backend checks all of them with
+/// as few round trips as possible.
+pub fn exists_many(
+    conn: &Connection,
+    storage: &StorageKind,
+    paths: &[&str],
+) -> Result<HashMap<String, bool>> {
+    Storage::new(conn, storage).exists_many(paths)
 }
 
 /// Store all files in a directory and return [[mimetype, filename]] as Json
 ///
-/// If there is an S3 Client configured, store files into an S3 bucket;
-/// otherwise, stores files into the 'files' table of the local database.
+/// Depending on the storage kind, files are stored into an S3 bucket, into the 'files' table of
+/// the local database or into a local directory.
 ///
-/// The mimetype is detected using `magic`.
+/// The mimetype is detected using `magic`. Files are compressed with the given algorithm, or
+/// stored uncompressed if it's `None`.
 ///
 /// Note that this function is used for uploading both sources
 /// and files generated by rustdoc.
 pub fn add_path_into_database<P: AsRef<Path>>(
     conn: &Connection,
+    storage: &StorageKind,
     prefix: &str,
     path: P,
+    compression: Option<CompressionAlgorithm>,
 ) -> Result<(Value, CompressionAlgorithms)> {
-    let mut backend = Storage::new(conn);
-    let (file_list, algorithms) = backend.store_all(conn, prefix, path.as_ref())?;
+    let mut backend = Storage::new(conn, storage);
+    let (file_list, algorithms) = backend.store_all(conn, prefix, path.as_ref(), compression)?;
     Ok((
         file_list_to_json(file_list.into_iter().collect())?,
         algorithms,
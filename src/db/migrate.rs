@@ -50,7 +50,79 @@ macro_rules! migration {
     }};
 }
 
+/// The direction a migration would be run in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
 pub fn migrate(version: Option<Version>, conn: &Connection) -> CratesfyiResult<()> {
+    let migrator = migrator(conn)?;
+
+    if let Some(version) = version {
+        if version > migrator.current_version()?.unwrap_or(0) {
+            migrator.up(Some(version))?;
+        } else {
+            migrator.down(Some(version))?;
+        }
+    } else {
+        migrator.up(version)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the version of the latest migration applied to the database, or 0 if no migrations
+/// were applied yet.
+pub fn current_version(conn: &Connection) -> CratesfyiResult<Version> {
+    Ok(migrator(conn)?.current_version()?.unwrap_or(0))
+}
+
+/// Returns the migrations `migrate` would run with the same arguments, in the order they would
+/// be run, without actually applying them.
+pub fn migrate_dry_run(
+    version: Option<Version>,
+    conn: &Connection,
+) -> CratesfyiResult<Vec<(Version, String, Direction)>> {
+    let migrator = migrator(conn)?;
+    let current = migrator.current_version()?.unwrap_or(0);
+    let migrated = migrator.migrated_versions()?;
+
+    let mut migrations = migrations();
+    migrations.sort_by_key(|migration| migration.version());
+
+    // Mirror the behavior of schemamama's `Migrator::up` and `Migrator::down`.
+    let plan = match version {
+        Some(target) if target <= current => migrations
+            .iter()
+            .rev()
+            .filter(|migration| {
+                let v = migration.version();
+                v > target && v <= current && migrated.contains(&v)
+            })
+            .map(|migration| {
+                (
+                    migration.version(),
+                    migration.description(),
+                    Direction::Down,
+                )
+            })
+            .collect(),
+        _ => migrations
+            .iter()
+            .filter(|migration| {
+                let v = migration.version();
+                v <= version.unwrap_or(Version::MAX) && !migrated.contains(&v)
+            })
+            .map(|migration| (migration.version(), migration.description(), Direction::Up))
+            .collect(),
+    };
+
+    Ok(plan)
+}
+
+fn migrator(conn: &Connection) -> CratesfyiResult<Migrator<PostgresAdapter<'_>>> {
     conn.execute(
         "CREATE TABLE IF NOT EXISTS database_versions (version BIGINT PRIMARY KEY);",
         &[],
@@ -58,8 +130,15 @@ pub fn migrate(version: Option<Version>, conn: &Connection) -> CratesfyiResult<(
     let adapter = PostgresAdapter::with_metadata_table(conn, "database_versions");
 
     let mut migrator = Migrator::new(adapter);
+    for migration in migrations() {
+        migrator.register(migration);
+    }
+
+    Ok(migrator)
+}
 
-    let migrations: Vec<Box<dyn PostgresMigration>> = vec![
+fn migrations() -> Vec<Box<dyn PostgresMigration>> {
+    vec![
         migration!(
             context,
             // version
@@ -364,21 +443,219 @@ pub fn migrate(version: Option<Version>, conn: &Connection) -> CratesfyiResult<(
             "DROP TABLE compression_rels;
              ALTER TABLE files DROP COLUMN compression;"
         ),
-    ];
+        migration!(
+            context,
+            // version
+            15,
+            // description
+            "Add full-text search over crate names and descriptions",
+            // upgrade query
+            "
+                CREATE FUNCTION crate_search_document(VARCHAR, VARCHAR)
+                RETURNS tsvector
+                AS $$
+                    SELECT setweight(to_tsvector('english', $1), 'A')
+                        || setweight(to_tsvector('english', COALESCE($2, '')), 'B');
+                $$ LANGUAGE SQL IMMUTABLE;
 
-    for migration in migrations {
-        migrator.register(migration);
-    }
+                ALTER TABLE crates ADD COLUMN content tsvector;
+                UPDATE crates
+                    SET content = crate_search_document(crates.name, releases.description)
+                    FROM releases
+                    WHERE releases.id = crates.latest_version_id;
+                CREATE INDEX crates_content_idx ON crates USING gin(content);
+            ",
+            // downgrade query
+            "
+                ALTER TABLE crates DROP COLUMN content;
+                DROP FUNCTION crate_search_document;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            16,
+            // description
+            "Add a trigram index on the crate names, to suggest crates similar to missing ones",
+            // upgrade query
+            "CREATE INDEX crates_name_trgm_idx ON crates USING gin(name gin_trgm_ops);",
+            // downgrade query
+            "DROP INDEX crates_name_trgm_idx;"
+        ),
+        migration!(
+            context,
+            // version
+            17,
+            // description
+            "Make the owners belong to crates instead of releases",
+            // upgrade query
+            "
+                -- The owners were always stored with the id of their crate, so the rows that were
+                -- inserted are kept as-is
+                DELETE FROM owner_rels WHERE cid NOT IN (SELECT id FROM crates);
+                ALTER TABLE owner_rels
+                    DROP CONSTRAINT owner_rels_cid_fkey,
+                    ADD CONSTRAINT owner_rels_cid_fkey FOREIGN KEY (cid) REFERENCES crates(id);
+            ",
+            // downgrade query
+            "
+                DELETE FROM owner_rels WHERE cid NOT IN (SELECT id FROM releases);
+                ALTER TABLE owner_rels
+                    DROP CONSTRAINT owner_rels_cid_fkey,
+                    ADD CONSTRAINT owner_rels_cid_fkey FOREIGN KEY (cid) REFERENCES releases(id);
+            "
+        ),
+        migration!(
+            context,
+            // version
+            18,
+            // description
+            "Track the releases whose documentation was removed by the retention policy",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN docs_removed BOOL NOT NULL DEFAULT FALSE;",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN docs_removed;"
+        ),
+        migration!(
+            context,
+            // version
+            19,
+            // description
+            "Record the size of the stored documentation of each release",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN doc_size_bytes BIGINT;",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN doc_size_bytes;"
+        ),
+        migration!(
+            context,
+            // version
+            20,
+            // description
+            "Record when each release was last built and why the build failed",
+            // upgrade query
+            "
+                ALTER TABLE releases
+                    ADD COLUMN last_build_attempt TIMESTAMP,
+                    ADD COLUMN last_build_error TEXT;
+            ",
+            // downgrade query
+            "
+                ALTER TABLE releases
+                    DROP COLUMN last_build_attempt,
+                    DROP COLUMN last_build_error;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            21,
+            // description
+            "Record the build status of every target documented for a release",
+            // upgrade query
+            "
+                CREATE TABLE release_targets (
+                    release INT NOT NULL REFERENCES releases(id),
+                    target TEXT NOT NULL,
+                    build_status BOOL NOT NULL,
+                    UNIQUE(release, target)
+                );
+            ",
+            // downgrade query
+            "DROP TABLE release_targets;"
+        ),
+        migration!(
+            context,
+            // version
+            22,
+            // description
+            "Record the features and cargo arguments used by each build",
+            // upgrade query
+            "
+                ALTER TABLE builds
+                    ADD COLUMN features JSON,
+                    ADD COLUMN cargo_args JSON;
+            ",
+            // downgrade query
+            "
+                ALTER TABLE builds
+                    DROP COLUMN features,
+                    DROP COLUMN cargo_args;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            23,
+            // description
+            "Track the progress of the data backfills",
+            // upgrade query
+            "
+                CREATE TABLE backfills (
+                    name TEXT PRIMARY KEY,
+                    last_id INT NOT NULL DEFAULT 0,
+                    completed_at TIMESTAMP
+                );
+            ",
+            // downgrade query
+            "DROP TABLE backfills;"
+        ),
+        migration!(
+            context,
+            // version
+            24,
+            // description
+            "Delay the retries of the failed builds",
+            // upgrade query
+            "ALTER TABLE queue ADD COLUMN next_attempt_at TIMESTAMP;",
+            // downgrade query
+            "ALTER TABLE queue DROP COLUMN next_attempt_at;"
+        ),
+    ]
+}
 
-    if let Some(version) = version {
-        if version > migrator.current_version()?.unwrap_or(0) {
-            migrator.up(Some(version))?;
-        } else {
-            migrator.down(Some(version))?;
-        }
-    } else {
-        migrator.up(version)?;
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
 
-    Ok(())
+    #[test]
+    fn test_dry_run_after_partial_migration() {
+        wrapper(|env| {
+            let conn = env.db().conn();
+            let latest = migrations().iter().map(|m| m.version()).max().unwrap();
+            assert_eq!(current_version(&conn)?, latest);
+            assert!(migrate_dry_run(None, &conn)?.is_empty());
+
+            migrate(Some(10), &conn)?;
+            assert_eq!(current_version(&conn)?, 10);
+
+            let up = migrate_dry_run(None, &conn)?;
+            assert_eq!(
+                up.iter().map(|(v, _, d)| (*v, *d)).collect::<Vec<_>>(),
+                (11..=latest)
+                    .map(|v| (v, Direction::Up))
+                    .collect::<Vec<_>>(),
+            );
+            assert_eq!(
+                up[0].1,
+                "Allow crates to be given a different default priority"
+            );
+
+            let down = migrate_dry_run(Some(7), &conn)?;
+            assert_eq!(
+                down.iter().map(|(v, _, d)| (*v, *d)).collect::<Vec<_>>(),
+                vec![
+                    (10, Direction::Down),
+                    (9, Direction::Down),
+                    (8, Direction::Down)
+                ],
+            );
+
+            // Nothing was actually applied by the dry runs.
+            assert_eq!(current_version(&conn)?, 10);
+
+            Ok(())
+        });
+    }
 }
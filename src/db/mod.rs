@@ -2,17 +2,27 @@
 
 pub(crate) use self::add_package::add_build_into_database;
 pub(crate) use self::add_package::add_package_into_database;
+pub(crate) use self::add_package::add_targets_into_database;
+pub(crate) use self::add_package::update_doc_size;
+pub use self::backfills::{list_backfills, run_backfill};
+pub(crate) use self::build_failures::recent_build_failures;
 pub use self::delete_crate::delete_crate;
-pub use self::file::add_path_into_database;
-pub use self::migrate::migrate;
-pub use self::pool::{Pool, PoolError};
-
-#[cfg(test)]
+pub use self::docs_retention::{gc_plan, remove_old_docs, RetentionPolicy};
+pub use self::file::{add_path_into_database, exists_many};
+pub use self::migrate::{
+    current_version, migrate, migrate_dry_run, Direction as MigrationDirection,
+};
 pub(crate) use self::pool::PoolConnection;
+pub use self::pool::{Pool, PoolError, PoolStatus};
+pub(crate) use self::yank::set_yanked;
 
 mod add_package;
+mod backfills;
 pub mod blacklist;
+mod build_failures;
 mod delete_crate;
+mod docs_retention;
 pub(crate) mod file;
 mod migrate;
 mod pool;
+mod yank;
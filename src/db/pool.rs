@@ -1,6 +1,13 @@
 use crate::Config;
 use postgres::Connection;
 use r2d2_postgres::PostgresConnectionManager;
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 pub(crate) type PoolConnection = r2d2::PooledConnection<PostgresConnectionManager>;
 
@@ -9,70 +16,139 @@ const DEFAULT_SCHEMA: &str = "public";
 #[derive(Debug, Clone)]
 pub struct Pool {
     pool: r2d2::Pool<PostgresConnectionManager>,
+    replica: Option<r2d2::Pool<PostgresConnectionManager>>,
+    waiters: Arc<AtomicU32>,
 }
 
 impl Pool {
     pub fn new(config: &Config) -> Result<Pool, PoolError> {
-        Self::new_inner(config, DEFAULT_SCHEMA)
+        let replica = match &config.replica_database_url {
+            Some(url) => Some(Self::build_pool(config, url, DEFAULT_SCHEMA)?),
+            None => None,
+        };
+
+        Self::new_inner(config, DEFAULT_SCHEMA, replica)
     }
 
+    /// Creates a pool using a different schema. The replica is never used by this pool, as the
+    /// schema might not exist there yet.
     #[cfg(test)]
     pub(crate) fn new_with_schema(config: &Config, schema: &str) -> Result<Pool, PoolError> {
-        Self::new_inner(config, schema)
+        Self::new_inner(config, schema, None)
     }
 
-    fn new_inner(config: &Config, schema: &str) -> Result<Pool, PoolError> {
+    fn new_inner(
+        config: &Config,
+        schema: &str,
+        replica: Option<r2d2::Pool<PostgresConnectionManager>>,
+    ) -> Result<Pool, PoolError> {
         crate::web::metrics::MAX_DB_CONNECTIONS.set(config.max_pool_size as i64);
 
-        let manager = PostgresConnectionManager::new(
-            config.database_url.as_str(),
-            r2d2_postgres::TlsMode::None,
-        )
-        .map_err(PoolError::InvalidDatabaseUrl)?;
+        Ok(Pool {
+            pool: Self::build_pool(config, &config.database_url, schema)?,
+            replica,
+            waiters: Arc::new(AtomicU32::new(0)),
+        })
+    }
+
+    fn build_pool(
+        config: &Config,
+        url: &str,
+        schema: &str,
+    ) -> Result<r2d2::Pool<PostgresConnectionManager>, PoolError> {
+        let manager = PostgresConnectionManager::new(url, r2d2_postgres::TlsMode::None)
+            .map_err(PoolError::InvalidDatabaseUrl)?;
 
-        let pool = r2d2::Pool::builder()
+        r2d2::Pool::builder()
             .max_size(config.max_pool_size)
             .min_idle(Some(config.min_pool_idle))
-            .connection_customizer(Box::new(SetSchema::new(schema)))
+            .connection_timeout(Duration::from_millis(config.pool_timeout))
+            .connection_customizer(Box::new(SetupConnection::new(
+                schema,
+                config.statement_timeout,
+            )))
             .build(manager)
-            .map_err(PoolError::PoolCreationFailed)?;
-
-        Ok(Pool { pool })
+            .map_err(PoolError::PoolCreationFailed)
     }
 
     pub fn get(&self) -> Result<PoolConnection, PoolError> {
-        match self.pool.get() {
-            Ok(conn) => Ok(conn),
-            Err(err) => {
-                crate::web::metrics::FAILED_DB_CONNECTIONS.inc();
-                Err(PoolError::ConnectionError(err))
-            }
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let conn = self.pool.get();
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+
+        conn.map_err(|err| Self::get_error(&self.pool, err))
+    }
+
+    /// Gets a connection to the read-only replica, which should only be used to serve queries
+    /// that don't write anything. If no replica is configured a connection to the primary
+    /// database is returned instead.
+    pub fn get_replica(&self) -> Result<PoolConnection, PoolError> {
+        match &self.replica {
+            Some(replica) => replica.get().map_err(|err| Self::get_error(replica, err)),
+            None => self.get(),
+        }
+    }
+
+    /// r2d2 only fails to return a connection after waiting for one for too long, which either
+    /// means all of them were in use or that new ones couldn't be opened.
+    fn get_error(pool: &r2d2::Pool<PostgresConnectionManager>, err: r2d2::Error) -> PoolError {
+        crate::web::metrics::FAILED_DB_CONNECTIONS.inc();
+
+        let state = pool.state();
+        if state.connections >= pool.max_size() && state.idle_connections == 0 {
+            PoolError::Timeout(err)
+        } else {
+            PoolError::ConnectionError(err)
         }
     }
 
-    pub(crate) fn used_connections(&self) -> u32 {
-        self.pool.state().connections - self.pool.state().idle_connections
+    /// Returns a snapshot of the current state of the pool.
+    pub fn status(&self) -> PoolStatus {
+        let state = self.pool.state();
+        PoolStatus {
+            size: state.connections,
+            max_size: self.pool.max_size(),
+            idle: state.idle_connections,
+            waiters: self.waiters.load(Ordering::SeqCst),
+        }
     }
+}
 
-    pub(crate) fn idle_connections(&self) -> u32 {
-        self.pool.state().idle_connections
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct PoolStatus {
+    /// Number of connections currently open, both idle and in use.
+    pub size: u32,
+    /// Maximum number of connections the pool will open.
+    pub max_size: u32,
+    /// Number of idle connections.
+    pub idle: u32,
+    /// Number of callers currently waiting in `Pool::get` for a connection.
+    pub waiters: u32,
+}
+
+impl PoolStatus {
+    /// Number of connections currently in use.
+    pub fn used(&self) -> u32 {
+        self.size - self.idle
     }
 }
 
 #[derive(Debug)]
-struct SetSchema {
+struct SetupConnection {
     schema: String,
+    statement_timeout: u64,
 }
 
-impl SetSchema {
-    fn new(schema: &str) -> Self {
+impl SetupConnection {
+    fn new(schema: &str, statement_timeout: u64) -> Self {
         Self {
             schema: schema.into(),
+            statement_timeout,
         }
     }
 }
 
-impl r2d2::CustomizeConnection<Connection, postgres::Error> for SetSchema {
+impl r2d2::CustomizeConnection<Connection, postgres::Error> for SetupConnection {
     fn on_acquire(&self, conn: &mut Connection) -> Result<(), postgres::Error> {
         if self.schema != DEFAULT_SCHEMA {
             conn.execute(
@@ -80,6 +156,11 @@ impl r2d2::CustomizeConnection<Connection, postgres::Error> for SetSchema {
                 &[],
             )?;
         }
+        // A timeout of 0 disables it in PostgreSQL too.
+        conn.execute(
+            &format!("SET statement_timeout TO {};", self.statement_timeout),
+            &[],
+        )?;
         Ok(())
     }
 }
@@ -94,4 +175,111 @@ pub enum PoolError {
 
     #[fail(display = "failed to get a database connection")]
     ConnectionError(#[fail(cause)] r2d2::Error),
+
+    #[fail(display = "timed out waiting for a database connection, all of them are in use")]
+    Timeout(#[fail(cause)] r2d2::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PoolError;
+    use crate::test::wrapper;
+
+    #[test]
+    fn test_status_respects_max_pool_size() {
+        wrapper(|env| {
+            let pool = env.db().pool();
+            let max_pool_size = env.config().max_pool_size;
+
+            let status = pool.status();
+            assert_eq!(status.max_size, max_pool_size);
+            assert_eq!(status.waiters, 0);
+
+            let conns = (0..max_pool_size)
+                .map(|_| pool.get())
+                .collect::<Result<Vec<_>, _>>()?;
+            let status = pool.status();
+            assert_eq!(status.size, max_pool_size);
+            assert_eq!(status.idle, 0);
+            assert_eq!(status.used(), max_pool_size);
+
+            drop(conns);
+            let status = pool.status();
+            assert!(status.size <= max_pool_size);
+            assert_eq!(status.idle, status.size);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_timeout_when_exhausted() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.max_pool_size = 1;
+                config.pool_timeout = 100;
+            });
+            let pool = env.db().pool();
+
+            let conn = pool.get()?;
+            match pool.get() {
+                Err(PoolError::Timeout(_)) => {}
+                other => panic!("expected a timeout, got {:?}", other.map(|_| ())),
+            }
+
+            // The connection can be acquired again once it's released
+            drop(conn);
+            pool.get()?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_get_replica_falls_back_to_primary() {
+        wrapper(|env| {
+            assert!(env.config().replica_database_url.is_none());
+            let pool = env.db().pool();
+
+            // The test schema only exists on the primary, so being able to see the tables created
+            // by the migrations means the connection points to it.
+            let conn = pool.get_replica()?;
+            let rows = conn.query("SELECT current_schema();", &[])?;
+            let schema: String = rows.get(0).get(0);
+            assert!(schema.starts_with("docs_rs_test_schema_"));
+            conn.query("SELECT COUNT(*) FROM releases;", &[])?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_statement_timeout() {
+        wrapper(|env| {
+            env.override_config(|config| config.statement_timeout = 100);
+            let conn = env.db().conn();
+
+            let err = conn.query("SELECT pg_sleep(1);", &[]).unwrap_err();
+            assert_eq!(err.code(), Some(&postgres::error::QUERY_CANCELED));
+
+            // The connection is still usable for fast queries.
+            conn.query("SELECT 1;", &[])?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_statement_timeout_disabled() {
+        wrapper(|env| {
+            env.override_config(|config| config.statement_timeout = 0);
+            let conn = env.db().conn();
+
+            let rows = conn.query("SHOW statement_timeout;", &[])?;
+            assert_eq!(rows.get(0).get::<_, String>(0), "0");
+            conn.query("SELECT pg_sleep(0.2);", &[])?;
+
+            Ok(())
+        });
+    }
 }
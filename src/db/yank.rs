@@ -0,0 +1,21 @@
+use postgres::Connection;
+
+/// Marks a release as yanked or not yanked, returning whether the release exists.
+///
+/// Yanked releases stay available, but they're flagged in the listings and aren't considered
+/// when looking for the latest release of a crate.
+pub(crate) fn set_yanked(
+    conn: &Connection,
+    name: &str,
+    version: &str,
+    yanked: bool,
+) -> Result<bool, postgres::Error> {
+    let updated = conn.execute(
+        "UPDATE releases
+         SET yanked = $3
+         FROM crates
+         WHERE crates.id = releases.crate_id AND crates.name = $1 AND releases.version = $2",
+        &[&name, &version, &yanked],
+    )?;
+    Ok(updated > 0)
+}
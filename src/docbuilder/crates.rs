@@ -1,20 +1,147 @@
 use crate::error::Result;
-use failure::err_msg;
+use failure::{err_msg, Fail};
+use flate2::read::GzDecoder;
+use git2::{Oid, Repository};
+use log::warn;
+use postgres::Connection;
+use rayon::prelude::*;
+use serde::Deserialize;
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 use std::io::prelude::*;
-use std::io::BufReader;
-use std::{fs, path::PathBuf, str::FromStr};
+use std::io::{BufReader, Cursor};
+use std::result::Result as StdResult;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-fn crates_from_file<F>(path: &PathBuf, func: &mut F) -> Result<()>
+/// A version listed in the index, as reported by `crates_from_path_detailed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexVersion {
+    pub name: String,
+    pub version: String,
+    pub deps: Vec<IndexDep>,
+    /// Hex encoded SHA256 checksum of the crate tarball, see `verify_checksum`
+    pub cksum: Option<String>,
+}
+
+/// A dependency of a version listed in the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexDep {
+    /// Name of the crate depended upon, even if the dependency was renamed
+    pub name: String,
+    pub req: String,
+    /// `normal`, `build` or `dev`
+    pub kind: String,
+    pub optional: bool,
+}
+
+/// Errors found while reading the index, which can be told apart by downcasting the errors
+/// returned when walking it.
+#[derive(Debug, Fail)]
+pub enum CrateParseError {
+    #[fail(display = "the index is not a directory")]
+    NotADirectory,
+
+    #[fail(display = "`{}` not found in JSON object", field)]
+    MissingField { field: &'static str },
+
+    #[fail(display = "line {} is not a JSON object", line)]
+    InvalidJson { line: usize },
+
+    #[fail(display = "failed to read the index")]
+    Io(#[fail(cause)] std::io::Error),
+}
+
+impl From<std::io::Error> for CrateParseError {
+    fn from(err: std::io::Error) -> Self {
+        CrateParseError::Io(err)
+    }
+}
+
+#[derive(Deserialize)]
+struct RawIndexDep {
+    name: String,
+    req: String,
+    kind: Option<String>,
+    #[serde(default)]
+    optional: bool,
+    package: Option<String>,
+}
+
+impl From<RawIndexDep> for IndexDep {
+    fn from(dep: RawIndexDep) -> Self {
+        IndexDep {
+            name: dep.package.unwrap_or(dep.name),
+            req: dep.req,
+            kind: dep.kind.unwrap_or_else(|| "normal".to_string()),
+            optional: dep.optional,
+        }
+    }
+}
+
+fn crates_from_file<F>(path: &PathBuf, func: &mut F) -> StdResult<(), CrateParseError>
 where
     F: FnMut(&str, &str) -> (),
 {
-    let reader = fs::File::open(path).map(BufReader::new)?;
+    crates_from_file_detailed(path, false, &mut |version| {
+        func(&version.name, &version.version)
+    })
+}
+
+/// Reads the versions listed in an index file, parsing their dependencies and checksums only if
+/// `detailed` is set.
+fn crates_from_file_detailed<F>(
+    path: &PathBuf,
+    detailed: bool,
+    func: &mut F,
+) -> StdResult<(), CrateParseError>
+where
+    F: FnMut(&IndexVersion),
+{
+    for version in read_index_file(path, detailed)? {
+        func(&version);
+    }
+
+    Ok(())
+}
+
+/// The bytes gzip compressed files start with.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Opens an index file, decompressing it first if it's gzip compressed as in some mirrors of the
+/// index. Compressed files are decompressed at once, so that a corrupted file fails here instead
+/// of in the middle of its lines.
+fn open_index_file(path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    if reader.fill_buf()?.starts_with(GZIP_MAGIC) {
+        let mut content = Vec::new();
+        GzDecoder::new(reader).read_to_end(&mut content)?;
+        Ok(Box::new(Cursor::new(content)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Returns the versions listed in an index file, in the order they're reported: the newest
+/// first, all under the name of the last one.
+fn read_index_file(path: &Path, detailed: bool) -> StdResult<Vec<IndexVersion>, CrateParseError> {
+    let reader = match open_index_file(path) {
+        Ok(reader) => reader,
+        Err(err) => {
+            warn!("skipping unreadable index file {}: {}", path.display(), err);
+            return Ok(Vec::new());
+        }
+    };
 
-    let mut name = String::new();
     let mut versions = Vec::new();
+    let mut seen_versions = HashSet::new();
 
-    for line in reader.lines() {
+    for (index, line) in reader.lines().enumerate() {
         // some crates have invalid UTF-8 (nanny-sys-0.0.7)
         // skip them
         let line = if let Ok(line) = line {
@@ -23,66 +150,1291 @@ where
             continue;
         };
 
-        let data = if let Ok(data) = Value::from_str(line.trim()) {
-            data
-        } else {
-            continue;
+        let version = match parse_index_line(&line, index + 1, detailed)? {
+            Some(version) => version,
+            None => continue,
         };
 
-        let obj = data
-            .as_object()
-            .ok_or_else(|| err_msg("Not a JSON object"))?;
-        let crate_name = obj
-            .get("name")
-            .and_then(|n| n.as_str())
-            .ok_or_else(|| err_msg("`name` not found in JSON object"))?;
-        let vers = obj
-            .get("vers")
-            .and_then(|n| n.as_str())
-            .ok_or_else(|| err_msg("`vers` not found in JSON object"))?;
-
-        // Skip yanked crates
-        if obj.get("yanked").and_then(|n| n.as_bool()).unwrap_or(false) {
+        // Listing the same version twice would build it twice
+        if !seen_versions.insert(version.version.clone()) {
+            warn!(
+                "skipping duplicate version {} {} in {}",
+                version.name,
+                version.version,
+                path.display()
+            );
             continue;
         }
 
-        name.clear();
-        name.push_str(crate_name);
-        versions.push(vers.to_string());
+        versions.push(version);
     }
 
-    if !name.is_empty() {
-        versions.reverse();
-        for version in versions {
-            func(&name[..], &version[..]);
+    // All the versions are reported under the name of the last one
+    if let Some(name) = versions.last().map(|version| version.name.clone()) {
+        for version in &mut versions {
+            version.name.clone_from(&name);
         }
     }
+    versions.reverse();
 
-    Ok(())
+    Ok(versions)
 }
 
-pub fn crates_from_path<F>(path: &PathBuf, func: &mut F) -> Result<()>
+/// Parses the line numbered `line_number` of an index file, returning the version it lists, or
+/// `None` if the line isn't valid JSON or the version was yanked.
+///
+/// The dependencies and the checksum are only parsed if `detailed` is set. Malformed
+/// dependencies are skipped.
+fn parse_index_line(
+    line: &str,
+    line_number: usize,
+    detailed: bool,
+) -> StdResult<Option<IndexVersion>, CrateParseError> {
+    let data = if let Ok(data) = Value::from_str(line.trim()) {
+        data
+    } else {
+        return Ok(None);
+    };
+
+    let obj = data
+        .as_object()
+        .ok_or(CrateParseError::InvalidJson { line: line_number })?;
+    let crate_name = obj
+        .get("name")
+        .and_then(|n| n.as_str())
+        .ok_or(CrateParseError::MissingField { field: "name" })?;
+    let vers = obj
+        .get("vers")
+        .and_then(|n| n.as_str())
+        .ok_or(CrateParseError::MissingField { field: "vers" })?;
+
+    // Skip yanked crates
+    if obj.get("yanked").and_then(|n| n.as_bool()).unwrap_or(false) {
+        return Ok(None);
+    }
+
+    let mut version = IndexVersion {
+        name: crate_name.to_string(),
+        version: vers.to_string(),
+        deps: Vec::new(),
+        cksum: None,
+    };
+    if detailed {
+        if let Some(deps) = obj.get("deps").and_then(|deps| deps.as_array()) {
+            version.deps = deps
+                .iter()
+                .filter_map(|dep| serde_json::from_value::<RawIndexDep>(dep.clone()).ok())
+                .map(IndexDep::from)
+                .collect();
+        }
+        version.cksum = obj
+            .get("cksum")
+            .and_then(|cksum| cksum.as_str())
+            .map(String::from);
+    }
+
+    Ok(Some(version))
+}
+
+/// Checks whether the SHA256 checksum of `bytes`, usually a downloaded crate tarball, matches
+/// the hex encoded `expected_hex`, like the `cksum` listed in the index.
+pub fn verify_checksum(bytes: &[u8], expected_hex: &str) -> bool {
+    hex::encode(Sha256::digest(bytes)).eq_ignore_ascii_case(expected_hex.trim())
+}
+
+/// Decides which crates are reported while walking the index, for example to exclude some
+/// crates from a backfill. Patterns can contain `*` wildcards and are matched case insensitively.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CrateNameFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl CrateNameFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only reports the crates matching one of the allowed patterns. Without any allowed
+    /// pattern every crate not denied is reported.
+    pub fn allow(mut self, pattern: impl Into<String>) -> Self {
+        self.allow.push(pattern.into().to_lowercase());
+        self
+    }
+
+    /// Never reports the crates matching the pattern, even if they are allowed.
+    pub fn deny(mut self, pattern: impl Into<String>) -> Self {
+        self.deny.push(pattern.into().to_lowercase());
+        self
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        let matches = |pattern: &String| glob_matches(pattern.as_bytes(), name.as_bytes());
+
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
+
+/// Matches `name` against `pattern`, where `*` matches any sequence of characters.
+fn glob_matches(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` in the pattern, and of the name when it was reached
+    let mut backtrack = None;
+
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == name[n] {
+            p += 1;
+            n += 1;
+        } else if let Some((star, matched)) = backtrack {
+            // Let the last `*` match one more character
+            backtrack = Some((star, matched + 1));
+            p = star + 1;
+            n = matched + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Calls `func` for every version in the index which isn't yanked, skipping the crates rejected
+/// by `filter`.
+///
+/// The entries of each directory are read in the order the filesystem lists them, unless `sorted`
+/// is set: they are then sorted by name, which makes the order of the calls the same on every
+/// machine at the cost of a slower walk.
+pub fn crates_from_path<F>(
+    path: &Path,
+    filter: Option<&CrateNameFilter>,
+    sorted: bool,
+    func: &mut F,
+) -> Result<()>
 where
     F: FnMut(&str, &str) -> (),
 {
+    let mut versions = CratesIter::new(path)?;
+    if sorted {
+        versions = versions.sorted();
+    }
+    for version in versions {
+        let version = version?;
+        if filter.map(|filter| filter.allows(&version.name)) != Some(false) {
+            func(&version.name, &version.version)
+        }
+    }
+
+    Ok(())
+}
+
+/// How many crates and versions which aren't yanked are listed in an index.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CrateWalkStats {
+    pub crates: usize,
+    pub versions: usize,
+}
+
+/// Counts the versions `crates_from_path` would report, and the crates they belong to, without
+/// building the list of versions of each crate.
+pub fn count_crates_in_path(path: &Path) -> Result<CrateWalkStats> {
     if !path.is_dir() {
-        return Err(err_msg("Not a directory"));
+        return Err(CrateParseError::NotADirectory.into());
     }
 
-    for file in path.read_dir()? {
-        let file = file?;
-        let path = file.path();
-        // skip files under .git and config.json
-        if path.to_str().unwrap().contains(".git") || path.file_name().unwrap() == "config.json" {
+    let mut stats = CrateWalkStats::default();
+    let mut pending = index_dir_entries(path)?;
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            pending.extend(index_subdir_entries(&path));
+        } else {
+            let versions = count_index_file(&path)?;
+            if versions > 0 {
+                stats.crates += 1;
+                stats.versions += versions;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Counts the distinct versions listed in an index file which aren't yanked, like the length of
+/// the list returned by `read_index_file`.
+fn count_index_file(path: &Path) -> StdResult<usize, CrateParseError> {
+    let reader = match open_index_file(path) {
+        Ok(reader) => reader,
+        Err(err) => {
+            warn!("skipping unreadable index file {}: {}", path.display(), err);
+            return Ok(0);
+        }
+    };
+
+    let mut seen_versions = HashSet::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = if let Ok(line) = line {
+            line
+        } else {
             continue;
+        };
+        if let Some(version) = parse_index_line(&line, index + 1, false)? {
+            seen_versions.insert(version.version);
+        }
+    }
+
+    Ok(seen_versions.len())
+}
+
+/// Walks the index like `crates_from_path`, also parsing the dependencies and the checksum of
+/// each version.
+pub fn crates_from_path_detailed<F>(path: &Path, func: &mut F) -> Result<()>
+where
+    F: FnMut(&IndexVersion),
+{
+    for version in CratesIter::new(path)?.detailed() {
+        func(&version?);
+    }
+
+    Ok(())
+}
+
+/// Iterator over every version in the index which isn't yanked, in the same order as
+/// `crates_from_path`. The files are read lazily, one at a time, as the versions are consumed.
+///
+/// The iteration stops after the first error.
+pub struct CratesIter {
+    detailed: bool,
+    sorted: bool,
+    /// Entries of the index left to read, the last one is read next
+    pending: Vec<PathBuf>,
+    /// Versions of the file read last which weren't reported yet
+    versions: std::vec::IntoIter<IndexVersion>,
+}
+
+impl CratesIter {
+    pub fn new(path: &Path) -> StdResult<Self, CrateParseError> {
+        if !path.is_dir() {
+            return Err(CrateParseError::NotADirectory);
         }
 
+        let mut pending = index_dir_entries(path)?;
+        pending.reverse();
+        Ok(Self {
+            detailed: false,
+            sorted: false,
+            pending,
+            versions: Vec::new().into_iter(),
+        })
+    }
+
+    /// Also parses the dependencies and the checksum of each version.
+    pub fn detailed(mut self) -> Self {
+        self.detailed = true;
+        self
+    }
+
+    /// Reads the entries of each directory sorted by name, see `crates_from_path`.
+    pub fn sorted(mut self) -> Self {
+        self.sorted = true;
+        // The entries are popped from the end
+        self.pending.sort_by(|a, b| b.cmp(a));
+        self
+    }
+}
+
+impl Iterator for CratesIter {
+    type Item = StdResult<IndexVersion, CrateParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(version) = self.versions.next() {
+                return Some(Ok(version));
+            }
+
+            let path = self.pending.pop()?;
+            if path.is_dir() {
+                let mut entries = index_subdir_entries(&path);
+                if self.sorted {
+                    entries.sort_by(|a, b| b.cmp(a));
+                } else {
+                    entries.reverse();
+                }
+                self.pending.extend(entries);
+            } else {
+                match read_index_file(&path, self.detailed) {
+                    Ok(versions) => self.versions = versions.into_iter(),
+                    Err(err) => {
+                        self.pending.clear();
+                        return Some(Err(err));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Calls `func` for every version of the crate `name` which isn't yanked, reading only its file
+/// in the index at `path`.
+pub fn crates_from_index_for<F>(path: &Path, name: &str, func: &mut F) -> Result<()>
+where
+    F: FnMut(&str, &str),
+{
+    // Crate names are ASCII, which also keeps the slicing in `index_file_path` valid
+    if name.is_empty() || !name.is_ascii() {
+        return Err(err_msg(format!("invalid crate name {:?}", name)));
+    }
+
+    let file = find_index_file(path, name)
+        .ok_or_else(|| err_msg(format!("crate {} not found in the index", name)))?;
+
+    Ok(crates_from_file(&file, func)?)
+}
+
+/// Returns whether the crate `name` is listed in the index at `path`, without reading its file.
+pub fn crate_exists_in_index(path: &Path, name: &str) -> bool {
+    !name.is_empty() && name.is_ascii() && find_index_file(path, name).is_some()
+}
+
+/// Returns the file listing the versions of the crate `name` in the index at `path`, either
+/// plain or gzip compressed with a `.gz` extension.
+fn find_index_file(path: &Path, name: &str) -> Option<PathBuf> {
+    let file = path.join(index_file_path(name));
+    let compressed = file.with_extension("gz");
+    if file.is_file() {
+        Some(file)
+    } else if compressed.is_file() {
+        Some(compressed)
+    } else {
+        None
+    }
+}
+
+/// Returns where the file listing the versions of the crate `name` is located in the index.
+/// Names of one, two and three characters are stored in the special `1`, `2` and `3`
+/// directories, while longer names are stored in directories named after their first four
+/// characters, like `se/rd/serde`.
+fn index_file_path(name: &str) -> PathBuf {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => Path::new("1").join(&name),
+        2 => Path::new("2").join(&name),
+        3 => Path::new("3").join(&name[..1]).join(&name),
+        _ => Path::new(&name[..2]).join(&name[2..4]).join(&name),
+    }
+}
+
+/// Walks the index like `crates_from_path`, but reads the files concurrently using `threads`
+/// threads, or one thread per CPU if it's 0.
+///
+/// The callback is called from all the threads, and the versions are not reported in any
+/// particular order.
+pub fn crates_from_path_parallel<F>(path: &Path, threads: usize, func: &F) -> Result<()>
+where
+    F: Fn(&str, &str) + Sync,
+{
+    if !path.is_dir() {
+        return Err(CrateParseError::NotADirectory.into());
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    let paths = index_dir_entries(path)?;
+    Ok(pool.install(|| walk_parallel(paths, func))?)
+}
+
+fn walk_parallel<F>(paths: Vec<PathBuf>, func: &F) -> StdResult<(), CrateParseError>
+where
+    F: Fn(&str, &str) + Sync,
+{
+    paths.into_par_iter().try_for_each(|path| {
         if path.is_dir() {
-            crates_from_path(&path, func)?;
+            walk_parallel(index_subdir_entries(&path), func)
         } else {
-            crates_from_file(&path, func)?;
+            crates_from_file(&path, &mut |name, version| func(name, version))
         }
+    })
+}
+
+/// Lists the entries of an index directory which aren't ignored, skipping the ones which can't
+/// be read.
+fn index_dir_entries(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    Ok(dir
+        .read_dir()?
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry.path()),
+            Err(err) => {
+                warn!("skipping unreadable entry in {}: {}", dir.display(), err);
+                None
+            }
+        })
+        .filter(|path| !is_ignored(path))
+        .collect())
+}
+
+/// Like `index_dir_entries`, but a directory which can't be read is skipped instead of aborting
+/// the whole walk.
+fn index_subdir_entries(dir: &Path) -> Vec<PathBuf> {
+    index_dir_entries(dir).unwrap_or_else(|err| {
+        warn!(
+            "skipping unreadable index directory {}: {}",
+            dir.display(),
+            err
+        );
+        Vec::new()
+    })
+}
+
+/// Skips the files under .git and config.json. Paths which aren't valid UTF-8 can't be part of
+/// the index, and are skipped too.
+fn is_ignored(path: &Path) -> bool {
+    let path_str = match path.to_str() {
+        Some(path_str) => path_str,
+        None => {
+            warn!("skipping non UTF-8 path in the index: {}", path.display());
+            return true;
+        }
+    };
+
+    path_str.contains(".git") || path.file_name() == Some(OsStr::new("config.json"))
+}
+
+/// Name of the `config` entry storing the last index commit processed by `IncrementalCrates`.
+const INDEX_CURSOR: &str = "index_cursor";
+
+/// Reports only the versions added to the index since the previous run. The index repository
+/// commit processed last is stored in the `config` table and used as a cursor.
+pub struct IncrementalCrates<'a> {
+    conn: &'a Connection,
+    index: &'a Path,
+}
+
+impl<'a> IncrementalCrates<'a> {
+    pub fn new(conn: &'a Connection, index: &'a Path) -> Self {
+        Self { conn, index }
     }
 
-    Ok(())
+    /// Calls `func` for every version added to the index since the cursor and allowed by the
+    /// `filter`, then moves the cursor to the current commit. Without a cursor every version in
+    /// the index is reported.
+    pub fn process<F>(&self, filter: Option<&CrateNameFilter>, func: &mut F) -> Result<()>
+    where
+        F: FnMut(&str, &str),
+    {
+        let repo = Repository::open(self.index)?;
+        let head = repo.head()?.peel_to_commit()?;
+        let old_tree = match self.cursor()? {
+            Some(oid) => match repo.find_commit(oid) {
+                Ok(commit) => Some(commit.tree()?),
+                // The index history is squashed from time to time
+                Err(err) => {
+                    warn!(
+                        "index cursor {} not found, processing every version: {}",
+                        oid, err
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        let diff = repo.diff_tree_to_tree(old_tree.as_ref(), Some(&head.tree()?), None)?;
+
+        let mut added_lines = Vec::new();
+        diff.foreach(
+            &mut |_, _| true,
+            None,
+            None,
+            Some(&mut |delta, _, line| {
+                let ignored = match delta.new_file().path() {
+                    Some(path) => is_ignored(path),
+                    None => true,
+                };
+                if line.origin() == '+' && !ignored {
+                    // some crates have invalid UTF-8 (nanny-sys-0.0.7)
+                    if let Ok(content) = std::str::from_utf8(line.content()) {
+                        let line_number = line.new_lineno().unwrap_or(0) as usize;
+                        added_lines.push((line_number, content.to_string()));
+                    }
+                }
+                true
+            }),
+        )?;
+
+        for (line_number, line) in added_lines {
+            if let Some(version) = parse_index_line(&line, line_number, false)? {
+                if filter.map(|filter| filter.allows(&version.name)) != Some(false) {
+                    func(&version.name, &version.version);
+                }
+            }
+        }
+
+        self.set_cursor(head.id())
+    }
+
+    fn cursor(&self) -> Result<Option<Oid>> {
+        let rows = self.conn.query(
+            "SELECT value FROM config WHERE name = $1;",
+            &[&INDEX_CURSOR],
+        )?;
+        match rows.iter().next().map(|row| row.get::<_, Value>(0)) {
+            Some(Value::String(oid)) => Ok(Some(Oid::from_str(&oid)?)),
+            Some(value) => Err(err_msg(format!("invalid index cursor: {}", value))),
+            None => Ok(None),
+        }
+    }
+
+    fn set_cursor(&self, oid: Oid) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO config (name, value) VALUES ($1, $2)
+             ON CONFLICT (name) DO UPDATE SET value = $2;",
+            &[&INDEX_CURSOR, &Value::String(oid.to_string())],
+        )?;
+        Ok(())
+    }
+}
+
+/// Returns the versions listed in the index at `path` which aren't yanked and have no release in
+/// the database, sorted by name and version, ready to be added to the build queue.
+pub fn undocumented_versions(conn: &Connection, path: &Path) -> Result<Vec<IndexVersion>> {
+    let mut documented: HashMap<String, HashSet<String>> = HashMap::new();
+    for row in &conn.query(
+        "SELECT crates.name, releases.version
+         FROM crates
+         INNER JOIN releases ON releases.crate_id = crates.id",
+        &[],
+    )? {
+        documented.entry(row.get(0)).or_default().insert(row.get(1));
+    }
+
+    let mut missing = Vec::new();
+    for version in CratesIter::new(path)? {
+        let version = version?;
+        let is_documented = documented
+            .get(&version.name)
+            .map_or(false, |versions| versions.contains(&version.version));
+        if !is_documented {
+            missing.push(version);
+        }
+    }
+    missing.sort_by_cached_key(|version| {
+        (
+            version.name.clone(),
+            semver::Version::parse(&version.version).ok(),
+        )
+    });
+
+    Ok(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use git2::Signature;
+    use std::sync::Mutex;
+
+    /// Creates an index with `count` crates laid out like the crates.io one, each with three
+    /// versions of which the second one is yanked.
+    fn synthetic_index(count: usize) -> Result<tempfile::TempDir> {
+        let index = tempfile::Builder::new()
+            .prefix("docs.rs-index-test")
+            .tempdir()?;
+        fs::create_dir(index.path().join(".git"))?;
+        fs::write(
+            index.path().join(".git").join("HEAD"),
+            "ref: refs/heads/master",
+        )?;
+        fs::write(index.path().join("config.json"), "{}")?;
+
+        for i in 0..count {
+            let name = format!("crate-{}", i);
+            let dir = index
+                .path()
+                .join(format!("{:02}", i % 50))
+                .join(format!("{:02}", i / 50 % 20));
+            fs::create_dir_all(&dir)?;
+
+            let mut file = fs::File::create(dir.join(&name))?;
+            for (patch, yanked) in &[(0, false), (1, true), (2, false)] {
+                writeln!(
+                    file,
+                    r#"{{"name":"{}","vers":"1.0.{}","deps":[],"cksum":"","features":{{}},"yanked":{}}}"#,
+                    name, patch, yanked
+                )?;
+            }
+        }
+
+        Ok(index)
+    }
+
+    #[test]
+    fn test_parallel_walk_matches_serial_walk() -> Result<()> {
+        const CRATES: usize = 2000;
+        let index = synthetic_index(CRATES)?;
+
+        let mut serial = HashSet::new();
+        crates_from_path(index.path(), None, false, &mut |name, version| {
+            assert!(serial.insert((name.to_string(), version.to_string())));
+        })?;
+
+        let parallel = Mutex::new(HashSet::new());
+        crates_from_path_parallel(index.path(), 4, &|name, version| {
+            let mut parallel = parallel.lock().unwrap();
+            assert!(parallel.insert((name.to_string(), version.to_string())));
+        })?;
+        let parallel = parallel.into_inner().unwrap();
+
+        assert_eq!(serial.len(), CRATES * 2);
+        assert_eq!(parallel, serial);
+        assert!(parallel.contains(&("crate-42".to_string(), "1.0.0".to_string())));
+        assert!(parallel.contains(&("crate-42".to_string(), "1.0.2".to_string())));
+        assert!(!parallel.contains(&("crate-42".to_string(), "1.0.1".to_string())));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_matches_callback_walk() -> Result<()> {
+        let index = synthetic_index(200)?;
+
+        let mut names = HashSet::new();
+        let mut versions = 0;
+        crates_from_path(index.path(), None, false, &mut |name, _| {
+            names.insert(name.to_string());
+            versions += 1;
+        })?;
+
+        let stats = count_crates_in_path(index.path())?;
+        assert_eq!(stats.crates, names.len());
+        assert_eq!(stats.versions, versions);
+        assert_eq!(
+            stats,
+            CrateWalkStats {
+                crates: 200,
+                versions: 400,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_undocumented_versions() {
+        wrapper(|env| {
+            let index = synthetic_index(4)?;
+            // Compared as strings, this version would come before 1.0.2
+            fs::OpenOptions::new()
+                .append(true)
+                .open(index.path().join("02").join("00").join("crate-2"))?
+                .write_all(
+                    br#"{"name":"crate-2","vers":"1.0.10","deps":[],"cksum":"","features":{},"yanked":false}"#,
+                )?;
+            let db = env.db();
+            for (name, version) in &[
+                ("crate-1", "1.0.0"),
+                ("crate-3", "1.0.2"),
+                ("not-in-index", "1.0.0"),
+            ] {
+                db.fake_release().name(name).version(version).create()?;
+            }
+
+            let missing = undocumented_versions(&db.conn(), index.path())?
+                .into_iter()
+                .map(|version| format!("{} {}", version.name, version.version))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                missing,
+                vec![
+                    "crate-0 1.0.0",
+                    "crate-0 1.0.2",
+                    "crate-1 1.0.2",
+                    "crate-2 1.0.0",
+                    "crate-2 1.0.2",
+                    "crate-2 1.0.10",
+                    "crate-3 1.0.0",
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_iterator_matches_callback_walk() -> Result<()> {
+        let index = synthetic_index(200)?;
+
+        let mut walked = Vec::new();
+        crates_from_path(index.path(), None, false, &mut |name, version| {
+            walked.push((name.to_string(), version.to_string()));
+        })?;
+
+        let iterated = CratesIter::new(index.path())?
+            .map(|version| version.map(|version| (version.name, version.version)))
+            .collect::<StdResult<Vec<_>, _>>()?;
+        assert_eq!(iterated, walked);
+
+        // The detailed walk reports the same versions
+        let mut detailed = Vec::new();
+        crates_from_path_detailed(index.path(), &mut |version| {
+            detailed.push(version.clone());
+        })?;
+        let iterated = CratesIter::new(index.path())?
+            .detailed()
+            .collect::<StdResult<Vec<_>, _>>()?;
+        assert_eq!(iterated, detailed);
+
+        // Iterator adapters only read as much of the index as needed
+        let first = CratesIter::new(index.path())?
+            .filter_map(StdResult::ok)
+            .filter(|version| version.version == "1.0.2")
+            .take(3)
+            .collect::<Vec<_>>();
+        assert_eq!(first.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sorted_walk() -> Result<()> {
+        let index = tempfile::Builder::new()
+            .prefix("docs.rs-index-test")
+            .tempdir()?;
+        for (dir, name) in &[
+            ("se/rd", "serde"),
+            ("3/l", "log"),
+            ("ra/nd", "rand"),
+            ("3/a", "anyhow"),
+            ("se/rd", "serde_json"),
+            ("1", "a"),
+        ] {
+            let dir = index.path().join(dir);
+            fs::create_dir_all(&dir)?;
+            fs::write(
+                dir.join(name),
+                format!(
+                    "{{\"name\":\"{0}\",\"vers\":\"0.1.0\"}}\n{{\"name\":\"{0}\",\"vers\":\"0.2.0\"}}\n",
+                    name
+                ),
+            )?;
+        }
+
+        let mut calls = Vec::new();
+        crates_from_path(index.path(), None, true, &mut |name, version| {
+            calls.push(format!("{} {}", name, version));
+        })?;
+        assert_eq!(
+            calls,
+            vec![
+                "a 0.2.0",
+                "a 0.1.0",
+                "anyhow 0.2.0",
+                "anyhow 0.1.0",
+                "log 0.2.0",
+                "log 0.1.0",
+                "rand 0.2.0",
+                "rand 0.1.0",
+                "serde 0.2.0",
+                "serde 0.1.0",
+                "serde_json 0.2.0",
+                "serde_json 0.1.0",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_versions_are_reported_once() -> Result<()> {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-index-test")
+            .tempdir()?;
+        let path = dir.path().join("foo");
+        let mut file = fs::File::create(&path)?;
+        for version in &["0.1.0", "0.2.0", "0.1.0", "0.3.0", "0.2.0", "0.2.0"] {
+            writeln!(
+                file,
+                r#"{{"name":"foo","vers":"{}","deps":[],"cksum":"","features":{{}},"yanked":false}}"#,
+                version
+            )?;
+        }
+
+        let mut versions = Vec::new();
+        crates_from_file(&path, &mut |name, version| {
+            assert_eq!(name, "foo");
+            versions.push(version.to_string());
+        })?;
+        assert_eq!(versions, vec!["0.3.0", "0.2.0", "0.1.0"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_walk_requires_directory() -> Result<()> {
+        let index = synthetic_index(1)?;
+        let file = index.path().join("config.json");
+        assert!(crates_from_path_parallel(&file, 0, &|_, _| {}).is_err());
+        Ok(())
+    }
+
+    /// Writes `content` to `path` inside the index and commits it.
+    fn commit_index_file(repo: &Repository, path: &str, content: &str) -> Result<()> {
+        let file = repo.workdir().unwrap().join(path);
+        fs::create_dir_all(file.parent().unwrap())?;
+        fs::write(file, content)?;
+
+        let mut index = repo.index()?;
+        index.add_path(Path::new(path))?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+
+        let signature = Signature::now("docs.rs", "docs@docs.rs")?;
+        let parent = repo
+            .head()
+            .ok()
+            .map(|head| head.peel_to_commit())
+            .transpose()?;
+        let parents = parent.iter().collect::<Vec<_>>();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "Update index",
+            &tree,
+            &parents,
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_crates_reports_new_versions() {
+        wrapper(|env| {
+            let dir = tempfile::Builder::new()
+                .prefix("docs.rs-index-test")
+                .tempdir()?;
+            let repo = Repository::init(dir.path())?;
+            let line = |name: &str, version: &str, yanked: bool| {
+                format!(
+                    r#"{{"name":"{}","vers":"{}","deps":[],"cksum":"","features":{{}},"yanked":{}}}"#,
+                    name, version, yanked
+                ) + "\n"
+            };
+
+            let mut foo = line("foo", "0.1.0", false) + &line("foo", "0.2.0", false);
+            commit_index_file(&repo, "config.json", "{}")?;
+            commit_index_file(&repo, "3/f/foo", &foo)?;
+
+            let conn = env.db().conn();
+            let incremental = IncrementalCrates::new(&conn, dir.path());
+            let run_with = |filter: Option<&CrateNameFilter>| -> Result<Vec<(String, String)>> {
+                let mut versions = Vec::new();
+                incremental.process(filter, &mut |name, version| {
+                    versions.push((name.to_string(), version.to_string()))
+                })?;
+                Ok(versions)
+            };
+            let run = || run_with(None);
+            let version = |name: &str, version: &str| (name.to_string(), version.to_string());
+
+            assert_eq!(
+                run()?,
+                vec![version("foo", "0.1.0"), version("foo", "0.2.0")]
+            );
+            // Nothing changed since the previous run
+            assert_eq!(run()?, vec![]);
+
+            foo += &line("foo", "0.3.0", false);
+            foo += &line("foo", "0.4.0", true);
+            commit_index_file(&repo, "3/f/foo", &foo)?;
+            commit_index_file(&repo, "3/b/bar", &line("bar", "1.0.0", false))?;
+            commit_index_file(&repo, "config.json", r#"{"dl":"https://crates.io"}"#)?;
+
+            let mut new_versions = run()?;
+            new_versions.sort();
+            assert_eq!(
+                new_versions,
+                vec![version("bar", "1.0.0"), version("foo", "0.3.0")]
+            );
+
+            // The versions of the crates which aren't allowed are skipped, and not reported later
+            foo += &line("foo", "0.5.0", false);
+            commit_index_file(&repo, "3/f/foo", &foo)?;
+            commit_index_file(&repo, "3/b/bar", &line("bar", "2.0.0", false))?;
+            assert_eq!(
+                run_with(Some(&CrateNameFilter::new().deny("foo")))?,
+                vec![version("bar", "2.0.0")]
+            );
+            assert_eq!(run()?, vec![]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_dependencies() -> Result<()> {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-index-test")
+            .tempdir()?;
+        fs::write(
+            dir.path().join("foo"),
+            concat!(
+                r#"{"name":"foo","vers":"0.1.0","cksum":"","features":{},"yanked":false}"#,
+                "\n",
+                r#"{"name":"foo","vers":"0.2.0","deps":{"serde":"1"},"cksum":"","features":{},"yanked":false}"#,
+                "\n",
+                r#"{"name":"foo","vers":"0.3.0","deps":["#,
+                r#"{"name":"serde","req":"^1.0","features":[],"optional":false,"default_features":true,"target":null,"kind":"normal"},"#,
+                r#"{"name":"cc","req":"^1.0.50","features":[],"optional":false,"default_features":true,"target":null,"kind":"build"},"#,
+                r#"{"name":"tempfile","req":"^3","features":[],"optional":false,"default_features":true,"target":null,"kind":"dev"},"#,
+                r#"{"name":"json","req":"^1.0","features":[],"optional":true,"default_features":true,"target":null,"kind":null,"package":"serde_json"},"#,
+                r#"{"name":"broken","features":[]}"#,
+                r#"],"cksum":"","features":{},"yanked":false}"#,
+                "\n",
+            ),
+        )?;
+
+        let dep = |name: &str, req: &str, kind: &str, optional: bool| IndexDep {
+            name: name.into(),
+            req: req.into(),
+            kind: kind.into(),
+            optional,
+        };
+
+        let mut versions = Vec::new();
+        crates_from_path_detailed(dir.path(), &mut |version| {
+            assert_eq!(version.name, "foo");
+            versions.push((version.version.clone(), version.deps.clone()));
+        })?;
+        assert_eq!(
+            versions,
+            vec![
+                (
+                    "0.3.0".to_string(),
+                    vec![
+                        dep("serde", "^1.0", "normal", false),
+                        dep("cc", "^1.0.50", "build", false),
+                        dep("tempfile", "^3", "dev", false),
+                        dep("serde_json", "^1.0", "normal", true),
+                    ]
+                ),
+                ("0.2.0".to_string(), vec![]),
+                ("0.1.0".to_string(), vec![]),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_checksums() -> Result<()> {
+        const CKSUM: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        let with_cksum = format!(
+            r#"{{"name":"foo","vers":"0.1.0","deps":[],"cksum":"{}","features":{{}},"yanked":false}}"#,
+            CKSUM
+        );
+        let without_cksum =
+            r#"{"name":"foo","vers":"0.2.0","deps":[],"features":{},"yanked":false}"#;
+
+        let version = parse_index_line(&with_cksum, 1, true)?.unwrap();
+        assert_eq!(version.cksum.as_deref(), Some(CKSUM));
+        assert_eq!(
+            parse_index_line(without_cksum, 1, true)?.unwrap().cksum,
+            None
+        );
+        // Only the detailed parse extracts the checksum
+        assert_eq!(
+            parse_index_line(&with_cksum, 1, false)?.unwrap().cksum,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let cksum = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        assert!(verify_checksum(b"hello world", cksum));
+        assert!(verify_checksum(b"hello world", &cksum.to_uppercase()));
+        assert!(!verify_checksum(b"hello world!", cksum));
+        assert!(!verify_checksum(b"hello world", ""));
+    }
+
+    #[test]
+    fn test_crate_name_filter() -> Result<()> {
+        let index = synthetic_index(30)?;
+        let walk = |filter: &CrateNameFilter| -> Result<Vec<String>> {
+            let mut names = Vec::new();
+            crates_from_path(index.path(), Some(filter), false, &mut |name, _| {
+                if !names.contains(&name.to_string()) {
+                    names.push(name.to_string());
+                }
+            })?;
+            names.sort();
+            Ok(names)
+        };
+
+        let filter = CrateNameFilter::new().allow("crate-1*").deny("CRATE-13");
+        let mut expected = [1, 10, 11, 12, 14, 15, 16, 17, 18, 19]
+            .iter()
+            .map(|i| format!("crate-{}", i))
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(walk(&filter)?, expected);
+
+        let filter = CrateNameFilter::new().deny("crate-2*").deny("crate-0");
+        let names = walk(&filter)?;
+        assert_eq!(names.len(), 18);
+        assert!(names.iter().all(|name| !name.starts_with("crate-2")));
+        assert!(!names.contains(&"crate-0".to_string()));
+        assert!(names.contains(&"crate-1".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        for (pattern, name, matches) in &[
+            ("foo", "foo", true),
+            ("foo", "foobar", false),
+            ("foo*", "foobar", true),
+            ("*bar", "foobar", true),
+            ("*o*a*", "foobar", true),
+            ("f*r*", "foobar", true),
+            ("*", "", true),
+            ("", "foo", false),
+            ("f*x", "foobar", false),
+            ("*ab", "abab", true),
+        ] {
+            assert_eq!(
+                glob_matches(pattern.as_bytes(), name.as_bytes()),
+                *matches,
+                "{} matching {}",
+                pattern,
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_walk_skips_unreadable_files() -> Result<()> {
+        let index = synthetic_index(20)?;
+        let dir = index.path().join("00").join("00");
+        // Symlinks are used instead of permissions, which aren't enforced when running as root
+        std::os::unix::fs::symlink(index.path().join("missing"), dir.join("broken-link"))?;
+        std::os::unix::fs::symlink(dir.join("symlink-loop"), dir.join("symlink-loop"))?;
+        assert!(fs::File::open(dir.join("broken-link")).is_err());
+        assert!(fs::File::open(dir.join("symlink-loop")).is_err());
+
+        let mut serial = HashSet::new();
+        crates_from_path(index.path(), None, false, &mut |name, version| {
+            serial.insert((name.to_string(), version.to_string()));
+        })?;
+        assert_eq!(serial.len(), 40);
+
+        let parallel = Mutex::new(HashSet::new());
+        crates_from_path_parallel(index.path(), 2, &|name, version| {
+            parallel
+                .lock()
+                .unwrap()
+                .insert((name.to_string(), version.to_string()));
+        })?;
+        assert_eq!(parallel.into_inner().unwrap(), serial);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_skips_non_utf8_paths() -> Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let index = synthetic_index(10)?;
+        let mut expected = HashSet::new();
+        crates_from_path(index.path(), None, false, &mut |name, version| {
+            expected.insert((name.to_string(), version.to_string()));
+        })?;
+
+        let invalid = OsStr::from_bytes(b"invalid-\xff-name");
+        fs::write(
+            index.path().join("00").join(invalid),
+            r#"{"name":"invalid","vers":"1.0.0","deps":[],"cksum":"","features":{},"yanked":false}"#,
+        )?;
+        fs::create_dir(index.path().join(invalid))?;
+
+        let mut serial = HashSet::new();
+        crates_from_path(index.path(), None, false, &mut |name, version| {
+            serial.insert((name.to_string(), version.to_string()));
+        })?;
+        assert_eq!(serial, expected);
+
+        let parallel = Mutex::new(HashSet::new());
+        crates_from_path_parallel(index.path(), 2, &|name, version| {
+            parallel
+                .lock()
+                .unwrap()
+                .insert((name.to_string(), version.to_string()));
+        })?;
+        assert_eq!(parallel.into_inner().unwrap(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_requires_index_directory() {
+        let missing = PathBuf::from("/nonexistent/docs.rs-index");
+        let err = crates_from_path(&missing, None, false, &mut |_, _| {}).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CrateParseError>(),
+            Some(CrateParseError::NotADirectory)
+        ));
+        let err = crates_from_path_parallel(&missing, 0, &|_, _| {}).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CrateParseError>(),
+            Some(CrateParseError::NotADirectory)
+        ));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            parse_index_line("[1, 2]", 3, false),
+            Err(CrateParseError::InvalidJson { line: 3 })
+        ));
+        assert!(matches!(
+            parse_index_line(r#"{"vers":"1.0.0"}"#, 1, false),
+            Err(CrateParseError::MissingField { field: "name" })
+        ));
+        assert!(matches!(
+            parse_index_line(r#"{"name":"foo","vers":1}"#, 1, false),
+            Err(CrateParseError::MissingField { field: "vers" })
+        ));
+        // Lines which aren't JSON at all are skipped
+        assert!(matches!(parse_index_line("not json", 1, false), Ok(None)));
+    }
+
+    #[test]
+    fn test_walk_reports_parse_errors() -> Result<()> {
+        let index = synthetic_index(1)?;
+        let dir = index.path().join("zz");
+        fs::create_dir(&dir)?;
+        fs::write(
+            dir.join("broken"),
+            "{\"name\":\"broken\",\"vers\":\"1.0.0\"}\n\"not an object\"\n",
+        )?;
+
+        let err = crates_from_path(index.path(), None, false, &mut |_, _| {}).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CrateParseError>(),
+            Some(CrateParseError::InvalidJson { line: 2 })
+        ));
+        let err = crates_from_path_parallel(index.path(), 2, &|_, _| {}).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CrateParseError>(),
+            Some(CrateParseError::InvalidJson { line: 2 })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzipped_index_files() -> Result<()> {
+        use flate2::{write::GzEncoder, Compression};
+
+        let content = concat!(
+            r#"{"name":"serde","vers":"1.0.0","deps":[],"cksum":"","features":{},"yanked":false}"#,
+            "\n",
+            r#"{"name":"serde","vers":"1.0.1","deps":[],"cksum":"","features":{},"yanked":true}"#,
+            "\n",
+            r#"{"name":"serde","vers":"1.1.0","deps":[],"cksum":"","features":{},"yanked":false}"#,
+            "\n",
+        );
+        let plain = tempfile::Builder::new()
+            .prefix("docs.rs-index-test")
+            .tempdir()?;
+        let file = plain.path().join(index_file_path("serde"));
+        fs::create_dir_all(file.parent().unwrap())?;
+        fs::write(&file, content)?;
+
+        let gzipped = tempfile::Builder::new()
+            .prefix("docs.rs-index-test")
+            .tempdir()?;
+        let file = gzipped
+            .path()
+            .join(index_file_path("serde"))
+            .with_extension("gz");
+        fs::create_dir_all(file.parent().unwrap())?;
+        let mut encoder = GzEncoder::new(fs::File::create(&file)?, Compression::default());
+        encoder.write_all(content.as_bytes())?;
+        encoder.finish()?;
+
+        let parse = |index: &Path| -> Result<Vec<IndexVersion>> {
+            Ok(CratesIter::new(index)?.collect::<StdResult<_, _>>()?)
+        };
+        let versions = parse(plain.path())?;
+        assert_eq!(versions.len(), 2);
+        assert_eq!(parse(gzipped.path())?, versions);
+
+        let mut found = Vec::new();
+        crates_from_index_for(gzipped.path(), "serde", &mut |name, version| {
+            found.push(format!("{} {}", name, version));
+        })?;
+        assert_eq!(found, vec!["serde 1.1.0", "serde 1.0.0"]);
+        assert!(crate_exists_in_index(gzipped.path(), "serde"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_file_path() {
+        assert_eq!(index_file_path("a"), Path::new("1/a"));
+        assert_eq!(index_file_path("ab"), Path::new("2/ab"));
+        assert_eq!(index_file_path("Syn"), Path::new("3/s/syn"));
+        assert_eq!(index_file_path("serde"), Path::new("se/rd/serde"));
+        assert_eq!(index_file_path("Rand_Core"), Path::new("ra/nd/rand_core"));
+    }
+
+    #[test]
+    fn test_crates_from_index_for() -> Result<()> {
+        let index = tempfile::Builder::new()
+            .prefix("docs.rs-index-test")
+            .tempdir()?;
+        for name in &["a", "syn", "serde", "serde_json"] {
+            let file = index.path().join(index_file_path(name));
+            fs::create_dir_all(file.parent().unwrap())?;
+            fs::write(
+                file,
+                format!(
+                    concat!(
+                        r#"{{"name":"{0}","vers":"1.0.0","deps":[],"cksum":"","features":{{}},"yanked":false}}"#,
+                        "\n",
+                        r#"{{"name":"{0}","vers":"1.1.0","deps":[],"cksum":"","features":{{}},"yanked":false}}"#,
+                        "\n",
+                    ),
+                    name
+                ),
+            )?;
+        }
+
+        for name in &["a", "syn", "serde"] {
+            let mut versions = Vec::new();
+            crates_from_index_for(index.path(), name, &mut |found, version| {
+                versions.push((found.to_string(), version.to_string()));
+            })?;
+            assert_eq!(
+                versions,
+                vec![
+                    (name.to_string(), "1.1.0".to_string()),
+                    (name.to_string(), "1.0.0".to_string()),
+                ]
+            );
+        }
+        for invalid in &["serd", "", "sérde"] {
+            assert!(crates_from_index_for(index.path(), invalid, &mut |_, _| {}).is_err());
+            assert!(!crate_exists_in_index(index.path(), invalid));
+        }
+        assert!(crate_exists_in_index(index.path(), "Serde_JSON"));
+
+        Ok(())
+    }
 }
@@ -181,8 +181,10 @@ impl Metadata {
         metadata
     }
 
-    pub(super) fn targets(&self) -> BuildTargets<'_> {
-        use super::rustwide_builder::{HOST_TARGET, TARGETS};
+    /// Selects the targets to build, falling back to `fallback_target` as the default target when
+    /// the crate doesn't specify one.
+    pub(super) fn targets<'a>(&'a self, fallback_target: &'a str) -> BuildTargets<'a> {
+        use super::rustwide_builder::TARGETS;
 
         let default_target = self
             .default_target
@@ -193,7 +195,7 @@ impl Metadata {
                     .as_ref()
                     .and_then(|targets| targets.iter().next().map(String::as_str))
             })
-            .unwrap_or(HOST_TARGET);
+            .unwrap_or(fallback_target);
 
         // Let people opt-in to only having specific targets
         let mut targets: HashSet<_> = self
@@ -305,9 +307,17 @@ mod test {
         let BuildTargets {
             default_target: default,
             other_targets: tier_one,
-        } = metadata.targets();
+        } = metadata.targets(HOST_TARGET);
         assert_eq!(default, HOST_TARGET);
 
+        // the fallback is used as the default target
+        let BuildTargets {
+            default_target: default,
+            other_targets: others,
+        } = metadata.targets("x86_64-pc-windows-msvc");
+        assert_eq!(default, "x86_64-pc-windows-msvc");
+        assert!(!others.contains(&"x86_64-pc-windows-msvc"));
+
         // should be equal to TARGETS \ {HOST_TARGET}
         for actual in &tier_one {
             assert!(TARGETS.contains(actual));
@@ -327,7 +337,7 @@ mod test {
         let BuildTargets {
             default_target: default,
             other_targets: others,
-        } = metadata.targets();
+        } = metadata.targets(HOST_TARGET);
 
         assert_eq!(default, HOST_TARGET);
         assert!(others.is_empty());
@@ -341,7 +351,7 @@ mod test {
         let BuildTargets {
             default_target: default,
             other_targets: others,
-        } = metadata.targets();
+        } = metadata.targets(HOST_TARGET);
 
         assert_eq!(default, "i686-pc-windows-msvc");
         assert_eq!(others.len(), 1);
@@ -352,7 +362,7 @@ mod test {
         let BuildTargets {
             default_target: default,
             other_targets: others,
-        } = metadata.targets();
+        } = metadata.targets(HOST_TARGET);
 
         assert_eq!(default, HOST_TARGET);
         assert!(others.is_empty());
@@ -366,7 +376,7 @@ mod test {
         let BuildTargets {
             default_target: default,
             other_targets: others,
-        } = metadata.targets();
+        } = metadata.targets(HOST_TARGET);
 
         assert_eq!(default, "i686-pc-windows-msvc");
         assert!(others.is_empty());
@@ -376,7 +386,7 @@ mod test {
         let BuildTargets {
             default_target: default,
             other_targets: others,
-        } = metadata.targets();
+        } = metadata.targets(HOST_TARGET);
 
         assert_eq!(default, "i686-apple-darwin");
         assert_eq!(others.len(), 1);
@@ -387,7 +397,7 @@ mod test {
         let BuildTargets {
             default_target: default,
             other_targets: others,
-        } = metadata.targets();
+        } = metadata.targets(HOST_TARGET);
 
         assert_eq!(default, "i686-apple-darwin");
         assert!(others.is_empty());
@@ -397,7 +407,7 @@ mod test {
         let BuildTargets {
             default_target: default,
             other_targets: others,
-        } = metadata.targets();
+        } = metadata.targets(HOST_TARGET);
 
         assert_eq!(default, "i686-apple-darwin");
         let tier_one_targets_no_default = TARGETS
@@ -5,10 +5,15 @@ pub(crate) mod options;
 mod queue;
 mod rustwide_builder;
 
+pub use self::crates::{
+    count_crates_in_path, crate_exists_in_index, crates_from_index_for, crates_from_path_detailed,
+    undocumented_versions, verify_checksum, CrateNameFilter, CrateParseError, CrateWalkStats,
+    CratesIter, IndexDep, IndexVersion,
+};
 pub(crate) use self::limits::Limits;
 pub(self) use self::metadata::Metadata;
-pub(crate) use self::rustwide_builder::BuildResult;
 pub use self::rustwide_builder::RustwideBuilder;
+pub(crate) use self::rustwide_builder::{BuildResult, HOST_TARGET, TARGETS};
 
 use crate::db::Pool;
 use crate::error::Result;
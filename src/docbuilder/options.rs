@@ -1,3 +1,4 @@
+use crate::docbuilder::CrateNameFilter;
 use crate::error::Result;
 use std::path::PathBuf;
 use std::{env, fmt};
@@ -12,6 +13,15 @@ pub struct DocBuilderOptions {
     pub skip_oldest_versions: bool,
     pub build_only_latest_version: bool,
     pub debug: bool,
+    /// Number of threads reading the registry index when building every crate, or 0 to use one
+    /// thread per CPU. Defaults to a single thread, with which crates start building while the
+    /// index is read.
+    pub index_walk_threads: usize,
+    /// Reads the index in the same order on every machine when building every crate, which
+    /// requires reading it with a single thread
+    pub sorted_index_walk: bool,
+    /// Crates to skip when building every crate
+    pub crate_name_filter: Option<CrateNameFilter>,
 }
 
 impl Default for DocBuilderOptions {
@@ -30,6 +40,9 @@ impl Default for DocBuilderOptions {
             skip_oldest_versions: false,
             build_only_latest_version: false,
             debug: false,
+            index_walk_threads: 1,
+            sorted_index_walk: false,
+            crate_name_filter: None,
         }
     }
 }
@@ -1,6 +1,7 @@
 //! Updates registry index and builds new packages
 
 use super::{DocBuilder, RustwideBuilder};
+use crate::db::set_yanked;
 use crate::error::Result;
 use crate::utils::get_crate_priority;
 use crates_index_diff::ChangeKind;
@@ -20,26 +21,13 @@ impl DocBuilder {
 
         for krate in &changes {
             match krate.kind {
-                ChangeKind::Yanked => {
-                    let res = conn.execute(
-                        "
-                        UPDATE releases
-                            SET yanked = TRUE
-                        FROM crates
-                        WHERE crates.id = releases.crate_id
-                            AND name = $1
-                            AND version = $2
-                        ",
-                        &[&krate.name, &krate.version],
-                    );
-                    match res {
-                        Ok(_) => debug!("{}-{} yanked", krate.name, krate.version),
-                        Err(err) => error!(
-                            "error while setting {}-{} to yanked: {}",
-                            krate.name, krate.version, err
-                        ),
-                    }
-                }
+                ChangeKind::Yanked => match set_yanked(&conn, &krate.name, &krate.version, true) {
+                    Ok(_) => debug!("{}-{} yanked", krate.name, krate.version),
+                    Err(err) => error!(
+                        "error while setting {}-{} to yanked: {}",
+                        krate.name, krate.version, err
+                    ),
+                },
 
                 ChangeKind::Added => {
                     let priority = get_crate_priority(&conn, &krate.name)?;
@@ -48,10 +36,13 @@ impl DocBuilder {
                         .build_queue
                         .add_crate(&krate.name, &krate.version, priority)
                     {
-                        Ok(()) => {
+                        Ok(true) => {
                             debug!("{}-{} added into build queue", krate.name, krate.version);
                             crates_added += 1;
                         }
+                        Ok(false) => {
+                            debug!("{}-{} is already queued", krate.name, krate.version);
+                        }
                         Err(err) => error!(
                             "failed adding {}-{} into build queue: {}",
                             krate.name, krate.version, err
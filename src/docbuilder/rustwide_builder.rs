@@ -2,11 +2,20 @@ use super::DocBuilder;
 use super::Metadata;
 use crate::db::blacklist::is_blacklisted;
 use crate::db::file::add_path_into_database;
-use crate::db::{add_build_into_database, add_package_into_database, Pool};
-use crate::docbuilder::{crates::crates_from_path, Limits};
+use crate::db::{
+    add_build_into_database, add_package_into_database, add_targets_into_database, update_doc_size,
+    Pool,
+};
+use crate::docbuilder::{
+    crates::{
+        crates_from_index_for, crates_from_path, crates_from_path_parallel, IncrementalCrates,
+    },
+    Limits,
+};
 use crate::error::Result;
-use crate::storage::CompressionAlgorithms;
+use crate::storage::{CompressionAlgorithm, CompressionAlgorithms, Storage, StorageKind};
 use crate::utils::{copy_doc_dir, parse_rustc_version, CargoMetadata};
+use crate::Config;
 use failure::ResultExt;
 use log::{debug, info, warn, LevelFilter};
 use postgres::Connection;
@@ -18,14 +27,15 @@ use serde_json::Value;
 use std::borrow::Cow;
 use std::collections::HashSet;
 use std::path::Path;
+use std::sync::Mutex;
 
 const USER_AGENT: &str = "docs.rs builder (https://github.com/rust-lang/docs.rs)";
 const DEFAULT_RUSTWIDE_WORKSPACE: &str = ".rustwide";
 
 // It is crucial that this be the same as the host that `docs.rs` is being run on.
 // Other values may cause strange and hard-to-debug errors.
-pub(super) const HOST_TARGET: &str = env!("CRATESFYI_HOST_TARGET"); // Set in build.rs
-pub(super) const TARGETS: &[&str] = &[
+pub(crate) const HOST_TARGET: &str = env!("CRATESFYI_HOST_TARGET"); // Set in build.rs
+pub(crate) const TARGETS: &[&str] = &[
     "i686-pc-windows-msvc",
     "i686-unknown-linux-gnu",
     "x86_64-apple-darwin",
@@ -69,10 +79,14 @@ pub struct RustwideBuilder {
     db: Pool,
     rustc_version: String,
     cpu_limit: Option<u32>,
+    storage_backend: StorageKind,
+    storage_compression: Option<CompressionAlgorithm>,
+    store_rustdoc_json: bool,
+    default_target: String,
 }
 
 impl RustwideBuilder {
-    pub fn init(db: Pool) -> Result<Self> {
+    pub fn init(config: &Config, db: Pool) -> Result<Self> {
         use rustwide::cmd::SandboxImage;
         let env_workspace_path = ::std::env::var("CRATESFYI_RUSTWIDE_WORKSPACE");
         let workspace_path = env_workspace_path
@@ -109,6 +123,10 @@ impl RustwideBuilder {
             db,
             rustc_version: String::new(),
             cpu_limit,
+            storage_backend: config.storage_backend.clone(),
+            storage_compression: config.storage_compression,
+            store_rustdoc_json: config.store_rustdoc_json,
+            default_target: config.default_target.clone(),
         })
     }
 
@@ -244,7 +262,13 @@ impl RustwideBuilder {
                     })?;
                 }
 
-                add_path_into_database(&conn, "", &dest)?;
+                add_path_into_database(
+                    &conn,
+                    &self.storage_backend,
+                    "",
+                    &dest,
+                    self.storage_compression,
+                )?;
                 conn.query(
                     "INSERT INTO config (name, value) VALUES ('rustc_version', $1) \
                      ON CONFLICT (name) DO UPDATE SET value = $1;",
@@ -260,24 +284,83 @@ impl RustwideBuilder {
     }
 
     pub fn build_world(&mut self, doc_builder: &mut DocBuilder) -> Result<()> {
+        let registry_index_path = doc_builder.options().registry_index_path.clone();
+        let threads = doc_builder.options().index_walk_threads;
+        let sorted = doc_builder.options().sorted_index_walk;
+        let filter = doc_builder.options().crate_name_filter.clone();
+
         let mut count = 0;
-        crates_from_path(
-            &doc_builder.options().registry_index_path.clone(),
-            &mut |name, version| {
-                match self.build_package(doc_builder, name, version, None) {
-                    Ok(status) => {
-                        count += 1;
-                        if status && count % 10 == 0 {
-                            let _ = doc_builder.save_cache();
-                        }
+        let mut build = |name: &str, version: &str| {
+            match self.build_package(doc_builder, name, version, None) {
+                Ok(status) => {
+                    count += 1;
+                    if status && count % 10 == 0 {
+                        let _ = doc_builder.save_cache();
                     }
-                    Err(err) => warn!("failed to build package {} {}: {}", name, version, err),
+                }
+                Err(err) => warn!("failed to build package {} {}: {}", name, version, err),
+            }
+            doc_builder.add_to_cache(name, version);
+        };
+
+        if threads == 1 || sorted {
+            return crates_from_path(&registry_index_path, filter.as_ref(), sorted, &mut build);
+        }
+
+        // Builds can't run concurrently, so the whole index is read before building
+        let crates = Mutex::new(Vec::new());
+        crates_from_path_parallel(&registry_index_path, threads, &|name, version| {
+            if filter.as_ref().map(|filter| filter.allows(name)) == Some(false) {
+                return;
+            }
+            crates
+                .lock()
+                .unwrap()
+                .push((name.to_string(), version.to_string()));
+        })?;
+        for (name, version) in crates.into_inner().unwrap() {
+            build(&name, &version);
+        }
+
+        Ok(())
+    }
+
+    /// Builds only the versions added to the registry index since the previous call, see
+    /// `IncrementalCrates`.
+    pub fn build_world_incremental(&mut self, doc_builder: &mut DocBuilder) -> Result<()> {
+        let registry_index_path = doc_builder.options().registry_index_path.clone();
+        let filter = doc_builder.options().crate_name_filter.clone();
+        let conn = self.db.get()?;
+
+        IncrementalCrates::new(&conn, &registry_index_path).process(
+            filter.as_ref(),
+            &mut |name, version| {
+                if let Err(err) = self.build_package(doc_builder, name, version, None) {
+                    warn!("failed to build package {} {}: {}", name, version, err);
                 }
                 doc_builder.add_to_cache(name, version);
             },
         )
     }
 
+    /// Builds every version of a crate listed in the registry index.
+    pub fn build_crate_versions(&mut self, doc_builder: &mut DocBuilder, name: &str) -> Result<()> {
+        let registry_index_path = doc_builder.options().registry_index_path.clone();
+
+        let mut versions = Vec::new();
+        crates_from_index_for(&registry_index_path, name, &mut |name, version| {
+            versions.push((name.to_string(), version.to_string()));
+        })?;
+        for (name, version) in versions {
+            if let Err(err) = self.build_package(doc_builder, &name, &version, None) {
+                warn!("failed to build package {} {}: {}", name, version, err);
+            }
+            doc_builder.add_to_cache(&name, &version);
+        }
+
+        Ok(())
+    }
+
     pub fn build_local_package(
         &mut self,
         doc_builder: &mut DocBuilder,
@@ -337,19 +420,25 @@ impl RustwideBuilder {
                 let mut has_docs = false;
                 let mut algs = CompressionAlgorithms::default();
                 let mut successful_targets = Vec::new();
+                let mut attempted_targets = Vec::new();
                 let metadata = Metadata::from_source_dir(&build.host_source_dir())?;
                 let BuildTargets {
                     default_target,
                     other_targets,
-                } = metadata.targets();
+                } = metadata.targets(&self.default_target);
 
                 // Do an initial build and then copy the sources in the database
                 let res = self.execute_build(default_target, true, &build, &limits, &metadata)?;
                 if res.result.successful {
                     debug!("adding sources into database");
                     let prefix = format!("sources/{}/{}", name, version);
-                    let (files, new_algs) =
-                        add_path_into_database(&conn, &prefix, build.host_source_dir())?;
+                    let (files, new_algs) = add_path_into_database(
+                        &conn,
+                        &self.storage_backend,
+                        &prefix,
+                        build.host_source_dir(),
+                        self.storage_compression,
+                    )?;
                     files_list = Some(files);
                     algs.extend(new_algs);
 
@@ -369,6 +458,7 @@ impl RustwideBuilder {
                     // Limit the number of targets so that no one can try to build all 200000 possible targets
                     for target in other_targets.into_iter().take(limits.targets()) {
                         debug!("building package {} {} for {}", name, version, target);
+                        attempted_targets.push(target);
                         self.build_target(
                             target,
                             &build,
@@ -380,6 +470,21 @@ impl RustwideBuilder {
                     }
                     let new_algs = self.upload_docs(&conn, name, version, local_storage.path())?;
                     algs.extend(new_algs);
+
+                    if self.store_rustdoc_json {
+                        if let Some(library_name) = res.cargo_metadata.root().library_name() {
+                            self.store_rustdoc_json(
+                                &conn,
+                                name,
+                                version,
+                                default_target,
+                                &library_name,
+                                build,
+                                &limits,
+                                &metadata,
+                            )?;
+                        }
+                    }
                 };
 
                 let has_examples = build.host_source_dir().join("examples").is_dir();
@@ -390,6 +495,14 @@ impl RustwideBuilder {
                 } else {
                     crate::web::metrics::NON_LIBRARY_BUILDS.inc();
                 }
+                // The build status of the default target is the one of the whole build, as it
+                // doesn't only build documentation.
+                let targets =
+                    std::iter::once((res.target.as_str(), res.result.successful))
+                        .chain(attempted_targets.iter().map(|&target| {
+                            (target, successful_targets.iter().any(|t| t == target))
+                        }))
+                        .collect::<Vec<_>>();
                 let release_id = add_package_into_database(
                     &conn,
                     res.cargo_metadata.root(),
@@ -403,7 +516,15 @@ impl RustwideBuilder {
                     has_examples,
                     algs,
                 )?;
-                add_build_into_database(&conn, release_id, &res.result)?;
+                update_doc_size(&conn, &self.storage_backend, release_id, name, version)?;
+                add_targets_into_database(&conn, release_id, &targets)?;
+                let build_id = add_build_into_database(&conn, release_id, &res.result)?;
+                Storage::new(&conn, &self.storage_backend).store_build_log(
+                    name,
+                    version,
+                    build_id,
+                    &res.result.build_log,
+                )?;
 
                 doc_builder.add_to_cache(name, version);
                 Ok(res)
@@ -544,12 +665,83 @@ impl RustwideBuilder {
                 rustc_version: self.rustc_version.clone(),
                 docsrs_version: format!("docsrs {}", crate::BUILD_VERSION),
                 successful,
+                features: metadata.features.clone().unwrap_or_default(),
+                cargo_args: cargo_args.iter().map(|arg| arg.to_string()).collect(),
             },
             cargo_metadata,
             target: target.to_string(),
         })
     }
 
+    /// Builds the JSON output of rustdoc for the default target and stores it. Failed builds are
+    /// only logged, as the format is unstable and shouldn't prevent publishing the documentation.
+    #[allow(clippy::too_many_arguments)]
+    fn store_rustdoc_json(
+        &self,
+        conn: &Connection,
+        name: &str,
+        version: &str,
+        target: &str,
+        library_name: &str,
+        build: &Build,
+        limits: &Limits,
+        metadata: &Metadata,
+    ) -> Result<()> {
+        let mut cargo_args = vec!["rustdoc", "--lib"];
+        if target != HOST_TARGET {
+            cargo_args.push("--target");
+            cargo_args.push(target);
+        }
+        let features;
+        if let Some(list) = &metadata.features {
+            cargo_args.push("--features");
+            features = list.join(" ");
+            cargo_args.push(&features);
+        }
+        if metadata.all_features {
+            cargo_args.push("--all-features");
+        }
+        if metadata.no_default_features {
+            cargo_args.push("--no-default-features");
+        }
+        cargo_args.extend(&["--", "-Z", "unstable-options", "--output-format", "json"]);
+
+        let mut log = LogStorage::new(LevelFilter::Info);
+        log.set_max_size(limits.max_log_size());
+        let successful = logging::capture(&log, || {
+            build
+                .cargo()
+                .timeout(Some(limits.timeout()))
+                .no_output_timeout(None)
+                .env("DOCS_RS", "1")
+                .args(&cargo_args)
+                .run()
+                .is_ok()
+        });
+
+        let mut doc_dir = build.host_target_dir();
+        if target != HOST_TARGET {
+            doc_dir = doc_dir.join(target);
+        }
+        let path = doc_dir.join("doc").join(format!("{}.json", library_name));
+        if !successful || !path.is_file() {
+            warn!(
+                "failed to build the rustdoc JSON of {} {}:\n{}",
+                name,
+                version,
+                log.to_string()
+            );
+            return Ok(());
+        }
+
+        debug!("adding the rustdoc JSON into the storage");
+        Storage::new(conn, &self.storage_backend).store_rustdoc_json(
+            name,
+            version,
+            std::fs::File::open(path)?,
+        )
+    }
+
     fn copy_docs(
         &self,
         target_dir: &Path,
@@ -583,8 +775,10 @@ impl RustwideBuilder {
         debug!("Adding documentation into database");
         add_path_into_database(
             conn,
+            &self.storage_backend,
             &format!("rustdoc/{}/{}", name, version),
             local_storage,
+            self.storage_compression,
         )
         .map(|t| t.1)
     }
@@ -601,4 +795,8 @@ pub(crate) struct BuildResult {
     pub(crate) docsrs_version: String,
     pub(crate) build_log: String,
     pub(crate) successful: bool,
+    /// Features explicitly enabled through the docs.rs metadata of the crate.
+    pub(crate) features: Vec<String>,
+    /// Arguments cargo was invoked with to build the documentation.
+    pub(crate) cargo_args: Vec<String>,
 }
@@ -3,10 +3,15 @@
 #![allow(clippy::cognitive_complexity)]
 
 pub use self::build_queue::BuildQueue;
-pub use self::config::Config;
+pub use self::config::{Config, ConfigError};
 pub use self::docbuilder::options::DocBuilderOptions;
 pub use self::docbuilder::DocBuilder;
 pub use self::docbuilder::RustwideBuilder;
+pub use self::docbuilder::{
+    count_crates_in_path, crates_from_index_for, crates_from_path_detailed, undocumented_versions,
+    verify_checksum, CrateNameFilter, CrateParseError, CrateWalkStats, CratesIter, IndexDep,
+    IndexVersion,
+};
 pub use self::web::Server;
 
 mod build_queue;
@@ -41,3 +46,6 @@ pub const BUILD_VERSION: &str = concat!(
     " ",
     include_str!(concat!(env!("OUT_DIR"), "/git_version"))
 );
+
+/// Abbreviated hash of the git commit docs.rs was built from, or ??????? if it's unknown
+pub const GIT_COMMIT: &str = env!("CRATESFYI_GIT_COMMIT");
@@ -1,11 +1,8 @@
-use super::Blob;
+use super::{Blob, DirectoryListing, PathNotFoundError};
 use chrono::{DateTime, NaiveDateTime, Utc};
-use failure::{Error, Fail};
-use postgres::{transaction::Transaction, Connection};
-
-#[derive(Debug, Fail)]
-#[fail(display = "the path is not present in the database")]
-struct PathNotFoundError;
+use failure::Error;
+use postgres::{transaction::Transaction, Connection, GenericConnection};
+use std::{collections::HashMap, io::Read};
 
 pub(crate) struct DatabaseBackend<'a> {
     conn: &'a Connection,
@@ -62,21 +59,108 @@ impl<'a> DatabaseBackend<'a> {
         }
     }
 
+    pub(super) fn exists_many(&self, paths: &[&str]) -> Result<HashMap<String, bool>, Error> {
+        let mut existing = paths
+            .iter()
+            .map(|&path| (path.to_string(), false))
+            .collect::<HashMap<_, _>>();
+        if paths.is_empty() {
+            return Ok(existing);
+        }
+
+        for row in &self
+            .conn
+            .query("SELECT path FROM files WHERE path = ANY($1);", &[&paths])?
+        {
+            existing.insert(row.get(0), true);
+        }
+
+        Ok(existing)
+    }
+
+    pub(super) fn list_directory(&self, dir: &str) -> Result<DirectoryListing, Error> {
+        let rows = self.conn.query(
+            "SELECT path FROM files WHERE path LIKE $1;",
+            &[&prefix_pattern(dir)],
+        )?;
+        Ok(DirectoryListing::from_paths(
+            dir,
+            rows.iter().map(|row| row.get::<_, String>(0)),
+        ))
+    }
+
+    pub(super) fn prefix_size(&self, prefix: &str) -> Result<u64, Error> {
+        let rows = self.conn.query(
+            "SELECT COALESCE(SUM(LENGTH(content)), 0)::BIGINT FROM files WHERE path LIKE $1;",
+            &[&prefix_pattern(prefix)],
+        )?;
+        Ok(rows.get(0).get::<_, i64>(0) as u64)
+    }
+
+    pub(super) fn delete_prefix(&self, prefix: &str) -> Result<usize, Error> {
+        let deleted = self.conn.execute(
+            "DELETE FROM files WHERE path LIKE $1;",
+            &[&prefix_pattern(prefix)],
+        )?;
+        Ok(deleted as usize)
+    }
+
+    /// Stores the content of the reader. The database can't receive the content in chunks, so it
+    /// is buffered before being inserted.
+    pub(super) fn store_stream(
+        &self,
+        path: &str,
+        content_type: &str,
+        mut reader: impl Read,
+    ) -> Result<(), Error> {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        insert_file(self.conn, path, content_type, &content, None)
+    }
+
     pub(super) fn store_batch(&self, batch: &[Blob], trans: &Transaction) -> Result<(), Error> {
         for blob in batch {
-            let compression = blob.compression.map(|alg| alg as i32);
-            trans.query(
-                "INSERT INTO files (path, mime, content, compression)
-                 VALUES ($1, $2, $3, $4)
-                 ON CONFLICT (path) DO UPDATE
-                    SET mime = EXCLUDED.mime, content = EXCLUDED.content, compression = EXCLUDED.compression",
-                &[&blob.path, &blob.mime, &blob.content, &compression],
+            insert_file(
+                trans,
+                &blob.path,
+                &blob.mime,
+                &blob.content,
+                blob.compression.map(|alg| alg as i32),
             )?;
         }
         Ok(())
     }
 }
 
+/// Returns the LIKE pattern matching all the paths starting with `prefix`.
+fn prefix_pattern(prefix: &str) -> String {
+    // `%`, `_` and the escape character itself are special in LIKE patterns
+    format!(
+        "{}%",
+        prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    )
+}
+
+fn insert_file(
+    conn: &dyn GenericConnection,
+    path: &str,
+    mime: &str,
+    content: &[u8],
+    compression: Option<i32>,
+) -> Result<(), Error> {
+    conn.query(
+        "INSERT INTO files (path, mime, content, compression)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (path) DO UPDATE
+            SET mime = EXCLUDED.mime, content = EXCLUDED.content, compression = EXCLUDED.compression",
+        &[&path, &mime, &content, &compression],
+    )?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,371 @@
+//! Storage backend keeping the files in a local directory, useful for development and for
+//! deployments without access to S3.
+//!
+//! The content of each file is stored at `{root}/content/{path}`, while its mime type and
+//! compression algorithm are stored as JSON at `{root}/metadata/{path}`.
+
+use super::{get_file_list, Blob, CompressionAlgorithm, DirectoryListing, PathNotFoundError};
+use chrono::{DateTime, Utc};
+use failure::Error;
+use path_slash::PathExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+
+const CONTENT_DIR: &str = "content";
+const METADATA_DIR: &str = "metadata";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Metadata {
+    mime: String,
+    compression: Option<i32>,
+}
+
+pub(crate) struct FsBackend {
+    root: PathBuf,
+}
+
+impl FsBackend {
+    pub(crate) fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Returns where `path` is located inside one of the directories of the backend. Paths
+    /// trying to escape the root directory are treated as missing.
+    fn resolve(&self, dir: &str, path: &str) -> Result<PathBuf, PathNotFoundError> {
+        let path = Path::new(path);
+        let is_valid = path.components().next().is_some()
+            && path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+
+        if is_valid {
+            Ok(self.root.join(dir).join(path))
+        } else {
+            Err(PathNotFoundError)
+        }
+    }
+
+    pub(super) fn get(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
+        let file = match fs::File::open(self.resolve(CONTENT_DIR, path)?) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(PathNotFoundError.into())
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let file_metadata = file.metadata()?;
+        if !file_metadata.is_file() {
+            return Err(PathNotFoundError.into());
+        }
+        if file_metadata.len() > max_size as u64 {
+            return Err(
+                io::Error::new(io::ErrorKind::Other, crate::error::SizeLimitReached).into(),
+            );
+        }
+        let mut content = Vec::with_capacity(file_metadata.len() as usize);
+        file.take(max_size as u64).read_to_end(&mut content)?;
+
+        let metadata: Metadata =
+            serde_json::from_slice(&fs::read(self.resolve(METADATA_DIR, path)?)?)?;
+        let compression = metadata.compression.map(|i| {
+            i.try_into()
+                .expect("invalid compression algorithm stored in the metadata")
+        });
+
+        Ok(Blob {
+            path: path.into(),
+            mime: metadata.mime,
+            date_updated: DateTime::<Utc>::from(file_metadata.modified()?),
+            content,
+            compression,
+        })
+    }
+
+    pub(super) fn exists_many(&self, paths: &[&str]) -> Result<HashMap<String, bool>, Error> {
+        Ok(paths
+            .iter()
+            .map(|&path| {
+                let exists = self
+                    .resolve(CONTENT_DIR, path)
+                    .map(|file| file.is_file())
+                    .unwrap_or(false);
+                (path.to_string(), exists)
+            })
+            .collect())
+    }
+
+    pub(super) fn list_directory(&self, dir: &str) -> Result<DirectoryListing, Error> {
+        let dir = dir.trim_end_matches('/');
+        let content_dir = if dir.is_empty() {
+            self.root.join(CONTENT_DIR)
+        } else {
+            match self.resolve(CONTENT_DIR, dir) {
+                Ok(content_dir) => content_dir,
+                Err(PathNotFoundError) => return Ok(DirectoryListing::default()),
+            }
+        };
+        let entries = match fs::read_dir(content_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(DirectoryListing::default())
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut listing = DirectoryListing::default();
+        for entry in entries {
+            let entry = entry?;
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                // Only UTF-8 paths can be stored
+                Err(_) => continue,
+            };
+            if entry.file_type()?.is_dir() {
+                listing.directories.push(name);
+            } else {
+                listing.files.push(name);
+            }
+        }
+        listing.directories.sort();
+        listing.files.sort();
+
+        Ok(listing)
+    }
+
+    /// Returns the paths of all the files starting with `prefix`.
+    fn files_with_prefix(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        // Only the deepest directory containing all the matching files has to be walked
+        let dir = prefix.rfind('/').map(|idx| &prefix[..idx]).unwrap_or("");
+        let content_dir = if dir.is_empty() {
+            self.root.join(CONTENT_DIR)
+        } else {
+            match self.resolve(CONTENT_DIR, dir) {
+                Ok(content_dir) => content_dir,
+                Err(PathNotFoundError) => return Ok(Vec::new()),
+            }
+        };
+        if !content_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        Ok(get_file_list(&content_dir)?
+            .into_iter()
+            .map(|file| {
+                let file = file.to_slash().unwrap();
+                if dir.is_empty() {
+                    file
+                } else {
+                    format!("{}/{}", dir, file)
+                }
+            })
+            .filter(|path| path.starts_with(prefix))
+            .collect())
+    }
+
+    pub(super) fn prefix_size(&self, prefix: &str) -> Result<u64, Error> {
+        let mut size = 0;
+        for path in self.files_with_prefix(prefix)? {
+            size += fs::metadata(self.resolve(CONTENT_DIR, &path)?)?.len();
+        }
+        Ok(size)
+    }
+
+    /// Deletes all the files whose path starts with `prefix`, returning how many were deleted.
+    pub(super) fn delete_prefix(&self, prefix: &str) -> Result<usize, Error> {
+        let dir = prefix.rfind('/').map(|idx| &prefix[..idx]).unwrap_or("");
+
+        let mut deleted = 0;
+        for path in self.files_with_prefix(prefix)? {
+            fs::remove_file(self.resolve(CONTENT_DIR, &path)?)?;
+            match fs::remove_file(self.resolve(METADATA_DIR, &path)?) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+            deleted += 1;
+        }
+
+        // The whole directory matched, remove it instead of leaving it empty
+        if prefix.ends_with('/') && !dir.is_empty() {
+            for base in &[CONTENT_DIR, METADATA_DIR] {
+                match self.resolve(base, dir) {
+                    Ok(path) if path.is_dir() => fs::remove_dir_all(path)?,
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    pub(super) fn store_stream(
+        &self,
+        path: &str,
+        content_type: &str,
+        mut reader: impl Read,
+    ) -> Result<(), Error> {
+        let content_path = self.resolve(CONTENT_DIR, path)?;
+        create_parent(&content_path)?;
+        io::copy(&mut reader, &mut fs::File::create(content_path)?)?;
+
+        self.write_metadata(path, content_type, None)
+    }
+
+    pub(super) fn store_batch(&self, batch: &[Blob]) -> Result<(), Error> {
+        for blob in batch {
+            let content_path = self.resolve(CONTENT_DIR, &blob.path)?;
+            create_parent(&content_path)?;
+            fs::write(content_path, &blob.content)?;
+
+            self.write_metadata(&blob.path, &blob.mime, blob.compression)?;
+        }
+        Ok(())
+    }
+
+    fn write_metadata(
+        &self,
+        path: &str,
+        mime: &str,
+        compression: Option<CompressionAlgorithm>,
+    ) -> Result<(), Error> {
+        let metadata = Metadata {
+            mime: mime.into(),
+            compression: compression.map(|alg| alg as i32),
+        };
+        let metadata_path = self.resolve(METADATA_DIR, path)?;
+        create_parent(&metadata_path)?;
+        fs::write(metadata_path, serde_json::to_vec(&metadata)?)?;
+        Ok(())
+    }
+}
+
+fn create_parent(path: &Path) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageKind;
+    use crate::test::wrapper;
+
+    fn blob(path: &str, content: &[u8]) -> Blob {
+        Blob {
+            path: path.into(),
+            mime: "text/plain".into(),
+            date_updated: Utc::now(),
+            content: content.into(),
+            compression: None,
+        }
+    }
+
+    #[test]
+    fn test_get() -> Result<(), Error> {
+        let root = tempfile::tempdir()?;
+        let backend = FsBackend::new(root.path());
+        backend.store_batch(&[blob("dir/foo.txt", b"Hello world!")])?;
+
+        let stored = backend.get("dir/foo.txt", usize::MAX)?;
+        assert_eq!(stored.path, "dir/foo.txt");
+        assert_eq!(stored.mime, "text/plain");
+        assert_eq!(stored.content, b"Hello world!");
+        assert_eq!(stored.compression, None);
+
+        for missing in &["dir/bar.txt", "foo.txt", "dir"] {
+            assert!(backend
+                .get(missing, usize::MAX)
+                .unwrap_err()
+                .downcast_ref::<PathNotFoundError>()
+                .is_some());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_too_big() -> Result<(), Error> {
+        const MAX_SIZE: usize = 1024;
+
+        let root = tempfile::tempdir()?;
+        let backend = FsBackend::new(root.path());
+        backend.store_batch(&[
+            blob("small-blob.bin", &[0; MAX_SIZE]),
+            blob("big-blob.bin", &[0; MAX_SIZE * 2]),
+        ])?;
+
+        assert_eq!(
+            backend.get("small-blob.bin", MAX_SIZE)?.content.len(),
+            MAX_SIZE
+        );
+        assert!(backend
+            .get("big-blob.bin", MAX_SIZE)
+            .unwrap_err()
+            .downcast_ref::<std::io::Error>()
+            .and_then(|io| io.get_ref())
+            .and_then(|err| err.downcast_ref::<crate::error::SizeLimitReached>())
+            .is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_paths_outside_root() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().join("storage");
+        let backend = FsBackend::new(&root);
+        fs::write(dir.path().join("secret.txt"), "secret")?;
+
+        for path in &[
+            "../secret.txt",
+            "/secret.txt",
+            "dir/../../../secret.txt",
+            "",
+        ] {
+            assert!(backend
+                .get(path, usize::MAX)
+                .unwrap_err()
+                .downcast_ref::<PathNotFoundError>()
+                .is_some());
+            assert!(!backend.exists_many(&[path])?[*path]);
+            assert!(backend.store_batch(&[blob(path, b"overwritten")]).is_err());
+        }
+        assert_eq!(fs::read_to_string(dir.path().join("secret.txt"))?, "secret");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_docs_from_filesystem() {
+        wrapper(|env| {
+            let root = tempfile::tempdir()?;
+            env.override_config(|config| {
+                config.storage_backend = StorageKind::Filesystem(root.path().into());
+            });
+            env.db()
+                .fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html", b"<p>Some documentation</p>")
+                .create()?;
+
+            assert!(root
+                .path()
+                .join("content/rustdoc/dummy/0.1.0/dummy/index.html")
+                .is_file());
+            let resp = env.frontend().get("/dummy/0.1.0/dummy/index.html").send()?;
+            assert!(resp.status().is_success());
+            assert!(resp.text()?.contains("<p>Some documentation</p>"));
+
+            Ok(())
+        });
+    }
+}
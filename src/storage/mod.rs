@@ -1,22 +1,33 @@
 mod database;
+mod fs;
 pub(crate) mod s3;
 
 pub(crate) use self::database::DatabaseBackend;
+pub(crate) use self::fs::FsBackend;
 pub(crate) use self::s3::S3Backend;
 use chrono::{DateTime, Utc};
-use failure::{err_msg, Error};
+use failure::{err_msg, Error, Fail};
+use log::warn;
 use path_slash::PathExt;
 use postgres::{transaction::Transaction, Connection};
+use serde::Serialize;
 use std::{
-    collections::{HashMap, HashSet},
+    borrow::Cow,
+    collections::{BTreeSet, HashMap, HashSet},
     ffi::OsStr,
-    fmt, fs,
+    fmt,
     io::Read,
     path::{Path, PathBuf},
 };
 
 const MAX_CONCURRENT_UPLOADS: usize = 1000;
-const DEFAULT_COMPRESSION: CompressionAlgorithm = CompressionAlgorithm::Zstd;
+/// Files bigger than this are streamed to the storage uncompressed instead of being buffered in
+/// memory, to avoid running out of memory while uploading huge crates.
+const MAX_BUFFERED_FILE_SIZE: u64 = 50 * 1024 * 1024;
+pub(crate) const DEFAULT_COMPRESSION: CompressionAlgorithm = CompressionAlgorithm::Zstd;
+/// Build logs bigger than this are truncated when stored, keeping their end as it usually
+/// contains the errors that made the build fail.
+pub(crate) const MAX_BUILD_LOG_SIZE: usize = 5 * 1024 * 1024;
 
 pub type CompressionAlgorithms = HashSet<CompressionAlgorithm>;
 
@@ -43,10 +54,12 @@ macro_rules! enum_id {
         impl std::str::FromStr for CompressionAlgorithm {
             type Err = ();
             fn from_str(s: &str) -> Result<Self, Self::Err> {
-                match s {
-                    $(stringify!($variant) => Ok(Self::$variant),)*
-                    _ => Err(()),
-                }
+                $(
+                    if s.eq_ignore_ascii_case(stringify!($variant)) {
+                        return Ok(Self::$variant);
+                    }
+                )*
+                Err(())
             }
         }
 
@@ -65,9 +78,34 @@ macro_rules! enum_id {
 enum_id! {
     pub enum CompressionAlgorithm {
         Zstd = 0,
+        Gzip = 1,
+    }
+}
+
+impl CompressionAlgorithm {
+    /// The token of the algorithm in the `Content-Encoding` header.
+    pub(crate) fn content_encoding(self) -> String {
+        self.to_string().to_ascii_lowercase()
     }
 }
 
+/// The backend where the files are stored, selected through the configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StorageKind {
+    /// The `files` table of the database.
+    Database,
+    /// The S3 bucket configured through the `AWS_*` and `S3_*` environment variables. The keys
+    /// of the objects start with `prefix` when it's not empty, so that multiple instances can
+    /// share a bucket.
+    S3 { prefix: String },
+    /// A directory on the local filesystem.
+    Filesystem(PathBuf),
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "the path is not present in the storage")]
+pub(crate) struct PathNotFoundError;
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) struct Blob {
     pub(crate) path: String,
@@ -77,6 +115,37 @@ pub(crate) struct Blob {
     pub(crate) compression: Option<CompressionAlgorithm>,
 }
 
+/// The files and directories directly inside a directory of the storage, sorted by name.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct DirectoryListing {
+    pub(crate) directories: Vec<String>,
+    pub(crate) files: Vec<String>,
+}
+
+impl DirectoryListing {
+    /// Builds the listing of `dir` (ending with `/`) out of the paths of all the files inside it,
+    /// at any depth.
+    fn from_paths(dir: &str, paths: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let mut directories = BTreeSet::new();
+        let mut files = BTreeSet::new();
+        for path in paths {
+            let rest = match path.as_ref().strip_prefix(dir) {
+                Some(rest) => rest,
+                None => continue,
+            };
+            match rest.find('/') {
+                Some(idx) => directories.insert(rest[..idx].to_string()),
+                None => files.insert(rest.to_string()),
+            };
+        }
+
+        Self {
+            directories: directories.into_iter().collect(),
+            files: files.into_iter().collect(),
+        }
+    }
+}
+
 fn get_file_list_from_dir<P: AsRef<Path>>(path: P, files: &mut Vec<PathBuf>) -> Result<(), Error> {
     let path = path.as_ref();
 
@@ -115,20 +184,28 @@ pub fn get_file_list<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Error> {
 pub(crate) enum Storage<'a> {
     Database(DatabaseBackend<'a>),
     S3(S3Backend<'a>),
+    Filesystem(FsBackend),
 }
 
 impl<'a> Storage<'a> {
-    pub(crate) fn new(conn: &'a Connection) -> Self {
-        if let Some(c) = s3::s3_client() {
-            Storage::from(S3Backend::new(c, s3::S3_BUCKET_NAME))
-        } else {
-            DatabaseBackend::new(conn).into()
+    pub(crate) fn new(conn: &'a Connection, kind: &StorageKind) -> Self {
+        match kind {
+            StorageKind::Database => DatabaseBackend::new(conn).into(),
+            StorageKind::S3 { prefix } => match s3::s3_client() {
+                Some(c) => Storage::from(S3Backend::new(c, s3::S3_BUCKET_NAME, prefix.clone())),
+                None => {
+                    warn!("failed to create the S3 client, falling back to the database storage");
+                    DatabaseBackend::new(conn).into()
+                }
+            },
+            StorageKind::Filesystem(root) => FsBackend::new(root).into(),
         }
     }
     pub(crate) fn get(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
         let mut blob = match self {
             Self::Database(db) => db.get(path, max_size),
             Self::S3(s3) => s3.get(path, max_size),
+            Self::Filesystem(fs) => fs.get(path, max_size),
         }?;
         if let Some(alg) = blob.compression {
             blob.content = decompress(blob.content.as_slice(), alg, max_size)?;
@@ -137,17 +214,122 @@ impl<'a> Storage<'a> {
         Ok(blob)
     }
 
+    /// Checks which of the paths exist in the storage, with as few round trips as possible.
+    pub(crate) fn exists_many(&self, paths: &[&str]) -> Result<HashMap<String, bool>, Error> {
+        match self {
+            Self::Database(db) => db.exists_many(paths),
+            Self::S3(s3) => s3.exists_many(paths),
+            Self::Filesystem(fs) => fs.exists_many(paths),
+        }
+    }
+
+    /// Lists the files and directories directly inside `dir`, which must end with `/`. Missing
+    /// directories are empty.
+    pub(crate) fn list_directory(&self, dir: &str) -> Result<DirectoryListing, Error> {
+        debug_assert!(dir.ends_with('/'), "{} is not a directory", dir);
+        match self {
+            Self::Database(db) => db.list_directory(dir),
+            Self::S3(s3) => s3.list_directory(dir),
+            Self::Filesystem(fs) => fs.list_directory(dir),
+        }
+    }
+
+    /// Returns the total size in bytes of the files whose path starts with `prefix`, as stored
+    /// (after compression).
+    pub(crate) fn prefix_size(&self, prefix: &str) -> Result<u64, Error> {
+        match self {
+            Self::Database(db) => db.prefix_size(prefix),
+            Self::S3(s3) => s3.prefix_size(prefix),
+            Self::Filesystem(fs) => fs.prefix_size(prefix),
+        }
+    }
+
+    /// Deletes all the files whose path starts with `prefix`, returning how many were deleted.
+    pub(crate) fn delete_prefix(&mut self, prefix: &str) -> Result<usize, Error> {
+        match self {
+            Self::Database(db) => db.delete_prefix(prefix),
+            Self::S3(s3) => s3.delete_prefix(prefix),
+            Self::Filesystem(fs) => fs.delete_prefix(prefix),
+        }
+    }
+
+    /// Stores the content of the reader as-is, without buffering it all in memory when the
+    /// backend supports it.
+    pub(crate) fn store_stream(
+        &mut self,
+        path: &str,
+        content_type: &str,
+        reader: impl Read,
+    ) -> Result<(), Error> {
+        match self {
+            Self::Database(db) => db.store_stream(path, content_type, reader),
+            Self::S3(s3) => s3.store_stream(path, content_type, reader),
+            Self::Filesystem(fs) => fs.store_stream(path, content_type, reader),
+        }
+    }
+
+    /// Stores the log of a build, truncating it to `MAX_BUILD_LOG_SIZE`.
+    pub(crate) fn store_build_log(
+        &mut self,
+        name: &str,
+        version: &str,
+        build_id: i32,
+        log: &str,
+    ) -> Result<(), Error> {
+        let log = cap_build_log(log, MAX_BUILD_LOG_SIZE);
+        self.store_stream(
+            &build_log_path(name, version, build_id),
+            "text/plain",
+            log.as_bytes(),
+        )
+    }
+
+    /// Returns the stored log of a build, failing with `PathNotFoundError` if it doesn't exist.
+    pub(crate) fn get_build_log(
+        &self,
+        name: &str,
+        version: &str,
+        build_id: i32,
+    ) -> Result<String, Error> {
+        let blob = self.get(&build_log_path(name, version, build_id), MAX_BUILD_LOG_SIZE)?;
+        Ok(String::from_utf8_lossy(&blob.content).into_owned())
+    }
+
+    /// Stores the JSON output of rustdoc for a release.
+    pub(crate) fn store_rustdoc_json(
+        &mut self,
+        name: &str,
+        version: &str,
+        json: impl Read,
+    ) -> Result<(), Error> {
+        self.store_stream(&rustdoc_json_path(name, version), "application/json", json)
+    }
+
+    /// Returns the stored rustdoc JSON of a release, failing with `PathNotFoundError` if it
+    /// wasn't stored.
+    pub(crate) fn get_rustdoc_json(
+        &self,
+        name: &str,
+        version: &str,
+        max_size: usize,
+    ) -> Result<Blob, Error> {
+        self.get(&rustdoc_json_path(name, version), max_size)
+    }
+
     fn store_batch(&mut self, batch: &[Blob], trans: &Transaction) -> Result<(), Error> {
         match self {
             Self::Database(db) => db.store_batch(batch, trans),
             Self::S3(s3) => s3.store_batch(batch),
+            Self::Filesystem(fs) => fs.store_batch(batch),
         }
     }
 
     // Store all files in `root_dir` into the backend under `prefix`.
     //
-    // If the environment is configured with S3 credentials, this will upload to S3;
-    // otherwise, this will store files in the database.
+    // Depending on the configured storage kind, this will upload to S3, store files in the
+    // database or write them to a local directory. Files are compressed with the given
+    // algorithm, or stored as-is if it's `None`. Files bigger than `MAX_BUFFERED_FILE_SIZE` are
+    // always streamed as-is.
     //
     // This returns (map<filename, mime type>, set<compression algorithms>).
     pub(crate) fn store_all(
@@ -155,10 +337,12 @@ impl<'a> Storage<'a> {
         conn: &Connection,
         prefix: &str,
         root_dir: &Path,
+        compression: Option<CompressionAlgorithm>,
     ) -> Result<(HashMap<PathBuf, String>, HashSet<CompressionAlgorithm>), Error> {
         let trans = conn.transaction()?;
         let mut file_paths_and_mimes = HashMap::new();
         let mut algs = HashSet::with_capacity(1);
+        let mut large_files = Vec::new();
 
         let mut blobs = get_file_list(root_dir)?
             .into_iter()
@@ -166,24 +350,44 @@ impl<'a> Storage<'a> {
                 // Some files have insufficient permissions
                 // (like .lock file created by cargo in documentation directory).
                 // Skip these files.
-                fs::File::open(root_dir.join(&file_path))
+                std::fs::File::open(root_dir.join(&file_path))
                     .ok()
                     .map(|file| (file_path, file))
             })
-            .map(|(file_path, file)| -> Result<_, Error> {
-                let alg = DEFAULT_COMPRESSION;
-                let content = compress(file, alg)?;
+            .filter_map(|(file_path, file)| {
+                let is_large = file
+                    .metadata()
+                    .map(|metadata| metadata.len() > MAX_BUFFERED_FILE_SIZE)
+                    .unwrap_or(false);
+                if is_large {
+                    large_files.push((file_path, file));
+                    None
+                } else {
+                    Some((file_path, file))
+                }
+            })
+            .map(|(file_path, mut file)| -> Result<_, Error> {
+                let content = match compression {
+                    Some(alg) => {
+                        algs.insert(alg);
+                        compress(file, alg)?
+                    }
+                    None => {
+                        let mut content = Vec::new();
+                        file.read_to_end(&mut content)?;
+                        content
+                    }
+                };
                 let bucket_path = Path::new(prefix).join(&file_path).to_slash().unwrap();
 
                 let mime = detect_mime(&file_path)?;
                 file_paths_and_mimes.insert(file_path, mime.to_string());
-                algs.insert(alg);
 
                 Ok(Blob {
                     path: bucket_path,
                     mime: mime.to_string(),
                     content,
-                    compression: Some(alg),
+                    compression,
                     // this field is ignored by the backend
                     date_updated: Utc::now(),
                 })
@@ -199,15 +403,51 @@ impl<'a> Storage<'a> {
             self.store_batch(&batch, &trans)?;
         }
 
+        for (file_path, file) in large_files {
+            let bucket_path = Path::new(prefix).join(&file_path).to_slash().unwrap();
+            let mime = detect_mime(&file_path)?;
+            self.store_stream(&bucket_path, mime, file)?;
+            file_paths_and_mimes.insert(file_path, mime.to_string());
+        }
+
         trans.commit()?;
         Ok((file_paths_and_mimes, algs))
     }
 }
 
+fn build_log_path(name: &str, version: &str, build_id: i32) -> String {
+    format!("build-logs/{}/{}/{}.txt", name, version, build_id)
+}
+
+fn rustdoc_json_path(name: &str, version: &str) -> String {
+    format!("rustdoc-json/{}/{}.json", name, version)
+}
+
+const TRUNCATED_LOG_MARKER: &str = "[log truncated]\n";
+
+/// Drops the start of the log if it's bigger than `max_size`, replacing it with a marker.
+fn cap_build_log(log: &str, max_size: usize) -> Cow<'_, str> {
+    if log.len() <= max_size {
+        return Cow::Borrowed(log);
+    }
+
+    let mut start = log.len() - max_size.saturating_sub(TRUNCATED_LOG_MARKER.len());
+    while !log.is_char_boundary(start) {
+        start += 1;
+    }
+    Cow::Owned(format!("{}{}", TRUNCATED_LOG_MARKER, &log[start..]))
+}
+
 // public for benchmarking
 pub fn compress(content: impl Read, algorithm: CompressionAlgorithm) -> Result<Vec<u8>, Error> {
     match algorithm {
         CompressionAlgorithm::Zstd => Ok(zstd::encode_all(content, 9)?),
+        CompressionAlgorithm::Gzip => {
+            let mut encoder = flate2::read::GzEncoder::new(content, flate2::Compression::best());
+            let mut compressed = Vec::new();
+            encoder.read_to_end(&mut compressed)?;
+            Ok(compressed)
+        }
     }
 }
 
@@ -221,31 +461,40 @@ pub fn decompress(
 
     match algorithm {
         CompressionAlgorithm::Zstd => zstd::stream::copy_decode(content, &mut buffer)?,
+        CompressionAlgorithm::Gzip => {
+            std::io::copy(&mut flate2::read::GzDecoder::new(content), &mut buffer)?;
+        }
     }
 
     Ok(buffer.into_inner())
 }
 
-fn detect_mime(file_path: &Path) -> Result<&'static str, Error> {
-    let mime = mime_guess::from_path(file_path)
-        .first_raw()
-        .map(|m| m)
-        .unwrap_or("text/plain");
-    Ok(match mime {
-        "text/plain" | "text/troff" | "text/x-markdown" | "text/x-rust" | "text/x-toml" => {
-            match file_path.extension().and_then(OsStr::to_str) {
-                Some("md") => "text/markdown",
-                Some("rs") => "text/rust",
-                Some("markdown") => "text/markdown",
-                Some("css") => "text/css",
-                Some("toml") => "text/toml",
-                Some("js") => "application/javascript",
-                Some("json") => "application/json",
-                _ => mime,
-            }
-        }
-        "image/svg" => "image/svg+xml",
-        _ => mime,
+/// Infers the content type of a file from its extension, so that browsers render the stored files
+/// instead of downloading them.
+///
+/// Files without an extension (like `README` or `LICENSE` in the crate sources) are assumed to be
+/// text, while files with an unknown extension default to `application/octet-stream`.
+pub(crate) fn detect_mime(file_path: &Path) -> Result<&'static str, Error> {
+    let extension = match file_path.extension().and_then(OsStr::to_str) {
+        Some(extension) => extension.to_ascii_lowercase(),
+        None => return Ok("text/plain"),
+    };
+    Ok(match extension.as_str() {
+        "md" | "markdown" => "text/markdown",
+        "rs" => "text/rust",
+        "toml" => "text/toml",
+        "lock" => "text/plain",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "json" => "application/json",
+        "svg" => "image/svg+xml",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        _ => mime_guess::from_ext(&extension)
+            .first_raw()
+            .unwrap_or("application/octet-stream"),
     })
 }
 
@@ -261,11 +510,38 @@ impl<'a> From<S3Backend<'a>> for Storage<'a> {
     }
 }
 
+impl<'a> From<FsBackend> for Storage<'a> {
+    fn from(fs: FsBackend) -> Self {
+        Self::Filesystem(fs)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::test::wrapper;
-    use std::env;
+    use std::{env, fs};
+
+    /// Runs the test against all the backends not requiring external services.
+    fn for_each_backend(f: impl Fn(&Connection, &mut Storage<'_>) -> Result<(), Error>) {
+        wrapper(|env| {
+            let db = env.db();
+            let conn = db.conn();
+            let root = tempfile::Builder::new()
+                .prefix("docs.rs-storage-test")
+                .tempdir()?;
+
+            let backends = vec![
+                Storage::Database(DatabaseBackend::new(&conn)),
+                Storage::Filesystem(FsBackend::new(root.path())),
+            ];
+            for mut backend in backends {
+                f(&conn, &mut backend)?;
+            }
+
+            Ok(())
+        });
+    }
 
     pub(crate) fn assert_blob_eq(blob: &Blob, actual: &Blob) {
         assert_eq!(blob.path, actual.path);
@@ -286,11 +562,10 @@ mod test {
             }
             fs::write(path, &blob.content).expect("failed to write to file");
         }
-        wrapper(|env| {
-            let db = env.db();
-            let conn = db.conn();
-            let mut backend = Storage::Database(DatabaseBackend::new(&conn));
-            let (stored_files, _algs) = backend.store_all(&conn, "", dir.path()).unwrap();
+        for_each_backend(|conn, backend| {
+            let (stored_files, _algs) = backend
+                .store_all(conn, "", dir.path(), Some(DEFAULT_COMPRESSION))
+                .unwrap();
             assert_eq!(stored_files.len(), blobs.len());
             for blob in blobs {
                 let name = Path::new(&blob.path);
@@ -306,7 +581,6 @@ mod test {
 
     #[test]
     fn test_uploads() {
-        use std::fs;
         let dir = tempfile::Builder::new()
             .prefix("docs.rs-upload-test")
             .tempdir()
@@ -319,11 +593,10 @@ mod test {
             }
             fs::write(path, "data").expect("failed to write to file");
         }
-        wrapper(|env| {
-            let db = env.db();
-            let conn = db.conn();
-            let mut backend = Storage::Database(DatabaseBackend::new(&conn));
-            let (stored_files, _algs) = backend.store_all(&conn, "rustdoc", dir.path()).unwrap();
+        for_each_backend(|conn, backend| {
+            let (stored_files, _algs) = backend
+                .store_all(conn, "rustdoc", dir.path(), Some(DEFAULT_COMPRESSION))
+                .unwrap();
             assert_eq!(stored_files.len(), files.len());
             for name in &files {
                 let name = Path::new(name);
@@ -351,6 +624,204 @@ mod test {
         })
     }
 
+    /// Content big enough to be uploaded in multiple parts, without repeating patterns hiding
+    /// misordered chunks.
+    pub(crate) fn large_content() -> Vec<u8> {
+        (0..12 * 1024 * 1024u32)
+            .map(|i| (i ^ (i >> 8) ^ (i >> 16)) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn test_store_stream() {
+        let content = large_content();
+        for_each_backend(|_conn, backend| {
+            backend.store_stream("dir/large.bin", "application/octet-stream", &content[..])?;
+
+            let blob = backend.get("dir/large.bin", usize::MAX)?;
+            assert_eq!(blob.mime, "application/octet-stream");
+            assert_eq!(blob.compression, None);
+            assert_eq!(blob.content.len(), content.len());
+            assert!(blob.content == content, "the stored content is different");
+
+            // Storing again replaces the previous content
+            backend.store_stream("dir/large.bin", "text/plain", &b"small"[..])?;
+            let blob = backend.get("dir/large.bin", usize::MAX)?;
+            assert_eq!(blob.mime, "text/plain");
+            assert_eq!(blob.content, b"small");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_delete_prefix() {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-upload-test")
+            .tempdir()
+            .unwrap();
+        for &file in &[
+            "foo_bar/1.0.0/index.html",
+            "foo_bar/1.0.0/foo_bar/index.html",
+            "foo_bar/1.0.0/foo_bar/struct.Foo.html",
+            "foo_bar/1.0.1/index.html",
+            "fooxbar/1.0.0/index.html",
+        ] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "data").unwrap();
+        }
+        for_each_backend(|conn, backend| {
+            backend.store_all(conn, "rustdoc", dir.path(), None)?;
+
+            assert_eq!(backend.delete_prefix("rustdoc/foo_bar/1.0.0/")?, 3);
+            assert_eq!(backend.delete_prefix("rustdoc/foo_bar/1.0.0/")?, 0);
+            // `_` must not match any character
+            assert_eq!(backend.delete_prefix("rustdoc/foo_bar/")?, 1);
+
+            let existing = backend.exists_many(&[
+                "rustdoc/foo_bar/1.0.0/index.html",
+                "rustdoc/foo_bar/1.0.0/foo_bar/index.html",
+                "rustdoc/foo_bar/1.0.0/foo_bar/struct.Foo.html",
+                "rustdoc/foo_bar/1.0.1/index.html",
+                "rustdoc/fooxbar/1.0.0/index.html",
+            ])?;
+            let expected = vec![
+                ("rustdoc/foo_bar/1.0.0/index.html".to_string(), false),
+                (
+                    "rustdoc/foo_bar/1.0.0/foo_bar/index.html".to_string(),
+                    false,
+                ),
+                (
+                    "rustdoc/foo_bar/1.0.0/foo_bar/struct.Foo.html".to_string(),
+                    false,
+                ),
+                ("rustdoc/foo_bar/1.0.1/index.html".to_string(), false),
+                ("rustdoc/fooxbar/1.0.0/index.html".to_string(), true),
+            ]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+            assert_eq!(existing, expected);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_list_directory() {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-upload-test")
+            .tempdir()
+            .unwrap();
+        for &file in &[
+            "Cargo.toml",
+            "src/lib.rs",
+            "src/foo/mod.rs",
+            "src/foo/bar.rs",
+            "src_files/data.txt",
+        ] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "data").unwrap();
+        }
+        for_each_backend(|conn, backend| {
+            backend.store_all(conn, "sources/foo/1.0.0", dir.path(), None)?;
+
+            assert_eq!(
+                backend.list_directory("sources/foo/1.0.0/")?,
+                DirectoryListing {
+                    directories: vec!["src".into(), "src_files".into()],
+                    files: vec!["Cargo.toml".into()],
+                }
+            );
+            assert_eq!(
+                backend.list_directory("sources/foo/1.0.0/src/")?,
+                DirectoryListing {
+                    directories: vec!["foo".into()],
+                    files: vec!["lib.rs".into()],
+                }
+            );
+            assert_eq!(
+                backend.list_directory("sources/foo/1.0.0/src/foo/")?,
+                DirectoryListing {
+                    directories: vec![],
+                    files: vec!["bar.rs".into(), "mod.rs".into()],
+                }
+            );
+            assert_eq!(
+                backend.list_directory("sources/foo/1.0.0/missing/")?,
+                DirectoryListing::default()
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_prefix_size() {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-upload-test")
+            .tempdir()
+            .unwrap();
+        for &(file, size) in &[
+            ("foo_bar/1.0.0/index.html", 10),
+            ("foo_bar/1.0.0/foo_bar/index.html", 20),
+            ("foo_bar/1.0.1/index.html", 40),
+            ("fooxbar/1.0.0/index.html", 80),
+        ] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, vec![b'a'; size]).unwrap();
+        }
+        for_each_backend(|conn, backend| {
+            backend.store_all(conn, "rustdoc", dir.path(), None)?;
+
+            assert_eq!(backend.prefix_size("rustdoc/foo_bar/1.0.0/")?, 30);
+            // `_` must not match any character
+            assert_eq!(backend.prefix_size("rustdoc/foo_bar/")?, 70);
+            assert_eq!(backend.prefix_size("rustdoc/")?, 150);
+            assert_eq!(backend.prefix_size("rustdoc/missing/")?, 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_exists_many() {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-upload-test")
+            .tempdir()
+            .unwrap();
+        for &file in &["Cargo.toml", "src/lib.rs", "src/foo/mod.rs"] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "data").unwrap();
+        }
+        for_each_backend(|conn, backend| {
+            backend.store_all(conn, "sources/foo/1.0.0", dir.path(), None)?;
+
+            let existing = backend.exists_many(&[
+                "sources/foo/1.0.0/Cargo.toml",
+                "sources/foo/1.0.0/src/foo/mod.rs",
+                "sources/foo/1.0.0/src/main.rs",
+                "sources/foo/1.0.0/README.md",
+            ])?;
+            let expected = vec![
+                ("sources/foo/1.0.0/Cargo.toml".to_string(), true),
+                ("sources/foo/1.0.0/src/foo/mod.rs".to_string(), true),
+                ("sources/foo/1.0.0/src/main.rs".to_string(), false),
+                ("sources/foo/1.0.0/README.md".to_string(), false),
+            ]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+            assert_eq!(existing, expected);
+
+            assert!(backend.exists_many(&[])?.is_empty());
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn test_batched_uploads() {
         let uploads: Vec<_> = (0..=MAX_CONCURRENT_UPLOADS + 1)
@@ -440,6 +911,31 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_build_logs() {
+        for_each_backend(|_, storage| {
+            storage.store_build_log("foo", "0.1.0", 1, "build log")?;
+            assert_eq!(storage.get_build_log("foo", "0.1.0", 1)?, "build log");
+            assert!(storage
+                .get_build_log("foo", "0.1.0", 2)
+                .unwrap_err()
+                .downcast_ref::<PathNotFoundError>()
+                .is_some());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_cap_build_log() {
+        assert_eq!(cap_build_log("short log", 100), "short log");
+
+        let log = format!("{}error: failed to build", "é".repeat(100));
+        let capped = cap_build_log(&log, 64);
+        assert!(capped.len() <= 64);
+        assert!(capped.starts_with(TRUNCATED_LOG_MARKER));
+        assert!(capped.ends_with("error: failed to build"));
+    }
+
     #[test]
     fn test_mime_types() {
         check_mime(".gitignore", "text/plain");
@@ -455,6 +951,37 @@ mod test {
         check_mime("important.svg", "image/svg+xml");
     }
 
+    #[test]
+    fn test_mime_types_of_docs() {
+        for &(path, expected_mime) in &[
+            ("foo/index.html", "text/html"),
+            ("foo/struct.Foo.HTML", "text/html"),
+            ("rustdoc-20200101-1.44.0-nightly.css", "text/css"),
+            (
+                "search-index-20200101-1.44.0-nightly.js",
+                "application/javascript",
+            ),
+            (
+                "crates-20200101-1.44.0-nightly.js",
+                "application/javascript",
+            ),
+            ("settings.json", "application/json"),
+            ("wheel.svg", "image/svg+xml"),
+            ("rust-logo.png", "image/png"),
+            ("favicon.ico", "image/x-icon"),
+            ("FiraSans-Regular.woff", "font/woff"),
+            ("FiraSans-Regular.woff2", "font/woff2"),
+            ("SourceSerifPro-Regular.ttf", "font/ttf"),
+            ("SourceCodePro-Regular.otf", "font/otf"),
+            ("COPYRIGHT.txt", "text/plain"),
+            ("LICENSE", "text/plain"),
+            ("Cargo.lock", "text/plain"),
+            ("data.unknown-extension", "application/octet-stream"),
+        ] {
+            check_mime(path, expected_mime);
+        }
+    }
+
     fn check_mime(path: &str, expected_mime: &str) {
         let detected_mime = detect_mime(Path::new(&path));
         let detected_mime = detected_mime.expect("no mime was given");
@@ -465,12 +992,53 @@ mod test {
     fn test_compression_try_from_is_exhaustive() {
         use std::convert::TryFrom;
 
-        let a = CompressionAlgorithm::Zstd;
-        match a {
-            CompressionAlgorithm::Zstd => {
-                assert_eq!(a, CompressionAlgorithm::try_from(a as i32).unwrap());
-                assert_eq!(a, a.to_string().parse().unwrap());
+        for &a in CompressionAlgorithm::AVAILABLE {
+            match a {
+                CompressionAlgorithm::Zstd | CompressionAlgorithm::Gzip => {
+                    assert_eq!(a, CompressionAlgorithm::try_from(a as i32).unwrap());
+                    assert_eq!(a, a.to_string().parse().unwrap());
+                    assert_eq!(a, a.to_string().to_lowercase().parse().unwrap());
+                }
             }
         }
     }
+
+    #[test]
+    fn test_store_all_compression() {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-upload-test")
+            .tempdir()
+            .unwrap();
+        let content = "<p>Some documentation</p>\n".repeat(1000);
+        fs::write(dir.path().join("index.html"), &content).unwrap();
+
+        wrapper(|env| {
+            let db = env.db();
+            let conn = db.conn();
+
+            for &(prefix, compression) in
+                &[("gzip", Some(CompressionAlgorithm::Gzip)), ("none", None)]
+            {
+                let mut backend = Storage::Database(DatabaseBackend::new(&conn));
+                let (_files, algs) = backend.store_all(&conn, prefix, dir.path(), compression)?;
+                assert_eq!(algs, compression.into_iter().collect());
+
+                let path = format!("{}/index.html", prefix);
+                let stored = DatabaseBackend::new(&conn).get(&path, usize::MAX)?;
+                assert_eq!(stored.compression, compression);
+                if compression.is_some() {
+                    assert!(stored.content.len() < content.len());
+                } else {
+                    assert_eq!(stored.content, content.as_bytes());
+                }
+
+                // Reading through the storage decompresses the content transparently
+                let blob = backend.get(&path, usize::MAX)?;
+                assert_eq!(blob.compression, None);
+                assert_eq!(blob.content, content.as_bytes());
+            }
+
+            Ok(())
+        });
+    }
 }
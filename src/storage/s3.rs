@@ -1,12 +1,22 @@
-use super::Blob;
+use super::{Blob, CompressionAlgorithm, DirectoryListing};
 use chrono::{DateTime, NaiveDateTime, Utc};
 use failure::Error;
 use futures::Future;
 use log::{error, warn};
 use rusoto_core::region::Region;
+use rusoto_core::RusotoError;
 use rusoto_credential::DefaultCredentialsProvider;
-use rusoto_s3::{GetObjectRequest, PutObjectRequest, S3Client, S3};
-use std::convert::TryInto;
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, Delete, DeleteObjectsRequest, GetObjectRequest,
+    HeadObjectError, HeadObjectRequest, ListObjectsV2Request, ObjectIdentifier, PutObjectRequest,
+    S3Client, UploadPartRequest, S3,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    io::Read,
+};
 use tokio::runtime::Runtime;
 
 #[cfg(test)]
@@ -16,27 +26,43 @@ pub(crate) use test::TestS3;
 
 pub(crate) static S3_BUCKET_NAME: &str = "rust-docs-rs";
 
+/// Size of the parts of multipart uploads. S3 requires all the parts except the last one to be
+/// at least 5MB.
+const MULTIPART_CHUNK_SIZE: usize = 5 * 1024 * 1024;
+
 pub(crate) struct S3Backend<'a> {
     client: S3Client,
     bucket: &'a str,
+    /// Prepended to the keys of all the objects, unless it's empty
+    prefix: String,
     runtime: Runtime,
 }
 
 impl<'a> S3Backend<'a> {
-    pub(crate) fn new(client: S3Client, bucket: &'a str) -> Self {
+    pub(crate) fn new(client: S3Client, bucket: &'a str, prefix: String) -> Self {
         Self {
             client,
             bucket,
+            prefix,
             runtime: Runtime::new().unwrap(),
         }
     }
 
+    /// Returns the key of the object storing `path`, under the prefix if there's one.
+    fn key(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.into()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+
     pub(super) fn get(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
         let res = self
             .client
             .get_object(GetObjectRequest {
                 bucket: self.bucket.to_string(),
-                key: path.into(),
+                key: self.key(path),
                 ..Default::default()
             })
             .sync()?;
@@ -63,6 +89,310 @@ impl<'a> S3Backend<'a> {
         })
     }
 
+    /// Checks which of the paths exist in the bucket.
+    ///
+    /// If all the paths share a directory, its contents are listed instead of sending a request
+    /// per path.
+    pub(super) fn exists_many(&self, paths: &[&str]) -> Result<HashMap<String, bool>, Error> {
+        if paths.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let prefix = common_directory(paths);
+        if prefix.is_empty() {
+            return paths
+                .iter()
+                .map(|&path| Ok((path.to_string(), self.exists(path)?)))
+                .collect();
+        }
+
+        let mut existing = HashSet::new();
+        let mut continuation_token = None;
+        loop {
+            let res = self
+                .client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.bucket.to_string(),
+                    prefix: Some(self.key(prefix)),
+                    continuation_token: continuation_token.take(),
+                    ..Default::default()
+                })
+                .sync()?;
+
+            existing.extend(
+                res.contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|object| object.key),
+            );
+
+            match res.next_continuation_token {
+                Some(token) if res.is_truncated.unwrap_or(false) => {
+                    continuation_token = Some(token)
+                }
+                _ => break,
+            }
+        }
+
+        Ok(paths
+            .iter()
+            .map(|&path| (path.to_string(), existing.contains(&self.key(path))))
+            .collect())
+    }
+
+    /// Lists the objects and "directories" directly inside `dir`, letting S3 group the objects of
+    /// the subdirectories.
+    pub(super) fn list_directory(&self, dir: &str) -> Result<DirectoryListing, Error> {
+        let mut directories = Vec::new();
+        let mut files = Vec::new();
+        let mut continuation_token = None;
+        let dir_key = self.key(dir);
+        loop {
+            let res = self
+                .client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.bucket.to_string(),
+                    prefix: Some(dir_key.clone()),
+                    delimiter: Some("/".into()),
+                    continuation_token: continuation_token.take(),
+                    ..Default::default()
+                })
+                .sync()?;
+
+            directories.extend(
+                res.common_prefixes
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|prefix| prefix.prefix)
+                    .map(|prefix| prefix[dir_key.len()..].trim_end_matches('/').to_string()),
+            );
+            files.extend(
+                res.contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|object| object.key)
+                    .map(|key| key[dir_key.len()..].to_string()),
+            );
+
+            match res.next_continuation_token {
+                Some(token) if res.is_truncated.unwrap_or(false) => {
+                    continuation_token = Some(token)
+                }
+                _ => break,
+            }
+        }
+
+        directories.sort();
+        files.sort();
+        Ok(DirectoryListing { directories, files })
+    }
+
+    pub(super) fn prefix_size(&self, prefix: &str) -> Result<u64, Error> {
+        let mut size = 0;
+        let mut continuation_token = None;
+        loop {
+            let res = self
+                .client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.bucket.to_string(),
+                    prefix: Some(self.key(prefix)),
+                    continuation_token: continuation_token.take(),
+                    ..Default::default()
+                })
+                .sync()?;
+
+            size += res
+                .contents
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|object| object.size)
+                .sum::<i64>() as u64;
+
+            match res.next_continuation_token {
+                Some(token) if res.is_truncated.unwrap_or(false) => {
+                    continuation_token = Some(token)
+                }
+                _ => return Ok(size),
+            }
+        }
+    }
+
+    /// Deletes all the objects whose path starts with `prefix`, returning how many were deleted.
+    pub(super) fn delete_prefix(&self, prefix: &str) -> Result<usize, Error> {
+        let mut deleted = 0;
+        let mut continuation_token = None;
+        loop {
+            let list = self
+                .client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.bucket.to_string(),
+                    prefix: Some(self.key(prefix)),
+                    continuation_token: continuation_token.take(),
+                    ..Default::default()
+                })
+                .sync()?;
+
+            // Each page contains at most 1000 objects, the limit of a single DeleteObjects call
+            let to_delete = list
+                .contents
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|object| object.key)
+                .map(|key| ObjectIdentifier {
+                    key,
+                    version_id: None,
+                })
+                .collect::<Vec<_>>();
+            if !to_delete.is_empty() {
+                let count = to_delete.len();
+                let resp = self
+                    .client
+                    .delete_objects(DeleteObjectsRequest {
+                        bucket: self.bucket.to_string(),
+                        delete: Delete {
+                            objects: to_delete,
+                            quiet: Some(true),
+                        },
+                        ..Default::default()
+                    })
+                    .sync()?;
+                if let Some(errs) = resp.errors.filter(|errs| !errs.is_empty()) {
+                    for err in &errs {
+                        error!("error deleting file from s3: {:?}", err);
+                    }
+                    failure::bail!("deleting from s3 failed");
+                }
+                deleted += count;
+            }
+
+            match list.next_continuation_token {
+                Some(token) if list.is_truncated.unwrap_or(false) => {
+                    continuation_token = Some(token)
+                }
+                _ => return Ok(deleted),
+            }
+        }
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, Error> {
+        let res = self
+            .client
+            .head_object(HeadObjectRequest {
+                bucket: self.bucket.to_string(),
+                key: self.key(path),
+                ..Default::default()
+            })
+            .sync();
+
+        match res {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(false),
+            // HEAD responses have no body, so a missing key is usually reported as a bare 404
+            Err(RusotoError::Unknown(ref http)) if http.status == 404 => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Uploads the content of the reader without buffering it all in memory. Content smaller than
+    /// a single part is uploaded with one request, while bigger content is sent as a multipart
+    /// upload.
+    pub(super) fn store_stream(
+        &self,
+        path: &str,
+        content_type: &str,
+        mut reader: impl Read,
+    ) -> Result<(), Error> {
+        let first_part = read_part(&mut reader)?;
+        if first_part.len() < MULTIPART_CHUNK_SIZE {
+            self.client
+                .put_object(PutObjectRequest {
+                    bucket: self.bucket.to_string(),
+                    key: self.key(path),
+                    body: Some(first_part.into()),
+                    content_type: Some(content_type.into()),
+                    ..Default::default()
+                })
+                .sync()?;
+        } else {
+            let upload_id = self
+                .client
+                .create_multipart_upload(CreateMultipartUploadRequest {
+                    bucket: self.bucket.to_string(),
+                    key: self.key(path),
+                    content_type: Some(content_type.into()),
+                    ..Default::default()
+                })
+                .sync()?
+                .upload_id
+                .ok_or_else(|| failure::err_msg("S3 didn't return a multipart upload id"))?;
+
+            let parts = match self.upload_parts(path, &upload_id, first_part, reader) {
+                Ok(parts) => parts,
+                Err(err) => {
+                    // Parts of aborted uploads would otherwise be kept (and billed) forever
+                    let abort = self
+                        .client
+                        .abort_multipart_upload(AbortMultipartUploadRequest {
+                            bucket: self.bucket.to_string(),
+                            key: self.key(path),
+                            upload_id,
+                            ..Default::default()
+                        })
+                        .sync();
+                    if let Err(abort_err) = abort {
+                        error!("failed to abort the upload of {}: {:?}", path, abort_err);
+                    }
+                    return Err(err);
+                }
+            };
+
+            self.client
+                .complete_multipart_upload(CompleteMultipartUploadRequest {
+                    bucket: self.bucket.to_string(),
+                    key: self.key(path),
+                    upload_id,
+                    multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+                    ..Default::default()
+                })
+                .sync()?;
+        }
+
+        crate::web::metrics::UPLOADED_FILES_TOTAL.inc_by(1);
+        Ok(())
+    }
+
+    fn upload_parts(
+        &self,
+        path: &str,
+        upload_id: &str,
+        mut part: Vec<u8>,
+        mut reader: impl Read,
+    ) -> Result<Vec<CompletedPart>, Error> {
+        let mut parts = Vec::new();
+        while !part.is_empty() {
+            let part_number = parts.len() as i64 + 1;
+            let res = self
+                .client
+                .upload_part(UploadPartRequest {
+                    bucket: self.bucket.to_string(),
+                    key: self.key(path),
+                    upload_id: upload_id.into(),
+                    part_number,
+                    content_length: Some(part.len() as i64),
+                    body: Some(part.into()),
+                    ..Default::default()
+                })
+                .sync()?;
+            parts.push(CompletedPart {
+                e_tag: res.e_tag,
+                part_number: Some(part_number),
+            });
+
+            part = read_part(&mut reader)?;
+        }
+        Ok(parts)
+    }
+
     pub(super) fn store_batch(&mut self, batch: &[Blob]) -> Result<(), Error> {
         use futures::stream::FuturesUnordered;
         use futures::stream::Stream;
@@ -76,10 +406,12 @@ impl<'a> S3Backend<'a> {
                     self.client
                         .put_object(PutObjectRequest {
                             bucket: self.bucket.to_string(),
-                            key: blob.path.clone(),
+                            key: self.key(&blob.path),
                             body: Some(blob.content.clone().into()),
                             content_type: Some(blob.mime.clone()),
-                            content_encoding: blob.compression.as_ref().map(|alg| alg.to_string()),
+                            content_encoding: blob
+                                .compression
+                                .map(CompressionAlgorithm::content_encoding),
                             ..Default::default()
                         })
                         .inspect(|_| {
@@ -105,6 +437,40 @@ impl<'a> S3Backend<'a> {
     }
 }
 
+/// Reads the next part of a multipart upload, which is shorter than the chunk size only at the
+/// end of the content.
+fn read_part(reader: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let mut part = Vec::with_capacity(MULTIPART_CHUNK_SIZE);
+    reader
+        .take(MULTIPART_CHUNK_SIZE as u64)
+        .read_to_end(&mut part)?;
+    Ok(part)
+}
+
+/// Returns the longest directory (ending with `/`) containing all the paths, or an empty string
+/// if they don't share one.
+fn common_directory<'p>(paths: &[&'p str]) -> &'p str {
+    let first = match paths.first() {
+        Some(first) => *first,
+        None => return "",
+    };
+    let mut len = paths.iter().skip(1).fold(first.len(), |len, path| {
+        first[..len]
+            .bytes()
+            .zip(path.bytes())
+            .take_while(|(a, b)| a == b)
+            .count()
+    });
+    // only keep whole directories
+    while len > 0 && !first.is_char_boundary(len) {
+        len -= 1;
+    }
+    match first[..len].rfind('/') {
+        Some(idx) => &first[..=idx],
+        None => "",
+    }
+}
+
 fn parse_timespec(mut raw: &str) -> Result<DateTime<Utc>, Error> {
     raw = raw.trim_end_matches(" GMT");
 
@@ -114,13 +480,12 @@ fn parse_timespec(mut raw: &str) -> Result<DateTime<Utc>, Error> {
     ))
 }
 
-pub(crate) fn s3_client() -> Option<S3Client> {
-    // If AWS keys aren't configured, then presume we should use the DB exclusively
-    // for file storage.
-    if std::env::var_os("AWS_ACCESS_KEY_ID").is_none() && std::env::var_os("FORCE_S3").is_none() {
-        return None;
-    }
+/// Whether the environment is configured to store the files on S3.
+pub(crate) fn s3_configured() -> bool {
+    std::env::var_os("AWS_ACCESS_KEY_ID").is_some() || std::env::var_os("FORCE_S3").is_some()
+}
 
+pub(super) fn s3_client() -> Option<S3Client> {
     let creds = match DefaultCredentialsProvider::new() {
         Ok(creds) => creds,
         Err(err) => {
@@ -145,6 +510,7 @@ pub(crate) fn s3_client() -> Option<S3Client> {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::storage::{compress, decompress, CompressionAlgorithm};
     use crate::test::*;
     use chrono::TimeZone;
     use std::slice;
@@ -165,6 +531,166 @@ pub(crate) mod tests {
         assert!(parse_timespec("foo").is_err());
     }
 
+    #[test]
+    fn test_common_directory() {
+        assert_eq!(common_directory(&[]), "");
+        assert_eq!(common_directory(&["foo.txt"]), "");
+        assert_eq!(common_directory(&["dir/foo.txt"]), "dir/");
+        assert_eq!(common_directory(&["dir/foo.txt", "dir/bar.txt"]), "dir/");
+        assert_eq!(common_directory(&["dir/a/foo", "dir/ab/foo"]), "dir/");
+        assert_eq!(common_directory(&["dir/a/foo", "dir/a/bar/baz"]), "dir/a/");
+        assert_eq!(common_directory(&["dir/foo.txt", "other/foo.txt"]), "");
+    }
+
+    #[test]
+    fn test_exists_many() {
+        wrapper(|env| {
+            let blobs: Vec<_> = ["dir/a.txt", "dir/b.txt", "dir/sub/c.txt", "other.txt"]
+                .iter()
+                .map(|&path| Blob {
+                    path: path.into(),
+                    mime: "text/plain".into(),
+                    date_updated: Utc::now(),
+                    content: "Hello world!".into(),
+                    compression: None,
+                })
+                .collect();
+
+            let s3 = env.s3();
+            s3.upload(&blobs)?;
+            // all the paths share a directory, so a listing is used
+            let existing = s3.exists_many(&["dir/a.txt", "dir/sub/c.txt", "dir/missing.txt"])?;
+            assert_eq!(existing.len(), 3);
+            assert!(existing["dir/a.txt"]);
+            assert!(existing["dir/sub/c.txt"]);
+            assert!(!existing["dir/missing.txt"]);
+
+            // no common directory, every path is checked individually
+            let existing = s3.exists_many(&["dir/b.txt", "other.txt", "missing.txt"])?;
+            assert_eq!(existing.len(), 3);
+            assert!(existing["dir/b.txt"]);
+            assert!(existing["other.txt"]);
+            assert!(!existing["missing.txt"]);
+
+            assert!(s3.exists_many(&[])?.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_upload_files_infers_content_type() {
+        wrapper(|env| {
+            let s3 = env.s3();
+            s3.upload_files(&[
+                ("foo/index.html", b"<p>docs</p>"),
+                ("main.css", b"body {}"),
+                ("FiraSans-Regular.woff2", b"font"),
+                ("data.unknown-extension", b"data"),
+            ])?;
+
+            s3.with_client(|client| {
+                for &(path, mime) in &[
+                    ("foo/index.html", "text/html"),
+                    ("main.css", "text/css"),
+                    ("FiraSans-Regular.woff2", "font/woff2"),
+                    ("data.unknown-extension", "application/octet-stream"),
+                ] {
+                    assert_eq!(client.get(path, usize::MAX).unwrap().mime, mime);
+                }
+            });
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_delete_prefix() {
+        wrapper(|env| {
+            let s3 = env.s3();
+            s3.upload_files(&[
+                ("rustdoc/foo/1.0.0/index.html", b"docs"),
+                ("rustdoc/foo/1.0.0/foo/index.html", b"docs"),
+                ("rustdoc/foo/1.0.0/foo/struct.Foo.html", b"docs"),
+                ("rustdoc/foo/1.0.1/index.html", b"docs"),
+                ("rustdoc/foo/1.0.1/foo/index.html", b"docs"),
+            ])?;
+
+            assert_eq!(s3.delete_prefix("rustdoc/foo/1.0.0/")?, 3);
+            assert_eq!(s3.delete_prefix("rustdoc/foo/1.0.0/")?, 0);
+
+            let existing = s3.exists_many(&[
+                "rustdoc/foo/1.0.0/index.html",
+                "rustdoc/foo/1.0.0/foo/index.html",
+                "rustdoc/foo/1.0.0/foo/struct.Foo.html",
+                "rustdoc/foo/1.0.1/index.html",
+                "rustdoc/foo/1.0.1/foo/index.html",
+            ])?;
+            assert!(!existing["rustdoc/foo/1.0.0/index.html"]);
+            assert!(!existing["rustdoc/foo/1.0.0/foo/index.html"]);
+            assert!(!existing["rustdoc/foo/1.0.0/foo/struct.Foo.html"]);
+            assert!(existing["rustdoc/foo/1.0.1/index.html"]);
+            assert!(existing["rustdoc/foo/1.0.1/foo/index.html"]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_list_directory() {
+        wrapper(|env| {
+            let s3 = env.s3();
+            s3.upload_files(&[
+                ("sources/foo/1.0.0/Cargo.toml", b"data"),
+                ("sources/foo/1.0.0/src/lib.rs", b"data"),
+                ("sources/foo/1.0.0/src/foo/mod.rs", b"data"),
+            ])?;
+
+            assert_eq!(
+                s3.list_directory("sources/foo/1.0.0/")?,
+                DirectoryListing {
+                    directories: vec!["src".into()],
+                    files: vec!["Cargo.toml".into()],
+                }
+            );
+            assert_eq!(
+                s3.list_directory("sources/foo/1.0.0/src/")?,
+                DirectoryListing {
+                    directories: vec!["foo".into()],
+                    files: vec!["lib.rs".into()],
+                }
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_store_stream() {
+        wrapper(|env| {
+            let content = crate::storage::test::large_content();
+            let s3 = env.s3();
+
+            // uploaded in three parts
+            s3.store_stream("dir/large.bin", "application/octet-stream", &content[..])?;
+            // uploaded in a single request
+            s3.store_stream("dir/small.txt", "text/plain", &b"Hello world!"[..])?;
+
+            s3.with_client(|client| {
+                let blob = client.get("dir/large.bin", usize::MAX).unwrap();
+                assert_eq!(blob.mime, "application/octet-stream");
+                assert_eq!(blob.content.len(), content.len());
+                assert!(blob.content == content, "the stored content is different");
+
+                let blob = client.get("dir/small.txt", usize::MAX).unwrap();
+                assert_eq!(blob.mime, "text/plain");
+                assert_eq!(blob.content, b"Hello world!");
+            });
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_get() {
         wrapper(|env| {
@@ -263,6 +789,116 @@ pub(crate) mod tests {
         })
     }
 
+    #[test]
+    fn test_store_compressed() {
+        wrapper(|env| {
+            let content = "Hello world!\n".repeat(1000);
+            let alg = CompressionAlgorithm::Gzip;
+            let blob = Blob {
+                path: "dir/foo.txt".into(),
+                mime: "text/plain".into(),
+                date_updated: Utc::now(),
+                content: compress(content.as_bytes(), alg)?,
+                compression: Some(alg),
+            };
+
+            let s3 = env.s3();
+            s3.upload(slice::from_ref(&blob))?;
+            s3.with_client(|client| {
+                let stored = client.get("dir/foo.txt", usize::MAX).unwrap();
+                assert_eq!(stored.compression, Some(alg));
+                assert!(stored.content.len() < content.len());
+                assert_eq!(
+                    decompress(stored.content.as_slice(), alg, usize::MAX).unwrap(),
+                    content.as_bytes()
+                );
+            });
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_store_all_compression() {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-upload-test")
+            .tempdir()
+            .unwrap();
+        let content = "<p>Some documentation</p>\n".repeat(1000);
+        std::fs::write(dir.path().join("index.html"), &content).unwrap();
+
+        wrapper(|env| {
+            let db = env.db();
+            let conn = db.conn();
+            let s3 = env.s3();
+
+            let alg = CompressionAlgorithm::Gzip;
+            let mut storage = s3.storage();
+            let (_files, algs) = storage.store_all(&conn, "gzip", dir.path(), Some(alg))?;
+            assert_eq!(algs, Some(alg).into_iter().collect());
+
+            // The encoding is stored as the token of the header
+            assert_eq!(
+                s3.content_encoding("gzip/index.html")?.as_deref(),
+                Some("gzip")
+            );
+            s3.with_client(|client| {
+                let stored = client.get("gzip/index.html", usize::MAX).unwrap();
+                assert_eq!(stored.compression, Some(alg));
+                assert!(stored.content.len() < content.len());
+            });
+
+            // Reading through the storage decompresses the content transparently
+            let blob = storage.get("gzip/index.html", usize::MAX)?;
+            assert_eq!(blob.compression, None);
+            assert_eq!(blob.content, content.as_bytes());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_storage_prefix() {
+        wrapper(|_env| {
+            let s3 = TestS3::with_prefix("instance-a");
+            s3.store_stream("dir/foo.txt", "text/plain", &b"Hello world!"[..])?;
+
+            s3.with_client(|client| {
+                let blob = client.get("dir/foo.txt", usize::MAX).unwrap();
+                assert_eq!(blob.path, "dir/foo.txt");
+                assert_eq!(blob.content, b"Hello world!");
+
+                let keys = client
+                    .client
+                    .list_objects_v2(ListObjectsV2Request {
+                        bucket: client.bucket.to_string(),
+                        ..Default::default()
+                    })
+                    .sync()
+                    .unwrap()
+                    .contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|object| object.key)
+                    .collect::<Vec<_>>();
+                assert_eq!(keys, vec!["instance-a/dir/foo.txt"]);
+            });
+
+            assert_eq!(
+                s3.list_directory("dir/")?,
+                DirectoryListing {
+                    directories: Vec::new(),
+                    files: vec!["foo.txt".into()],
+                }
+            );
+            assert!(s3.exists_many(&["dir/foo.txt"])?["dir/foo.txt"]);
+            assert_eq!(s3.delete_prefix("dir/")?, 1);
+            s3.assert_404("dir/foo.txt");
+
+            Ok(())
+        });
+    }
+
     // NOTE: trying to upload a file ending with `/` will behave differently in test and prod.
     // NOTE: On s3, it will succeed and create a file called `/`.
     // NOTE: On min.io, it will fail with 'Object name contains unsupported characters.'
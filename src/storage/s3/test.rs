@@ -1,14 +1,18 @@
 use super::*;
-use crate::storage::test::assert_blob_eq;
+use crate::storage::{test::assert_blob_eq, Storage};
 use rusoto_s3::{
     CreateBucketRequest, DeleteBucketRequest, DeleteObjectRequest, ListObjectsRequest, S3,
 };
-use std::cell::RefCell;
+use std::{cell::RefCell, path::Path};
 
 pub(crate) struct TestS3(RefCell<S3Backend<'static>>);
 
 impl TestS3 {
     pub(crate) fn new() -> Self {
+        Self::with_prefix("")
+    }
+    /// Creates a test bucket whose object keys start with `prefix`, unless it's empty.
+    pub(crate) fn with_prefix(prefix: &str) -> Self {
         // A random bucket name is generated and used for the current connection.
         // This allows each test to create a fresh bucket to test with.
         let bucket = format!("docs-rs-test-bucket-{}", rand::random::<u64>());
@@ -21,11 +25,44 @@ impl TestS3 {
             .sync()
             .expect("failed to create test bucket");
         let bucket = Box::leak(bucket.into_boxed_str());
-        TestS3(RefCell::new(S3Backend::new(client, bucket)))
+        TestS3(RefCell::new(S3Backend::new(client, bucket, prefix.into())))
     }
     pub(crate) fn upload(&self, blobs: &[Blob]) -> Result<(), Error> {
         self.0.borrow_mut().store_batch(blobs)
     }
+    /// Uploads the files uncompressed, with the content type inferred from their extension.
+    pub(crate) fn upload_files(&self, files: &[(&str, &[u8])]) -> Result<(), Error> {
+        let blobs = files
+            .iter()
+            .map(|&(path, content)| {
+                Ok(Blob {
+                    path: path.into(),
+                    mime: crate::storage::detect_mime(Path::new(path))?.into(),
+                    date_updated: Utc::now(),
+                    content: content.into(),
+                    compression: None,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.upload(&blobs)
+    }
+    pub(crate) fn store_stream(
+        &self,
+        path: &str,
+        content_type: &str,
+        reader: impl std::io::Read,
+    ) -> Result<(), Error> {
+        self.0.borrow().store_stream(path, content_type, reader)
+    }
+    pub(crate) fn delete_prefix(&self, prefix: &str) -> Result<usize, Error> {
+        self.0.borrow().delete_prefix(prefix)
+    }
+    pub(crate) fn exists_many(&self, paths: &[&str]) -> Result<HashMap<String, bool>, Error> {
+        self.0.borrow().exists_many(paths)
+    }
+    pub(crate) fn list_directory(&self, dir: &str) -> Result<DirectoryListing, Error> {
+        self.0.borrow().list_directory(dir)
+    }
     pub(crate) fn assert_404(&self, path: &'static str) {
         use rusoto_core::RusotoError;
         use rusoto_s3::GetObjectError;
@@ -45,6 +82,26 @@ impl TestS3 {
         assert_blob_eq(blob, &actual);
     }
 
+    /// Returns a storage writing to the test bucket.
+    pub(crate) fn storage(&self) -> Storage<'static> {
+        let inner = self.0.borrow();
+        S3Backend::new(inner.client.clone(), inner.bucket, inner.prefix.clone()).into()
+    }
+
+    /// Returns the raw `Content-Encoding` of the object storing `path`.
+    pub(crate) fn content_encoding(&self, path: &str) -> Result<Option<String>, Error> {
+        let inner = self.0.borrow();
+        let res = inner
+            .client
+            .head_object(HeadObjectRequest {
+                bucket: inner.bucket.to_string(),
+                key: inner.key(path),
+                ..Default::default()
+            })
+            .sync()?;
+        Ok(res.content_encoding)
+    }
+
     pub(crate) fn with_client(&self, f: impl FnOnce(&mut S3Backend<'static>)) {
         f(&mut self.0.borrow_mut())
     }
@@ -1,7 +1,9 @@
 use super::TestDatabase;
+use crate::build_queue::QueuedCrate;
 use crate::docbuilder::BuildResult;
-use crate::index::api::RegistryCrateData;
+use crate::index::api::{CrateOwner, RegistryCrateData};
 use crate::utils::{Dependency, MetadataPackage, Target};
+use crate::BuildQueue;
 use chrono::{DateTime, Utc};
 use failure::Error;
 
@@ -14,7 +16,10 @@ pub(crate) struct FakeRelease<'a> {
     source_files: Vec<(&'a str, &'a [u8])>,
     /// name, content
     rustdoc_files: Vec<(&'a str, &'a [u8])>,
+    /// name, content, mime type
+    stored_files: Vec<(&'a str, &'a [u8], &'a str)>,
     doc_targets: Vec<String>,
+    failed_targets: Vec<String>,
     default_target: Option<&'a str>,
     registry_crate_data: RegistryCrateData,
     has_docs: bool,
@@ -49,10 +54,14 @@ impl<'a> FakeRelease<'a> {
                 docsrs_version: "docs.rs 1.0.0 (000000000 1970-01-01)".into(),
                 build_log: "It works!".into(),
                 successful: true,
+                features: Vec::new(),
+                cargo_args: vec!["doc".into(), "--lib".into(), "--no-deps".into()],
             },
             source_files: Vec::new(),
             rustdoc_files: Vec::new(),
+            stored_files: Vec::new(),
             doc_targets: Vec::new(),
+            failed_targets: Vec::new(),
             default_target: None,
             registry_crate_data: RegistryCrateData {
                 release_time: Utc::now(),
@@ -108,6 +117,31 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    pub(crate) fn build_log(mut self, log: impl Into<String>) -> Self {
+        self.build_result.build_log = log.into();
+        self
+    }
+
+    pub(crate) fn features(mut self, features: &[&str]) -> Self {
+        self.build_result.features = features.iter().map(|&feature| feature.into()).collect();
+        self
+    }
+
+    pub(crate) fn cargo_args(mut self, args: &[&str]) -> Self {
+        self.build_result.cargo_args = args.iter().map(|&arg| arg.into()).collect();
+        self
+    }
+
+    pub(crate) fn add_owner(mut self, login: &str) -> Self {
+        self.registry_crate_data.owners.push(CrateOwner {
+            avatar: format!("https://avatars.example.com/{}", login),
+            email: format!("{}@example.com", login),
+            login: login.into(),
+            name: login.into(),
+        });
+        self
+    }
+
     pub(crate) fn yanked(mut self, new: bool) -> Self {
         self.registry_crate_data.yanked = new;
         self
@@ -123,6 +157,13 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    /// Stores a source file as-is with the given mime type, bypassing the detection done when
+    /// uploading the other files.
+    pub(crate) fn add_file(mut self, path: &'a str, data: &'a [u8], mime: &'a str) -> Self {
+        self.stored_files.push((path, data, mime));
+        self
+    }
+
     pub(crate) fn default_target(mut self, target: &'a str) -> Self {
         self = self.add_target(target);
         self.default_target = Some(target);
@@ -134,6 +175,11 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    pub(crate) fn add_failed_target(mut self, target: &str) -> Self {
+        self.failed_targets.push(target.into());
+        self
+    }
+
     pub(crate) fn binary(mut self, bin: bool) -> Self {
         self.has_docs = !bin;
         if bin {
@@ -190,7 +236,13 @@ impl<'a> FakeRelease<'a> {
                     target.unwrap_or("")
                 );
                 log::debug!("adding directory {} from {}", prefix, path_prefix.display());
-                crate::db::add_path_into_database(&db.conn(), &prefix, path_prefix)
+                crate::db::add_path_into_database(
+                    &db.conn(),
+                    db.storage_backend(),
+                    &prefix,
+                    path_prefix,
+                    Some(crate::storage::DEFAULT_COMPRESSION),
+                )
             };
 
             let index = [&package.name, "index.html"].join("/");
@@ -207,12 +259,21 @@ impl<'a> FakeRelease<'a> {
             let (rustdoc_meta, new_algs) = upload_files("rustdoc", &rustdoc_files, None)?;
             algs.extend(new_algs);
             log::debug!("added rustdoc files {}", rustdoc_meta);
-            match upload_files("source", &self.source_files, None)? {
+            match upload_files("sources", &self.source_files, None)? {
                 (json, new_algs) => {
                     source_meta = Some(json);
                     algs.extend(new_algs);
                 }
             }
+            let conn = db.conn();
+            let mut storage = crate::storage::Storage::new(&conn, db.storage_backend());
+            for (path, data, mime) in &self.stored_files {
+                let full_path = format!("sources/{}/{}/{}", package.name, package.version, path);
+                storage.store_stream(&full_path, mime, *data)?;
+                if let Some(serde_json::Value::Array(files)) = source_meta.as_mut() {
+                    files.push(serde_json::json!([mime, path]));
+                }
+            }
             log::debug!("added source files {}", source_meta.as_ref().unwrap());
 
             for target in &package.targets[1..] {
@@ -222,21 +283,82 @@ impl<'a> FakeRelease<'a> {
             }
         }
 
+        let default_target = self.default_target.unwrap_or_else(|| db.default_target());
+        let targets = std::iter::once((default_target, self.build_result.successful))
+            .chain(
+                self.doc_targets
+                    .iter()
+                    .filter(|target| *target != default_target)
+                    .map(|target| (target.as_str(), true)),
+            )
+            .chain(
+                self.failed_targets
+                    .iter()
+                    .map(|target| (target.as_str(), false)),
+            )
+            .collect::<Vec<_>>();
+
         let release_id = crate::db::add_package_into_database(
             &db.conn(),
             &package,
             tempdir.path(),
             &self.build_result,
-            self.default_target.unwrap_or("x86_64-unknown-linux-gnu"),
+            default_target,
             source_meta,
-            self.doc_targets,
+            self.doc_targets.clone(),
             &self.registry_crate_data,
             self.has_docs,
             self.has_examples,
             HashSet::new(),
         )?;
+        crate::db::update_doc_size(
+            &db.conn(),
+            db.storage_backend(),
+            release_id,
+            &package.name,
+            &package.version,
+        )?;
+        crate::db::add_targets_into_database(&db.conn(), release_id, &targets)?;
         crate::db::add_build_into_database(&db.conn(), release_id, &self.build_result)?;
 
         Ok(release_id)
     }
 }
+
+/// Stand-in for the `RustwideBuilder` processing the build queue in the tests, which stores a
+/// fake release for each crate instead of building it with rustdoc.
+pub(crate) struct FakeBuilder<'a> {
+    db: &'a TestDatabase,
+    successful: bool,
+}
+
+impl<'a> FakeBuilder<'a> {
+    pub(super) fn new(db: &'a TestDatabase) -> Self {
+        FakeBuilder {
+            db,
+            successful: true,
+        }
+    }
+
+    /// Whether the following builds succeed, they do by default.
+    pub(crate) fn build_result_successful(mut self, new: bool) -> Self {
+        self.successful = new;
+        self
+    }
+
+    /// Stores the documentation of a crate, or a failed build of it.
+    pub(crate) fn build(&self, krate: &QueuedCrate) -> Result<(), Error> {
+        self.db
+            .fake_release()
+            .name(&krate.name)
+            .version(&krate.version)
+            .build_result_successful(self.successful)
+            .create()?;
+        Ok(())
+    }
+
+    /// Builds the next crate handed out by the build queue, if any.
+    pub(crate) fn build_next(&self, queue: &BuildQueue) -> Result<(), Error> {
+        queue.process_next_crate(|krate| self.build(krate))
+    }
+}
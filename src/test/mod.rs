@@ -1,19 +1,23 @@
 mod fakes;
 
 use crate::db::{Pool, PoolConnection};
-use crate::storage::s3::TestS3;
+use crate::storage::{s3::TestS3, StorageKind};
 use crate::web::Server;
 use crate::BuildQueue;
 use crate::Config;
 use failure::Error;
-use log::error;
-use once_cell::unsync::OnceCell;
+use log::{error, Level, Log, Metadata, Record};
+use once_cell::{sync::Lazy, unsync::OnceCell};
 use postgres::Connection;
 use reqwest::{
-    blocking::{Client, RequestBuilder},
+    blocking::{Client, RequestBuilder, Response},
     Method,
 };
-use std::{panic, sync::Arc};
+use serde::de::DeserializeOwned;
+use std::{
+    panic,
+    sync::{Arc, Mutex},
+};
 
 pub(crate) fn wrapper(f: impl FnOnce(&TestEnvironment) -> Result<(), Error>) {
     let _ = dotenv::dotenv();
@@ -46,6 +50,30 @@ pub(crate) fn assert_success(path: &str, web: &TestFrontend) -> Result<(), Error
     Ok(())
 }
 
+/// Make sure that a URL is served with a `Content-Type` starting with `expected`, which allows
+/// omitting the parameters like the charset
+pub(crate) fn assert_content_type(
+    path: &str,
+    expected: &str,
+    web: &TestFrontend,
+) -> Result<(), Error> {
+    let response = web.get(path).send()?;
+    let content_type = response
+        .headers()
+        .get("content-type")
+        .map(|value| value.to_str())
+        .transpose()?
+        .unwrap_or("");
+    assert!(
+        content_type.starts_with(expected),
+        "{}: expected Content-Type {}, got {:?}",
+        path,
+        expected,
+        content_type
+    );
+    Ok(())
+}
+
 /// Make sure that a URL redirects to a specific page
 pub(crate) fn assert_redirect(
     path: &str,
@@ -100,9 +128,64 @@ pub(crate) struct TestEnvironment {
     s3: OnceCell<TestS3>,
 }
 
+/// Log records at this level or above emitted by docs.rs itself are captured, to let tests
+/// inspect them with [`captured_logs`].
+const CAPTURED_LEVEL: Level = Level::Info;
+
+static CAPTURED_LOGS: Lazy<Mutex<Vec<(String, String)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Logger forwarding the records to `env_logger` while also capturing them.
+struct TestLogger {
+    inner: env_logger::Logger,
+}
+
+impl TestLogger {
+    fn captures(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= CAPTURED_LEVEL && metadata.target().starts_with("cratesfyi")
+    }
+}
+
+impl Log for TestLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.captures(metadata) || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.captures(record.metadata()) {
+            CAPTURED_LOGS
+                .lock()
+                .unwrap()
+                .push((record.target().into(), record.args().to_string()));
+        }
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
 pub(crate) fn init_logger() {
+    let inner = env_logger::builder().is_test(true).build();
+    let max_level = inner.filter().max(CAPTURED_LEVEL.to_level_filter());
+
     // If this fails it's probably already initialized
-    let _ = env_logger::builder().is_test(true).try_init();
+    if log::set_boxed_logger(Box::new(TestLogger { inner })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Returns the messages logged so far with the `target`, by all the tests running in the process.
+pub(crate) fn captured_logs(target: &str) -> Vec<String> {
+    CAPTURED_LOGS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(record_target, _)| record_target == target)
+        .map(|(_, message)| message.clone())
+        .collect()
 }
 
 impl TestEnvironment {
@@ -129,7 +212,10 @@ impl TestEnvironment {
         // Use less connections for each test compared to production.
         config.max_pool_size = 2;
         config.min_pool_idle = 0;
+        // Retry the failed builds right away, the tests of the backoff enable it themselves.
+        config.build_retry_backoff = 0;
 
+        config.validate().expect("the base config is not valid");
         config
     }
 
@@ -172,6 +258,8 @@ impl TestEnvironment {
 pub(crate) struct TestDatabase {
     pool: Pool,
     schema: String,
+    storage_backend: StorageKind,
+    default_target: String,
 }
 
 impl TestDatabase {
@@ -193,6 +281,8 @@ impl TestDatabase {
         Ok(TestDatabase {
             pool: Pool::new_with_schema(config, &schema)?,
             schema,
+            storage_backend: config.storage_backend.clone(),
+            default_target: config.default_target.clone(),
         })
     }
 
@@ -206,9 +296,21 @@ impl TestDatabase {
             .expect("failed to get a connection out of the pool")
     }
 
+    pub(crate) fn default_target(&self) -> &str {
+        &self.default_target
+    }
+
+    pub(crate) fn storage_backend(&self) -> &StorageKind {
+        &self.storage_backend
+    }
+
     pub(crate) fn fake_release(&self) -> fakes::FakeRelease {
         fakes::FakeRelease::new(self)
     }
+
+    pub(crate) fn fake_builder(&self) -> fakes::FakeBuilder {
+        fakes::FakeBuilder::new(self)
+    }
 }
 
 impl Drop for TestDatabase {
@@ -226,11 +328,13 @@ impl Drop for TestDatabase {
 pub(crate) struct TestFrontend {
     server: Server,
     client: Client,
+    admin_token: Option<String>,
 }
 
 impl TestFrontend {
     fn new(db: &TestDatabase, config: Arc<Config>, build_queue: Arc<BuildQueue>) -> Self {
         Self {
+            admin_token: config.admin_token.clone(),
             server: Server::start(
                 Some("127.0.0.1:0"),
                 false,
@@ -243,12 +347,100 @@ impl TestFrontend {
         }
     }
 
-    fn build_request(&self, method: Method, url: &str) -> RequestBuilder {
+    pub(crate) fn request(&self, method: Method, url: &str) -> RequestBuilder {
         self.client
             .request(method, &format!("http://{}{}", self.server.addr(), url))
     }
 
     pub(crate) fn get(&self, url: &str) -> RequestBuilder {
-        self.build_request(Method::GET, url)
+        self.request(Method::GET, url)
+    }
+
+    pub(crate) fn post(&self, url: &str) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
+    /// Builds a GET request authenticated with the admin token set in the configuration.
+    pub(crate) fn get_admin(&self, url: &str) -> RequestBuilder {
+        self.with_admin_token(self.get(url))
+    }
+
+    /// Builds a POST request authenticated with the admin token set in the configuration.
+    pub(crate) fn post_admin(&self, url: &str) -> RequestBuilder {
+        self.with_admin_token(self.post(url))
+    }
+
+    fn with_admin_token(&self, req: RequestBuilder) -> RequestBuilder {
+        let token = self
+            .admin_token
+            .as_ref()
+            .expect("no admin token is configured");
+        req.bearer_auth(token)
+    }
+
+    /// Fetches the body of a page, failing if it doesn't return a status code between 200-299.
+    pub(crate) fn get_body(&self, url: &str) -> Result<String, Error> {
+        Ok(self.get_success(url)?.text()?)
+    }
+
+    /// Fetches and deserializes the JSON body of a page, failing if it doesn't return a status
+    /// code between 200-299.
+    pub(crate) fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        Ok(self.get_success(url)?.json()?)
+    }
+
+    fn get_success(&self, url: &str) -> Result<Response, Error> {
+        let response = self.get(url).send()?;
+        let status = response.status();
+        if !status.is_success() {
+            failure::bail!("failed to GET {}: {}\n{}", url, status, response.text()?);
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wrapper;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_get_body_and_json() {
+        wrapper(|env| {
+            env.db().fake_release().name("foo").create()?;
+            let web = env.frontend();
+
+            assert!(web.get_body("/about")?.contains("<html"));
+            let owners: Value = web.get_json("/crate/foo/owners")?;
+            assert_eq!(owners, json!([]));
+
+            let err = web.get_body("/crate/bar/owners").unwrap_err();
+            assert!(err.to_string().contains("404 Not Found"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_fake_builder() {
+        wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "0.1.0", 0)?;
+            queue.add_crate("bar", "0.1.0", 0)?;
+
+            let builder = env.db().fake_builder();
+            builder.build_next(&queue)?;
+            builder.build_result_successful(false).build_next(&queue)?;
+            assert_eq!(queue.pending_count()?, 0);
+
+            let web = env.frontend();
+            assert!(web
+                .get_body("/foo/0.1.0/foo/")?
+                .contains("default index content"));
+            let badge: Value = web.get_json("/crate/bar/0.1.0/badge.json")?;
+            assert_eq!(badge["message"], "failing");
+
+            Ok(())
+        });
     }
 }
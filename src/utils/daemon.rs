@@ -72,6 +72,7 @@ pub fn start_daemon(
     // REFACTOR: Break this into smaller functions
     let cloned_db = db.clone();
     let cloned_build_queue = build_queue.clone();
+    let cloned_config = config.clone();
     thread::Builder::new().name("build queue reader".to_string()).spawn(move || {
         let opts = opts();
         let mut doc_builder = DocBuilder::new(opts, cloned_db.clone(), cloned_build_queue.clone());
@@ -84,12 +85,14 @@ pub fn start_daemon(
             EmptyQueue,
             /// The builder has just seen the lock file.
             Locked,
+            /// The builder has just seen that the build queue is paused.
+            Paused,
             /// The builder has just finished building a crate. The enclosed count is the number of
             /// crates built since the caches have been refreshed.
             QueueInProgress(usize),
         }
 
-        let mut builder = RustwideBuilder::init(cloned_db).unwrap();
+        let mut builder = RustwideBuilder::init(&cloned_config, cloned_db).unwrap();
 
         let mut status = BuilderState::Fresh;
 
@@ -105,6 +108,19 @@ pub fn start_daemon(
                 continue;
             }
 
+            match cloned_build_queue.is_paused() {
+                Ok(true) => {
+                    warn!("The build queue is paused, skipping building new crates");
+                    status = BuilderState::Paused;
+                    continue;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    error!("Failed to check whether the build queue is paused: {}", e);
+                    continue;
+                }
+            }
+
             if status.count() >= 10 {
                 // periodically, we need to flush our caches and ping the hubs
                 debug!("10 builds in a row; flushing caches");
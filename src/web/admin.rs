@@ -0,0 +1,545 @@
+//! Administrative endpoints, only accessible with the configured admin token
+
+use super::error::Nope;
+use crate::{
+    config::Config,
+    db::{recent_build_failures, set_yanked, Pool},
+    utils::get_crate_priority,
+    BuildQueue,
+};
+use iron::{
+    headers::{Authorization, Bearer, ContentType},
+    status, Handler, IronError, IronResult, Plugin, Request, Response,
+};
+use params::Params;
+use router::Router;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+/// Number of build failures returned by `/-/admin/failures` when no `limit` is given.
+const DEFAULT_FAILURES_LIMIT: i64 = 50;
+/// Upper bound of the `limit` parameter of `/-/admin/failures`.
+const MAX_FAILURES_LIMIT: i64 = 1000;
+
+/// Iron handler wrapper that only forwards requests carrying the admin token configured with
+/// `DOCSRS_ADMIN_TOKEN` in the `Authorization: Bearer` header, responding with `401 Unauthorized`
+/// otherwise. All the admin endpoints are disabled if no token is configured.
+pub(super) struct RequireAdminToken {
+    handler: Box<dyn Handler>,
+}
+
+impl RequireAdminToken {
+    pub(super) fn new(handler: impl Handler) -> Self {
+        Self {
+            handler: Box::new(handler),
+        }
+    }
+}
+
+impl Handler for RequireAdminToken {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let expected = match &extension!(req, Config).admin_token {
+            Some(token) => token.clone(),
+            None => {
+                return Err(IronError::new(Nope::ResourceNotFound, status::NotFound));
+            }
+        };
+
+        let token = req
+            .headers
+            .get::<Authorization<Bearer>>()
+            .map(|auth| auth.token.as_str());
+
+        if token.map_or(false, |token| tokens_match(token, &expected)) {
+            self.handler.handle(req)
+        } else {
+            let mut resp = Response::with((status::Unauthorized, "unauthorized"));
+            resp.headers
+                .set_raw("WWW-Authenticate", vec![b"Bearer".to_vec()]);
+            Ok(resp)
+        }
+    }
+}
+
+/// Compares the tokens in constant time, so that the response time doesn't reveal how much of
+/// the expected token was guessed. Hashing them first makes the comparison independent of the
+/// tokens' lengths too.
+fn tokens_match(token: &str, expected: &str) -> bool {
+    let token = Sha256::digest(token.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+    token
+        .iter()
+        .zip(expected.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+pub(super) fn queue_handler(req: &mut Request) -> IronResult<Response> {
+    let queue = extension!(req, BuildQueue);
+    let body = json!({
+        "paused": ctry!(queue.is_paused()),
+        "crates": ctry!(queue.queued_crates()),
+    });
+
+    let mut resp = Response::with((status::Ok, body.to_string()));
+    resp.headers.set(ContentType::json());
+    Ok(resp)
+}
+
+pub(super) fn queue_pause_handler(req: &mut Request) -> IronResult<Response> {
+    set_queue_paused(req, true)
+}
+
+pub(super) fn queue_resume_handler(req: &mut Request) -> IronResult<Response> {
+    set_queue_paused(req, false)
+}
+
+fn set_queue_paused(req: &mut Request, paused: bool) -> IronResult<Response> {
+    let queue = extension!(req, BuildQueue);
+    ctry!(queue.set_paused(paused));
+
+    log::info!(
+        "the build queue was {} through the admin endpoint",
+        if paused { "paused" } else { "resumed" }
+    );
+
+    let mut resp = Response::with((status::Ok, json!({ "paused": paused }).to_string()));
+    resp.headers.set(ContentType::json());
+    Ok(resp)
+}
+
+pub(super) fn queue_remove_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let version = cexpect!(router.find("version"));
+
+    let queue = extension!(req, BuildQueue);
+    let removed = ctry!(queue.remove(name, version));
+
+    if removed {
+        log::info!(
+            "{}-{} was removed from the build queue through the admin endpoint",
+            name,
+            version
+        );
+    }
+
+    let mut resp = Response::with((status::Ok, json!({ "removed": removed }).to_string()));
+    resp.headers.set(ContentType::json());
+    Ok(resp)
+}
+
+/// Queues a rebuild of a release, returning its position in the build queue. Releases already in
+/// the queue are left untouched.
+///
+/// A builder can pick the release up and finish building it before its position is looked up, in
+/// which case no position is returned and the release is reported as building.
+pub(super) fn rebuild_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let version = cexpect!(router.find("version"));
+
+    let conn = extension!(req, Pool).get()?;
+    let exists = !ctry!(conn.query(
+        "SELECT 1
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE crates.name = $1 AND releases.version = $2",
+        &[&name, &version]
+    ))
+    .is_empty();
+    if !exists {
+        return Ok(release_not_found());
+    }
+
+    let queue = extension!(req, BuildQueue);
+    let priority = ctry!(get_crate_priority(&conn, name));
+    if ctry!(queue.add_crate(name, version, priority)) {
+        log::info!(
+            "a rebuild of {}-{} was queued through the admin endpoint",
+            name,
+            version
+        );
+    }
+    let position = ctry!(queue.position(name, version));
+
+    let mut resp = Response::with((
+        status::Ok,
+        json!({ "position": position, "building": position.is_none() }).to_string(),
+    ));
+    resp.headers.set(ContentType::json());
+    Ok(resp)
+}
+
+pub(super) fn yank_handler(req: &mut Request) -> IronResult<Response> {
+    set_release_yanked(req, true)
+}
+
+pub(super) fn unyank_handler(req: &mut Request) -> IronResult<Response> {
+    set_release_yanked(req, false)
+}
+
+/// Flags a release as yanked or not yanked, for the releases whose yanking happened while the
+/// index wasn't watched or was reverted.
+fn set_release_yanked(req: &mut Request, yanked: bool) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let version = cexpect!(router.find("version"));
+
+    let conn = extension!(req, Pool).get()?;
+    if !ctry!(set_yanked(&conn, name, version, yanked)) {
+        return Ok(release_not_found());
+    }
+
+    log::info!(
+        "{}-{} was {} through the admin endpoint",
+        name,
+        version,
+        if yanked { "yanked" } else { "unyanked" }
+    );
+
+    let mut resp = Response::with((status::Ok, json!({ "yanked": yanked }).to_string()));
+    resp.headers.set(ContentType::json());
+    Ok(resp)
+}
+
+/// Lists the releases whose last build failed with their error summary, most recent failure
+/// first. The `limit` parameter sets how many failures are returned.
+pub(super) fn failures_handler(req: &mut Request) -> IronResult<Response> {
+    let limit = match ctry!(req.get::<Params>()).find(&["limit"]) {
+        Some(params::Value::String(limit)) => match limit.parse::<i64>() {
+            Ok(limit) if limit > 0 => limit.min(MAX_FAILURES_LIMIT),
+            _ => {
+                let mut resp = Response::with((
+                    status::BadRequest,
+                    json!({ "error": "invalid limit" }).to_string(),
+                ));
+                resp.headers.set(ContentType::json());
+                return Ok(resp);
+            }
+        },
+        _ => DEFAULT_FAILURES_LIMIT,
+    };
+
+    let conn = extension!(req, Pool).get()?;
+    let failures = ctry!(recent_build_failures(&conn, limit));
+
+    let mut resp = Response::with((status::Ok, json!({ "failures": failures }).to_string()));
+    resp.headers.set(ContentType::json());
+    Ok(resp)
+}
+
+fn release_not_found() -> Response {
+    let mut resp = Response::with((
+        status::NotFound,
+        json!({ "error": "release not found" }).to_string(),
+    ));
+    resp.headers.set(ContentType::json());
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(super::tokens_match("secret", "secret"));
+        assert!(!super::tokens_match("secreT", "secret"));
+        assert!(!super::tokens_match("secret2", "secret"));
+        assert!(!super::tokens_match("", "secret"));
+    }
+
+    #[test]
+    fn pause_and_resume_queue() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.admin_token = Some("secret".into());
+            });
+            let queue = env.build_queue();
+            let web = env.frontend();
+
+            let resp = web.post_admin("/-/queue/pause").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert!(queue.is_paused()?);
+
+            let resp = web.post_admin("/-/queue/resume").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert!(!queue.is_paused()?);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rebuild() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.admin_token = Some("secret".into());
+            });
+            env.db()
+                .fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .create()?;
+            let queue = env.build_queue();
+            let web = env.frontend();
+
+            queue.add_crate("bar", "1.0.0", 0)?;
+
+            let resp = web.post_admin("/-/admin/rebuild/foo/0.1.0").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({ "position": 2, "building": false })
+            );
+            assert_eq!(queue.pending_count()?, 2);
+
+            // Rebuilding a release that's already queued doesn't queue it again
+            let resp = web.post_admin("/-/admin/rebuild/foo/0.1.0").send()?;
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({ "position": 2, "building": false })
+            );
+            assert_eq!(queue.pending_count()?, 2);
+
+            for url in &["/-/admin/rebuild/foo/0.2.0", "/-/admin/rebuild/baz/0.1.0"] {
+                let resp = web.post_admin(url).send()?;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+                assert_eq!(
+                    resp.json::<Value>()?,
+                    json!({ "error": "release not found" })
+                );
+            }
+            assert_eq!(queue.pending_count()?, 2);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn yank_and_unyank() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.admin_token = Some("secret".into());
+            });
+            env.db()
+                .fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .create()?;
+            env.db()
+                .fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .create()?;
+            let web = env.frontend();
+
+            let yanked_versions = || -> Result<Vec<(String, bool)>, failure::Error> {
+                let versions: Value = web.get("/api/v1/crates/foo/versions").send()?.json()?;
+                Ok(versions
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|version| {
+                        (
+                            version["version"].as_str().unwrap().to_string(),
+                            version["yanked"].as_bool().unwrap(),
+                        )
+                    })
+                    .collect())
+            };
+
+            let resp = web.post("/-/admin/yank/foo/0.2.0").send()?;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+            let resp = web.post_admin("/-/admin/yank/foo/0.2.0").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.json::<Value>()?, json!({ "yanked": true }));
+            assert_eq!(
+                yanked_versions()?,
+                vec![("0.2.0".into(), true), ("0.1.0".into(), false)]
+            );
+            let latest: Value = web.get("/api/v1/crates/foo/latest").send()?.json()?;
+            assert_eq!(latest["version"], "0.1.0");
+
+            let resp = web.post_admin("/-/admin/unyank/foo/0.2.0").send()?;
+            assert_eq!(resp.json::<Value>()?, json!({ "yanked": false }));
+            assert_eq!(
+                yanked_versions()?,
+                vec![("0.2.0".into(), false), ("0.1.0".into(), false)]
+            );
+
+            let resp = web.post_admin("/-/admin/yank/foo/0.3.0").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn recent_failures() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.admin_token = Some("secret".into());
+            });
+            let db = env.db();
+            db.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .build_result_successful(false)
+                .build_log("error: first failure")
+                .create()?;
+            db.fake_release().name("foo").version("0.2.0").create()?;
+            db.fake_release()
+                .name("bar")
+                .version("1.0.0")
+                .build_result_successful(false)
+                .build_log("error: second failure")
+                .create()?;
+            let web = env.frontend();
+
+            let failures = |url: &str| -> Result<Vec<(String, String, String)>, failure::Error> {
+                let resp = web.get_admin(url).send()?;
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = resp.json::<Value>()?;
+                Ok(body["failures"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|failure| {
+                        assert!(failure["time"].is_string());
+                        (
+                            failure["name"].as_str().unwrap().to_string(),
+                            failure["version"].as_str().unwrap().to_string(),
+                            failure["error"].as_str().unwrap().to_string(),
+                        )
+                    })
+                    .collect())
+            };
+
+            assert_eq!(
+                failures("/-/admin/failures")?,
+                vec![
+                    ("bar".into(), "1.0.0".into(), "error: second failure".into()),
+                    ("foo".into(), "0.1.0".into(), "error: first failure".into()),
+                ]
+            );
+            assert_eq!(
+                failures("/-/admin/failures?limit=1")?,
+                vec![("bar".into(), "1.0.0".into(), "error: second failure".into())]
+            );
+
+            let resp = web.get_admin("/-/admin/failures?limit=0").send()?;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            let resp = web.get("/-/admin/failures").send()?;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn remove_from_queue() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.admin_token = Some("secret".into());
+            });
+            let queue = env.build_queue();
+            let web = env.frontend();
+
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.add_crate("bar", "1.0.0", 0)?;
+
+            let resp = web.post("/-/queue/remove/foo/1.0.0").send()?;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(queue.pending_count()?, 2);
+
+            let resp = web.post_admin("/-/queue/remove/foo/1.0.0").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.json::<Value>()?, json!({ "removed": true }));
+            assert_eq!(queue.pending_count()?, 1);
+
+            let resp = web.post_admin("/-/queue/remove/foo/1.0.0").send()?;
+            assert_eq!(resp.json::<Value>()?, json!({ "removed": false }));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn queue_page() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.admin_token = Some("secret".into());
+            });
+            let queue = env.build_queue();
+            let web = env.frontend();
+
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.add_crate("bar", "0.1.0", -10)?;
+
+            let resp = web.get_admin("/-/queue").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({
+                    "paused": false,
+                    "crates": [
+                        { "name": "bar", "version": "0.1.0", "priority": -10 },
+                        { "name": "foo", "version": "1.0.0", "priority": 0 },
+                    ],
+                })
+            );
+
+            for token in &[None, Some("wrong")] {
+                let mut req = web.get("/-/queue");
+                if let Some(token) = token {
+                    req = req.bearer_auth(token);
+                }
+                let resp = req.send()?;
+                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+                assert_eq!(resp.headers()["www-authenticate"], "Bearer");
+            }
+
+            // Public routes don't need the token
+            assert!(web.get("/-/build-info").send()?.status().is_success());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn pause_requires_token() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.admin_token = Some("secret".into());
+            });
+            let queue = env.build_queue();
+            let web = env.frontend();
+
+            let resp = web.post("/-/queue/pause").send()?;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            let resp = web.post("/-/queue/pause").bearer_auth("wrong").send()?;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            assert!(!queue.is_paused()?);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn admin_endpoints_disabled_without_token() {
+        wrapper(|env| {
+            let web = env.frontend();
+
+            let resp = web.post("/-/queue/pause").bearer_auth("secret").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            assert!(!env.build_queue().is_paused()?);
+
+            Ok(())
+        });
+    }
+}
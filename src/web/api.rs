@@ -0,0 +1,497 @@
+//! Machine-readable JSON API, served under `/api/v1`
+
+use super::releases::get_releases_page;
+use crate::db::Pool;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use iron::{headers::ContentType, status, IronResult, Plugin, Request, Response};
+use params::Params;
+use postgres::Connection;
+use router::Router;
+use serde::Serialize;
+use serde_json::json;
+use std::cmp::Reverse;
+
+/// Number of releases in each page of `/api/v1/releases`
+const RELEASES_PER_PAGE: i64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct LatestVersion {
+    name: String,
+    version: String,
+    has_docs: bool,
+    build_status: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct VersionInfo {
+    version: String,
+    yanked: bool,
+    build_status: bool,
+    release_time: DateTime<Utc>,
+    /// Total size of the stored documentation, if it was recorded
+    doc_size_bytes: Option<i64>,
+    last_build_attempt: Option<DateTime<Utc>>,
+    /// Why the last build failed, if it did
+    last_build_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ReleaseInfo {
+    name: String,
+    version: String,
+    has_docs: bool,
+    release_time: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ReleaseList {
+    releases: Vec<ReleaseInfo>,
+    next_page: Option<String>,
+    previous_page: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct ApiRelease {
+    name: String,
+    version: String,
+    has_docs: bool,
+    build_status: bool,
+    yanked: bool,
+    release_time: DateTime<Utc>,
+    doc_size_bytes: Option<i64>,
+    last_build_attempt: Option<DateTime<Utc>>,
+    last_build_error: Option<String>,
+}
+
+/// Loads all the releases of a crate, sorted newest first by semver. Versions that aren't valid
+/// semver are put at the end.
+fn load_releases(conn: &Connection, name: &str) -> Result<Vec<ApiRelease>, postgres::Error> {
+    let rows = conn.query(
+        "SELECT crates.name,
+                releases.version,
+                releases.rustdoc_status,
+                releases.build_status,
+                releases.yanked,
+                releases.release_time,
+                releases.doc_size_bytes,
+                releases.last_build_attempt,
+                releases.last_build_error
+         FROM releases
+         INNER JOIN crates ON releases.crate_id = crates.id
+         WHERE normalize_crate_name(crates.name) = normalize_crate_name($1)",
+        &[&name],
+    )?;
+
+    let mut releases = rows
+        .iter()
+        .map(|row| ApiRelease {
+            name: row.get(0),
+            version: row.get(1),
+            has_docs: row.get(2),
+            build_status: row.get(3),
+            yanked: row.get(4),
+            release_time: DateTime::from_utc(row.get::<_, NaiveDateTime>(5), Utc),
+            doc_size_bytes: row.get(6),
+            last_build_attempt: row
+                .get::<_, Option<NaiveDateTime>>(7)
+                .map(|time| DateTime::from_utc(time, Utc)),
+            last_build_error: row.get(8),
+        })
+        .collect::<Vec<_>>();
+    releases.sort_by_cached_key(|release| Reverse(semver::Version::parse(&release.version).ok()));
+
+    Ok(releases)
+}
+
+/// Handler for `/api/v1/crates/:name/latest`, returning the latest non-yanked release of a crate,
+/// or the latest yanked one if all the releases were yanked.
+pub fn latest_version_handler(req: &mut Request) -> IronResult<Response> {
+    let name = cexpect!(extension!(req, Router).find("name"));
+    let conn = extension!(req, Pool).get_replica()?;
+    let releases = ctry!(load_releases(&conn, name));
+
+    let latest = releases
+        .iter()
+        .find(|release| !release.yanked)
+        .or_else(|| releases.first());
+
+    match latest {
+        Some(release) => Ok(json_response(
+            status::Ok,
+            &LatestVersion {
+                name: release.name.clone(),
+                version: release.version.clone(),
+                has_docs: release.has_docs,
+                build_status: release.build_status,
+            },
+        )),
+        None => Ok(not_found()),
+    }
+}
+
+/// Handler for `/api/v1/crates/:name/versions`, returning all the releases of a crate, newest
+/// first.
+pub fn versions_handler(req: &mut Request) -> IronResult<Response> {
+    let name = cexpect!(extension!(req, Router).find("name"));
+    let conn = extension!(req, Pool).get_replica()?;
+    let releases = ctry!(load_releases(&conn, name));
+
+    if releases.is_empty() {
+        return Ok(not_found());
+    }
+
+    let versions = releases
+        .into_iter()
+        .map(|release| VersionInfo {
+            version: release.version,
+            yanked: release.yanked,
+            build_status: release.build_status,
+            release_time: release.release_time,
+            doc_size_bytes: release.doc_size_bytes,
+            last_build_attempt: release.last_build_attempt,
+            last_build_error: release.last_build_error,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(json_response(status::Ok, &versions))
+}
+
+/// Handler for `/api/v1/releases`, returning a page of the releases of all crates, newest first.
+///
+/// The `page` parameter selects which page to load, using the `next_page` and `previous_page`
+/// values of the other pages. The first page is returned when it's missing.
+pub fn releases_handler(req: &mut Request) -> IronResult<Response> {
+    let cursor = match ctry!(req.get::<Params>()).find(&["page"]) {
+        Some(params::Value::String(page)) => match page.parse() {
+            Ok(cursor) => Some(cursor),
+            Err(()) => {
+                return Ok(json_response(
+                    status::BadRequest,
+                    &json!({ "error": "invalid page" }),
+                ))
+            }
+        },
+        _ => None,
+    };
+
+    let conn = extension!(req, Pool).get_replica()?;
+    let page = ctry!(get_releases_page(&conn, cursor, RELEASES_PER_PAGE));
+
+    Ok(json_response(
+        status::Ok,
+        &ReleaseList {
+            releases: page
+                .releases
+                .into_iter()
+                .map(|release| ReleaseInfo {
+                    name: release.name,
+                    version: release.version,
+                    has_docs: release.rustdoc_status,
+                    release_time: release.release_time,
+                })
+                .collect(),
+            next_page: page.next.map(|cursor| cursor.to_string()),
+            previous_page: page.previous.map(|cursor| cursor.to_string()),
+        },
+    ))
+}
+
+pub(super) fn not_found() -> Response {
+    json_response(status::NotFound, &json!({ "error": "crate not found" }))
+}
+
+pub(super) fn json_response(status: status::Status, body: &impl Serialize) -> Response {
+    let mut resp = Response::with((status, serde_json::to_string(body).unwrap()));
+    resp.headers.set(ContentType::json());
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn releases() {
+        wrapper(|env| {
+            let db = env.db();
+            let start = Utc.ymd(2020, 4, 1).and_hms(12, 0, 0);
+            for i in 0..=super::RELEASES_PER_PAGE {
+                db.fake_release()
+                    .name(&format!("crate-{}", i))
+                    .release_time(start + Duration::minutes(i))
+                    .create()?;
+            }
+
+            let web = env.frontend();
+            let first = web.get("/api/v1/releases").send()?.json::<Value>()?;
+            let releases = first["releases"].as_array().unwrap();
+            assert_eq!(releases.len() as i64, super::RELEASES_PER_PAGE);
+            assert_eq!(
+                releases[0],
+                json!({
+                    "name": format!("crate-{}", super::RELEASES_PER_PAGE),
+                    "version": "1.0.0",
+                    "has_docs": true,
+                    "release_time": "2020-04-01T12:30:00Z",
+                })
+            );
+            assert_eq!(releases.last().unwrap()["name"], "crate-1");
+            assert_eq!(first["previous_page"], Value::Null);
+
+            let next_page = first["next_page"].as_str().unwrap();
+            let second = web
+                .get(&format!("/api/v1/releases?page={}", next_page))
+                .send()?
+                .json::<Value>()?;
+            assert_eq!(second["releases"].as_array().unwrap().len(), 1);
+            assert_eq!(second["releases"][0]["name"], "crate-0");
+            assert_eq!(second["next_page"], Value::Null);
+
+            let previous_page = second["previous_page"].as_str().unwrap();
+            let back = web
+                .get(&format!("/api/v1/releases?page={}", previous_page))
+                .send()?
+                .json::<Value>()?;
+            assert_eq!(back, first);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn releases_invalid_page() {
+        wrapper(|env| {
+            let resp = env.frontend().get("/api/v1/releases?page=42").send()?;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(resp.json::<Value>()?, json!({ "error": "invalid page" }));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn latest_version() {
+        wrapper(|env| {
+            let db = env.db();
+            db.fake_release().name("foo").version("0.1.0").create()?;
+            db.fake_release().name("foo").version("0.10.0").create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .build_result_successful(false)
+                .create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.11.0")
+                .yanked(true)
+                .create()?;
+
+            let resp = env.frontend().get("/api/v1/crates/foo/latest").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({
+                    "name": "foo",
+                    "version": "0.10.0",
+                    "has_docs": true,
+                    "build_status": true,
+                })
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn latest_version_only_yanked() {
+        wrapper(|env| {
+            let db = env.db();
+            db.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .yanked(true)
+                .create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .yanked(true)
+                .create()?;
+
+            let resp = env.frontend().get("/api/v1/crates/foo/latest").send()?;
+            assert_eq!(resp.json::<Value>()?["version"], "0.2.0");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn latest_version_unknown_crate() {
+        wrapper(|env| {
+            let resp = env.frontend().get("/api/v1/crates/foo/latest").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            assert_eq!(resp.json::<Value>()?, json!({ "error": "crate not found" }));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn versions() {
+        wrapper(|env| {
+            let db = env.db();
+            let time = Utc.ymd(2020, 4, 1).and_hms(12, 0, 0);
+            db.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .release_time(time)
+                .create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.10.0")
+                .release_time(time)
+                .yanked(true)
+                .create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.3.0")
+                .release_time(time)
+                .build_result_successful(false)
+                .create()?;
+
+            // Only the default index page is stored for the successful builds
+            let doc_size = crate::storage::compress(
+                &b"default index content"[..],
+                crate::storage::DEFAULT_COMPRESSION,
+            )?
+            .len();
+
+            let resp = env.frontend().get("/api/v1/crates/foo/versions").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let mut versions = resp.json::<Value>()?;
+            for version in versions.as_array_mut().unwrap() {
+                let attempt = version
+                    .as_object_mut()
+                    .unwrap()
+                    .remove("last_build_attempt");
+                assert!(attempt.unwrap().is_string());
+            }
+            assert_eq!(
+                versions,
+                json!([
+                    {
+                        "version": "0.10.0",
+                        "yanked": true,
+                        "build_status": true,
+                        "release_time": "2020-04-01T12:00:00Z",
+                        "doc_size_bytes": doc_size,
+                        "last_build_error": null,
+                    },
+                    {
+                        "version": "0.3.0",
+                        "yanked": false,
+                        "build_status": false,
+                        "release_time": "2020-04-01T12:00:00Z",
+                        "doc_size_bytes": 0,
+                        "last_build_error": "It works!",
+                    },
+                    {
+                        "version": "0.2.0",
+                        "yanked": false,
+                        "build_status": true,
+                        "release_time": "2020-04-01T12:00:00Z",
+                        "doc_size_bytes": doc_size,
+                        "last_build_error": null,
+                    },
+                ])
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn versions_doc_size() {
+        wrapper(|env| {
+            // Pseudo-random content, so that compressing it doesn't make it smaller
+            let mut state = 1u32;
+            let content = (0..16 * 1024)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    (state >> 16) as u8
+                })
+                .collect::<Vec<_>>();
+            env.db()
+                .fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .rustdoc_file("foo/index.html", &content)
+                .create()?;
+
+            let resp = env.frontend().get("/api/v1/crates/foo/versions").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let size = resp.json::<Value>()?[0]["doc_size_bytes"].as_u64().unwrap();
+            assert!(size >= content.len() as u64);
+            assert!(size < content.len() as u64 + 1024);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn versions_last_build() {
+        wrapper(|env| {
+            let db = env.db();
+            db.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .build_result_successful(false)
+                .build_log(
+                    "   Compiling foo v0.1.0\n\
+                     error[E0425]: cannot find value `bar` in this scope\n\
+                     \n\
+                     error: aborting due to previous error\n",
+                )
+                .create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .build_result_successful(false)
+                .build_log(format!("error: {}", "x".repeat(2000)))
+                .create()?;
+
+            let resp = env.frontend().get("/api/v1/crates/foo/versions").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let versions = resp.json::<Value>()?;
+
+            let truncated = versions[0]["last_build_error"].as_str().unwrap();
+            assert_eq!(truncated.chars().count(), 1000 + "...".len());
+            assert!(truncated.starts_with("error: xxx"));
+            assert!(truncated.ends_with("x..."));
+
+            assert_eq!(
+                versions[1]["last_build_error"],
+                "error[E0425]: cannot find value `bar` in this scope\n\n\
+                 error: aborting due to previous error",
+            );
+            let attempt = versions[1]["last_build_attempt"].as_str().unwrap();
+            let attempt = DateTime::parse_from_rfc3339(attempt)?.with_timezone(&Utc);
+            assert!((Utc::now() - attempt).num_minutes().abs() < 5);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn versions_unknown_crate() {
+        wrapper(|env| {
+            let resp = env.frontend().get("/api/v1/crates/foo/versions").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            assert_eq!(resp.json::<Value>()?, json!({ "error": "crate not found" }));
+
+            Ok(())
+        });
+    }
+}
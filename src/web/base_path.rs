@@ -0,0 +1,118 @@
+//! Support for serving the site under a path prefix, for deployments where a reverse proxy
+//! forwards only part of a domain (for example `example.com/docs`) to docs.rs.
+//!
+//! The proxy is expected to forward the full path: [`StripBasePath`] removes the configured prefix
+//! before the request reaches the routes, which are all defined relative to the root. The prefix
+//! is added back to the generated links by [`redirect_base`](super::redirect_base) and by the
+//! `base_path` function available in the templates.
+
+use iron::prelude::*;
+use iron::{status, Handler};
+
+pub(super) struct StripBasePath<H: Handler> {
+    handler: H,
+    base_path: String,
+}
+
+impl<H: Handler> StripBasePath<H> {
+    pub(super) fn new(handler: H, base_path: &str) -> Self {
+        Self {
+            handler,
+            base_path: base_path.into(),
+        }
+    }
+}
+
+impl<H: Handler> Handler for StripBasePath<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        if self.base_path.is_empty() {
+            return self.handler.handle(req);
+        }
+
+        let mut url: iron::url::Url = req.url.clone().into();
+        let path = match strip_base_path(url.path(), &self.base_path) {
+            Some(path) => path.to_string(),
+            None => return Ok(Response::with((status::NotFound, "not found"))),
+        };
+        url.set_path(&path);
+        req.url =
+            iron::Url::from_generic_url(url).expect("stripping the path made the URL invalid");
+
+        self.handler.handle(req)
+    }
+}
+
+/// Returns the part of `path` after `base_path`, or `None` if `path` is outside of it.
+fn strip_base_path<'a>(path: &'a str, base_path: &str) -> Option<&'a str> {
+    match path.strip_prefix(base_path)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        // The base path only matched part of a segment, like `/docsfoo` for `/docs`
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{assert_redirect, wrapper, TestFrontend};
+    use kuchiki::traits::TendrilSink;
+    use reqwest::StatusCode;
+
+    #[test]
+    fn test_strip_base_path() {
+        assert_eq!(strip_base_path("/docs", "/docs"), Some("/"));
+        assert_eq!(strip_base_path("/docs/", "/docs"), Some("/"));
+        assert_eq!(
+            strip_base_path("/docs/crate/foo", "/docs"),
+            Some("/crate/foo")
+        );
+        assert_eq!(strip_base_path("/docsfoo", "/docs"), None);
+        assert_eq!(strip_base_path("/crate/foo", "/docs"), None);
+    }
+
+    fn links(web: &TestFrontend, path: &str) -> Result<Vec<String>, failure::Error> {
+        let resp = web.get(path).send()?;
+        assert!(resp.status().is_success());
+        Ok(kuchiki::parse_html()
+            .one(resp.text()?)
+            .select("a")
+            .unwrap()
+            .filter_map(|link| link.attributes.borrow().get("href").map(String::from))
+            .collect())
+    }
+
+    #[test]
+    fn test_serve_under_base_path() {
+        wrapper(|env| {
+            env.override_config(|config| config.base_path = "/docs".into());
+            env.db()
+                .fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html", b"some content")
+                .create()?;
+            let web = env.frontend();
+
+            assert_redirect("/docs/dummy", "/docs/dummy/0.1.0/dummy/", web)?;
+            assert_redirect(
+                "/docs/crate/dummy/latest",
+                "/docs/dummy/0.1.0/dummy/index.html",
+                web,
+            )?;
+            assert_redirect("/docs/releases/", "/docs/releases", web)?;
+
+            // The links of both the tera and handlebars templates include the base path
+            let home = links(web, "/docs")?;
+            assert!(home.iter().any(|link| link == "/docs/releases"));
+            assert!(home.iter().any(|link| link == "/docs/dummy/0.1.0/dummy"));
+            let docs = links(web, "/docs/dummy/0.1.0/dummy/")?;
+            assert!(docs.iter().any(|link| link == "/docs/crate/dummy/0.1.0"));
+
+            let resp = web.get("/dummy").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+}
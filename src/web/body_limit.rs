@@ -0,0 +1,87 @@
+//! Middleware rejecting the requests with a body larger than the configured limit, so that the
+//! write endpoints can't be used to make the server buffer arbitrary amounts of data.
+//!
+//! The size is checked against the `Content-Length` header before anything is read. Bodies sent
+//! without it (with the chunked transfer encoding) can't be checked upfront, so they're rejected
+//! with `411 Length Required`. `GET` and `HEAD` requests are never limited.
+
+use iron::headers::{Connection, ContentLength, TransferEncoding};
+use iron::method::Method;
+use iron::prelude::*;
+use iron::{status, Handler};
+use std::io::{self, Read};
+
+pub(super) struct BodyLimit<H: Handler> {
+    handler: H,
+    max_size: u64,
+}
+
+impl<H: Handler> BodyLimit<H> {
+    pub(super) fn new(handler: H, max_size: u64) -> Self {
+        Self { handler, max_size }
+    }
+}
+
+impl<H: Handler> Handler for BodyLimit<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        if req.method == Method::Get || req.method == Method::Head {
+            return self.handler.handle(req);
+        }
+
+        let mut resp = match req.headers.get::<ContentLength>() {
+            Some(&ContentLength(size)) if size > self.max_size => Response::with((
+                status::PayloadTooLarge,
+                format!("the request body must not exceed {} bytes", self.max_size),
+            )),
+            None if req.headers.has::<TransferEncoding>() => Response::with((
+                status::LengthRequired,
+                "the request body must have a Content-Length",
+            )),
+            _ => {
+                let result = self.handler.handle(req);
+                // Handlers ignoring the body would leave it in the connection, where it would be
+                // parsed as the start of the next request. It's small enough to be discarded.
+                let _ = io::copy(&mut req.body.by_ref().take(self.max_size), &mut io::sink());
+                return result;
+            }
+        };
+        // The body was never read, so the connection can't be used for other requests
+        resp.headers.set(Connection::close());
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+
+    #[test]
+    fn test_oversized_bodies_are_rejected() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.admin_token = Some("secret".into());
+                config.max_request_body_size = 16;
+            });
+            let web = env.frontend();
+
+            let resp = web
+                .post_admin("/-/admin/rebuild/foo/0.1.0")
+                .body(vec![b'a'; 17])
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+            // Bodies within the limit reach the endpoint, which doesn't know the release
+            let resp = web
+                .post_admin("/-/admin/rebuild/foo/0.1.0")
+                .body(vec![b'a'; 16])
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            // GET requests are not limited
+            assert!(web.get("/about").send()?.status().is_success());
+
+            Ok(())
+        });
+    }
+}
@@ -1,12 +1,22 @@
+use super::api::{json_response, not_found};
 use super::duration_to_str;
+use super::error::Nope;
+use super::match_version;
 use super::page::Page;
+use super::rustdoc::{badge_response, set_badge_cache_headers};
 use super::MetaData;
 use crate::db::Pool;
 use crate::docbuilder::Limits;
+use crate::storage::{PathNotFoundError, Storage};
+use crate::Config;
+use badge::BadgeOptions;
 use chrono::{DateTime, NaiveDateTime, Utc};
+use iron::headers::{CacheControl, CacheDirective, ContentType, Expires, HttpDate};
 use iron::prelude::*;
+use iron::status;
 use router::Router;
-use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Build {
@@ -113,10 +123,7 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
         .collect::<Vec<Build>>();
 
     if req.url.path().join("/").ends_with(".json") {
-        use iron::headers::{
-            AccessControlAllowOrigin, CacheControl, CacheDirective, ContentType, Expires, HttpDate,
-        };
-        use iron::status;
+        use iron::headers::AccessControlAllowOrigin;
 
         // Remove build output from build list for json output
         for build in build_list.as_mut_slice() {
@@ -148,12 +155,348 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
     }
 }
 
+/// Serves the stored log of a build as plain text.
+pub fn build_log_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let version = cexpect!(router.find("version"));
+    let build_id: i32 = match router.find("id").and_then(|id| id.parse().ok()) {
+        Some(id) => id,
+        None => return Err(IronError::new(Nope::ResourceNotFound, status::NotFound)),
+    };
+
+    let conn = extension!(req, Pool).get()?;
+    let config = extension!(req, Config);
+    let log =
+        match Storage::new(&conn, &config.storage_backend).get_build_log(name, version, build_id) {
+            Ok(log) => log,
+            Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => {
+                return Err(IronError::new(Nope::ResourceNotFound, status::NotFound));
+            }
+            Err(err) => return Err(IronError::new(err.compat(), status::InternalServerError)),
+        };
+
+    let mut resp = Response::with((status::Ok, log));
+    resp.headers.set(ContentType::plaintext());
+    Ok(resp)
+}
+
+/// Environment the documentation of a release was built in by its latest build.
+#[derive(Debug, Serialize)]
+struct BuildDetails {
+    rustc_version: String,
+    docsrs_version: String,
+    default_target: String,
+    /// `None` for the builds made before the features were recorded.
+    features: Option<serde_json::Value>,
+    /// `None` for the builds made before the cargo arguments were recorded.
+    cargo_args: Option<serde_json::Value>,
+}
+
+/// Serves the rustc version, default target and flags used by the latest build of a release.
+pub fn build_details_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let version = cexpect!(router.find("version"));
+    let conn = extension!(req, Pool).get_replica()?;
+
+    let rows = ctry!(conn.query(
+        "SELECT builds.rustc_version,
+                builds.cratesfyi_version,
+                releases.default_target,
+                builds.features,
+                builds.cargo_args
+         FROM builds
+         INNER JOIN releases ON releases.id = builds.rid
+         INNER JOIN crates ON releases.crate_id = crates.id
+         WHERE crates.name = $1 AND releases.version = $2
+         ORDER BY builds.id DESC
+         LIMIT 1",
+        &[&name, &version],
+    ));
+    let row = match rows.iter().next() {
+        Some(row) => row,
+        None => return Ok(not_found()),
+    };
+
+    Ok(json_response(
+        status::Ok,
+        &BuildDetails {
+            rustc_version: row.get(0),
+            docsrs_version: row.get(1),
+            default_target: row.get(2),
+            features: row.get(3),
+            cargo_args: row.get(4),
+        },
+    ))
+}
+
+/// Label shown on the left side of the build badges
+const BADGE_LABEL: &str = "build";
+
+/// Outcome of the latest build of a release, as shown by its badge.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum BuildBadgeStatus {
+    Passing,
+    Failing,
+    /// The release wasn't built yet.
+    Unknown,
+}
+
+impl BuildBadgeStatus {
+    /// Loads the status of a release, returning `None` if the release doesn't exist. The version
+    /// can also be a semver requirement or `latest`, which are resolved here instead of
+    /// redirecting, as the badges are embedded in READMEs and the redirects would be cached by the
+    /// image proxies.
+    fn load(
+        conn: &postgres::Connection,
+        name: &str,
+        version: &str,
+    ) -> Result<Option<Self>, postgres::Error> {
+        let release_id = match match_version(conn, name, Some(version)) {
+            Some(matched) => matched.version.into_parts().1,
+            None => return Ok(None),
+        };
+
+        let rows = conn.query(
+            "SELECT build_status FROM builds WHERE rid = $1 ORDER BY id DESC LIMIT 1",
+            &[&release_id],
+        )?;
+        Ok(Some(match rows.iter().next().map(|row| row.get(0)) {
+            Some(true) => BuildBadgeStatus::Passing,
+            Some(false) => BuildBadgeStatus::Failing,
+            None => BuildBadgeStatus::Unknown,
+        }))
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            BuildBadgeStatus::Passing => "passing",
+            BuildBadgeStatus::Failing => "failing",
+            BuildBadgeStatus::Unknown => "unknown",
+        }
+    }
+
+    /// Hex code of the badge color, without the leading `#`.
+    fn color(self) -> &'static str {
+        match self {
+            BuildBadgeStatus::Passing => "4c1",
+            BuildBadgeStatus::Failing => "e05d44",
+            BuildBadgeStatus::Unknown => "9f9f9f",
+        }
+    }
+}
+
+/// Body of the JSON badges, in the format of the shields.io [endpoint badges].
+///
+/// [endpoint badges]: https://shields.io/endpoint
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ShieldsEndpoint {
+    schema_version: u8,
+    label: &'static str,
+    message: &'static str,
+    color: &'static str,
+}
+
+/// Serves a SVG badge with the status of the latest build of a release.
+pub fn build_badge_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let version = cexpect!(router.find("version"));
+    let conn = extension!(req, Pool).get()?;
+
+    let build_status =
+        ctry!(BuildBadgeStatus::load(&conn, name, version)).unwrap_or(BuildBadgeStatus::Unknown);
+    badge_response(BadgeOptions {
+        subject: BADGE_LABEL.to_owned(),
+        status: build_status.message().to_owned(),
+        color: format!("#{}", build_status.color()),
+    })
+}
+
+/// Serves the status of the latest build of a release as a shields.io endpoint badge.
+pub fn build_badge_json_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let version = cexpect!(router.find("version"));
+    let conn = extension!(req, Pool).get()?;
+
+    let mut resp = match ctry!(BuildBadgeStatus::load(&conn, name, version)) {
+        Some(build_status) => json_response(
+            status::Ok,
+            &ShieldsEndpoint {
+                schema_version: 1,
+                label: BADGE_LABEL,
+                message: build_status.message(),
+                color: build_status.color(),
+            },
+        ),
+        None => not_found(),
+    };
+    set_badge_cache_headers(&mut resp);
+    Ok(resp)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test::{assert_content_type, wrapper};
     use chrono::Utc;
+    use reqwest::StatusCode;
     use serde_json::json;
 
+    #[test]
+    fn build_log() {
+        wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .create()?;
+            let build_id: i32 = env
+                .db()
+                .conn()
+                .query("SELECT id FROM builds", &[])?
+                .get(0)
+                .get(0);
+            Storage::new(&env.db().conn(), &env.config().storage_backend).store_build_log(
+                "foo",
+                "0.1.0",
+                build_id,
+                "error: could not compile `foo`",
+            )?;
+
+            let web = env.frontend();
+            let resp = web
+                .get(&format!("/crate/foo/0.1.0/builds/{}/log", build_id))
+                .send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(resp.text()?, "error: could not compile `foo`");
+
+            let resp = web
+                .get(&format!("/crate/foo/0.1.0/builds/{}/log", build_id + 1))
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn build_details() {
+        wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .default_target("x86_64-pc-windows-msvc")
+                .features(&["serde", "std"])
+                .cargo_args(&["doc", "--lib", "--no-deps", "--features", "serde std"])
+                .create()?;
+
+            let web = env.frontend();
+            assert_content_type("/crate/foo/0.1.0/build-details", "application/json", web)?;
+            let resp = web.get("/crate/foo/0.1.0/build-details").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.json::<serde_json::Value>()?,
+                json!({
+                    "rustc_version": "rustc 2.0.0-nightly (000000000 1970-01-01)",
+                    "docsrs_version": "docs.rs 1.0.0 (000000000 1970-01-01)",
+                    "default_target": "x86_64-pc-windows-msvc",
+                    "features": ["serde", "std"],
+                    "cargo_args": ["doc", "--lib", "--no-deps", "--features", "serde std"],
+                })
+            );
+
+            let resp = web.get("/crate/foo/0.2.0/build-details").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn build_badge() {
+        wrapper(|env| {
+            let db = env.db();
+            db.fake_release().name("foo").version("0.1.0").create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .build_result_successful(false)
+                .create()?;
+
+            let web = env.frontend();
+            for (url, expected) in &[
+                ("/crate/foo/0.1.0/badge.svg", "passing"),
+                ("/crate/foo/0.2.0/badge.svg", "failing"),
+                ("/crate/foo/latest/badge.svg", "failing"),
+                ("/crate/foo/^0.1/badge.svg", "passing"),
+                ("/crate/foo/0.3.0/badge.svg", "unknown"),
+                ("/crate/bar/latest/badge.svg", "unknown"),
+            ] {
+                let resp = web.get(url).send()?;
+                assert_eq!(resp.status(), StatusCode::OK, "{}", url);
+                assert_eq!(resp.headers()["content-type"], "image/svg+xml");
+                assert!(resp.headers()["cache-control"]
+                    .to_str()?
+                    .contains("no-cache"));
+                let svg = resp.text()?;
+                assert!(svg.starts_with("<svg"), "{} didn't return a SVG", url);
+                assert!(svg.contains(&format!(">{}</text>", expected)), "{}", url);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn build_badge_json() {
+        wrapper(|env| {
+            let db = env.db();
+            db.fake_release().name("foo").version("0.1.0").create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .build_result_successful(false)
+                .create()?;
+
+            let web = env.frontend();
+            for (url, message, color) in &[
+                ("/crate/foo/0.1.0/badge.json", "passing", "4c1"),
+                ("/crate/foo/latest/badge.json", "failing", "e05d44"),
+            ] {
+                let resp = web.get(url).send()?;
+                assert_eq!(resp.status(), StatusCode::OK, "{}", url);
+                assert_eq!(
+                    resp.json::<serde_json::Value>()?,
+                    json!({
+                        "schemaVersion": 1,
+                        "label": "build",
+                        "message": message,
+                        "color": color,
+                    })
+                );
+            }
+
+            for url in &[
+                "/crate/foo/0.3.0/badge.json",
+                "/crate/bar/latest/badge.json",
+            ] {
+                let resp = web.get(url).send()?;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", url);
+                assert_eq!(
+                    resp.json::<serde_json::Value>()?,
+                    json!({ "error": "crate not found" })
+                );
+            }
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn serialize_build() {
         let time = Utc::now();
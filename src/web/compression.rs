@@ -0,0 +1,328 @@
+//! Response compression, negotiated with the `Accept-Encoding` header sent by the client
+
+use iron::{
+    headers::{AcceptEncoding, ContentEncoding, ContentLength, ContentType, Encoding, Quality},
+    mime::{Mime, SubLevel, TopLevel},
+    response::WriteBody,
+    Request, Response,
+};
+use std::io::{self, Write};
+
+/// Responses smaller than this many bytes are not compressed, as the savings would be negligible.
+const MIN_COMPRESSION_SIZE: u64 = 1024;
+
+/// Compression level used for brotli, trading some compression ratio for speed.
+const BROTLI_QUALITY: u32 = 5;
+/// Size of the brotli sliding window, as a power of two.
+const BROTLI_WINDOW_SIZE: u32 = 22;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ResponseEncoding {
+    Brotli,
+    Gzip,
+}
+
+impl ResponseEncoding {
+    fn header(self) -> Encoding {
+        match self {
+            ResponseEncoding::Brotli => Encoding::EncodingExt("br".into()),
+            ResponseEncoding::Gzip => Encoding::Gzip,
+        }
+    }
+
+    /// Writes `body` to `out` compressed with this encoding, as it is produced.
+    fn compress_into(self, body: &mut dyn WriteBody, out: &mut dyn Write) -> io::Result<()> {
+        match self {
+            ResponseEncoding::Brotli => {
+                let out = ErrorTracking {
+                    inner: out,
+                    error: None,
+                };
+                let mut encoder =
+                    brotli::CompressorWriter::new(out, 4096, BROTLI_QUALITY, BROTLI_WINDOW_SIZE);
+                body.write_body(&mut encoder)?;
+                // Consuming the encoder finishes the brotli stream, but discards its errors
+                match encoder.into_inner().error {
+                    Some(err) => Err(err),
+                    None => Ok(()),
+                }
+            }
+            ResponseEncoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(out, flate2::Compression::default());
+                body.write_body(&mut encoder)?;
+                encoder.finish().map(|_| ())
+            }
+        }
+    }
+}
+
+/// Writer keeping a copy of the first error of the wrapped one, for the callers which don't
+/// report them.
+struct ErrorTracking<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W> ErrorTracking<W> {
+    fn track<T>(&mut self, res: io::Result<T>) -> io::Result<T> {
+        if let Err(err) = &res {
+            if self.error.is_none() {
+                self.error = Some(io::Error::new(err.kind(), err.to_string()));
+            }
+        }
+        res
+    }
+}
+
+impl<W: Write> Write for ErrorTracking<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = self.inner.write(buf);
+        self.track(res)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let res = self.inner.flush();
+        self.track(res)
+    }
+}
+
+/// Response body compressing the wrapped body while it is written to the client, without
+/// buffering it in memory.
+struct CompressedBody {
+    body: Box<dyn WriteBody>,
+    encoding: ResponseEncoding,
+}
+
+impl WriteBody for CompressedBody {
+    fn write_body(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.encoding.compress_into(&mut *self.body, out)
+    }
+}
+
+/// Picks the encoding with the highest quality accepted by the client, preferring brotli when
+/// both are equally acceptable.
+fn negotiate(req: &Request) -> Option<ResponseEncoding> {
+    let accepted = req.headers.get::<AcceptEncoding>()?;
+
+    let mut best: Option<(Quality, ResponseEncoding)> = None;
+    for item in accepted.iter() {
+        let encoding = match &item.item {
+            Encoding::Gzip => ResponseEncoding::Gzip,
+            Encoding::EncodingExt(ext) if ext == "br" => ResponseEncoding::Brotli,
+            _ => continue,
+        };
+        if item.quality == Quality(0) {
+            continue;
+        }
+
+        let better = match best {
+            Some((quality, current)) => {
+                item.quality > quality
+                    || (item.quality == quality
+                        && encoding == ResponseEncoding::Brotli
+                        && current != ResponseEncoding::Brotli)
+            }
+            None => true,
+        };
+        if better {
+            best = Some((item.quality, encoding));
+        }
+    }
+
+    best.map(|(_, encoding)| encoding)
+}
+
+/// Only text-based content benefits from compression: images and archives are already
+/// compressed.
+fn is_compressible(resp: &Response) -> bool {
+    match resp.headers.get::<ContentType>() {
+        Some(ContentType(Mime(TopLevel::Text, _, _))) => true,
+        Some(ContentType(Mime(TopLevel::Application, sub, _))) => match sub {
+            SubLevel::Javascript | SubLevel::Json | SubLevel::Xml => true,
+            SubLevel::Ext(ext) => ext == "javascript" || ext.ends_with("+xml"),
+            _ => false,
+        },
+        Some(ContentType(Mime(TopLevel::Image, SubLevel::Ext(ext), _))) => ext == "svg+xml",
+        _ => false,
+    }
+}
+
+/// Compresses the body of the response with the best encoding accepted by the client.
+///
+/// Responses which are already encoded, or known to be too small to be worth compressing, are
+/// left untouched, while bodies of unknown length are always compressed.
+pub(super) fn compress_response(req: &Request, mut resp: Response) -> Response {
+    if resp.headers.has::<ContentEncoding>() || !is_compressible(&resp) {
+        return resp;
+    }
+    // Caches must not serve a compressed response to clients not accepting it
+    resp.headers.append_raw("Vary", b"Accept-Encoding".to_vec());
+
+    let encoding = match negotiate(req) {
+        Some(encoding) => encoding,
+        None => return resp,
+    };
+    if let Some(ContentLength(length)) = resp.headers.get::<ContentLength>() {
+        if *length < MIN_COMPRESSION_SIZE {
+            return resp;
+        }
+    }
+    let body = match resp.body.take() {
+        Some(body) => body,
+        None => return resp,
+    };
+
+    resp.headers.remove::<ContentLength>();
+    resp.headers.set(ContentEncoding(vec![encoding.header()]));
+    resp.body = Some(Box::new(CompressedBody { body, encoding }));
+
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResponseEncoding;
+    use crate::test::wrapper;
+    use reqwest::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
+    use std::io::Read;
+
+    fn large_html() -> Vec<u8> {
+        b"<p>Some documentation</p>\n".repeat(1000)
+    }
+
+    #[test]
+    fn gzip_for_large_html() {
+        wrapper(|env| {
+            let content = large_html();
+            env.db()
+                .fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html", &content)
+                .create()?;
+
+            let resp = env
+                .frontend()
+                .get("/dummy/0.1.0/dummy/index.html")
+                .header(ACCEPT_ENCODING, "gzip, deflate")
+                .send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+            assert_eq!(resp.headers()["vary"], "Accept-Encoding");
+
+            let mut decoded = String::new();
+            flate2::read::GzDecoder::new(&resp.bytes()?[..]).read_to_string(&mut decoded)?;
+            assert!(decoded.contains("<p>Some documentation</p>"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn brotli_preferred_for_large_html() {
+        wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html", &large_html())
+                .create()?;
+
+            let resp = env
+                .frontend()
+                .get("/dummy/0.1.0/dummy/index.html")
+                .header(ACCEPT_ENCODING, "gzip, br")
+                .send()?;
+            assert_eq!(resp.headers()[CONTENT_ENCODING], "br");
+
+            let mut decoded = String::new();
+            brotli::Decompressor::new(&resp.bytes()?[..], 4096).read_to_string(&mut decoded)?;
+            assert!(decoded.contains("<p>Some documentation</p>"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn no_compression_when_not_accepted() {
+        wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html", &large_html())
+                .create()?;
+            let web = env.frontend();
+
+            let resp = web.get("/dummy/0.1.0/dummy/index.html").send()?;
+            assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+
+            let resp = web
+                .get("/dummy/0.1.0/dummy/index.html")
+                .header(ACCEPT_ENCODING, "gzip;q=0")
+                .send()?;
+            assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn no_compression_for_small_responses() {
+        wrapper(|env| {
+            let resp = env
+                .frontend()
+                .get("/robots.txt")
+                .header(ACCEPT_ENCODING, "gzip")
+                .send()?;
+            assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+            assert_eq!(resp.text()?, env.config().robots_txt);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn gzip_for_streamed_responses() {
+        wrapper(|env| {
+            let resp = env
+                .frontend()
+                .get("/sitemap.xml")
+                .header(ACCEPT_ENCODING, "gzip")
+                .send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+
+            let mut decoded = String::new();
+            flate2::read::GzDecoder::new(&resp.bytes()?[..]).read_to_string(&mut decoded)?;
+            assert!(decoded.ends_with("</urlset>\n"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn compression_errors_are_reported() {
+        struct BrokenPipe;
+
+        impl std::io::Write for BrokenPipe {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // The small bodies are only written out when the stream is finished
+        for encoding in &[ResponseEncoding::Brotli, ResponseEncoding::Gzip] {
+            let mut body = b"<p>Some documentation</p>".to_vec();
+            let err = encoding
+                .compress_into(&mut body, &mut BrokenPipe)
+                .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        }
+    }
+}
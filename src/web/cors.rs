@@ -0,0 +1,155 @@
+//! Cross-Origin Resource Sharing support for the JSON API, allowing browser-based tools hosted on
+//! other domains to call it.
+//!
+//! The API is read-only, so only `GET` requests are allowed. Any origin can call it unless a list
+//! of allowed origins is set with `DOCSRS_CORS_ALLOWED_ORIGINS`.
+
+use crate::Config;
+use iron::headers::{AccessControlAllowMethods, AccessControlAllowOrigin, AccessControlMaxAge};
+use iron::method::Method;
+use iron::prelude::*;
+use iron::{status, Handler};
+
+/// How long browsers can cache the result of a preflight request, in seconds
+const PREFLIGHT_MAX_AGE: u32 = 24 * 60 * 60;
+
+/// Handler wrapper adding the CORS headers to the responses of an API endpoint.
+pub(super) struct Cors<H: Handler> {
+    handler: H,
+}
+
+impl<H: Handler> Cors<H> {
+    pub(super) fn new(handler: H) -> Self {
+        Self { handler }
+    }
+}
+
+impl<H: Handler> Handler for Cors<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let allow_origin = allowed_origin(req);
+        let mut result = self.handler.handle(req);
+
+        let resp = match &mut result {
+            Ok(resp) => resp,
+            Err(err) => &mut err.response,
+        };
+        set_allow_origin(resp, allow_origin);
+
+        result
+    }
+}
+
+/// Responds to the preflight `OPTIONS` requests browsers send before calling the API.
+pub(super) fn preflight_handler(req: &mut Request) -> IronResult<Response> {
+    let mut resp = Response::with(status::NoContent);
+    set_allow_origin(&mut resp, allowed_origin(req));
+    resp.headers.set(AccessControlAllowMethods(vec![
+        Method::Get,
+        Method::Options,
+    ]));
+    resp.headers.set(AccessControlMaxAge(PREFLIGHT_MAX_AGE));
+    Ok(resp)
+}
+
+/// Returns the `Access-Control-Allow-Origin` allowing the request's origin, if it's allowed.
+fn allowed_origin(req: &Request) -> Option<AccessControlAllowOrigin> {
+    let allowed_origins = &req.extensions.get::<Config>()?.cors_allowed_origins;
+    if allowed_origins.is_empty() {
+        return Some(AccessControlAllowOrigin::Any);
+    }
+
+    let origin = req
+        .headers
+        .get_raw("origin")
+        .and_then(|values| values.first())
+        .and_then(|value| std::str::from_utf8(value).ok())?;
+    if allowed_origins.contains(origin) {
+        Some(AccessControlAllowOrigin::Value(origin.into()))
+    } else {
+        None
+    }
+}
+
+fn set_allow_origin(resp: &mut Response, allow_origin: Option<AccessControlAllowOrigin>) {
+    // The header depends on the request's origin unless all origins are allowed
+    if allow_origin != Some(AccessControlAllowOrigin::Any) {
+        resp.headers.append_raw("Vary", b"Origin".to_vec());
+    }
+    if let Some(allow_origin) = allow_origin {
+        resp.headers.set(allow_origin);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use reqwest::{Method, StatusCode};
+
+    #[test]
+    fn test_preflight() {
+        wrapper(|env| {
+            let web = env.frontend();
+
+            let resp = web
+                .request(Method::OPTIONS, "/api/v1/releases")
+                .header("Origin", "https://example.com")
+                .header("Access-Control-Request-Method", "GET")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+            assert_eq!(resp.headers()["access-control-allow-origin"], "*");
+            assert_eq!(
+                resp.headers()["access-control-allow-methods"],
+                "GET, OPTIONS"
+            );
+            assert_eq!(resp.headers()["access-control-max-age"], "86400");
+
+            let resp = web
+                .get("/api/v1/releases")
+                .header("Origin", "https://example.com")
+                .send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(resp.headers()["access-control-allow-origin"], "*");
+
+            // Only the API is available to other origins
+            let resp = web
+                .get("/about")
+                .header("Origin", "https://example.com")
+                .send()?;
+            assert!(!resp.headers().contains_key("access-control-allow-origin"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_allowed_origins() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.cors_allowed_origins = vec!["https://example.com".to_string()]
+                    .into_iter()
+                    .collect();
+            });
+            let web = env.frontend();
+
+            for method in &[Method::OPTIONS, Method::GET] {
+                let resp = web
+                    .request(method.clone(), "/api/v1/crates/foo/versions")
+                    .header("Origin", "https://example.com")
+                    .send()?;
+                assert_eq!(
+                    resp.headers()["access-control-allow-origin"],
+                    "https://example.com"
+                );
+                assert_eq!(resp.headers()["vary"], "Origin");
+
+                let resp = web
+                    .request(method.clone(), "/api/v1/crates/foo/versions")
+                    .header("Origin", "https://example.org")
+                    .send()?;
+                assert!(!resp.headers().contains_key("access-control-allow-origin"));
+            }
+
+            Ok(())
+        });
+    }
+}
@@ -1,12 +1,18 @@
 use super::error::Nope;
 use super::page::Page;
 use super::{
-    duration_to_str, match_version, redirect_base, render_markdown, MatchSemver, MetaData,
+    api, duration_to_str, match_version, redirect_base, render_markdown, MatchSemver, MetaData,
 };
 use crate::db::Pool;
+use crate::storage::{PathNotFoundError, Storage};
+use crate::Config;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use iron::prelude::*;
-use iron::{status, Url};
+use iron::{
+    headers::{Accept, Quality},
+    mime::{Mime, SubLevel, TopLevel},
+    status, Url,
+};
 use postgres::Connection;
 use router::Router;
 use serde::{
@@ -23,7 +29,7 @@ pub struct CrateDetails {
     version: String,
     description: Option<String>,
     authors: Vec<(String, String)>,
-    owners: Vec<(String, String)>,
+    owners: Vec<Owner>,
     authors_json: Option<Value>,
     dependencies: Option<Value>,
     readme: Option<String>,
@@ -31,6 +37,8 @@ pub struct CrateDetails {
     release_time: DateTime<Utc>,
     build_status: bool,
     last_successful_build: Option<String>,
+    last_build_attempt: Option<DateTime<Utc>>,
+    last_build_error: Option<String>,
     rustdoc_status: bool,
     repository_url: Option<String>,
     homepage_url: Option<String>,
@@ -48,6 +56,8 @@ pub struct CrateDetails {
     pub(crate) doc_targets: Vec<String>,
     license: Option<String>,
     documentation_url: Option<String>,
+    /// Path of the changelog among the source files of the release, if it ships one.
+    changelog: Option<String>,
 }
 
 impl Serialize for CrateDetails {
@@ -61,7 +71,7 @@ impl Serialize for CrateDetails {
         // needs to be serialized)
         let mut state = serializer.serialize_struct(
             "CrateDetails",
-            26 + self.readme.is_some() as usize + self.rustdoc.is_some() as usize,
+            30 + self.readme.is_some() as usize + self.rustdoc.is_some() as usize,
         )?;
 
         state.serialize_field("metadata", &self.metadata)?;
@@ -72,6 +82,18 @@ impl Serialize for CrateDetails {
         state.serialize_field("owners", &self.owners)?;
         state.serialize_field("authors_json", &self.authors_json)?;
         state.serialize_field("dependencies", &self.dependencies)?;
+        // Lets the description link the dependencies it mentions
+        let dependency_names = self
+            .dependencies
+            .as_ref()
+            .and_then(|deps| deps.as_array())
+            .map(|deps| {
+                deps.iter()
+                    .filter_map(|dep| dep.get(0).and_then(|name| name.as_str()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        state.serialize_field("dependency_names", &dependency_names)?;
 
         if let Some(ref readme) = self.readme {
             state.serialize_field("readme", &render_markdown(&readme))?;
@@ -84,13 +106,28 @@ impl Serialize for CrateDetails {
         state.serialize_field("release_time", &duration_to_str(self.release_time))?;
         state.serialize_field("build_status", &self.build_status)?;
         state.serialize_field("last_successful_build", &self.last_successful_build)?;
+        state.serialize_field(
+            "last_build_attempt",
+            &self.last_build_attempt.map(duration_to_str),
+        )?;
+        state.serialize_field("last_build_error", &self.last_build_error)?;
         state.serialize_field("rustdoc_status", &self.rustdoc_status)?;
         state.serialize_field("repository_url", &self.repository_url)?;
         state.serialize_field("homepage_url", &self.homepage_url)?;
         state.serialize_field("keywords", &self.keywords)?;
         state.serialize_field("have_examples", &self.have_examples)?;
         state.serialize_field("target_name", &self.target_name)?;
-        state.serialize_field("releases", &self.releases)?;
+        // The list of versions highlights what changed since the previous, older, version
+        let releases = self
+            .releases
+            .iter()
+            .enumerate()
+            .map(|(idx, release)| ListedRelease {
+                release,
+                previous_version: self.releases.get(idx + 1).map(|prev| prev.version.as_str()),
+            })
+            .collect::<Vec<_>>();
+        state.serialize_field("releases", &releases)?;
         state.serialize_field("github", &self.github)?;
         state.serialize_field("github_stars", &self.github_stars)?;
         state.serialize_field("github_forks", &self.github_forks)?;
@@ -101,11 +138,19 @@ impl Serialize for CrateDetails {
         state.serialize_field("yanked", &self.yanked)?;
         state.serialize_field("license", &self.license)?;
         state.serialize_field("documentation_url", &self.documentation_url)?;
+        state.serialize_field("changelog", &self.changelog)?;
 
         state.end()
     }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+struct Owner {
+    login: String,
+    name: Option<String>,
+    avatar: Option<String>,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct Release {
     pub version: String,
@@ -113,6 +158,15 @@ pub struct Release {
     pub yanked: bool,
 }
 
+/// A release in the list of versions of `CrateDetails`, which are sorted from the newest to the
+/// oldest.
+#[derive(Debug, Serialize)]
+struct ListedRelease<'a> {
+    #[serde(flatten)]
+    release: &'a Release,
+    previous_version: Option<&'a str>,
+}
+
 impl CrateDetails {
     pub fn new(conn: &Connection, name: &str, version: &str) -> Option<CrateDetails> {
         // get all stuff, I love you rustfmt
@@ -129,6 +183,8 @@ impl CrateDetails {
                 releases.description_long,
                 releases.release_time,
                 releases.build_status,
+                releases.last_build_attempt,
+                releases.last_build_error,
                 releases.rustdoc_status,
                 releases.repository_url,
                 releases.homepage_url,
@@ -144,7 +200,8 @@ impl CrateDetails {
                 releases.doc_targets,
                 releases.license,
                 releases.documentation_url,
-                releases.default_target
+                releases.default_target,
+                releases.files
             FROM releases
             INNER JOIN crates ON releases.crate_id = crates.id
             WHERE crates.name = $1 AND releases.version = $2;";
@@ -210,6 +267,10 @@ impl CrateDetails {
             release_time: DateTime::from_utc(krate.get::<_, NaiveDateTime>("release_time"), Utc),
             build_status: krate.get("build_status"),
             last_successful_build: None,
+            last_build_attempt: krate
+                .get::<_, Option<NaiveDateTime>>("last_build_attempt")
+                .map(|time| DateTime::from_utc(time, Utc)),
+            last_build_error: krate.get("last_build_error"),
             rustdoc_status: krate.get("rustdoc_status"),
             repository_url: krate.get("repository_url"),
             homepage_url: krate.get("homepage_url"),
@@ -227,6 +288,9 @@ impl CrateDetails {
             doc_targets,
             license: krate.get("license"),
             documentation_url: krate.get("documentation_url"),
+            changelog: krate
+                .get::<_, Option<Value>>("files")
+                .and_then(|files| find_changelog(&files)),
         };
 
         if let Some(repository_url) = crate_details.repository_url.clone() {
@@ -250,21 +314,7 @@ impl CrateDetails {
             .map(|row| (row.get("name"), row.get("slug")))
             .collect();
 
-        // get owners
-        let owners = conn
-            .query(
-                "SELECT login, avatar
-                 FROM owners
-                 INNER JOIN owner_rels ON owner_rels.oid = owners.id
-                 WHERE cid = $1",
-                &[&crate_id],
-            )
-            .unwrap();
-
-        crate_details.owners = owners
-            .into_iter()
-            .map(|row| (row.get("login"), row.get("avatar")))
-            .collect();
+        crate_details.owners = load_owners(conn, crate_id).unwrap();
 
         if !crate_details.build_status {
             crate_details.last_successful_build = crate_details
@@ -302,6 +352,8 @@ impl CrateDetails {
             release_time,
             build_status: true,
             last_successful_build: None,
+            last_build_attempt: None,
+            last_build_error: None,
             rustdoc_status: true,
             repository_url: None,
             homepage_url: None,
@@ -326,8 +378,135 @@ impl CrateDetails {
             doc_targets: vec![],
             license: None,
             documentation_url: None,
+            changelog: None,
+        }
+    }
+}
+
+/// File names, without extension and compared case-insensitively, of the changelogs.
+const CHANGELOG_NAMES: &[&str] = &["changelog", "changes", "history"];
+
+/// Finds the changelog in the `[[mimetype, path]]` list of the source files of a release, only
+/// looking at the root of the crate.
+fn find_changelog(files: &Value) -> Option<String> {
+    files
+        .as_array()?
+        .iter()
+        .filter_map(|file| file.get(1)?.as_str())
+        .find(|path| {
+            let stem = path.split('.').next().unwrap_or(path).to_lowercase();
+            !path.contains('/') && CHANGELOG_NAMES.contains(&stem.as_str())
+        })
+        .map(|path| path.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ChangelogPage {
+    metadata: MetaData,
+    changelog: String,
+}
+
+/// Renders the changelog shipped with a release as HTML.
+pub fn changelog_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let version = cexpect!(router.find("version"));
+    let conn = extension!(req, Pool).get()?;
+    let config = extension!(req, Config);
+
+    let details = match CrateDetails::new(&conn, name, version) {
+        Some(details) => details,
+        None => return Err(IronError::new(Nope::CrateNotFound, status::NotFound)),
+    };
+    let path = match &details.changelog {
+        Some(path) => format!("sources/{}/{}/{}", name, version, path),
+        None => return Err(IronError::new(Nope::ResourceNotFound, status::NotFound)),
+    };
+    let blob = match Storage::new(&conn, &config.storage_backend).get(&path, config.max_file_size) {
+        Ok(blob) => blob,
+        Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => {
+            return Err(IronError::new(Nope::ResourceNotFound, status::NotFound));
+        }
+        Err(err) => return Err(IronError::new(err.compat(), status::InternalServerError)),
+    };
+
+    Page::new(ChangelogPage {
+        metadata: details.metadata,
+        changelog: render_markdown(&String::from_utf8_lossy(&blob.content)),
+    })
+    .title("Changelog")
+    .set_true("show_package_navigation")
+    .to_resp("changelog")
+}
+
+/// The documented releases of a crate surrounding one of its versions, in semver order.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct AdjacentVersions {
+    pub(crate) older: Option<String>,
+    pub(crate) newer: Option<String>,
+}
+
+/// Finds the documented releases immediately older and newer than `version`, which are `None` at
+/// the boundaries of the version history.
+pub(crate) fn adjacent_versions(
+    conn: &Connection,
+    name: &str,
+    version: &str,
+) -> Result<AdjacentVersions, postgres::Error> {
+    let current = match semver::Version::parse(version) {
+        Ok(current) => current,
+        Err(_) => return Ok(AdjacentVersions::default()),
+    };
+
+    let rows = conn.query(
+        "SELECT releases.version
+         FROM releases
+         INNER JOIN crates ON releases.crate_id = crates.id
+         WHERE crates.name = $1 AND releases.rustdoc_status = TRUE",
+        &[&name],
+    )?;
+
+    let mut older: Option<(semver::Version, String)> = None;
+    let mut newer: Option<(semver::Version, String)> = None;
+    for row in &rows {
+        let raw: String = row.get(0);
+        let parsed = match semver::Version::parse(&raw) {
+            Ok(parsed) => parsed,
+            Err(_) => continue,
+        };
+
+        if parsed < current && older.as_ref().map_or(true, |(older, _)| parsed > *older) {
+            older = Some((parsed, raw));
+        } else if parsed > current && newer.as_ref().map_or(true, |(newer, _)| parsed < *newer) {
+            newer = Some((parsed, raw));
         }
     }
+
+    Ok(AdjacentVersions {
+        older: older.map(|(_, raw)| raw),
+        newer: newer.map(|(_, raw)| raw),
+    })
+}
+
+/// Loads the owners of a crate, sorted by login.
+fn load_owners(conn: &Connection, crate_id: i32) -> Result<Vec<Owner>, postgres::Error> {
+    let rows = conn.query(
+        "SELECT login, name, avatar
+         FROM owners
+         INNER JOIN owner_rels ON owner_rels.oid = owners.id
+         WHERE cid = $1
+         ORDER BY login",
+        &[&crate_id],
+    )?;
+
+    Ok(rows
+        .iter()
+        .map(|row| Owner {
+            login: row.get(0),
+            name: row.get(1),
+            avatar: row.get(2),
+        })
+        .collect())
 }
 
 fn map_to_release(conn: &Connection, crate_id: i32, version: String) -> Release {
@@ -365,11 +544,35 @@ pub fn crate_details_handler(req: &mut Request) -> IronResult<Response> {
         Some(MatchSemver::Exact((version, _))) => {
             let details = CrateDetails::new(&conn, &name, &version);
 
-            Page::new(details)
-                .set_true("show_package_navigation")
-                .set_true("javascript_highlightjs")
-                .set_true("package_navigation_crate_tab")
-                .to_resp("crate_details")
+            let mut resp = if prefers_json(req) {
+                match &details {
+                    Some(details) => api::json_response(status::Ok, details),
+                    None => api::not_found(),
+                }
+            } else if let Some(details) = details.as_ref().filter(|details| details.rustdoc_status)
+            {
+                // Releases with documentation are redirected to the documentation of their
+                // default target instead of showing the metadata
+                let url = ctry!(Url::parse(&format!(
+                    "{}/{}/{}/{}/index.html",
+                    redirect_base(req),
+                    details.name,
+                    details.version,
+                    details.target_name,
+                )));
+
+                super::redirect(url)
+            } else {
+                Page::new(details)
+                    .set_true("show_package_navigation")
+                    .set_true("javascript_highlightjs")
+                    .set_true("package_navigation_crate_tab")
+                    .to_resp("crate_details")?
+            };
+            // Caches must not serve the JSON response to browsers and vice versa
+            resp.headers.append_raw("Vary", b"Accept".to_vec());
+
+            Ok(resp)
         }
         Some(MatchSemver::Semver((version, _))) => {
             let url = ctry!(Url::parse(
@@ -378,14 +581,150 @@ pub fn crate_details_handler(req: &mut Request) -> IronResult<Response> {
 
             Ok(super::redirect(url))
         }
+        None if prefers_json(req) => Ok(api::not_found()),
         None => Err(IronError::new(Nope::CrateNotFound, status::NotFound)),
     }
 }
 
+/// Returns whether the client prefers JSON over HTML according to its `Accept` header, HTML is
+/// picked when both are equally acceptable or when the header is missing.
+fn prefers_json(req: &Request) -> bool {
+    let accepted = match req.headers.get::<Accept>() {
+        Some(accepted) => accepted,
+        None => return false,
+    };
+
+    let quality = |accepts: fn(&Mime) -> bool| {
+        accepted
+            .iter()
+            .filter(|item| item.quality != Quality(0) && accepts(&item.item))
+            .map(|item| item.quality)
+            .max()
+    };
+    let json = quality(|mime| matches!(mime, Mime(TopLevel::Application, SubLevel::Json, _)));
+    let html = quality(|mime| {
+        matches!(
+            mime,
+            Mime(TopLevel::Text, SubLevel::Html, _)
+                | Mime(TopLevel::Text, SubLevel::Star, _)
+                | Mime(TopLevel::Star, _, _)
+        )
+    });
+
+    json > html
+}
+
+/// Handler for `/crate/:name/owners`, returning the owners of a crate as JSON. The list is empty
+/// when the owners of the crate are unknown.
+pub fn crate_owners_handler(req: &mut Request) -> IronResult<Response> {
+    let name = cexpect!(extension!(req, Router).find("name"));
+    let conn = extension!(req, Pool).get_replica()?;
+
+    let rows = ctry!(conn.query(
+        "SELECT id FROM crates WHERE normalize_crate_name(name) = normalize_crate_name($1)",
+        &[&name],
+    ));
+    if rows.is_empty() {
+        return Ok(api::not_found());
+    }
+    let owners = ctry!(load_owners(&conn, rows.get(0).get(0)));
+
+    Ok(api::json_response(status::Ok, &owners))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct TargetInfo {
+    target: String,
+    default: bool,
+    build_status: bool,
+}
+
+/// Loads the targets documented for a release, the default one first.
+///
+/// Releases built before the status of each target was recorded fall back to their default
+/// target and the targets documented successfully.
+fn load_targets(conn: &Connection, release_id: i32) -> Result<Vec<TargetInfo>, postgres::Error> {
+    let rows = conn.query(
+        "SELECT release_targets.target,
+                release_targets.target = releases.default_target,
+                release_targets.build_status
+         FROM release_targets
+         INNER JOIN releases ON releases.id = release_targets.release
+         WHERE release_targets.release = $1
+         ORDER BY 2 DESC, 1",
+        &[&release_id],
+    )?;
+    if !rows.is_empty() {
+        return Ok(rows
+            .iter()
+            .map(|row| TargetInfo {
+                target: row.get(0),
+                default: row.get(1),
+                build_status: row.get(2),
+            })
+            .collect());
+    }
+
+    let rows = conn.query(
+        "SELECT default_target, build_status, doc_targets FROM releases WHERE id = $1",
+        &[&release_id],
+    )?;
+    let row = rows.get(0);
+    let default_target: String = row.get(0);
+    let mut doc_targets = row
+        .get::<_, Value>(2)
+        .as_array()
+        .map(|array| {
+            array
+                .iter()
+                .filter_map(|item| item.as_str())
+                .filter(|&target| target != default_target)
+                .map(|target| target.to_owned())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    doc_targets.sort();
+
+    let mut targets = vec![TargetInfo {
+        target: default_target,
+        default: true,
+        build_status: row.get(1),
+    }];
+    targets.extend(doc_targets.into_iter().map(|target| TargetInfo {
+        target,
+        default: false,
+        build_status: true,
+    }));
+    Ok(targets)
+}
+
+/// Handler for `/crate/:name/:version/targets`, returning the targets documented for a release
+/// and their build status as JSON, the default target first.
+pub fn crate_targets_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let version = cexpect!(router.find("version"));
+    let conn = extension!(req, Pool).get_replica()?;
+
+    let rows = ctry!(conn.query(
+        "SELECT releases.id
+         FROM releases
+         INNER JOIN crates ON releases.crate_id = crates.id
+         WHERE crates.name = $1 AND releases.version = $2",
+        &[&name, &version],
+    ));
+    if rows.is_empty() {
+        return Ok(api::not_found());
+    }
+    let targets = ctry!(load_targets(&conn, rows.get(0).get(0)));
+
+    Ok(api::json_response(status::Ok, &targets))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test::TestDatabase;
+    use crate::test::{assert_redirect, assert_success, TestDatabase};
     use chrono::Utc;
     use failure::Error;
     use serde_json::json;
@@ -647,9 +986,12 @@ mod tests {
             "owners": [],
             "authors_json": null,
             "dependencies": null,
+            "dependency_names": [],
             "release_time": super::super::duration_to_str(time),
             "build_status": true,
             "last_successful_build": null,
+            "last_build_attempt": null,
+            "last_build_error": null,
             "rustdoc_status": true,
             "repository_url": null,
             "homepage_url": null,
@@ -673,13 +1015,18 @@ mod tests {
             "is_library": true,
             "doc_targets": [],
             "license": null,
-            "documentation_url": null
+            "documentation_url": null,
+            "changelog": null
         });
 
         assert_eq!(correct_json, serde_json::to_value(&details).unwrap());
 
         let authors = vec![("Somebody".to_string(), "somebody@somebody.com".to_string())];
-        let owners = vec![("Owner".to_string(), "owner@ownsstuff.com".to_string())];
+        let owners = vec![Owner {
+            login: "owner".into(),
+            name: Some("Owner".into()),
+            avatar: Some("https://avatars.example.com/owner".into()),
+        }];
         let description = "serde does stuff".to_string();
 
         correct_json["description"] = Value::String(description.clone());
@@ -693,6 +1040,61 @@ mod tests {
         details.authors = authors;
 
         assert_eq!(correct_json, serde_json::to_value(&details).unwrap());
+
+        let release = |version: &str| Release {
+            version: version.into(),
+            build_status: true,
+            yanked: false,
+        };
+        details.releases = vec![release("1.1.0"), release("1.0.0")];
+        correct_json["releases"] = json!([
+            {"version": "1.1.0", "build_status": true, "yanked": false, "previous_version": "1.0.0"},
+            {"version": "1.0.0", "build_status": true, "yanked": false, "previous_version": null},
+        ]);
+
+        assert_eq!(correct_json, serde_json::to_value(&details).unwrap());
+    }
+
+    #[test]
+    fn description_links_dependencies() {
+        crate::test::wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .description("Wraps fake-dependency in <b>fake</b> ways")
+                .binary(true)
+                .create()?;
+
+            let page = env.frontend().get("/crate/foo/0.1.0").send()?.text()?;
+            assert!(page.contains(
+                r#"Wraps <a href="/crate/fake-dependency">fake-dependency</a> in &lt;b&gt;fake&lt;&#x2F;b&gt; ways"#
+            ));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn versions_highlight_the_changed_component() {
+        crate::test::wrapper(|env| {
+            let db = env.db();
+            // The crate page of documented releases redirects to their documentation
+            for version in &["0.1.0", "0.2.0", "0.2.1"] {
+                db.fake_release()
+                    .name("foo")
+                    .version(version)
+                    .binary(true)
+                    .create()?;
+            }
+
+            let page = env.frontend().get("/crate/foo/0.2.1").send()?.text()?;
+            assert!(page.contains(r#"0.2.<span class="version-changed">1</span>"#));
+            assert!(page.contains(r#"0.<span class="version-changed">2</span>.0"#));
+            assert!(page.contains(">0.1.0</a>"));
+
+            Ok(())
+        })
     }
 
     #[test]
@@ -711,4 +1113,285 @@ mod tests {
 
         assert_eq!(correct_json, serde_json::to_value(&release).unwrap());
     }
+
+    #[test]
+    fn redirect_to_documentation() {
+        crate::test::wrapper(|env| {
+            let db = env.db();
+            db.fake_release().name("foo").version("0.1.0").create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .build_result_successful(false)
+                .create()?;
+            let web = env.frontend();
+
+            assert_redirect("/crate/foo/0.1.0", "/foo/0.1.0/foo/index.html", web)?;
+            assert_redirect("/crate/foo/~0.1", "/foo/0.1.0/foo/index.html", web)?;
+
+            // Releases without documentation still show their metadata
+            assert_success("/crate/foo/0.2.0", web)?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn content_negotiation() {
+        crate::test::wrapper(|env| {
+            let db = env.db();
+            db.fake_release().name("foo").version("0.1.0").create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .binary(true)
+                .create()?;
+            let web = env.frontend();
+
+            for path in &["/crate/foo/0.1.0", "/crate/foo/0.2.0"] {
+                let resp = web
+                    .get(path)
+                    .header("Accept", "text/html;q=0.9, application/json")
+                    .send()?;
+                assert_eq!(resp.status(), reqwest::StatusCode::OK);
+                assert_eq!(resp.url().path(), *path);
+                assert_eq!(resp.headers()["content-type"], "application/json");
+                let details: Value = resp.json()?;
+                assert_eq!(details["name"], "foo");
+                assert_eq!(details["version"], path.rsplit('/').next().unwrap());
+            }
+
+            let resp = web
+                .get("/crate/foo/0.3.0")
+                .header("Accept", "application/json")
+                .send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+            assert_eq!(resp.headers()["content-type"], "application/json");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn html_by_default() {
+        crate::test::wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .binary(true)
+                .create()?;
+            let web = env.frontend();
+
+            for accept in &[None, Some("*/*"), Some("text/html, application/json")] {
+                let mut req = web.get("/crate/foo/0.1.0");
+                if let Some(accept) = accept {
+                    req = req.header("Accept", *accept);
+                }
+                let resp = req.send()?;
+                assert_eq!(resp.status(), reqwest::StatusCode::OK);
+                assert!(resp.headers()["content-type"]
+                    .to_str()?
+                    .starts_with("text/html"));
+                assert!(resp.text()?.contains("<html"));
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn failed_and_yanked_releases() {
+        crate::test::wrapper(|env| {
+            let db = env.db();
+            db.fake_release().name("foo").version("0.1.0").create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .build_result_successful(false)
+                .create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.3.0")
+                .build_result_successful(false)
+                .yanked(true)
+                .create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.4.0")
+                .yanked(true)
+                .create()?;
+            let web = env.frontend();
+
+            let failed = web.get_body("/crate/foo/0.2.0")?;
+            assert!(failed.contains("docs.rs failed to build foo-0.2.0"));
+            assert!(failed.contains(r#"<a href="/crate/foo/0.1.0">foo-0.1.0</a>"#));
+
+            let yanked = web.get_body("/crate/foo/0.3.0")?;
+            assert!(yanked.contains("foo-0.3.0 has been yanked."));
+            assert!(!yanked.contains("docs.rs failed to build foo-0.3.0"));
+
+            let yanked_docs = web.get_body("/foo/0.4.0/foo/")?;
+            assert!(yanked_docs.contains("This release has been yanked, go to latest version"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn crate_targets() {
+        crate::test::wrapper(|env| {
+            let db = env.db();
+            db.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .default_target("x86_64-unknown-linux-gnu")
+                .add_target("x86_64-pc-windows-msvc")
+                .add_target("i686-unknown-linux-gnu")
+                .add_failed_target("wasm32-unknown-unknown")
+                .create()?;
+            db.fake_release().name("foo").version("0.2.0").create()?;
+            let web = env.frontend();
+
+            let targets: Value = web.get_json("/crate/foo/0.1.0/targets")?;
+            assert_eq!(
+                targets,
+                json!([
+                    {
+                        "target": "x86_64-unknown-linux-gnu",
+                        "default": true,
+                        "build_status": true,
+                    },
+                    {
+                        "target": "i686-unknown-linux-gnu",
+                        "default": false,
+                        "build_status": true,
+                    },
+                    {
+                        "target": "wasm32-unknown-unknown",
+                        "default": false,
+                        "build_status": false,
+                    },
+                    {
+                        "target": "x86_64-pc-windows-msvc",
+                        "default": false,
+                        "build_status": true,
+                    },
+                ])
+            );
+
+            // Only the default target is listed when no other target is documented
+            let targets: Value = web.get_json("/crate/foo/0.2.0/targets")?;
+            assert_eq!(
+                targets,
+                json!([{
+                    "target": "x86_64-unknown-linux-gnu",
+                    "default": true,
+                    "build_status": true,
+                }])
+            );
+
+            // Releases built before the targets were recorded fall back to the doc targets
+            db.conn().execute("DELETE FROM release_targets", &[])?;
+            let targets: Value = web.get_json("/crate/foo/0.1.0/targets")?;
+            assert_eq!(
+                targets
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|target| target["target"].as_str().unwrap())
+                    .collect::<Vec<_>>(),
+                [
+                    "x86_64-unknown-linux-gnu",
+                    "i686-unknown-linux-gnu",
+                    "x86_64-pc-windows-msvc",
+                ],
+            );
+
+            let resp = web.get("/crate/foo/0.3.0/targets").send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn crate_owners() {
+        crate::test::wrapper(|env| {
+            let db = env.db();
+            db.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .add_owner("bob")
+                .add_owner("alice")
+                .create()?;
+            db.fake_release().name("bar").create()?;
+            let web = env.frontend();
+
+            let owners: Value = web.get_json("/crate/foo/owners")?;
+            assert_eq!(
+                owners,
+                json!([
+                    {
+                        "login": "alice",
+                        "name": "alice",
+                        "avatar": "https://avatars.example.com/alice",
+                    },
+                    {
+                        "login": "bob",
+                        "name": "bob",
+                        "avatar": "https://avatars.example.com/bob",
+                    },
+                ])
+            );
+
+            // New releases replace the owners
+            db.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .add_owner("alice")
+                .create()?;
+            let owners: Value = web.get_json("/crate/foo/owners")?;
+            assert_eq!(owners.as_array().unwrap().len(), 1);
+            assert_eq!(owners[0]["login"], "alice");
+
+            let resp = web.get("/crate/bar/owners").send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(resp.json::<Value>()?, json!([]));
+
+            let resp = web.get("/crate/baz/owners").send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn changelog() {
+        crate::test::wrapper(|env| {
+            let db = env.db();
+            db.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .source_file("CHANGELOG.md", b"# Changes\n\n* Fixed *everything*")
+                .create()?;
+            db.fake_release().name("foo").version("0.2.0").create()?;
+            let web = env.frontend();
+
+            crate::test::assert_content_type("/crate/foo/0.1.0/changelog", "text/html", web)?;
+            let resp = web.get("/crate/foo/0.1.0/changelog").send()?;
+            assert!(resp.status().is_success());
+            let page = resp.text()?;
+            assert!(page.contains("<h1>Changes</h1>"));
+            assert!(page.contains("<li>Fixed <em>everything</em></li>"));
+
+            let details = CrateDetails::new(&db.conn(), "foo", "0.1.0").unwrap();
+            assert_eq!(details.changelog.as_deref(), Some("CHANGELOG.md"));
+
+            let resp = web.get("/crate/foo/0.2.0/changelog").send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
 }
@@ -1,14 +1,16 @@
 use crate::{
     db::PoolError,
     web::{
-        page::{Page, WebPage},
+        page::WebPage,
         releases::Search,
+        suggestions::{crate_suggestions, missing_crate, MissingCrate},
     },
 };
 use failure::Fail;
 use iron::{status, Handler, IronError, IronResult, Plugin, Request, Response};
 use params::{Params, Value};
-use std::{error::Error, fmt};
+use serde::Serialize;
+use std::{borrow::Cow, error::Error, fmt};
 
 #[derive(Debug, Copy, Clone)]
 pub enum Nope {
@@ -16,6 +18,7 @@ pub enum Nope {
     CrateNotFound,
     NoResults,
     InternalServerError,
+    ServiceUnavailable,
 }
 
 impl fmt::Display for Nope {
@@ -25,6 +28,7 @@ impl fmt::Display for Nope {
             Nope::CrateNotFound => "Requested crate not found",
             Nope::NoResults => "Search yielded no results",
             Nope::InternalServerError => "Internal server error",
+            Nope::ServiceUnavailable => "Service unavailable",
         })
     }
 }
@@ -36,19 +40,41 @@ impl Handler for Nope {
         match *self {
             Nope::ResourceNotFound => {
                 // user tried to navigate to a resource (doc page/file) that doesn't exist
-                Page::new("no such resource".to_owned())
-                    .set_status(status::NotFound)
-                    .title("The requested resource does not exist")
-                    .to_resp("error")
+                ErrorPage {
+                    title: "The requested resource does not exist".into(),
+                    message: Some("no such resource".into()),
+                    status: status::NotFound,
+                    suggestions: crate_suggestions(req),
+                }
+                .into_response(req)
             }
 
-            Nope::CrateNotFound => {
+            Nope::CrateNotFound => match missing_crate(req) {
                 // user tried to navigate to a crate that doesn't exist
-                Page::new("no such crate".to_owned())
-                    .set_status(status::NotFound)
-                    .title("The requested crate does not exist")
-                    .to_resp("error")
-            }
+                MissingCrate::Unknown => ErrorPage {
+                    title: "The requested crate does not exist".into(),
+                    message: Some("no such crate".into()),
+                    status: status::NotFound,
+                    suggestions: crate_suggestions(req),
+                }
+                .into_response(req),
+
+                // the crate exists on crates.io, but docs.rs didn't build it yet
+                MissingCrate::Queued => ErrorPage {
+                    title: "The requested crate has not been documented yet".into(),
+                    message: Some("the crate is queued but not yet documented".into()),
+                    status: status::NotFound,
+                    suggestions: Vec::new(),
+                }
+                .into_response(req),
+                MissingCrate::NotDocumented => ErrorPage {
+                    title: "The requested crate has not been documented yet".into(),
+                    message: Some("the crate exists on crates.io but is not documented".into()),
+                    status: status::NotFound,
+                    suggestions: Vec::new(),
+                }
+                .into_response(req),
+            },
 
             Nope::NoResults => {
                 let params = req.get::<Params>().unwrap();
@@ -75,17 +101,182 @@ impl Handler for Nope {
 
             Nope::InternalServerError => {
                 // something went wrong, details should have been logged
-                Page::new("internal server error".to_owned())
-                    .set_status(status::InternalServerError)
-                    .title("Internal server error")
-                    .to_resp("error")
+                ErrorPage::new(status::InternalServerError, "internal server error")
+                    .into_response(req)
+            }
+
+            Nope::ServiceUnavailable => {
+                // the server is overloaded, the request can be retried later
+                let mut resp = ErrorPage::new(
+                    status::ServiceUnavailable,
+                    "the server is overloaded, please try again later",
+                )
+                .into_response(req)?;
+                resp.headers.set_raw(
+                    "Retry-After",
+                    vec![SERVICE_UNAVAILABLE_RETRY_AFTER.to_string().into_bytes()],
+                );
+                Ok(resp)
             }
         }
     }
 }
 
+/// Seconds clients are asked to wait before retrying a request when the server is overloaded
+const SERVICE_UNAVAILABLE_RETRY_AFTER: u64 = 5;
+
+/// Templates of the statuses with a dedicated error page, the others use `error/error.html`.
+const STATUS_TEMPLATES: &[(status::Status, &str)] = &[
+    (status::NotFound, "error/404.html"),
+    (status::InternalServerError, "error/500.html"),
+];
+
+/// A styled error page, rendered with the template of its status if it has one.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct ErrorPage {
+    /// The title of the page
+    pub title: Cow<'static, str>,
+    /// The error message, displayed below the title
+    pub message: Option<Cow<'static, str>>,
+    #[serde(skip)]
+    pub status: status::Status,
+    /// Names of existing crates similar to the one that was requested
+    pub suggestions: Vec<String>,
+}
+
+impl ErrorPage {
+    /// Creates an error page for `status` titled after its reason phrase, like "Not Found".
+    pub(crate) fn new(status: status::Status, message: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            title: status.canonical_reason().unwrap_or("Error").into(),
+            message: Some(message.into()),
+            status,
+            suggestions: Vec::new(),
+        }
+    }
+}
+
+impl WebPage for ErrorPage {
+    const TEMPLATE: &'static str = "error/error.html";
+
+    fn template(&self) -> Cow<'static, str> {
+        let template = STATUS_TEMPLATES
+            .iter()
+            .find(|(status, _)| *status == self.status)
+            .map(|(_, template)| *template)
+            .unwrap_or(Self::TEMPLATE);
+        Cow::Borrowed(template)
+    }
+
+    fn get_status(&self) -> status::Status {
+        self.status
+    }
+}
+
 impl From<PoolError> for IronError {
     fn from(err: PoolError) -> IronError {
-        IronError::new(err.compat(), status::InternalServerError)
+        let status = match err {
+            // Running out of connections is temporary, clients can retry later
+            PoolError::Timeout(_) => status::ServiceUnavailable,
+            _ => status::InternalServerError,
+        };
+        IronError::new(err.compat(), status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn test_templates_of_statuses() {
+        let template = |status| ErrorPage::new(status, "message").template();
+        assert_eq!(template(status::NotFound), "error/404.html");
+        assert_eq!(template(status::InternalServerError), "error/500.html");
+        assert_eq!(template(status::BadRequest), "error/error.html");
+    }
+
+    #[test]
+    fn test_pool_exhaustion_returns_503() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.max_pool_size = 1;
+                config.pool_timeout = 100;
+            });
+            let web = env.frontend();
+
+            let conn = env.db().conn();
+            let resp = web.get("/releases").send()?;
+            assert_eq!(resp.status().as_u16(), 503);
+            assert_eq!(resp.headers()["retry-after"], "5");
+
+            drop(conn);
+            assert!(web.get("/releases").send()?.status().is_success());
+
+            Ok(())
+        });
+    }
+
+    /// Creates a registry index listing the crates `names`, laid out like the crates.io one.
+    fn seeded_index(names: &[&str]) -> Result<tempfile::TempDir, failure::Error> {
+        let index = tempfile::Builder::new()
+            .prefix("docs.rs-index-test")
+            .tempdir()?;
+        for name in names {
+            let dir = index.path().join(&name[..2]).join(&name[2..4]);
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(
+                dir.join(name),
+                format!(
+                    r#"{{"name":"{}","vers":"1.0.0","deps":[],"cksum":"","features":{{}},"yanked":false}}"#,
+                    name
+                ),
+            )?;
+        }
+        Ok(index)
+    }
+
+    #[test]
+    fn test_undocumented_crate_renders_distinct_404_page() {
+        wrapper(|env| {
+            let index = seeded_index(&["undocumented", "queued-crate"])?;
+            let index_path = index.path().to_path_buf();
+            env.override_config(|config| config.registry_index_path = Some(index_path));
+            env.build_queue().add_crate("queued-crate", "1.0.0", 0)?;
+            let web = env.frontend();
+
+            let resp = web.get("/crate/undocumented").send()?;
+            assert_eq!(resp.status().as_u16(), 404);
+            let body = resp.text()?;
+            assert!(body.contains("has not been documented yet</h1>"));
+            assert!(body.contains("exists on crates.io but is not documented"));
+
+            let resp = web.get("/crate/queued-crate").send()?;
+            assert_eq!(resp.status().as_u16(), 404);
+            let body = resp.text()?;
+            assert!(body.contains("has not been documented yet</h1>"));
+            assert!(body.contains("queued but not yet documented"));
+
+            let resp = web.get("/crate/missing-crate").send()?;
+            assert_eq!(resp.status().as_u16(), 404);
+            assert!(resp.text()?.contains("does not exist</h1>"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_missing_crate_renders_404_page() {
+        wrapper(|env| {
+            let resp = env.frontend().get("/crate/missing-crate").send()?;
+            assert_eq!(resp.status().as_u16(), 404);
+
+            let body = resp.text()?;
+            assert!(body.contains("does not exist</h1>"));
+            assert!(body.contains("look for the crate using the search bar above"));
+
+            Ok(())
+        });
     }
 }
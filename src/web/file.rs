@@ -17,7 +17,12 @@ impl File {
             config.max_file_size
         };
 
-        Ok(File(db::file::get_path(conn, path, max_size)?))
+        Ok(File(db::file::get_path(
+            conn,
+            &config.storage_backend,
+            path,
+            max_size,
+        )?))
     }
 
     /// Consumes File and creates a iron response
@@ -0,0 +1,53 @@
+//! Server-side syntax highlighting of the source files shown in the source browser.
+
+use once_cell::sync::Lazy;
+use std::path::Path;
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::highlighted_html_for_string,
+    parsing::SyntaxSet,
+};
+
+/// Files bigger than this are shown without highlighting, as highlighting takes a lot of CPU time
+/// for big files.
+const MAX_HIGHLIGHTED_SIZE: usize = 512 * 1024;
+
+static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+
+static THEME: Lazy<Theme> = Lazy::new(|| {
+    ThemeSet::load_defaults()
+        .themes
+        .remove("InspiredGitHub")
+        .expect("missing the default theme")
+});
+
+/// Returns `code` as highlighted HTML, wrapped in a `<pre>` element, or `None` if it's too big or
+/// if the extension of `path` isn't recognized.
+pub(super) fn highlight(path: &str, code: &str) -> Option<String> {
+    if code.len() > MAX_HIGHLIGHTED_SIZE {
+        return None;
+    }
+    let extension = Path::new(path).extension()?.to_str()?;
+    let syntax = SYNTAXES.find_syntax_by_extension(extension)?;
+
+    highlighted_html_for_string(code, &SYNTAXES, syntax, &THEME).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight() {
+        let html = highlight("src/lib.rs", "fn main() {}\n").unwrap();
+        assert!(html.starts_with("<pre"));
+        assert!(html.contains("<span"));
+        assert!(html.contains("main"));
+
+        assert_eq!(highlight("data.unknown-extension", "fn main() {}\n"), None);
+        assert_eq!(highlight("README", "fn main() {}\n"), None);
+
+        let big = "// comment\n".repeat(MAX_HIGHLIGHTED_SIZE / 11 + 1);
+        assert_eq!(highlight("src/lib.rs", &big), None);
+    }
+}
@@ -5,9 +5,9 @@ use iron::prelude::*;
 use iron::status::Status;
 use once_cell::sync::Lazy;
 use prometheus::{
-    opts, register_counter, register_int_counter, register_int_gauge, Encoder, IntCounter,
-    IntGauge, TextEncoder, __register_gauge, register_int_counter_vec, IntCounterVec,
-    __register_counter_vec, histogram_opts, register_histogram_vec, HistogramVec,
+    __register_counter_vec, __register_gauge, histogram_opts, opts, register_counter,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
 };
 use std::time::{Duration, Instant};
 
@@ -35,6 +35,14 @@ static FAILED_CRATES_COUNT: Lazy<IntGauge> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static QUEUED_CRATES_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "docsrs_queued_crates_total",
+        "Number of crates added to the build queue"
+    )
+    .unwrap()
+});
+
 pub static TOTAL_BUILDS: Lazy<IntCounter> =
     Lazy::new(|| register_int_counter!("docsrs_total_builds", "Number of crates built").unwrap());
 
@@ -79,11 +87,20 @@ pub static ROUTES_VISITED: Lazy<IntCounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static RESPONSES_BY_STATUS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "docsrs_responses_by_status",
+        "Number of responses sent, by HTTP status",
+        &["status"]
+    )
+    .unwrap()
+});
+
 pub static RESPONSE_TIMES: Lazy<HistogramVec> = Lazy::new(|| {
     register_histogram_vec!(
         "docsrs_response_time",
-        "The response times of various docs.rs routes",
-        &["route"]
+        "The response times of various docs.rs routes, also labeled with the route template",
+        &["route", "template"]
     )
     .unwrap()
 });
@@ -97,6 +114,14 @@ pub static RUSTDOC_RENDERING_TIMES: Lazy<HistogramVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static FAILED_TEMPLATE_RELOADS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "docsrs_failed_template_reloads",
+        "Number of times the templates failed to be reloaded after a change"
+    )
+    .unwrap()
+});
+
 pub static FAILED_DB_CONNECTIONS: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
         "docsrs_failed_db_connections",
@@ -121,6 +146,14 @@ pub static IDLE_DB_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static WAITING_DB_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "docsrs_waiting_db_connections",
+        "The number of callers waiting for a database connection"
+    )
+    .unwrap()
+});
+
 pub static MAX_DB_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
     register_int_gauge!(
         "docsrs_max_db_connections",
@@ -151,8 +184,10 @@ pub fn metrics_handler(req: &mut Request) -> IronResult<Response> {
     let pool = extension!(req, Pool);
     let queue = extension!(req, BuildQueue);
 
-    USED_DB_CONNECTIONS.set(pool.used_connections() as i64);
-    IDLE_DB_CONNECTIONS.set(pool.idle_connections() as i64);
+    let pool_status = pool.status();
+    USED_DB_CONNECTIONS.set(pool_status.used() as i64);
+    IDLE_DB_CONNECTIONS.set(pool_status.idle as i64);
+    WAITING_DB_CONNECTIONS.set(pool_status.waiters as i64);
 
     QUEUED_CRATES_COUNT.set(ctry!(queue.pending_count()) as i64);
     PRIORITIZED_CRATES_COUNT.set(ctry!(queue.prioritized_count()) as i64);
@@ -185,16 +220,50 @@ fn duration_to_seconds(d: Duration) -> f64 {
     d.as_secs() as f64 + nanos
 }
 
+/// Turns a router pattern into the template used to label the metrics, replacing the parameters
+/// and wildcards with placeholders: `/crate/:name/:version` becomes `/crate/{name}/{version}`.
+pub(super) fn normalize_route(pattern: &str) -> String {
+    pattern
+        .split('/')
+        .map(|segment| {
+            if let Some(param) = segment.strip_prefix(':') {
+                format!("{{{}}}", param)
+            } else {
+                segment.replace('*', "{path}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
 pub struct RequestRecorder {
     handler: Box<dyn iron::Handler>,
     route_name: String,
+    template: String,
 }
 
 impl RequestRecorder {
     pub fn new(handler: impl iron::Handler, route: impl Into<String>) -> Self {
+        let route = route.into();
+        Self {
+            handler: Box::new(handler),
+            template: normalize_route(&route),
+            route_name: route,
+        }
+    }
+
+    /// Like [`new`](Self::new), but records the visits and response times of routes sharing the
+    /// same `route` name together. The response times are still labeled with the template of
+    /// `pattern`.
+    pub(super) fn grouped(
+        handler: impl iron::Handler,
+        pattern: &str,
+        route: impl Into<String>,
+    ) -> Self {
         Self {
             handler: Box::new(handler),
             route_name: route.into(),
+            template: normalize_route(pattern),
         }
     }
 }
@@ -207,7 +276,7 @@ impl iron::Handler for RequestRecorder {
 
         ROUTES_VISITED.with_label_values(&[&self.route_name]).inc();
         RESPONSE_TIMES
-            .with_label_values(&[&self.route_name])
+            .with_label_values(&[&self.route_name, &self.template])
             .observe(resp_time);
 
         #[cfg(test)]
@@ -260,6 +329,7 @@ impl Drop for RenderingTimesRecorder {
 
 #[cfg(test)]
 mod tests {
+    use super::normalize_route;
     use crate::test::{assert_success, wrapper};
     use once_cell::sync::Lazy;
     use std::{
@@ -395,11 +465,13 @@ mod tests {
                 .fake_release()
                 .name("rcc")
                 .version("0.0.0")
+                .binary(true)
                 .create()?;
             env.db()
                 .fake_release()
                 .name("hexponent")
                 .version("0.2.0")
+                .binary(true)
                 .create()?;
 
             let frontend = env.frontend();
@@ -430,4 +502,79 @@ mod tests {
             assert_success("/about/metrics", web)
         })
     }
+
+    #[test]
+    fn test_normalize_route() {
+        assert_eq!(normalize_route("/"), "/");
+        assert_eq!(normalize_route("/about"), "/about");
+        assert_eq!(
+            normalize_route("/crate/:name/:version"),
+            "/crate/{name}/{version}"
+        );
+        assert_eq!(
+            normalize_route("/:crate/:version/:target/*.html"),
+            "/{crate}/{version}/{target}/{path}.html"
+        );
+        assert_eq!(normalize_route("/crate/:name/"), "/crate/{name}/");
+    }
+
+    #[test]
+    fn response_times_by_route_template() {
+        wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("rcc")
+                .version("0.0.0")
+                .create()?;
+            env.db()
+                .fake_release()
+                .name("hexponent")
+                .version("0.2.0")
+                .create()?;
+            let web = env.frontend();
+            assert_success("/crate/rcc/0.0.0", web)?;
+            assert_success("/crate/hexponent/0.2.0", web)?;
+
+            let metrics = web.get("/-/metrics").send()?.text()?;
+            let series = metrics
+                .lines()
+                .filter(|line| line.starts_with("docsrs_response_time_count"))
+                .collect::<Vec<_>>();
+            assert!(series
+                .iter()
+                .any(|line| line.contains("template=\"/crate/{name}/{version}\"")));
+            assert!(!series
+                .iter()
+                .any(|line| line.contains("rcc") || line.contains("hexponent")));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn scrape_metrics() {
+        wrapper(|env| {
+            env.build_queue().add_crate("foo", "0.1.0", 0)?;
+            let web = env.frontend();
+            assert_success("/about", web)?;
+
+            let resp = web.get("/-/metrics").send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(resp.headers()["content-type"], "text/plain; version=0.0.4");
+            let metrics = resp.text()?;
+            for name in &[
+                "docsrs_responses_by_status{status=\"200\"}",
+                "docsrs_queued_crates_count",
+                "docsrs_queued_crates_total",
+                "docsrs_used_db_connections",
+                "docsrs_idle_db_connections",
+                "docsrs_max_db_connections",
+                "docsrs_failed_template_reloads",
+            ] {
+                assert!(metrics.contains(name), "missing metric {}", name);
+            }
+
+            Ok(())
+        })
+    }
 }
@@ -48,35 +48,62 @@ macro_rules! extension {
     };
 }
 
+mod admin;
+mod api;
+mod base_path;
+mod body_limit;
 mod builds;
+mod compression;
+mod cors;
 mod crate_details;
 mod error;
 mod extensions;
 mod file;
+mod highlight;
 pub(crate) mod metrics;
+mod rate_limit;
 mod releases;
+mod request_logger;
 mod routes;
 mod rustdoc;
+mod shutdown;
 mod sitemap;
 mod source;
+mod suggestions;
+mod unix_socket;
 
+use self::base_path::StripBasePath;
+use self::body_limit::BodyLimit;
 use self::extensions::InjectExtensions;
 use self::page::TemplateData;
+use self::rate_limit::RateLimiter;
+use self::request_logger::RequestLogger;
+use self::shutdown::ShutdownState;
+use self::unix_socket::{UnixSocketFile, UnixSocketListener, UNIX_SOCKET_PREFIX};
 use crate::config::Config;
 use crate::db::Pool;
 use crate::BuildQueue;
 use chrono::{DateTime, Utc};
 use failure::Error;
+use handlebars_iron::handlebars::{Handlebars, Helper, RenderContext, RenderError};
 use handlebars_iron::{DirectorySource, HandlebarsEngine, SourceError};
+use hyper::net::HttpListener;
 use iron::headers::{CacheControl, CacheDirective, ContentType, Expires, HttpDate};
 use iron::modifiers::Redirect;
 use iron::prelude::*;
 use iron::{self, status, Handler, Listening, Url};
+use once_cell::sync::Lazy;
 use postgres::Connection;
 use router::NoRoute;
 use semver::{Version, VersionReq};
 use staticfile::Static;
-use std::{env, fmt, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    env, fmt,
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 /// Duration of static files for staticfile and DatabaseFileHandler (in seconds)
 const STATIC_FILE_CACHE_DURATION: u64 = 60 * 60 * 24 * 30 * 12; // 12 months
@@ -87,7 +114,10 @@ const OPENSEARCH_XML: &[u8] = include_bytes!("opensearch.xml");
 
 const DEFAULT_BIND: &str = "0.0.0.0:3000";
 
-fn handlebars_engine() -> Result<HandlebarsEngine, SourceError> {
+/// When the web server was started, used to report its uptime
+static SERVER_START: Lazy<Instant> = Lazy::new(Instant::now);
+
+fn handlebars_engine(base_path: &str) -> Result<HandlebarsEngine, SourceError> {
     // TODO: Use DocBuilderOptions for paths
     let mut hbse = HandlebarsEngine::new();
     hbse.add(Box::new(DirectorySource::new("./templates", ".hbs")));
@@ -95,6 +125,26 @@ fn handlebars_engine() -> Result<HandlebarsEngine, SourceError> {
     // load templates
     hbse.reload()?;
 
+    // `{{base_path "/path"}}` outputs the path prefixed with the path the site is served under
+    let base_path = base_path.to_string();
+    hbse.handlebars_mut().register_helper(
+        "base_path",
+        Box::new(
+            move |h: &Helper, _: &Handlebars, rc: &mut RenderContext| -> Result<(), RenderError> {
+                let path = h
+                    .param(0)
+                    .and_then(|param| param.value().as_str())
+                    .ok_or_else(|| RenderError::new("base_path takes a path string argument"))?;
+                rc.writer().write_all(base_path.as_bytes())?;
+                rc.writer().write_all(path.as_bytes())?;
+                Ok(())
+            },
+        ),
+    );
+
+    // Some of the tera filters are available to the handlebars templates too
+    page::register_filter_helpers(&mut hbse.handlebars_mut());
+
     Ok(hbse)
 }
 
@@ -108,10 +158,12 @@ struct CratesfyiHandler {
 
 impl CratesfyiHandler {
     fn chain<H: Handler>(inject_extensions: InjectExtensions, base: H) -> Chain {
-        let hbse = handlebars_engine().expect("Failed to load handlebar templates");
+        let hbse = handlebars_engine(&inject_extensions.config.base_path)
+            .expect("Failed to load handlebar templates");
 
         let mut chain = Chain::new(base);
         chain.link_before(inject_extensions);
+        chain.link_after(page::GlobalAlertMiddleware);
         chain.link_after(hbse);
         chain
     }
@@ -171,11 +223,29 @@ impl Handler for CratesfyiHandler {
 
         // try serving shared rustdoc resources first, then router, then db/static file handler
         // return 404 if none of them return Ok
-        self.shared_resource_handler
+        let response = self
+            .shared_resource_handler
             .handle(req)
             .or_else(|e| if_404(e, || self.router_handler.handle(req)))
-            .or_else(|e| if_404(e, || self.database_file_handler.handle(req)))
-            .or_else(|e| if_404(e, || self.static_handler.handle(req)))
+            .or_else(|router_error| {
+                if router_error.response.status != Some(status::NotFound) {
+                    return Err(router_error);
+                }
+
+                self.database_file_handler
+                    .handle(req)
+                    .or_else(|e| if_404(e, || self.static_handler.handle(req)))
+                    .map_err(|e| {
+                        // The file handlers know nothing about crates, so a missing crate reported
+                        // by the router is more useful than their generic 404
+                        let router_nope = router_error.error.downcast::<error::Nope>();
+                        if e.response.status == Some(status::NotFound) && router_nope.is_some() {
+                            router_error
+                        } else {
+                            e
+                        }
+                    })
+            })
             .or_else(|e| {
                 let err = if let Some(err) = e.error.downcast::<error::Nope>() {
                     *err
@@ -186,6 +256,9 @@ impl Handler for CratesfyiHandler {
                 } else if e.response.status == Some(status::InternalServerError) {
                     log::error!("internal server error: {}", e.error);
                     error::Nope::InternalServerError
+                } else if e.response.status == Some(status::ServiceUnavailable) {
+                    log::warn!("service unavailable: {}", e.error);
+                    error::Nope::ServiceUnavailable
                 } else {
                     log::error!(
                         "No error page for status {:?}; {}",
@@ -221,7 +294,9 @@ impl Handler for CratesfyiHandler {
                 }
 
                 Self::chain(self.inject_extensions.clone(), err).handle(req)
-            })
+            });
+
+        response.map(|resp| compression::compress_response(req, resp))
     }
 }
 
@@ -268,6 +343,17 @@ impl MatchSemver {
     }
 }
 
+/// Returns the name of the crate as published, looking it up with `-` and `_` considered equal
+/// and ignoring the case, the same way crates.io prevents publishing similar names. Returns
+/// `None` if there's no such crate.
+fn canonical_crate_name(conn: &Connection, name: &str) -> Result<Option<String>, postgres::Error> {
+    let rows = conn.query(
+        "SELECT name FROM crates WHERE normalize_crate_name(name) = normalize_crate_name($1)",
+        &[&name],
+    )?;
+    Ok(rows.iter().next().map(|row| row.get(0)))
+}
+
 /// Checks the database for crate releases that match the given name and version.
 ///
 /// `version` may be an exact version number or loose semver version requirement. The return value
@@ -280,15 +366,11 @@ fn match_version(conn: &Connection, name: &str, version: Option<&str>) -> Option
     // version is an Option<&str> from router::Router::get, need to decode first
     use iron::url::percent_encoding::percent_decode;
 
-    let req_version = version
-        .and_then(|v| percent_decode(v.as_bytes()).decode_utf8().ok())
-        .map(|v| {
-            if v == "newest" || v == "latest" {
-                "*".into()
-            } else {
-                v
-            }
-        })
+    let req_version = version.and_then(|v| percent_decode(v.as_bytes()).decode_utf8().ok());
+    let latest_requested =
+        req_version.as_deref() == Some("newest") || req_version.as_deref() == Some("latest");
+    let req_version = req_version
+        .map(|v| if latest_requested { "*".into() } else { v })
         .unwrap_or_else(|| "*".into());
 
     let mut corrected_name = None;
@@ -350,10 +432,26 @@ fn match_version(conn: &Connection, name: &str, version: Option<&str>) -> Option
     // semver is acting weird for '*' (any) range if a crate only have pre-release versions
     // return first non-yanked version if requested version is '*'
     if req_version == "*" {
-        return versions_sem.first().map(|v| MatchVersion {
-            corrected_name,
-            version: MatchSemver::Semver((v.0.to_string(), v.1)),
-        });
+        if let Some(v) = versions_sem.first() {
+            return Some(MatchVersion {
+                corrected_name,
+                version: MatchSemver::Semver((v.0.to_string(), v.1)),
+            });
+        }
+
+        // if the latest version was explicitly requested and all the versions were yanked, return
+        // the newest yanked one
+        if !latest_requested {
+            return None;
+        }
+        return versions
+            .iter()
+            .filter_map(|(version, id, _)| Some((Version::parse(version).ok()?, *id)))
+            .max()
+            .map(|v| MatchVersion {
+                corrected_name,
+                version: MatchSemver::Semver((v.0.to_string(), v.1)),
+            });
     }
 
     None
@@ -379,6 +477,8 @@ fn render_markdown(text: &str) -> String {
 
 pub struct Server {
     inner: Listening,
+    state: Arc<ShutdownState>,
+    unix_socket: Option<UnixSocketFile>,
 }
 
 impl Server {
@@ -389,10 +489,19 @@ impl Server {
         config: Arc<Config>,
         build_queue: Arc<BuildQueue>,
     ) -> Result<Self, Error> {
+        Lazy::force(&SERVER_START);
+
         // Initialize templates
-        let template_data = Arc::new(TemplateData::new(&*db.get()?)?);
+        let template_data = Arc::new(TemplateData::new(&db, &config, reload_templates)?);
+        if let Err(err) = template_data.warm() {
+            log::error!("{}", err);
+        }
         if reload_templates {
-            TemplateData::start_template_reloading(template_data.clone(), db.clone());
+            TemplateData::start_template_reloading(
+                template_data.clone(),
+                db.clone(),
+                config.clone(),
+            );
         }
 
         let server = Self::start_inner(
@@ -402,7 +511,14 @@ impl Server {
             template_data,
             build_queue,
         );
-        info!("Running docs.rs web server on http://{}", server.addr());
+        match &server.unix_socket {
+            Some(socket) => info!(
+                "Running docs.rs web server on {}{}",
+                UNIX_SOCKET_PREFIX,
+                socket.path().display()
+            ),
+            None => info!("Running docs.rs web server on http://{}", server.addr()),
+        }
         Ok(server)
     }
 
@@ -420,19 +536,64 @@ impl Server {
         metrics::NON_LIBRARY_BUILDS.inc_by(0);
         metrics::UPLOADED_FILES_TOTAL.inc_by(0);
         metrics::FAILED_DB_CONNECTIONS.inc_by(0);
+        metrics::FAILED_TEMPLATE_RELOADS.inc_by(0);
+        metrics::QUEUED_CRATES_TOTAL.inc_by(0);
+
+        let cratesfyi = CratesfyiHandler::new(pool, config.clone(), template_data, build_queue);
+        let handler = StripBasePath::new(
+            RequestLogger::new(BodyLimit::new(
+                RateLimiter::new(cratesfyi, &config),
+                config.max_request_body_size,
+            )),
+            &config.base_path,
+        );
+        Self::bind(addr, handler)
+            .unwrap_or_else(|err| panic!("Failed to bind to socket on {}: {}", addr, err))
+    }
 
-        let cratesfyi = CratesfyiHandler::new(pool, config, template_data, build_queue);
-        let inner = Iron::new(cratesfyi)
-            .http(addr)
-            .unwrap_or_else(|_| panic!("Failed to bind to socket on {}", addr));
-
-        Server { inner }
+    /// Starts serving `handler` either on a TCP address or, when `addr` starts with `unix:`, on a
+    /// Unix domain socket.
+    fn bind(addr: &str, handler: impl Handler) -> Result<Self, Error> {
+        let ((inner, state), unix_socket) =
+            if let Some(path) = addr.strip_prefix(UNIX_SOCKET_PREFIX) {
+                let (listener, file) = UnixSocketListener::bind(Path::new(path))?;
+                (shutdown::listen(handler, listener)?, Some(file))
+            } else {
+                let listener = HttpListener::from(TcpListener::bind(addr)?);
+                (shutdown::listen(handler, listener)?, None)
+            };
+
+        Ok(Server {
+            inner,
+            state,
+            unix_socket,
+        })
     }
 
     pub(crate) fn addr(&self) -> SocketAddr {
         self.inner.socket
     }
 
+    /// Stops accepting new connections and waits up to `timeout` for the requests currently being
+    /// processed to complete, returning an error if some of them are still running afterwards.
+    pub fn shutdown(mut self, timeout: Duration) -> Result<(), Error> {
+        info!("Shutting down the docs.rs web server");
+        let in_flight = self.state.shutdown(timeout);
+
+        // Dropping `Listening` would join hyper's (now parked) threads, closing it detaches them
+        // instead.
+        let _ = self.inner.close();
+
+        if in_flight > 0 {
+            failure::bail!(
+                "{} requests were still in flight after {:?}",
+                in_flight,
+                timeout
+            );
+        }
+        Ok(())
+    }
+
     /// Iron is bugged, and it never closes the server even when the listener is dropped. To
     /// avoid never-ending tests this method forgets about the server, leaking it and allowing the
     /// program to end.
@@ -495,10 +656,15 @@ fn redirect_base(req: &Request) -> String {
 
     // Only include the port if it's needed
     let port = req.url.port();
+    let base_path = req
+        .extensions
+        .get::<Config>()
+        .map(|config| config.base_path.as_str())
+        .unwrap_or("");
     if port == 80 {
-        format!("{}://{}", scheme, req.url.host())
+        format!("{}://{}{}", scheme, req.url.host(), base_path)
     } else {
-        format!("{}://{}:{}", scheme, req.url.host(), port)
+        format!("{}://{}:{}{}", scheme, req.url.host(), port, base_path)
     }
 }
 
@@ -615,6 +781,8 @@ mod test {
     };
     use kuchiki::traits::TendrilSink;
     use serde_json::json;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
 
     fn release(version: &str, db: &TestDatabase) -> i32 {
         db.fake_release()
@@ -643,6 +811,55 @@ mod test {
         node.select("#clipboard").unwrap().count() == 1
     }
 
+    #[test]
+    fn test_shutdown_refuses_new_connections() {
+        wrapper(|env| {
+            let server = Server::start(
+                Some("127.0.0.1:0"),
+                false,
+                env.db().pool(),
+                env.config(),
+                env.build_queue(),
+            )?;
+            let url = format!("http://{}/-/build-info", server.addr());
+
+            assert!(reqwest::blocking::get(&url)?.status().is_success());
+
+            server.shutdown(Duration::from_secs(5))?;
+            assert!(reqwest::blocking::get(&url).is_err());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_serve_over_unix_socket() {
+        wrapper(|env| {
+            let dir = tempfile::tempdir()?;
+            let path = dir.path().join("docsrs.sock");
+            let server = Server::start(
+                Some(&format!("unix:{}", path.display())),
+                false,
+                env.db().pool(),
+                env.config(),
+                env.build_queue(),
+            )?;
+
+            let mut stream = UnixStream::connect(&path)?;
+            stream.write_all(
+                b"GET /-/build-info HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )?;
+            let mut resp = String::new();
+            stream.read_to_string(&mut resp)?;
+            assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+
+            server.leak();
+            assert!(!path.exists());
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_index_returns_success() {
         wrapper(|env| {
@@ -660,6 +877,7 @@ mod test {
                 .name("fake_crate")
                 .version("0.0.1")
                 .source_file("test.rs", &[])
+                .binary(true)
                 .create()
                 .unwrap();
             let web = env.frontend();
@@ -823,7 +1041,7 @@ mod test {
 
     #[test]
     fn test_templates_are_valid() {
-        handlebars_engine().expect("Failed to load handlebar templates");
+        handlebars_engine("").expect("Failed to load handlebar templates");
     }
 
     #[test]
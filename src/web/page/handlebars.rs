@@ -1,8 +1,12 @@
 //! Generic page struct
 
+use super::templates::{Filter, HANDLEBARS_FILTERS};
+use super::{dismissed_alert, show_global_alert, RenderedAlert};
+use handlebars_iron::handlebars::{Handlebars, Helper, HelperDef, RenderContext, RenderError};
 use handlebars_iron::Template;
+use iron::headers::Cookie;
 use iron::response::Response;
-use iron::{status, IronResult, Set};
+use iron::{status, AfterMiddleware, IronError, IronResult, Request, Set};
 use once_cell::sync::Lazy;
 use serde::{
     ser::{SerializeStruct, Serializer},
@@ -99,8 +103,49 @@ impl<T: Serialize> Page<T> {
     pub fn to_resp(self, template: &str) -> IronResult<Response> {
         let mut resp = Response::new();
         let status = self.status;
-        let temp = Template::new(template, self);
-        resp.set_mut(temp).set_mut(status);
+        let value = serde_json::to_value(&self)
+            .map_err(|err| IronError::new(err, status::InternalServerError))?;
+        resp.extensions.insert::<PendingPage>(PendingPage {
+            template: template.to_owned(),
+            value,
+        });
+        resp.set_mut(status);
+
+        Ok(resp)
+    }
+}
+
+/// A page waiting for the request to be known before being rendered, as whether the global alert
+/// is shown depends on the cookies of the user.
+struct PendingPage {
+    template: String,
+    value: Value,
+}
+
+impl iron::typemap::Key for PendingPage {
+    type Value = PendingPage;
+}
+
+/// Middleware turning the pages into handlebars templates, hiding the global alert when the user
+/// dismissed it. It must run before the `HandlebarsEngine`.
+pub(in crate::web) struct GlobalAlertMiddleware;
+
+impl AfterMiddleware for GlobalAlertMiddleware {
+    fn after(&self, req: &mut Request, mut resp: Response) -> IronResult<Response> {
+        if let Some(PendingPage {
+            template,
+            mut value,
+        }) = resp.extensions.remove::<PendingPage>()
+        {
+            let dismissed = dismissed_alert(req.headers.get::<Cookie>());
+            value["show_global_alert"] =
+                Value::Bool(show_global_alert(crate::GLOBAL_ALERT.as_ref(), dismissed));
+            if crate::GLOBAL_ALERT.is_some() {
+                // Caches must not serve the page to users who dismissed the alert and vice versa
+                resp.headers.append_raw("Vary", b"Cookie".to_vec());
+            }
+            resp.set_mut(Template::new(&template, value));
+        }
 
         Ok(resp)
     }
@@ -126,7 +171,7 @@ impl<T: Serialize> Serialize for Page<T> {
 
         state.serialize_field("has_global_alert", &crate::GLOBAL_ALERT.is_some())?;
         if let Some(ref global_alert) = crate::GLOBAL_ALERT {
-            state.serialize_field("global_alert", global_alert)?;
+            state.serialize_field("global_alert", &RenderedAlert::new(global_alert))?;
         }
 
         state.serialize_field("content", &self.content)?;
@@ -148,6 +193,42 @@ fn build_version_safe(version: &str) -> String {
     version.replace(" ", "-").replace("(", "").replace(")", "")
 }
 
+/// Calls a tera filter from the handlebars templates, with the first parameter of the helper as
+/// the filtered value and its hash as the named arguments.
+struct FilterHelper {
+    name: &'static str,
+    filter: Filter,
+}
+
+impl HelperDef for FilterHelper {
+    fn call(&self, h: &Helper, _: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
+        let value = h
+            .param(0)
+            .map_or(Value::Null, |param| param.value().clone());
+        let args = h
+            .hash()
+            .iter()
+            .map(|(key, arg)| (key.clone(), arg.value().clone()))
+            .collect();
+        let output = (self.filter)(&value, &args)
+            .map_err(|err| RenderError::new(format!("{} failed: {}", self.name, err)))?;
+
+        // The output isn't escaped, the filters producing HTML escape their input already
+        match output {
+            Value::String(string) => rc.writer().write_all(string.as_bytes())?,
+            other => rc.writer().write_all(other.to_string().as_bytes())?,
+        }
+        Ok(())
+    }
+}
+
+/// Registers the tera filters the handlebars templates use as helpers of the same name.
+pub(crate) fn register_filter_helpers(handlebars: &mut Handlebars) {
+    for &(name, filter) in HANDLEBARS_FILTERS {
+        handlebars.register_helper(name, Box::new(FilterHelper { name, filter }));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
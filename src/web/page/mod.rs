@@ -3,10 +3,15 @@ mod templates;
 mod web_page;
 
 pub use handlebars::*;
-pub(crate) use templates::TemplateData;
+pub(crate) use templates::{load_rustc_resource_suffix, TemplateData};
 pub(crate) use web_page::WebPage;
 
+use iron::headers::Cookie;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Name of the cookie storing the id of the last global alert the user dismissed
+const DISMISSED_ALERT_COOKIE: &str = "dismissed_alert";
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
 pub(crate) struct GlobalAlert {
@@ -16,6 +21,50 @@ pub(crate) struct GlobalAlert {
     pub(crate) fa_icon: &'static str,
 }
 
+impl GlobalAlert {
+    /// Identifies the alert when users dismiss it, a new alert gets a different id and is shown
+    /// again to everyone.
+    pub(crate) fn id(&self) -> String {
+        let hash = Sha256::digest(format!("{}\n{}", self.url, self.text).as_bytes());
+        hex::encode(&hash[..8])
+    }
+}
+
+/// The global alert as rendered in the templates, along with the id used to dismiss it
+#[derive(Serialize)]
+struct RenderedAlert<'a> {
+    #[serde(flatten)]
+    alert: &'a GlobalAlert,
+    id: String,
+}
+
+impl<'a> RenderedAlert<'a> {
+    fn new(alert: &'a GlobalAlert) -> Self {
+        Self {
+            alert,
+            id: alert.id(),
+        }
+    }
+}
+
+/// Returns the id of the global alert dismissed by the user, stored in the `dismissed_alert` cookie
+fn dismissed_alert(cookies: Option<&Cookie>) -> Option<&str> {
+    cookies?
+        .iter()
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| {
+            let mut parts = cookie.trim().splitn(2, '=');
+            Some((parts.next()?, parts.next()?))
+        })
+        .find(|(name, _)| *name == DISMISSED_ALERT_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// Whether the global alert is shown, which it isn't once the user dismissed it
+fn show_global_alert(global_alert: Option<&GlobalAlert>, dismissed: Option<&str>) -> bool {
+    global_alert.map_or(false, |alert| Some(alert.id().as_str()) != dismissed)
+}
+
 #[cfg(test)]
 mod tera_tests {
     use super::*;
@@ -39,4 +88,36 @@ mod tera_tests {
 
         assert_eq!(correct_json, serde_json::to_value(&alert).unwrap());
     }
+
+    #[test]
+    fn test_dismissed_alert_cookie() {
+        assert_eq!(dismissed_alert(None), None);
+        assert_eq!(
+            dismissed_alert(Some(&Cookie(vec!["theme=dark".into()]))),
+            None
+        );
+        assert_eq!(
+            dismissed_alert(Some(&Cookie(vec![
+                "theme=dark; dismissed_alert=abc".into(),
+                "other=1".into()
+            ]))),
+            Some("abc")
+        );
+    }
+
+    #[test]
+    fn test_show_global_alert() {
+        let alert = GlobalAlert {
+            url: "https://blog.rust-lang.org/",
+            text: "Scheduled maintenance",
+            css_class: "warn",
+            fa_icon: "warning",
+        };
+
+        assert!(show_global_alert(Some(&alert), None));
+        assert!(!show_global_alert(Some(&alert), Some(&alert.id())));
+        // Dismissing an older alert doesn't hide the new one
+        assert!(show_global_alert(Some(&alert), Some("0123456789abcdef")));
+        assert!(!show_global_alert(None, None));
+    }
 }
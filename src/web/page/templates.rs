@@ -1,4 +1,4 @@
-use crate::{db::Pool, error::Result};
+use crate::{db::Pool, error::Result, BuildQueue, Config};
 use arc_swap::ArcSwap;
 use chrono::{DateTime, Utc};
 use failure::ResultExt;
@@ -7,31 +7,55 @@ use path_slash::PathExt;
 use postgres::Connection;
 use serde_json::Value;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::PathBuf,
-    sync::{mpsc::channel, Arc},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::channel,
+        Arc, Mutex,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
+};
+use tera::{
+    ast::{Expr, ExprVal, FunctionCall, MacroDefinition, Node},
+    Result as TeraResult, Tera,
 };
-use tera::{Result as TeraResult, Tera};
 use walkdir::WalkDir;
 
 const TEMPLATES_DIRECTORY: &str = "tera-templates";
 
+/// How long `maintenance_mode` caches whether the build queue is paused, to avoid querying the
+/// database every time a page is rendered.
+const MAINTENANCE_MODE_TTL: Duration = Duration::from_secs(30);
+
 /// Holds all data relevant to templating
 #[derive(Debug)]
 pub(crate) struct TemplateData {
-    /// The actual templates, stored in an `ArcSwap` so that they're hot-swappable
-    // TODO: Conditional compilation so it's not always wrapped, the `ArcSwap` is unneeded overhead for prod
-    pub templates: ArcSwap<Tera>,
+    templates: Templates,
+}
+
+/// The compiled templates. They're only stored in an `ArcSwap` when they can be reloaded, to
+/// avoid its overhead in production.
+#[derive(Debug)]
+enum Templates {
+    Static(Arc<Tera>),
+    Reloadable(ArcSwap<Tera>),
 }
 
 impl TemplateData {
-    pub(crate) fn new(conn: &Connection) -> Result<Self> {
+    /// Loads the templates, which can only be replaced later with `reload` if `reloadable` is
+    /// set.
+    pub(crate) fn new(pool: &Pool, config: &Config, reloadable: bool) -> Result<Self> {
         log::trace!("Loading templates");
 
+        let templates = load_templates(pool, config)?;
         let data = Self {
-            templates: ArcSwap::from_pointee(load_templates(conn)?),
+            templates: if reloadable {
+                Templates::Reloadable(ArcSwap::from_pointee(templates))
+            } else {
+                Templates::Static(Arc::new(templates))
+            },
         };
 
         log::trace!("Finished loading templates");
@@ -39,7 +63,68 @@ impl TemplateData {
         Ok(data)
     }
 
-    pub(crate) fn start_template_reloading(template_data: Arc<TemplateData>, pool: Pool) {
+    /// Renders the template `name` with the current templates, independently of the web layer.
+    pub(crate) fn render(&self, name: &str, context: &tera::Context) -> Result<String> {
+        Ok(match &self.templates {
+            Templates::Static(templates) => templates.render(name, context)?,
+            Templates::Reloadable(templates) => templates.load().render(name, context)?,
+        })
+    }
+
+    /// Renders every template once against a minimal context, so that the first requests don't
+    /// pay for the work done lazily on the first render. Templates defining macros can only be
+    /// imported and are skipped. All the templates are rendered even if some fail, each failure is
+    /// logged and the error lists the templates that failed.
+    pub(crate) fn warm(&self) -> Result<()> {
+        let templates = match &self.templates {
+            Templates::Static(templates) => templates.clone(),
+            Templates::Reloadable(templates) => templates.load_full(),
+        };
+        let context = warm_context();
+
+        let mut failed = Vec::new();
+        for (name, template) in &templates.templates {
+            if !template.macros.is_empty() {
+                continue;
+            }
+            if let Err(err) = templates.render(name, &context) {
+                log::error!("failed to warm template {}: {:?}", name, err);
+                failed.push(name.as_str());
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            failed.sort_unstable();
+            failure::bail!("failed to warm templates: {}", failed.join(", "));
+        }
+    }
+
+    /// Loads the templates again, returning whether they were replaced. Nothing happens if the
+    /// templates aren't reloadable.
+    pub(crate) fn reload(&self, pool: &Pool, config: &Config) -> Result<bool> {
+        match &self.templates {
+            Templates::Static(_) => Ok(false),
+            Templates::Reloadable(templates) => {
+                templates.store(Arc::new(load_templates(pool, config)?));
+                Ok(true)
+            }
+        }
+    }
+
+    /// Watches the templates directory, reloading the templates when they're changed. Nothing
+    /// happens if the templates aren't reloadable.
+    pub(crate) fn start_template_reloading(
+        template_data: Arc<TemplateData>,
+        pool: Pool,
+        config: Arc<Config>,
+    ) {
+        if let Templates::Static(_) = template_data.templates {
+            log::warn!("the templates can't be reloaded, they were loaded as static");
+            return;
+        }
+
         let (tx, rx) = channel();
         // Set a 2 second event debounce for the watcher
         let mut watcher = watcher(tx, Duration::from_secs(2)).unwrap();
@@ -49,11 +134,8 @@ impl TemplateData {
             .unwrap();
 
         thread::spawn(move || {
-            fn reload(template_data: &TemplateData, pool: &Pool) -> Result<()> {
-                let conn = pool.get()?;
-                template_data
-                    .templates
-                    .swap(Arc::new(load_templates(&conn)?));
+            fn reload(template_data: &TemplateData, pool: &Pool, config: &Config) -> Result<()> {
+                template_data.reload(pool, config)?;
                 log::info!("Reloaded templates");
 
                 Ok(())
@@ -64,15 +146,32 @@ impl TemplateData {
             let _watcher = watcher;
 
             while rx.recv().is_ok() {
-                if let Err(err) = reload(&template_data, &pool) {
+                if let Err(err) = reload(&template_data, &pool, &config) {
                     log::error!("failed to reload templates: {:?}", err);
+                    crate::web::metrics::FAILED_TEMPLATE_RELOADS.inc();
                 }
             }
         });
     }
 }
 
-fn load_rustc_resource_suffix(conn: &Connection) -> Result<String> {
+/// The context the templates are warmed with: the values every page has, plus empty values for
+/// the ones the templates can't render without.
+fn warm_context() -> tera::Context {
+    let mut context = tera::Context::new();
+    context.insert("global_alert", &None::<()>);
+    context.insert("show_global_alert", &false);
+    context.insert("docsrs_version", crate::BUILD_VERSION);
+    context.insert("title", "");
+    context.insert("limits", &crate::docbuilder::Limits::default());
+    context.insert("recent_releases", &Vec::<()>::new());
+    context.insert("releases", &Vec::<()>::new());
+    context.insert("release_type", "recent");
+    context.insert("suggestions", &Vec::<String>::new());
+    context
+}
+
+pub(crate) fn load_rustc_resource_suffix(conn: &Connection) -> Result<String> {
     let res = conn.query(
         "SELECT value FROM config WHERE name = 'rustc_version';",
         &[],
@@ -91,7 +190,11 @@ fn load_rustc_resource_suffix(conn: &Connection) -> Result<String> {
     failure::bail!("failed to parse the rustc version");
 }
 
-pub(super) fn load_templates(conn: &Connection) -> Result<Tera> {
+pub(super) fn load_templates(pool: &Pool, config: &Config) -> Result<Tera> {
+    load_templates_from(pool, config, TEMPLATES_DIRECTORY)
+}
+
+fn load_templates_from(pool: &Pool, config: &Config, base: &str) -> Result<Tera> {
     // This uses a custom function to find the templates in the filesystem instead of Tera's
     // builtin way (passing a glob expression to Tera::new), speeding up the startup of the
     // application and running the tests.
@@ -103,53 +206,197 @@ pub(super) fn load_templates(conn: &Connection) -> Result<Tera> {
     //
     // TODO: remove this when https://github.com/Gilnaa/globwalk/issues/29 is fixed
     let mut tera = Tera::default();
-    let template_files = find_templates_in_filesystem(TEMPLATES_DIRECTORY).with_context(|_| {
-        format!(
-            "failed to search {:?} for tera templates",
-            TEMPLATES_DIRECTORY
-        )
-    })?;
-    tera.add_template_files(template_files).with_context(|_| {
-        format!(
-            "failed while loading tera templates in {:?}",
-            TEMPLATES_DIRECTORY
-        )
-    })?;
-
-    // This function will return any global alert, if present.
+    let template_files = find_templates_in_filesystem(base)
+        .with_context(|_| format!("failed to search {:?} for tera templates", base))?;
+    tera.add_template_files(template_files)
+        .with_context(|_| format!("failed while loading tera templates in {:?}", base))?;
+
+    // This function will return the resource suffix of the latest nightly used to build
+    // documentation on docs.rs, or ??? if no resource suffix was found.
     ReturnValue::add_function_to(
         &mut tera,
-        "global_alert",
-        serde_json::to_value(crate::GLOBAL_ALERT)?,
+        "rustc_resource_suffix",
+        Value::String(
+            load_rustc_resource_suffix(&*pool.get()?).unwrap_or_else(|err| {
+                log::error!("Failed to load rustc resource suffix: {:?}", err);
+                // This is not fatal because the server might be started before essential files are
+                // generated during development. Returning "???" provides a degraded UX, but allows the
+                // server to start every time.
+                String::from("???")
+            }),
+        ),
     );
-    // This function will return the current version of docs.rs.
+
+    // This function will return the path the site is served under, which must prefix the links
+    // to the docs.rs pages.
     ReturnValue::add_function_to(
         &mut tera,
-        "docsrs_version",
-        Value::String(crate::BUILD_VERSION.into()),
+        "base_path",
+        Value::String(config.base_path.clone()),
     );
-    // This function will return the resource suffix of the latest nightly used to build
-    // documentation on docs.rs, or ??? if no resource suffix was found.
-    ReturnValue::add_function_to(
-        &mut tera,
-        "rustc_resource_suffix",
-        Value::String(load_rustc_resource_suffix(conn).unwrap_or_else(|err| {
-            log::error!("Failed to load rustc resource suffix: {:?}", err);
-            // This is not fatal because the server might be started before essential files are
-            // generated during development. Returning "???" provides a degraded UX, but allows the
-            // server to start every time.
-            String::from("???")
-        })),
+
+    // This function will return whether an experimental UI feature is enabled.
+    tera.register_function(
+        "feature_enabled",
+        FeatureEnabled {
+            features: config.enabled_features.clone(),
+        },
+    );
+
+    // This function will return whether the build queue is paused, during which docs.rs is in
+    // maintenance mode.
+    tera.register_function(
+        "maintenance_mode",
+        MaintenanceMode::new(BuildQueue::new(pool.clone(), config), MAINTENANCE_MODE_TTL),
     );
 
     // Custom filters
     tera.register_filter("timeformat", timeformat);
     tera.register_filter("dbg", dbg);
     tera.register_filter("dedent", dedent);
+    tera.register_filter("crate_links", crate_links);
+    tera.register_filter("pluralize", pluralize);
+    tera.register_filter("version_diff", version_diff);
+
+    validate_templates(&tera).with_context(|_| format!("invalid tera templates in {:?}", base))?;
 
     Ok(tera)
 }
 
+/// Checks the parts of the templates Tera only resolves while rendering, so that broken templates
+/// are rejected when they're loaded instead of failing the requests rendering them.
+fn validate_templates(tera: &Tera) -> Result<()> {
+    tera.check_macro_files()?;
+
+    for (name, template) in &tera.templates {
+        let validator = TemplateValidator { tera };
+        validator
+            .nodes(&template.ast)
+            .and_then(|_| {
+                template
+                    .macros
+                    .values()
+                    .try_for_each(|definition| validator.macro_definition(definition))
+            })
+            .with_context(|_| format!("template {} is invalid", name))?;
+    }
+
+    Ok(())
+}
+
+/// Walks the AST of a template, checking that all the filters, functions, tests and included
+/// templates it uses exist.
+struct TemplateValidator<'a> {
+    tera: &'a Tera,
+}
+
+impl TemplateValidator<'_> {
+    fn nodes(&self, nodes: &[Node]) -> Result<()> {
+        for node in nodes {
+            match node {
+                Node::VariableBlock(_, expr) => self.expr(expr)?,
+                Node::MacroDefinition(_, definition, _) => self.macro_definition(definition)?,
+                Node::Include(_, name) => {
+                    if !self.tera.templates.contains_key(name) {
+                        failure::bail!("included template {} doesn't exist", name);
+                    }
+                }
+                Node::Set(_, set) => self.expr(&set.value)?,
+                Node::FilterSection(_, section, _) => {
+                    self.filter(&section.filter)?;
+                    self.nodes(&section.body)?;
+                }
+                Node::Block(_, block, _) => self.nodes(&block.body)?,
+                Node::Forloop(_, forloop, _) => {
+                    self.expr(&forloop.container)?;
+                    self.nodes(&forloop.body)?;
+                    if let Some(body) = &forloop.empty_body {
+                        self.nodes(body)?;
+                    }
+                }
+                Node::If(condition, _) => {
+                    for (_, expr, body) in &condition.conditions {
+                        self.expr(expr)?;
+                        self.nodes(body)?;
+                    }
+                    if let Some((_, body)) = &condition.otherwise {
+                        self.nodes(body)?;
+                    }
+                }
+                Node::Super
+                | Node::Text(_)
+                | Node::Extends(..)
+                | Node::ImportMacro(..)
+                | Node::Raw(..)
+                | Node::Break(_)
+                | Node::Continue(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn macro_definition(&self, definition: &MacroDefinition) -> Result<()> {
+        for default in definition.args.values().flatten() {
+            self.expr(default)?;
+        }
+        self.nodes(&definition.body)
+    }
+
+    fn expr(&self, expr: &Expr) -> Result<()> {
+        // `safe` and `default` are handled by the renderer itself instead of being registered.
+        for filter in &expr.filters {
+            if filter.name != "safe" && filter.name != "default" {
+                self.filter(filter)?;
+            } else {
+                self.exprs(filter.args.values())?;
+            }
+        }
+
+        match &expr.val {
+            ExprVal::Math(math) => {
+                self.expr(&math.lhs)?;
+                self.expr(&math.rhs)?;
+            }
+            ExprVal::Logic(logic) => {
+                self.expr(&logic.lhs)?;
+                self.expr(&logic.rhs)?;
+            }
+            ExprVal::In(in_) => {
+                self.expr(&in_.lhs)?;
+                self.expr(&in_.rhs)?;
+            }
+            ExprVal::Test(test) => {
+                self.tera.get_tester(&test.name)?;
+                self.exprs(&test.args)?;
+            }
+            ExprVal::MacroCall(call) => self.exprs(call.args.values())?,
+            ExprVal::FunctionCall(call) => {
+                self.tera.get_function(&call.name)?;
+                self.exprs(call.args.values())?;
+            }
+            ExprVal::Array(values) => self.exprs(values)?,
+            ExprVal::String(_)
+            | ExprVal::Int(_)
+            | ExprVal::Float(_)
+            | ExprVal::Bool(_)
+            | ExprVal::Ident(_)
+            | ExprVal::StringConcat(_) => {}
+        }
+
+        Ok(())
+    }
+
+    fn exprs<'e>(&self, exprs: impl IntoIterator<Item = &'e Expr>) -> Result<()> {
+        exprs.into_iter().try_for_each(|expr| self.expr(expr))
+    }
+
+    fn filter(&self, filter: &FunctionCall) -> Result<()> {
+        self.tera.get_filter(&filter.name)?;
+        self.exprs(filter.args.values())
+    }
+}
+
 fn find_templates_in_filesystem(base: &str) -> Result<Vec<(PathBuf, Option<String>)>> {
     let root = std::fs::canonicalize(base)?;
 
@@ -193,6 +440,80 @@ impl tera::Function for ReturnValue {
     }
 }
 
+/// Function that returns whether the feature passed as `name` is enabled, unknown features are
+/// disabled.
+struct FeatureEnabled {
+    features: HashSet<String>,
+}
+
+impl tera::Function for FeatureEnabled {
+    fn call(&self, args: &HashMap<String, Value>) -> TeraResult<Value> {
+        let name = args
+            .get("name")
+            .and_then(|name| name.as_str())
+            .ok_or_else(|| tera::Error::msg("feature_enabled takes a `name` string argument"))?;
+        Ok(Value::Bool(self.features.contains(name)))
+    }
+}
+
+/// Function that returns whether docs.rs is in maintenance mode, which is the case while the
+/// build queue is paused. The state is cached for `ttl`.
+struct MaintenanceMode {
+    queue: BuildQueue,
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, bool)>>,
+    /// Whether a render is loading the state, while the others keep using the expired one
+    refreshing: AtomicBool,
+}
+
+impl MaintenanceMode {
+    fn new(queue: BuildQueue, ttl: Duration) -> Self {
+        let function = Self {
+            queue,
+            ttl,
+            cached: Mutex::new(None),
+            refreshing: AtomicBool::new(false),
+        };
+        // Load the state right away, as no connection is held by a request handler yet
+        function.is_paused();
+        function
+    }
+
+    fn is_paused(&self) -> bool {
+        // The lock is only held to copy the state, so that the renders don't wait for a database
+        // connection while another one is loading it
+        let cached = *self.cached.lock().unwrap();
+        if let Some((loaded, paused)) = cached {
+            if loaded.elapsed() < self.ttl || self.refreshing.swap(true, Ordering::SeqCst) {
+                return paused;
+            }
+        }
+
+        let paused = match self.queue.is_paused() {
+            Ok(paused) => {
+                *self.cached.lock().unwrap() = Some((Instant::now(), paused));
+                paused
+            }
+            // Failing to check shouldn't prevent the pages from rendering
+            Err(err) => {
+                log::error!(
+                    "failed to check whether the build queue is paused: {:?}",
+                    err
+                );
+                cached.map(|(_, paused)| paused).unwrap_or(false)
+            }
+        };
+        self.refreshing.store(false, Ordering::SeqCst);
+        paused
+    }
+}
+
+impl tera::Function for MaintenanceMode {
+    fn call(&self, _args: &HashMap<String, Value>) -> TeraResult<Value> {
+        Ok(Value::Bool(self.is_paused()))
+    }
+}
+
 /// Prettily format a timestamp
 // TODO: This can be replaced by chrono
 fn timeformat(value: &Value, args: &HashMap<String, Value>) -> TeraResult<Value> {
@@ -249,6 +570,132 @@ fn dedent(value: &Value, _args: &HashMap<String, Value>) -> TeraResult<Value> {
     ))
 }
 
+/// Escape a string as HTML, linking the words which are one of the crate names passed as `known`
+/// to the crate's page. The output has to be marked as `safe`.
+fn crate_links(value: &Value, args: &HashMap<String, Value>) -> TeraResult<Value> {
+    let string = value.as_str().expect("crate_links takes a string");
+    let known = args
+        .get("known")
+        .and_then(|known| known.as_array())
+        .ok_or_else(|| tera::Error::msg("crate_links takes a `known` list of crate names"))?
+        .iter()
+        .filter_map(|name| name.as_str())
+        .collect::<HashSet<_>>();
+
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    let mut html = String::with_capacity(string.len());
+    let mut rest = string;
+    while !rest.is_empty() {
+        // Alternate between words that could be crate names and the text between them
+        let starts_with_word = rest.starts_with(is_name_char);
+        let end = rest
+            .find(|c: char| is_name_char(c) != starts_with_word)
+            .unwrap_or(rest.len());
+        let (token, remaining) = rest.split_at(end);
+
+        let token = tera::escape_html(token);
+        if starts_with_word && known.contains(token.as_str()) {
+            html.push_str(&format!(r#"<a href="/crate/{0}">{0}</a>"#, token));
+        } else {
+            html.push_str(&token);
+        }
+        rest = remaining;
+    }
+
+    Ok(Value::String(html))
+}
+
+/// Returns the `singular` word if the count is 1 or -1, and the `plural` one otherwise. The plural
+/// defaults to the singular followed by an `s`.
+fn pluralize(value: &Value, args: &HashMap<String, Value>) -> TeraResult<Value> {
+    let count = value
+        .as_f64()
+        .ok_or_else(|| tera::Error::msg("pluralize takes a number"))?;
+    let singular = args
+        .get("singular")
+        .and_then(|singular| singular.as_str())
+        .ok_or_else(|| tera::Error::msg("pluralize takes a `singular` word"))?;
+
+    let word = if count.abs() == 1.0 {
+        singular.to_string()
+    } else {
+        match args.get("plural").and_then(|plural| plural.as_str()) {
+            Some(plural) => plural.to_string(),
+            None => format!("{}s", singular),
+        }
+    };
+
+    Ok(Value::String(word))
+}
+
+/// A tera filter, called with the filtered value and the named arguments.
+pub(super) type Filter = fn(&Value, &HashMap<String, Value>) -> TeraResult<Value>;
+
+/// The filters which the handlebars templates can use too, as helpers taking the filtered value
+/// followed by the same named arguments, like `{{version_diff version previous=other}}`.
+pub(super) const HANDLEBARS_FILTERS: &[(&str, Filter)] = &[
+    ("crate_links", crate_links),
+    ("pluralize", pluralize),
+    ("version_diff", version_diff),
+];
+
+/// Escape a version as HTML, wrapping the component (major, minor or patch) which changed since
+/// the `previous` version in a `version-changed` span. Versions which aren't valid semver are
+/// returned as-is. The output has to be marked as `safe`.
+fn version_diff(value: &Value, args: &HashMap<String, Value>) -> TeraResult<Value> {
+    let version = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("version_diff takes a version string"))?;
+    let plain = Value::String(tera::escape_html(version));
+
+    let previous = match args.get("previous").and_then(|previous| previous.as_str()) {
+        Some(previous) => previous,
+        None => return Ok(plain),
+    };
+    let (current, previous) = match (
+        semver::Version::parse(version),
+        semver::Version::parse(previous),
+    ) {
+        (Ok(current), Ok(previous)) => (current, previous),
+        _ => return Ok(plain),
+    };
+
+    let components = [current.major, current.minor, current.patch];
+    let previous_components = [previous.major, previous.minor, previous.patch];
+    let changed = match components
+        .iter()
+        .zip(&previous_components)
+        .position(|(current, previous)| current != previous)
+    {
+        Some(changed) => changed,
+        None => return Ok(plain),
+    };
+    // The pre-release and build metadata are kept as they're written
+    let core = format!("{}.{}.{}", current.major, current.minor, current.patch);
+    let rest = match version.strip_prefix(&core) {
+        Some(rest) => rest,
+        None => return Ok(plain),
+    };
+
+    let mut html = String::with_capacity(version.len() + 40);
+    for (index, component) in components.iter().enumerate() {
+        if index > 0 {
+            html.push('.');
+        }
+        if index == changed {
+            html.push_str(&format!(
+                r#"<span class="version-changed">{}</span>"#,
+                component
+            ));
+        } else {
+            html.push_str(&component.to_string());
+        }
+    }
+    html.push_str(&tera::escape_html(rest));
+
+    Ok(Value::String(html))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -258,10 +705,277 @@ mod tests {
         crate::test::wrapper(|env| {
             let db = env.db();
 
-            let tera = load_templates(&db.conn()).unwrap();
+            let tera = load_templates(&db.pool(), &env.config()).unwrap();
             tera.check_macro_files().unwrap();
 
             Ok(())
         });
     }
+
+    #[test]
+    fn test_invalid_templates_are_rejected() {
+        crate::test::wrapper(|env| {
+            let pool = env.db().pool();
+            let config = env.config();
+
+            let broken = [
+                ("filter.html", "{{ name | not_a_filter }}"),
+                ("function.html", "{{ not_a_function() }}"),
+                ("test.html", "{% if name is not_a_test %}{% endif %}"),
+                ("include.html", "{% include \"missing.html\" %}"),
+                (
+                    "nested.html",
+                    "{% for x in xs %}{% if x %}{{ x | not_a_filter }}{% endif %}{% endfor %}",
+                ),
+            ];
+            for (name, content) in broken.iter() {
+                let dir = tempfile::tempdir()?;
+                std::fs::write(dir.path().join("valid.html"), "{{ name | escape }}")?;
+                let base = dir.path().to_str().unwrap();
+                assert!(load_templates_from(&pool, &config, base).is_ok());
+
+                std::fs::write(dir.path().join(name), content)?;
+                let err = load_templates_from(&pool, &config, base).unwrap_err();
+                let message = err
+                    .iter_chain()
+                    .map(|cause| cause.to_string())
+                    .collect::<Vec<_>>()
+                    .join(": ");
+                assert!(message.contains(name), "{}", message);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_render() {
+        crate::test::wrapper(|env| {
+            let pool = env.db().pool();
+            let config = env.config();
+
+            for &reloadable in &[false, true] {
+                let data = TemplateData::new(&pool, &config, reloadable)?;
+                let rendered = data.render("core/Cargo.toml.example", &tera::Context::new())?;
+                assert!(rendered.contains("[package]\nname = \"test\"\n"));
+                assert!(!rendered.contains("{#"));
+                assert!(data.render("missing.html", &tera::Context::new()).is_err());
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_warm() {
+        crate::test::wrapper(|env| {
+            let pool = env.db().pool();
+            let config = env.config();
+
+            for &reloadable in &[false, true] {
+                TemplateData::new(&pool, &config, reloadable)?.warm()?;
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_reload() {
+        fn current(data: &TemplateData) -> Arc<Tera> {
+            match &data.templates {
+                Templates::Static(templates) => templates.clone(),
+                Templates::Reloadable(templates) => templates.load_full(),
+            }
+        }
+
+        crate::test::wrapper(|env| {
+            let pool = env.db().pool();
+            let config = env.config();
+
+            let data = TemplateData::new(&pool, &config, false)?;
+            let before = current(&data);
+            assert!(!data.reload(&pool, &config)?);
+            assert!(Arc::ptr_eq(&before, &current(&data)));
+
+            let data = TemplateData::new(&pool, &config, true)?;
+            let before = current(&data);
+            assert!(data.reload(&pool, &config)?);
+            assert!(!Arc::ptr_eq(&before, &current(&data)));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_feature_enabled() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.enabled_features = vec!["new-sidebar".to_string()].into_iter().collect();
+            });
+
+            let mut tera = load_templates(&env.db().pool(), &env.config())?;
+            tera.add_raw_template(
+                "feature.html",
+                r#"{% if feature_enabled(name=feature) %}enabled{% else %}disabled{% endif %}"#,
+            )?;
+            let render = |feature: &str| -> Result<String> {
+                let mut context = tera::Context::new();
+                context.insert("feature", feature);
+                Ok(tera.render("feature.html", &context)?)
+            };
+
+            assert_eq!(render("new-sidebar")?, "enabled");
+            assert_eq!(render("unknown-feature")?, "disabled");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_maintenance_mode() {
+        crate::test::wrapper(|env| {
+            let mut tera = Tera::default();
+            tera.register_function(
+                "maintenance_mode",
+                MaintenanceMode::new(
+                    BuildQueue::new(env.db().pool(), &env.config()),
+                    Duration::from_secs(0),
+                ),
+            );
+            tera.add_raw_template(
+                "maintenance.html",
+                r#"{% if maintenance_mode() %}maintenance{% else %}running{% endif %}"#,
+            )?;
+            let render = || -> Result<String> {
+                Ok(tera.render("maintenance.html", &tera::Context::new())?)
+            };
+
+            assert_eq!(render()?, "running");
+            env.build_queue().set_paused(true)?;
+            assert_eq!(render()?, "maintenance");
+            env.build_queue().set_paused(false)?;
+            assert_eq!(render()?, "running");
+
+            // The banner is shown on the pages while builds are paused
+            env.build_queue().set_paused(true)?;
+            let home = env.frontend().get("/").send()?.text()?;
+            assert!(home.contains("maintenance-alert"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_maintenance_mode_refresh() {
+        crate::test::wrapper(|env| {
+            let function = MaintenanceMode::new(
+                BuildQueue::new(env.db().pool(), &env.config()),
+                Duration::from_secs(0),
+            );
+            env.build_queue().set_paused(true)?;
+
+            // While another render is loading the state, the expired one is used
+            function.refreshing.store(true, Ordering::SeqCst);
+            assert!(!function.is_paused());
+
+            function.refreshing.store(false, Ordering::SeqCst);
+            assert!(function.is_paused());
+            assert!(!function.refreshing.load(Ordering::SeqCst));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_pluralize() {
+        crate::test::wrapper(|env| {
+            let mut tera = load_templates(&env.db().pool(), &env.config())?;
+            tera.add_raw_template(
+                "pluralize.html",
+                r#"{{ count }} {{ count | pluralize(singular="version") }}, {{ count }} {{ count | pluralize(singular="dependency", plural="dependencies") }}"#,
+            )?;
+            let render = |count: i64| -> Result<String> {
+                let mut context = tera::Context::new();
+                context.insert("count", &count);
+                Ok(tera.render("pluralize.html", &context)?)
+            };
+
+            assert_eq!(render(0)?, "0 versions, 0 dependencies");
+            assert_eq!(render(1)?, "1 version, 1 dependency");
+            assert_eq!(render(-1)?, "-1 version, -1 dependency");
+            assert_eq!(render(42)?, "42 versions, 42 dependencies");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_version_diff() {
+        let render = |version: &str, previous: Option<&str>| {
+            let mut args = HashMap::new();
+            if let Some(previous) = previous {
+                args.insert("previous".to_string(), Value::String(previous.into()));
+            }
+            version_diff(&Value::String(version.into()), &args)
+                .unwrap()
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(
+            render("2.0.0", Some("1.4.2")),
+            r#"<span class="version-changed">2</span>.0.0"#
+        );
+        assert_eq!(
+            render("1.5.0", Some("1.4.2")),
+            r#"1.<span class="version-changed">5</span>.0"#
+        );
+        assert_eq!(
+            render("1.4.3-beta.1", Some("1.4.2")),
+            r#"1.4.<span class="version-changed">3</span>-beta.1"#
+        );
+
+        // Nothing is highlighted without a previous version or when the core didn't change
+        assert_eq!(render("1.4.2", None), "1.4.2");
+        assert_eq!(render("1.4.2", Some("1.4.2-rc.1")), "1.4.2");
+        // Versions which aren't semver are escaped as-is
+        assert_eq!(render("<1.0>", Some("0.9.0")), "&lt;1.0&gt;");
+        assert_eq!(render("1.0.0", Some("latest")), "1.0.0");
+    }
+
+    #[test]
+    fn test_crate_links() {
+        let render = |text: &str| {
+            let mut args = HashMap::new();
+            args.insert(
+                "known".to_string(),
+                serde_json::json!(["serde", "serde_json"]),
+            );
+            crate_links(&Value::String(text.into()), &args)
+                .unwrap()
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(
+            render("serde and serde_json"),
+            r#"<a href="/crate/serde">serde</a> and <a href="/crate/serde_json">serde_json</a>"#
+        );
+        assert_eq!(
+            render("(serde), serde."),
+            r#"(<a href="/crate/serde">serde</a>), <a href="/crate/serde">serde</a>."#
+        );
+        // Only whole words are linked
+        assert_eq!(render("serde-derive serdes"), "serde-derive serdes");
+        assert_eq!(
+            render("<script>serde</script>"),
+            r#"&lt;script&gt;<a href="/crate/serde">serde</a>&lt;&#x2F;script&gt;"#
+        );
+        assert_eq!(render(""), "");
+
+        assert!(crate_links(&Value::String("serde".into()), &HashMap::new()).is_err());
+    }
 }
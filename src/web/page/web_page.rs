@@ -1,6 +1,13 @@
-use super::TemplateData;
-use iron::{headers::ContentType, response::Response, status::Status, IronResult, Request};
+use super::{dismissed_alert, show_global_alert, GlobalAlert, RenderedAlert, TemplateData};
+use crate::error::Result;
+use iron::{
+    headers::{ContentType, Cookie},
+    response::Response,
+    status::Status,
+    IronResult, Request,
+};
 use serde::Serialize;
+use std::borrow::Cow;
 use tera::Context;
 
 /// When making using a custom status, use a closure that coerces to a `fn(&Self) -> Status`
@@ -26,24 +33,68 @@ macro_rules! impl_webpage {
     };
 }
 
+/// The context templates are rendered with, pre-populated with the values every page needs so
+/// that handlers only have to add their own
+#[derive(Debug, Clone)]
+pub(crate) struct TemplateContext {
+    context: Context,
+}
+
+impl TemplateContext {
+    /// Creates a context containing the global alert, unless the user dismissed it, and the
+    /// current version of docs.rs
+    pub(crate) fn base(req: &Request) -> Self {
+        Self::with_global_alert(
+            crate::GLOBAL_ALERT.as_ref(),
+            dismissed_alert(req.headers.get::<Cookie>()),
+        )
+    }
+
+    fn with_global_alert(global_alert: Option<&GlobalAlert>, dismissed: Option<&str>) -> Self {
+        let mut context = Context::new();
+        context.insert("global_alert", &global_alert.map(RenderedAlert::new));
+        context.insert(
+            "show_global_alert",
+            &show_global_alert(global_alert, dismissed),
+        );
+        context.insert("docsrs_version", crate::BUILD_VERSION);
+
+        Self { context }
+    }
+
+    /// Adds the fields of `page` to the context, which fails if it doesn't serialize to a map
+    pub(crate) fn page(mut self, page: &impl Serialize) -> tera::Result<Self> {
+        self.context.extend(Context::from_serialize(page)?);
+        Ok(self)
+    }
+
+    pub(crate) fn render(&self, templates: &TemplateData, template: &str) -> Result<String> {
+        templates.render(template, &self.context)
+    }
+}
+
 /// The central trait that rendering pages revolves around, it handles selecting and rendering the template
 pub trait WebPage: Serialize + Sized {
     /// Turn the current instance into a `Response`, ready to be served
     // TODO: We could cache similar pages using the `&Context`
     fn into_response(self, req: &Request) -> IronResult<Response> {
-        let ctx = Context::from_serialize(&self).unwrap();
-
-        let rendered = req
-            .extensions
-            .get::<TemplateData>()
-            .expect("missing TemplateData from the request extensions")
-            .templates
-            .load()
-            .render(Self::TEMPLATE, &ctx)
+        let ctx = TemplateContext::base(req).page(&self).unwrap();
+
+        let rendered = ctx
+            .render(
+                req.extensions
+                    .get::<TemplateData>()
+                    .expect("missing TemplateData from the request extensions"),
+                &self.template(),
+            )
             .unwrap();
 
         let mut response = Response::with((self.get_status(), rendered));
         response.headers.set(Self::content_type());
+        if crate::GLOBAL_ALERT.is_some() {
+            // Caches must not serve the page to users who dismissed the alert and vice versa
+            response.headers.append_raw("Vary", b"Cookie".to_vec());
+        }
 
         Ok(response)
     }
@@ -51,6 +102,11 @@ pub trait WebPage: Serialize + Sized {
     /// The name of the template to be rendered
     const TEMPLATE: &'static str;
 
+    /// Gets the name of the template to render for this instance, defaults to `TEMPLATE`
+    fn template(&self) -> Cow<'static, str> {
+        Cow::Borrowed(Self::TEMPLATE)
+    }
+
     /// Gets the status of the request, defaults to `Ok`
     fn get_status(&self) -> Status {
         Status::Ok
@@ -61,3 +117,84 @@ pub trait WebPage: Serialize + Sized {
         ContentType::html()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::{error::ErrorPage, releases::Search};
+
+    #[test]
+    fn test_global_alert_is_rendered_on_every_page() {
+        crate::test::wrapper(|env| {
+            let templates = TemplateData::new(&env.db().pool(), &env.config(), false)?;
+            let alert = GlobalAlert {
+                url: "https://blog.rust-lang.org/",
+                text: "Scheduled maintenance",
+                css_class: "warn",
+                fa_icon: "warning",
+            };
+            let render = |page: &dyn Fn(TemplateContext) -> tera::Result<TemplateContext>,
+                          template: &str| {
+                page(TemplateContext::with_global_alert(Some(&alert), None))?
+                    .render(&templates, template)
+            };
+
+            let error = ErrorPage::new(Status::NotFound, "no such crate");
+            let search = Search {
+                title: "No crates found matching 'foo'".into(),
+                search_query: Some("foo".into()),
+                ..Default::default()
+            };
+            for rendered in &[
+                render(&|ctx| ctx.page(&error), &error.template())?,
+                render(&|ctx| ctx.page(&search), &search.template())?,
+            ] {
+                assert!(rendered.contains("Scheduled maintenance"));
+                assert!(rendered.contains("fa-warning"));
+                assert!(rendered.contains(crate::BUILD_VERSION));
+            }
+
+            let without_alert = TemplateContext::with_global_alert(None, None)
+                .page(&error)?
+                .render(&templates, &error.template())?;
+            assert!(!without_alert.contains("Scheduled maintenance"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_dismissed_global_alert_is_hidden() {
+        crate::test::wrapper(|env| {
+            let templates = TemplateData::new(&env.db().pool(), &env.config(), false)?;
+            let alert = GlobalAlert {
+                url: "https://blog.rust-lang.org/",
+                text: "Scheduled maintenance",
+                css_class: "warn",
+                fa_icon: "warning",
+            };
+            let error = ErrorPage::new(Status::NotFound, "no such crate");
+            let render = |cookie: Cookie| -> Result<String> {
+                TemplateContext::with_global_alert(Some(&alert), dismissed_alert(Some(&cookie)))
+                    .page(&error)?
+                    .render(&templates, &error.template())
+            };
+
+            let shown = render(Cookie(vec![]))?;
+            assert!(shown.contains("Scheduled maintenance"));
+            assert!(shown.contains(&format!(r#"data-alert-id="{}""#, alert.id())));
+
+            let dismissed = render(Cookie(vec![
+                "theme=dark".into(),
+                format!("dismissed_alert={}", alert.id()),
+            ]))?;
+            assert!(!dismissed.contains("Scheduled maintenance"));
+
+            // Dismissing an older alert doesn't hide the new one
+            let outdated = render(Cookie(vec!["dismissed_alert=0123456789abcdef".into()]))?;
+            assert!(outdated.contains("Scheduled maintenance"));
+
+            Ok(())
+        });
+    }
+}
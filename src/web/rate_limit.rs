@@ -0,0 +1,328 @@
+//! Middleware limiting how many requests each client IP can make, to protect the server against
+//! misbehaving crawlers.
+//!
+//! Every IP gets a token bucket refilled at the configured rate, and each request takes a token
+//! out of it. Once the bucket is empty the requests are rejected with `429 Too Many Requests`
+//! until enough time passed for a new token to be added.
+//!
+//! Clients whose user agent matches one of the configured exemptions, like the crawlers of the
+//! search engines, are never limited. The user agent isn't verified.
+//!
+//! Behind reverse proxies every request comes from the address of the proxy, so the client IP is
+//! instead read from the `X-Forwarded-For` entry added by the outermost of the configured number
+//! of trusted proxies. Entries further left are set by the client and can't be trusted.
+
+use crate::Config;
+use iron::headers::UserAgent;
+use iron::prelude::*;
+use iron::{status, Handler};
+use log::debug;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Above this number of tracked clients, the buckets that refilled completely are forgotten.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Paths starting with this prefix are never rate limited, as they're used by the health checks
+/// and by the internal tools.
+const EXEMPT_PREFIX: &str = "-";
+
+pub(super) struct RateLimiter<H: Handler> {
+    handler: H,
+    limits: Option<Limits>,
+    /// Lowercase substrings of the exempted user agents.
+    exempt_user_agents: Vec<String>,
+    /// Number of reverse proxies appending to `X-Forwarded-For` in front of the server.
+    trusted_proxies: usize,
+}
+
+impl<H: Handler> RateLimiter<H> {
+    /// Wraps `handler`, limiting the requests as set in the configuration. Nothing is limited when
+    /// no rate is configured.
+    pub(super) fn new(handler: H, config: &Config) -> Self {
+        Self {
+            handler,
+            limits: config
+                .rate_limit_per_second
+                .map(|per_second| Limits::new(per_second, config.rate_limit_burst, Instant::now())),
+            exempt_user_agents: config
+                .rate_limit_exempt_user_agents
+                .iter()
+                .map(|agent| agent.to_lowercase())
+                .collect(),
+            trusted_proxies: config.rate_limit_trusted_proxies,
+        }
+    }
+
+    fn client_ip(&self, req: &Request) -> IpAddr {
+        forwarded_client(req.headers.get_raw("X-Forwarded-For"), self.trusted_proxies)
+            .unwrap_or_else(|| req.remote_addr.ip())
+    }
+
+    fn is_exempt_user_agent(&self, req: &Request) -> bool {
+        let user_agent = match req.headers.get::<UserAgent>() {
+            Some(user_agent) => user_agent.to_lowercase(),
+            None => return false,
+        };
+        self.exempt_user_agents
+            .iter()
+            .any(|exempt| user_agent.contains(exempt.as_str()))
+    }
+}
+
+impl<H: Handler> Handler for RateLimiter<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let limits = match &self.limits {
+            Some(limits) => limits,
+            None => return self.handler.handle(req),
+        };
+        if req.url.path().first() == Some(&EXEMPT_PREFIX) {
+            return self.handler.handle(req);
+        }
+        if self.is_exempt_user_agent(req) {
+            debug!(
+                "not rate limiting {} from {}, its user agent is exempted",
+                req.url,
+                self.client_ip(req)
+            );
+            return self.handler.handle(req);
+        }
+
+        match limits.acquire(self.client_ip(req), Instant::now()) {
+            Ok(()) => self.handler.handle(req),
+            Err(retry_after) => {
+                let mut resp = Response::with((status::TooManyRequests, "too many requests"));
+                // Retry-After only accepts whole seconds, round up to avoid retrying too early
+                let seconds = retry_after.as_secs() + (retry_after.subsec_nanos() > 0) as u64;
+                resp.headers
+                    .set_raw("Retry-After", vec![seconds.to_string().into_bytes()]);
+                Ok(resp)
+            }
+        }
+    }
+}
+
+/// Finds the client address in the `X-Forwarded-For` header lines, which is the entry added by the
+/// outermost of the `trusted_proxies` proxies. Returns `None` when no proxy is trusted or when the
+/// request didn't go through all of them.
+fn forwarded_client(lines: Option<&[Vec<u8>]>, trusted_proxies: usize) -> Option<IpAddr> {
+    if trusted_proxies == 0 {
+        return None;
+    }
+
+    let entries = lines?
+        .iter()
+        .filter_map(|line| std::str::from_utf8(line).ok())
+        .flat_map(|line| line.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    let index = entries.len().checked_sub(trusted_proxies)?;
+    entries[index].parse().ok()
+}
+
+/// Token buckets of all the clients.
+struct Limits {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    last_cleanup: Mutex<Instant>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Limits {
+    fn new(per_second: f64, burst: u32, now: Instant) -> Self {
+        Self {
+            per_second,
+            burst: f64::from(burst),
+            buckets: Mutex::new(HashMap::new()),
+            last_cleanup: Mutex::new(now),
+        }
+    }
+
+    /// Takes a token from the bucket of `ip`, returning how long to wait for the next token if
+    /// the bucket is empty.
+    fn acquire(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            self.forget_full_buckets(&mut buckets, now);
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            ))
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.burst)
+    }
+
+    /// A full bucket is the same as a missing one, so they can be removed to save memory. This is
+    /// done at most once per refill period to avoid scanning all the buckets on every request
+    /// while under attack from a lot of IPs.
+    fn forget_full_buckets(&self, buckets: &mut HashMap<IpAddr, Bucket>, now: Instant) {
+        let mut last_cleanup = self.last_cleanup.lock().unwrap();
+        let refill_period = Duration::from_secs_f64(self.burst / self.per_second);
+        if now.saturating_duration_since(*last_cleanup) < refill_period {
+            return;
+        }
+
+        buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+        *last_cleanup = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let limits = Limits::new(2.0, 3, start);
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let other_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+        for _ in 0..3 {
+            assert_eq!(limits.acquire(ip, start), Ok(()));
+        }
+        assert_eq!(limits.acquire(ip, start), Err(Duration::from_millis(500)));
+        // Other clients have their own bucket
+        assert_eq!(limits.acquire(other_ip, start), Ok(()));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limits.acquire(ip, later), Ok(()));
+        assert!(limits.acquire(ip, later).is_err());
+
+        // The bucket never holds more than the burst
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limits.acquire(ip, much_later), Ok(()));
+        }
+        assert!(limits.acquire(ip, much_later).is_err());
+    }
+
+    #[test]
+    fn test_forwarded_client() {
+        let header = |lines: &[&str]| {
+            lines
+                .iter()
+                .map(|line| line.as_bytes().to_vec())
+                .collect::<Vec<_>>()
+        };
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+
+        let lines = header(&["1.1.1.1, 2.2.2.2", "3.3.3.3"]);
+        assert_eq!(forwarded_client(Some(&lines), 0), None);
+        assert_eq!(forwarded_client(Some(&lines), 1), ip("3.3.3.3"));
+        assert_eq!(forwarded_client(Some(&lines), 2), ip("2.2.2.2"));
+        assert_eq!(forwarded_client(Some(&lines), 3), ip("1.1.1.1"));
+        // The request didn't go through all the trusted proxies
+        assert_eq!(forwarded_client(Some(&lines), 4), None);
+        assert_eq!(forwarded_client(None, 1), None);
+
+        let lines = header(&["::1, not an ip"]);
+        assert_eq!(forwarded_client(Some(&lines), 1), None);
+        assert_eq!(forwarded_client(Some(&lines), 2), ip("::1"));
+    }
+
+    #[test]
+    fn test_clients_behind_a_proxy_are_limited_separately() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.rate_limit_per_second = Some(0.1);
+                config.rate_limit_burst = 1;
+                config.rate_limit_trusted_proxies = 1;
+            });
+            let web = env.frontend();
+            let get = |forwarded_for: &str| {
+                web.get("/about")
+                    .header("X-Forwarded-For", forwarded_for)
+                    .send()
+                    .map(|resp| resp.status())
+            };
+
+            assert!(get("10.0.0.1")?.is_success());
+            assert_eq!(get("10.0.0.1")?, StatusCode::TOO_MANY_REQUESTS);
+            // Spoofed entries left of the trusted one are ignored
+            assert_eq!(get("10.0.0.3, 10.0.0.1")?, StatusCode::TOO_MANY_REQUESTS);
+            assert!(get("10.0.0.2")?.is_success());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_requests_over_the_limit_are_rejected() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.rate_limit_per_second = Some(0.1);
+                config.rate_limit_burst = 2;
+            });
+            let web = env.frontend();
+
+            for _ in 0..2 {
+                assert!(web.get("/about").send()?.status().is_success());
+            }
+            let resp = web.get("/about").send()?;
+            assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(resp.headers()["retry-after"], "10");
+
+            // The internal routes are never limited
+            assert!(web.get("/-/build-info").send()?.status().is_success());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_exempt_user_agents_are_not_limited() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.rate_limit_per_second = Some(0.1);
+                config.rate_limit_burst = 1;
+                config.rate_limit_exempt_user_agents =
+                    vec!["Googlebot".to_string()].into_iter().collect();
+            });
+            let web = env.frontend();
+            let crawler =
+                "Mozilla/5.0 (compatible; googlebot/2.1; +http://www.google.com/bot.html)";
+
+            for _ in 0..5 {
+                let resp = web.get("/about").header("User-Agent", crawler).send()?;
+                assert!(resp.status().is_success());
+            }
+
+            // Other clients are still limited
+            assert!(web.get("/about").send()?.status().is_success());
+            let resp = web.get("/about").send()?;
+            assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+            Ok(())
+        });
+    }
+}
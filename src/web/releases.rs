@@ -1,6 +1,7 @@
 //! Releases web handlers
 
 use crate::{
+    build_queue::QueuedCrate,
     db::Pool,
     impl_webpage,
     web::{
@@ -18,10 +19,11 @@ use iron::{
     modifiers::Redirect,
     status, IronError, IronResult, Plugin, Request, Response, Url,
 };
-use postgres::Connection;
+use postgres::{rows::Row, Connection};
 use router::Router;
 use serde::Serialize;
 use serde_json::Value;
+use std::{fmt, str::FromStr};
 
 /// Number of release in home page
 const RELEASES_IN_HOME: i64 = 15;
@@ -29,6 +31,8 @@ const RELEASES_IN_HOME: i64 = 15;
 const RELEASES_IN_RELEASES: i64 = 30;
 /// Releases in recent releases feed
 const RELEASES_IN_FEED: i64 = 150;
+/// Maximum number of releases in the feed that can be requested with the `count` parameter
+const MAX_RELEASES_IN_FEED: i64 = 500;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Release {
@@ -36,7 +40,7 @@ pub struct Release {
     pub(crate) version: String,
     description: Option<String>,
     target_name: Option<String>,
-    rustdoc_status: bool,
+    pub(crate) rustdoc_status: bool,
     pub(crate) release_time: DateTime<Utc>,
     stars: i32,
 }
@@ -132,18 +136,152 @@ pub(crate) fn get_releases(conn: &Connection, page: i64, limit: i64, order: Orde
     };
     let query = conn.query(&query, &[&limit, &offset]).unwrap();
 
-    query
-        .into_iter()
-        .map(|row| Release {
-            name: row.get(0),
-            version: row.get(1),
-            description: row.get(2),
-            target_name: row.get(3),
-            release_time: DateTime::from_utc(row.get::<_, NaiveDateTime>(4), Utc),
-            rustdoc_status: row.get(5),
-            stars: row.get(6),
+    query.iter().map(|row| release_from_row(&row)).collect()
+}
+
+fn release_from_row(row: &Row) -> Release {
+    Release {
+        name: row.get(0),
+        version: row.get(1),
+        description: row.get(2),
+        target_name: row.get(3),
+        release_time: DateTime::from_utc(row.get::<_, NaiveDateTime>(4), Utc),
+        rustdoc_status: row.get(5),
+        stars: row.get(6),
+    }
+}
+
+/// Position in the listing of all the releases sorted by release time, newest first. Releases
+/// published at the same time are sorted by their id.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum ReleaseCursor {
+    /// The releases coming after the given one in the listing.
+    After(NaiveDateTime, i32),
+    /// The releases coming before the given one in the listing.
+    Before(NaiveDateTime, i32),
+}
+
+impl fmt::Display for ReleaseCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (direction, time, id) = match self {
+            Self::After(time, id) => ("after", time, id),
+            Self::Before(time, id) => ("before", time, id),
+        };
+        let micros = time.timestamp() * 1_000_000 + i64::from(time.timestamp_subsec_micros());
+        write!(f, "{}.{}.{}", direction, micros, id)
+    }
+}
+
+impl FromStr for ReleaseCursor {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, '.');
+        let direction = parts.next().ok_or(())?;
+        let micros: i64 = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        let id: i32 = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+
+        let time = NaiveDateTime::from_timestamp_opt(
+            micros.div_euclid(1_000_000),
+            micros.rem_euclid(1_000_000) as u32 * 1000,
+        )
+        .ok_or(())?;
+        match direction {
+            "after" => Ok(Self::After(time, id)),
+            "before" => Ok(Self::Before(time, id)),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ReleasesPage {
+    pub(crate) releases: Vec<Release>,
+    /// Where the next page starts, if there are more releases after this one.
+    pub(crate) next: Option<ReleaseCursor>,
+    /// Where the previous page ends, if there are more releases before this one.
+    pub(crate) previous: Option<ReleaseCursor>,
+}
+
+/// Loads a page of at most `limit` releases, sorted by release time with the newest first,
+/// starting from `cursor` or from the newest release if it's `None`.
+///
+/// Unlike `LIMIT`/`OFFSET` pagination, the cost of loading a page doesn't depend on how far it is
+/// in the listing, and the pages don't shift when new releases are published.
+pub(crate) fn get_releases_page(
+    conn: &Connection,
+    cursor: Option<ReleaseCursor>,
+    limit: i64,
+) -> Result<ReleasesPage, postgres::Error> {
+    let (filter, order, position) = match cursor {
+        None => ("", "DESC", None),
+        Some(ReleaseCursor::After(time, id)) => (
+            "WHERE (releases.release_time, releases.id) < ($2, $3)",
+            "DESC",
+            Some((time, id)),
+        ),
+        Some(ReleaseCursor::Before(time, id)) => (
+            "WHERE (releases.release_time, releases.id) > ($2, $3)",
+            "ASC",
+            Some((time, id)),
+        ),
+    };
+    let query = format!(
+        "SELECT crates.name,
+                releases.version,
+                releases.description,
+                releases.target_name,
+                releases.release_time,
+                releases.rustdoc_status,
+                crates.github_stars,
+                releases.id
+         FROM crates
+         INNER JOIN releases ON crates.id = releases.crate_id
+         {}
+         ORDER BY releases.release_time {order}, releases.id {order}
+         LIMIT $1",
+        filter,
+        order = order,
+    );
+
+    // One more release than needed is loaded to know whether there are more pages
+    let fetch = limit + 1;
+    let rows = match &position {
+        Some((time, id)) => conn.query(&query, &[&fetch, time, id])?,
+        None => conn.query(&query, &[&fetch])?,
+    };
+    let mut rows: Vec<_> = rows
+        .iter()
+        .map(|row| {
+            let cursor = (row.get::<_, NaiveDateTime>(4), row.get::<_, i32>(7));
+            (cursor, release_from_row(&row))
         })
-        .collect()
+        .collect();
+    let has_more = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+
+    let going_back = matches!(cursor, Some(ReleaseCursor::Before(..)));
+    if going_back {
+        rows.reverse();
+    }
+
+    let first = rows
+        .first()
+        .map(|&((time, id), _)| ReleaseCursor::Before(time, id));
+    let last = rows
+        .last()
+        .map(|&((time, id), _)| ReleaseCursor::After(time, id));
+    let (next, previous) = match cursor {
+        None => (last.filter(|_| has_more), None),
+        Some(ReleaseCursor::After(..)) => (last.filter(|_| has_more), first),
+        Some(ReleaseCursor::Before(..)) => (last, first.filter(|_| has_more)),
+    };
+
+    Ok(ReleasesPage {
+        releases: rows.into_iter().map(|(_, release)| release).collect(),
+        next,
+        previous,
+    })
 }
 
 fn get_releases_by_author(
@@ -358,12 +496,28 @@ impl_webpage! {
 }
 
 pub fn releases_feed_handler(req: &mut Request) -> IronResult<Response> {
-    let conn = extension!(req, Pool).get()?;
-    let recent_releases = get_releases(&conn, 1, RELEASES_IN_FEED, Order::ReleaseTime);
+    use params::{Params, Value};
+
+    let count = match ctry!(req.get::<Params>()).find(&["count"]) {
+        Some(Value::String(count)) => feed_release_count(count),
+        _ => RELEASES_IN_FEED,
+    };
+
+    let conn = extension!(req, Pool).get_replica()?;
+    let recent_releases = get_releases(&conn, 1, count, Order::ReleaseTime);
 
     ReleaseFeed { recent_releases }.into_response(req)
 }
 
+/// Parses the `count` parameter of the feed, falling back to the default for invalid values and
+/// capping it to `MAX_RELEASES_IN_FEED`.
+fn feed_release_count(count: &str) -> i64 {
+    match count.parse::<i64>() {
+        Ok(count) if count > 0 => count.min(MAX_RELEASES_IN_FEED),
+        _ => RELEASES_IN_FEED,
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 struct ViewReleases {
     releases: Vec<Release>,
@@ -411,7 +565,7 @@ fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<
     };
 
     let releases = {
-        let conn = extension!(req, Pool).get()?;
+        let conn = extension!(req, Pool).get_replica()?;
         get_releases(&conn, page_number, RELEASES_IN_RELEASES, release_order)
     };
 
@@ -542,7 +696,7 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
 
     let params = ctry!(req.get::<Params>());
     let query = params.find(&["query"]);
-    let conn = extension!(req, Pool).get()?;
+    let conn = extension!(req, Pool).get_replica()?;
 
     if let Some(Value::String(query)) = query {
         // check if I am feeling lucky button pressed and redirect user to crate page
@@ -651,6 +805,72 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
     }
 }
 
+/// Searches the crate names and descriptions with the Postgres full-text search, returning the
+/// latest release of the matching crates, the most relevant first.
+///
+/// Matches in the crate name are ranked higher than matches in the description.
+fn search_crates(
+    conn: &Connection,
+    query: &str,
+    limit: i64,
+) -> Result<Vec<Release>, postgres::Error> {
+    let rows = conn.query(
+        "SELECT crates.name,
+                releases.version,
+                releases.description,
+                releases.target_name,
+                releases.release_time,
+                releases.rustdoc_status,
+                crates.github_stars
+         FROM crates
+         INNER JOIN releases ON releases.id = crates.latest_version_id,
+              plainto_tsquery('english', $1) AS query
+         WHERE crates.content @@ query
+         ORDER BY ts_rank(crates.content, query) DESC,
+                  releases.downloads DESC,
+                  crates.name
+         LIMIT $2",
+        &[&query, &limit],
+    )?;
+
+    Ok(rows.iter().map(|row| release_from_row(&row)).collect())
+}
+
+/// Handler for `/search?q=`, listing the crates matching the full-text search query.
+pub fn full_text_search_handler(req: &mut Request) -> IronResult<Response> {
+    use params::{Params, Value};
+
+    let query = match ctry!(req.get::<Params>()).find(&["q"]) {
+        Some(Value::String(query)) => query.trim().to_owned(),
+        _ => String::new(),
+    };
+    if query.is_empty() {
+        return Search {
+            title: "Please enter a search query".into(),
+            search_query: Some(query),
+            status: status::BadRequest,
+            ..Default::default()
+        }
+        .into_response(req);
+    }
+
+    let conn = extension!(req, Pool).get_replica()?;
+    let results = ctry!(search_crates(&conn, &query, RELEASES_IN_RELEASES));
+    let title = if results.is_empty() {
+        format!("No results found for '{}'", query)
+    } else {
+        format!("Search results for '{}'", query)
+    };
+
+    Search {
+        title,
+        results,
+        search_query: Some(query),
+        ..Default::default()
+    }
+    .into_response(req)
+}
+
 pub fn activity_handler(req: &mut Request) -> IronResult<Response> {
     let conn = extension!(req, Pool).get()?;
     let release_activity_data: Value = ctry!(conn.query(
@@ -670,6 +890,13 @@ pub fn activity_handler(req: &mut Request) -> IronResult<Response> {
         .to_resp("releases_activity")
 }
 
+#[derive(Serialize)]
+struct QueueContent {
+    queued: Vec<QueuedCrate>,
+    queued_count: usize,
+    failed: Vec<QueuedCrate>,
+}
+
 pub fn build_queue_handler(req: &mut Request) -> IronResult<Response> {
     let queue = extension!(req, BuildQueue);
 
@@ -682,7 +909,13 @@ pub fn build_queue_handler(req: &mut Request) -> IronResult<Response> {
     }
 
     let is_empty = crates.is_empty();
-    Page::new(crates)
+    let content = QueueContent {
+        queued_count: crates.len(),
+        queued: crates,
+        failed: ctry!(queue.failed_crates()),
+    };
+
+    Page::new(content)
         .title("Build queue")
         .set("description", "List of crates scheduled to build")
         .set_bool("queue_empty", is_empty)
@@ -695,9 +928,10 @@ pub fn build_queue_handler(req: &mut Request) -> IronResult<Response> {
 mod tests {
     use super::*;
     use crate::test::{assert_success, wrapper, TestEnvironment};
-    use chrono::TimeZone;
+    use chrono::{Duration, NaiveDate, TimeZone};
     use failure::Error;
     use kuchiki::traits::TendrilSink;
+    use reqwest::StatusCode;
 
     #[test]
     fn database_search() {
@@ -876,6 +1110,65 @@ mod tests {
         })
     }
 
+    fn names(page: &ReleasesPage) -> Vec<&str> {
+        page.releases
+            .iter()
+            .map(|release| release.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn releases_keyset_pagination() {
+        wrapper(|env| {
+            let db = env.db();
+            for (name, day) in &[("a", 1), ("b", 2), ("c", 3), ("d", 3), ("e", 4)] {
+                db.fake_release()
+                    .name(name)
+                    .release_time(Utc.ymd(2020, 1, *day).and_hms(12, 0, 0))
+                    .create()?;
+            }
+            let first = get_releases_page(&db.conn(), None, 2)?;
+            assert_eq!(names(&first), vec!["e", "d"]);
+            assert_eq!(first.previous, None);
+
+            let second = get_releases_page(&db.conn(), first.next, 2)?;
+            assert_eq!(names(&second), vec!["c", "b"]);
+
+            let third = get_releases_page(&db.conn(), second.next, 2)?;
+            assert_eq!(names(&third), vec!["a"]);
+            assert_eq!(third.next, None);
+
+            let back = get_releases_page(&db.conn(), third.previous, 2)?;
+            assert_eq!(back, second);
+            let back = get_releases_page(&db.conn(), back.previous, 2)?;
+            assert_eq!(names(&back), vec!["e", "d"]);
+            assert_eq!(back.previous, None);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn release_cursor_roundtrip() {
+        let time = NaiveDate::from_ymd(1969, 7, 20).and_hms_micro(20, 17, 40, 123_456);
+        for cursor in &[
+            ReleaseCursor::After(time, 42),
+            ReleaseCursor::Before(time, 1),
+        ] {
+            assert_eq!(cursor.to_string().parse(), Ok(*cursor));
+        }
+        for invalid in &[
+            "",
+            "after",
+            "after-1",
+            "sideways-1-1",
+            "after-x-1",
+            "before-1-y",
+        ] {
+            assert_eq!(invalid.parse::<ReleaseCursor>(), Err(()));
+        }
+    }
+
     #[test]
     fn search_offsets() {
         wrapper(|env| {
@@ -1078,6 +1371,68 @@ mod tests {
         })
     }
 
+    #[test]
+    fn full_text_search() {
+        wrapper(|env| {
+            let db = env.db();
+            db.fake_release()
+                .name("json-parser")
+                .description("A fast JSON library")
+                .create()?;
+            db.fake_release()
+                .name("toml-edit")
+                .description("Format preserving TOML parser")
+                .create()?;
+            db.fake_release()
+                .name("colors")
+                .description("Colorful terminal output")
+                .create()?;
+
+            let names = |results: Vec<Release>| {
+                results
+                    .into_iter()
+                    .map(|release| release.name)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                names(search_crates(&db.conn(), "parser", 10)?),
+                vec!["json-parser", "toml-edit"]
+            );
+            assert_eq!(
+                names(search_crates(&db.conn(), "TOML parsers", 10)?),
+                vec!["toml-edit"]
+            );
+            assert!(search_crates(&db.conn(), "database", 10)?.is_empty());
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/search?q=parser")
+                    .send()?
+                    .error_for_status()?
+                    .text()?,
+            );
+            let results = page
+                .select(".release .name")
+                .unwrap()
+                .map(|node| node.text_contents().trim().to_owned())
+                .collect::<Vec<_>>();
+            assert_eq!(results, vec!["json-parser-1.0.0", "toml-edit-1.0.0"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn full_text_search_empty_query() {
+        wrapper(|env| {
+            let web = env.frontend();
+            for url in &["/search", "/search?q=", "/search?q=%20%20"] {
+                assert_eq!(web.get(url).send()?.status(), StatusCode::BAD_REQUEST);
+            }
+            Ok(())
+        })
+    }
+
     #[test]
     fn releases() {
         wrapper(|env| {
@@ -1121,6 +1476,44 @@ mod tests {
         })
     }
 
+    #[test]
+    fn release_feed_count() {
+        wrapper(|env| {
+            let db = env.db();
+            let now = Utc::now();
+            for (i, name) in ["oldest", "middle", "newest"].iter().enumerate() {
+                db.fake_release()
+                    .name(name)
+                    .release_time(now - Duration::minutes(10 - i as i64))
+                    .create()?;
+            }
+
+            let body = env
+                .frontend()
+                .get("/releases/feed?count=2")
+                .send()?
+                .text()?;
+            assert_eq!(body.matches("<entry>").count(), 2);
+            let newest = body.find("<title>newest-1.0.0</title>").unwrap();
+            let middle = body.find("<title>middle-1.0.0</title>").unwrap();
+            assert!(newest < middle);
+            assert!(!body.contains("oldest"));
+
+            let body = env.frontend().get("/releases/feed").send()?.text()?;
+            assert_eq!(body.matches("<entry>").count(), 3);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn release_feed_count_is_capped() {
+        assert_eq!(feed_release_count("10"), 10);
+        assert_eq!(feed_release_count("100000"), MAX_RELEASES_IN_FEED);
+        assert_eq!(feed_release_count("0"), RELEASES_IN_FEED);
+        assert_eq!(feed_release_count("many"), RELEASES_IN_FEED);
+    }
+
     #[test]
     fn test_releases_queue() {
         wrapper(|env| {
@@ -1144,6 +1537,10 @@ mod tests {
                 .collect::<Vec<_>>();
 
             assert_eq!(items.len(), 3);
+            assert!(full
+                .select(".release > strong")
+                .expect("missing heading")
+                .any(|el| el.text_contents().contains("3 crates in queue")));
             let expected = [
                 ("bar", "0.1.0", Some(10)),
                 ("foo", "1.0.0", None),
@@ -1165,6 +1562,42 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_releases_queue_failed() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.build_attempts = 1;
+            });
+            let queue = env.build_queue();
+            let web = env.frontend();
+
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.add_crate("bar", "0.1.0", 0)?;
+            queue.process_next_crate(|_| failure::bail!("this failed"))?;
+
+            let page = kuchiki::parse_html().one(web.get("/releases/queue").send()?.text()?);
+            let queued = page
+                .select("#queued-list > li")
+                .expect("missing list items")
+                .collect::<Vec<_>>();
+            assert_eq!(queued.len(), 1);
+            assert!(queued[0].text_contents().contains("bar"));
+            assert!(page
+                .select(".release > strong")
+                .expect("missing heading")
+                .any(|el| el.text_contents().contains("1 crate in queue")));
+
+            let failed = page
+                .select("#failed-list > li")
+                .expect("missing list items")
+                .collect::<Vec<_>>();
+            assert_eq!(failed.len(), 1);
+            assert!(failed[0].text_contents().contains("foo"));
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn authors_page() {
         wrapper(|env| {
@@ -0,0 +1,80 @@
+//! Middleware logging every request served by the web server.
+//!
+//! Each request produces a single `info` line in the logfmt format, for example:
+//!
+//! ```text
+//! request_id=42 method=GET path=/crate/rand status=200 duration_ms=3.172
+//! ```
+//!
+//! The keys and their order are stable, so the lines can be parsed by log processing tools. The
+//! responses are also counted by status in the `docsrs_responses_by_status` metric.
+
+use iron::prelude::*;
+use iron::{status, Handler};
+use log::info;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+/// Id of the next request, unique for the lifetime of the process.
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+pub(super) struct RequestLogger<H: Handler> {
+    handler: H,
+}
+
+impl<H: Handler> RequestLogger<H> {
+    pub(super) fn new(handler: H) -> Self {
+        Self { handler }
+    }
+}
+
+impl<H: Handler> Handler for RequestLogger<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        let result = self.handler.handle(req);
+        let duration = start.elapsed();
+
+        let response = match &result {
+            Ok(resp) => resp,
+            Err(err) => &err.response,
+        };
+        // Iron responds with a 404 when the handler didn't set any status
+        let status = response.status.unwrap_or(status::NotFound);
+
+        super::metrics::RESPONSES_BY_STATUS
+            .with_label_values(&[&status.to_u16().to_string()])
+            .inc();
+        info!(
+            "request_id={} method={} path={} status={} duration_ms={:.3}",
+            request_id,
+            req.method,
+            req.url.as_ref().path(),
+            status.to_u16(),
+            duration.as_secs_f64() * 1000.0,
+        );
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{captured_logs, wrapper};
+
+    #[test]
+    fn test_requests_are_logged() {
+        wrapper(|env| {
+            let resp = env.frontend().get("/-/build-info").send()?;
+            assert!(resp.status().is_success());
+
+            let logged = captured_logs("cratesfyi::web::request_logger");
+            assert!(logged.iter().any(|line| line.starts_with("request_id=")
+                && line.contains(" method=GET path=/-/build-info status=200 duration_ms=")));
+
+            Ok(())
+        });
+    }
+}
@@ -1,4 +1,7 @@
+use super::admin::RequireAdminToken;
 use super::metrics::RequestRecorder;
+use super::MatchSemver;
+use crate::db::Pool;
 use crate::web::{INDEX_JS, MENU_JS};
 use iron::middleware::Handler;
 use iron::Request;
@@ -30,6 +33,7 @@ pub(super) fn build_routes() -> Routes {
     routes.internal_page("/releases/:author/:page", super::releases::author_handler);
     routes.internal_page("/releases/activity", super::releases::activity_handler);
     routes.internal_page("/releases/search", super::releases::search_handler);
+    routes.internal_page("/search", super::releases::full_text_search_handler);
     routes.internal_page("/releases/queue", super::releases::build_queue_handler);
     routes.internal_page(
         "/releases/recent/:page",
@@ -60,38 +64,89 @@ pub(super) fn build_routes() -> Routes {
         super::releases::releases_failures_by_stars_handler,
     );
 
-    routes.internal_page("/crate/:name", super::crate_details::crate_details_handler);
+    routes.internal_page(
+        "/crate/:name",
+        CanonicalCrateName::new(super::crate_details::crate_details_handler),
+    );
     routes.internal_page(
         "/crate/:name/:version",
-        super::crate_details::crate_details_handler,
+        CanonicalCrateName::new(super::crate_details::crate_details_handler),
+    );
+    routes.static_resource(
+        "/crate/:name/owners",
+        CanonicalCrateName::new(super::crate_details::crate_owners_handler),
+    );
+    routes.internal_page(
+        "/crate/:name/:version/changelog",
+        ResolveSemverVersion::new(super::crate_details::changelog_handler),
     );
     routes.internal_page(
         "/crate/:name/:version/builds",
-        super::builds::build_list_handler,
+        ResolveSemverVersion::new(super::builds::build_list_handler),
     );
     routes.static_resource(
         "/crate/:name/:version/builds.json",
-        super::builds::build_list_handler,
+        ResolveSemverVersion::new(super::builds::build_list_handler),
     );
     routes.internal_page(
         "/crate/:name/:version/builds/:id",
-        super::builds::build_list_handler,
+        ResolveSemverVersion::new(super::builds::build_list_handler),
+    );
+    routes.static_resource(
+        "/crate/:name/:version/build-details",
+        ResolveSemverVersion::new(super::builds::build_details_handler),
+    );
+    routes.static_resource(
+        "/crate/:name/:version/targets",
+        ResolveSemverVersion::new(super::crate_details::crate_targets_handler),
+    );
+    routes.static_resource(
+        "/crate/:name/:version/rustdoc.json",
+        ResolveSemverVersion::new(super::rustdoc::rustdoc_json_handler),
+    );
+    routes.static_resource(
+        "/crate/:name/:version/badge.svg",
+        super::builds::build_badge_handler,
+    );
+    routes.static_resource(
+        "/crate/:name/:version/badge.json",
+        super::builds::build_badge_json_handler,
+    );
+    routes.internal_page(
+        "/crate/:name/:version/builds/:id/log",
+        ResolveSemverVersion::new(super::builds::build_log_handler),
     );
     routes.internal_page(
         "/crate/:name/:version/source",
-        SimpleRedirect::new(|url| url.set_path(&format!("{}/", url.path()))),
+        ResolveSemverVersion::new(SimpleRedirect::new(|url| {
+            url.set_path(&format!("{}/", url.path()))
+        })),
     );
     routes.internal_page(
         "/crate/:name/:version/source/",
-        super::source::source_browser_handler,
+        ResolveSemverVersion::new(super::source::source_browser_handler),
     );
     routes.internal_page(
         "/crate/:name/:version/source/*",
-        super::source::source_browser_handler,
+        ResolveSemverVersion::new(super::source::source_browser_handler),
+    );
+    routes.static_resource(
+        "/crate/:name/:version/source-list",
+        ResolveSemverVersion::new(super::source::source_list_handler),
     );
     routes.internal_page(
         "/crate/:name/:version/target-redirect/*",
-        super::rustdoc::target_redirect_handler,
+        ResolveSemverVersion::new(super::rustdoc::target_redirect_handler),
+    );
+
+    routes.api_endpoint("/api/v1/releases", super::api::releases_handler);
+    routes.api_endpoint(
+        "/api/v1/crates/:name/latest",
+        CanonicalCrateName::new(super::api::latest_version_handler),
+    );
+    routes.api_endpoint(
+        "/api/v1/crates/:name/versions",
+        CanonicalCrateName::new(super::api::versions_handler),
     );
 
     routes.rustdoc_page("/:crate", super::rustdoc::rustdoc_redirector_handler);
@@ -126,6 +181,27 @@ pub(super) fn build_routes() -> Routes {
         super::rustdoc::rustdoc_html_server_handler,
     );
 
+    routes.internal_page("/-/build-info", super::sitemap::build_info_handler);
+    routes.internal_page("/-/metrics", super::metrics::metrics_handler);
+
+    routes.admin_page("/-/queue", super::admin::queue_handler);
+    routes.admin_endpoint("/-/queue/pause", super::admin::queue_pause_handler);
+    routes.admin_endpoint("/-/queue/resume", super::admin::queue_resume_handler);
+    routes.admin_endpoint(
+        "/-/queue/remove/:name/:version",
+        super::admin::queue_remove_handler,
+    );
+    routes.admin_endpoint(
+        "/-/admin/rebuild/:name/:version",
+        super::admin::rebuild_handler,
+    );
+    routes.admin_page("/-/admin/failures", super::admin::failures_handler);
+    routes.admin_endpoint("/-/admin/yank/:name/:version", super::admin::yank_handler);
+    routes.admin_endpoint(
+        "/-/admin/unyank/:name/:version",
+        super::admin::unyank_handler,
+    );
+
     for redirect in DOC_RUST_LANG_ORG_REDIRECTS {
         routes.internal_page(
             &format!("/{}", redirect),
@@ -145,6 +221,11 @@ pub(super) struct Routes {
     /// GET routes serving rustdoc content. The BlockBlacklistedPrefixes middleware is added
     /// automatically to all of them.
     rustdoc_get: Vec<(String, Box<dyn Handler>)>,
+    /// POST routes of the admin endpoints. The RequireAdminToken middleware is added
+    /// automatically to all of them.
+    admin_post: Vec<(String, Box<dyn Handler>)>,
+    /// OPTIONS routes answering the CORS preflight requests of the API endpoints.
+    options: Vec<(String, Box<dyn Handler>)>,
     /// Prefixes of all the internal routes. This data is used to power the
     /// BlockBlacklistedPrefixes middleware.
     page_prefixes: HashSet<String>,
@@ -155,6 +236,8 @@ impl Routes {
         Self {
             get: Vec::new(),
             rustdoc_get: Vec::new(),
+            admin_post: Vec::new(),
+            options: Vec::new(),
             page_prefixes: HashSet::new(),
         }
     }
@@ -180,6 +263,18 @@ impl Routes {
             );
         }
 
+        for (pattern, handler) in self.admin_post.drain(..) {
+            router.post(&pattern, handler, calculate_id(&pattern));
+        }
+
+        for (pattern, handler) in self.options.drain(..) {
+            router.options(
+                &pattern,
+                handler,
+                calculate_id(&format!("{}-options", pattern)),
+            );
+        }
+
         router
     }
 
@@ -187,7 +282,11 @@ impl Routes {
     fn static_resource(&mut self, pattern: &str, handler: impl Handler) {
         self.get.push((
             pattern.to_string(),
-            Box::new(RequestRecorder::new(handler, "static resource")),
+            Box::new(RequestRecorder::grouped(
+                handler,
+                pattern,
+                "static resource",
+            )),
         ));
     }
 
@@ -220,8 +319,55 @@ impl Routes {
             ));
         }
 
-        // Register the prefix if it's not the home page and the first path component is not a
-        // pattern or a wildcard.
+        self.register_page_prefix(pattern);
+    }
+
+    /// API endpoints are internal pages that can be called from other origins: the Cors
+    /// middleware is added automatically to them, along with an OPTIONS route answering the
+    /// preflight requests.
+    fn api_endpoint(&mut self, pattern: &str, handler: impl Handler) {
+        self.internal_page(pattern, super::cors::Cors::new(handler));
+        self.options.push((
+            pattern.to_string(),
+            Box::new(RequestRecorder::new(
+                super::cors::preflight_handler,
+                pattern,
+            )),
+        ));
+    }
+
+    /// Admin endpoints are POST routes only reachable when the request carries the admin token
+    /// set in the configuration. Their prefix is registered as a page prefix like the one of
+    /// internal pages.
+    fn admin_endpoint(&mut self, pattern: &str, handler: impl Handler) {
+        self.admin_post.push((
+            pattern.to_string(),
+            Box::new(RequestRecorder::new(
+                RequireAdminToken::new(handler),
+                pattern,
+            )),
+        ));
+
+        self.register_page_prefix(pattern);
+    }
+
+    /// Admin pages are the GET counterpart of the admin endpoints, and are only reachable when
+    /// the request carries the admin token set in the configuration.
+    fn admin_page(&mut self, pattern: &str, handler: impl Handler) {
+        self.get.push((
+            pattern.to_string(),
+            Box::new(RequestRecorder::new(
+                RequireAdminToken::new(handler),
+                pattern,
+            )),
+        ));
+
+        self.register_page_prefix(pattern);
+    }
+
+    /// Register the prefix if it's not the home page and the first path component is not a
+    /// pattern or a wildcard.
+    fn register_page_prefix(&mut self, pattern: &str) {
         if pattern != "/" {
             if let Some(first_component) = pattern.trim_matches('/').split('/').next() {
                 if !first_component.contains('*') && !first_component.starts_with(':') {
@@ -237,7 +383,7 @@ impl Routes {
     fn rustdoc_page(&mut self, pattern: &str, handler: impl Handler) {
         self.get.push((
             pattern.to_string(),
-            Box::new(RequestRecorder::new(handler, "rustdoc page")),
+            Box::new(RequestRecorder::grouped(handler, pattern, "rustdoc page")),
         ));
     }
 }
@@ -257,13 +403,153 @@ impl Handler for SimpleRedirect {
     fn handle(&self, req: &mut iron::Request) -> iron::IronResult<iron::Response> {
         let mut url: iron::url::Url = req.url.clone().into();
         (self.url_mangler)(&mut url);
+
+        // Rebuild the URL on top of the redirect base, as the request's URL lacks the base path
+        let mut target = format!("{}{}", super::redirect_base(req), url.path());
+        if let Some(query) = url.query() {
+            target.push('?');
+            target.push_str(query);
+        }
         Ok(iron::Response::with((
             iron::status::Found,
-            iron::modifiers::Redirect(iron::Url::parse(&url.to_string()).unwrap()),
+            iron::modifiers::Redirect(iron::Url::parse(&target).unwrap()),
         )))
     }
 }
 
+/// Iron Middleware redirecting requests whose `:name` component isn't spelled like the crate was
+/// published, for example `/crate/serde-json` to `/crate/serde_json`. Names of crates that don't
+/// exist are left to the wrapped handler.
+pub(super) struct CanonicalCrateName {
+    handler: Box<dyn Handler>,
+}
+
+impl CanonicalCrateName {
+    pub(super) fn new(handler: impl Handler) -> Self {
+        Self {
+            handler: Box::new(handler),
+        }
+    }
+}
+
+impl Handler for CanonicalCrateName {
+    fn handle(&self, req: &mut iron::Request) -> iron::IronResult<iron::Response> {
+        let name = match extension!(req, Router).find("name") {
+            Some(name) => name.to_string(),
+            None => return self.handler.handle(req),
+        };
+
+        let canonical = {
+            let conn = extension!(req, Pool).get()?;
+            ctry!(super::canonical_crate_name(&conn, &name))
+        };
+
+        match canonical {
+            Some(canonical) if canonical != name => {
+                let position = req.url.path().iter().position(|segment| *segment == name);
+                match position {
+                    Some(position) => redirect_replacing_segments(req, &[(position, &canonical)]),
+                    None => self.handler.handle(req),
+                }
+            }
+            _ => self.handler.handle(req),
+        }
+    }
+}
+
+/// Redirects to the URL of the request with the path segment at each `(position, to)` pair
+/// replaced, leaving the rest of the path (like the source file paths) untouched.
+fn redirect_replacing_segments(
+    req: &iron::Request,
+    replacements: &[(usize, &str)],
+) -> iron::IronResult<iron::Response> {
+    let url: iron::url::Url = req.url.clone().into();
+    let mut segments = url
+        .path_segments()
+        .map(|segments| segments.collect::<Vec<_>>())
+        .unwrap_or_default();
+    for &(position, to) in replacements {
+        if let Some(segment) = segments.get_mut(position) {
+            *segment = to;
+        }
+    }
+    let path = segments.join("/");
+
+    let mut target = format!("{}/{}", super::redirect_base(req), path);
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+
+    Ok(super::redirect(ctry!(iron::Url::parse(&target))))
+}
+
+/// Iron Middleware that resolves semver requirements in the `:version` component of crate pages,
+/// whose paths start with `/crate/:name/:version`.
+///
+/// If the version isn't an exact version of the crate but a requirement matching one of its
+/// releases (for example `^1.0`), the request is redirected to the same URL with the concrete
+/// version in it. Requests with a version not matching any release of the crate return a 404.
+///
+/// Names not spelled like the crate was published are corrected in the same redirect, see
+/// `CanonicalCrateName`.
+pub(super) struct ResolveSemverVersion {
+    handler: Box<dyn Handler>,
+}
+
+/// Positions of the `:name` and `:version` segments in the paths of the crate pages
+const NAME_SEGMENT: usize = 1;
+const VERSION_SEGMENT: usize = 2;
+
+impl ResolveSemverVersion {
+    pub(super) fn new(handler: impl Handler) -> Self {
+        Self {
+            handler: Box::new(handler),
+        }
+    }
+}
+
+impl Handler for ResolveSemverVersion {
+    fn handle(&self, req: &mut iron::Request) -> iron::IronResult<iron::Response> {
+        let (name, req_version) = {
+            let router = extension!(req, Router);
+            match (router.find("name"), router.find("version")) {
+                (Some(name), Some(version)) => (name.to_string(), version.to_string()),
+                _ => return self.handler.handle(req),
+            }
+        };
+
+        let matched = {
+            let conn = extension!(req, Pool).get_replica()?;
+            super::match_version(&conn, &name, Some(&req_version))
+        };
+
+        let matched = match matched {
+            Some(matched) => matched,
+            None => {
+                return Err(iron::IronError::new(
+                    super::error::Nope::CrateNotFound,
+                    iron::status::NotFound,
+                ))
+            }
+        };
+
+        let mut replacements = Vec::new();
+        if let Some(corrected_name) = &matched.corrected_name {
+            replacements.push((NAME_SEGMENT, corrected_name.as_str()));
+        }
+        if let MatchSemver::Semver((version, _)) = &matched.version {
+            replacements.push((VERSION_SEGMENT, version.as_str()));
+        }
+
+        if replacements.is_empty() {
+            self.handler.handle(req)
+        } else {
+            redirect_replacing_segments(req, &replacements)
+        }
+    }
+}
+
 /// Iron Middleware that prevents requests to blacklisted prefixes.
 ///
 /// In our application, a prefix is blacklisted if a docs.rs page exists below it. For example,
@@ -304,3 +590,153 @@ fn calculate_id(pattern: &str) -> String {
         .map(|c| if c.is_alphanumeric() { c } else { '_' })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{assert_redirect, assert_success, wrapper};
+    use reqwest::StatusCode;
+
+    #[test]
+    fn crate_names_redirect_to_the_canonical_name() {
+        wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("foo-bar")
+                .version("0.1.0")
+                .binary(true)
+                .create()?;
+            let web = env.frontend();
+
+            assert_redirect("/crate/foo_bar", "/crate/foo-bar/0.1.0", web)?;
+            assert_redirect("/crate/foo_bar/0.1.0", "/crate/foo-bar/0.1.0", web)?;
+            assert_redirect("/crate/foo_bar/owners", "/crate/foo-bar/owners", web)?;
+            assert_redirect(
+                "/crate/Foo_Bar/^0.1/builds",
+                "/crate/foo-bar/0.1.0/builds",
+                web,
+            )?;
+            assert_redirect(
+                "/api/v1/crates/foo_bar/latest",
+                "/api/v1/crates/foo-bar/latest",
+                web,
+            )?;
+            assert_redirect(
+                "/api/v1/crates/foo_bar/versions",
+                "/api/v1/crates/foo-bar/versions",
+                web,
+            )?;
+
+            // The canonical names are served without redirecting
+            assert_success("/crate/foo-bar/0.1.0", web)?;
+            assert_success("/crate/foo-bar/0.1.0/builds", web)?;
+            assert_success("/api/v1/crates/foo-bar/latest", web)?;
+
+            let resp = web.get("/api/v1/crates/foo_baz/latest").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn semver_requirements_redirect_to_the_matching_version() {
+        wrapper(|env| {
+            let db = env.db();
+            for version in &["0.9.0", "1.1.0", "1.2.3", "1.3.0", "2.0.0"] {
+                db.fake_release().name("foo").version(version).create()?;
+            }
+            let web = env.frontend();
+
+            assert_redirect("/crate/foo/^1/builds", "/crate/foo/1.3.0/builds", web)?;
+            assert_redirect("/crate/foo/~1.2/builds", "/crate/foo/1.2.3/builds", web)?;
+            assert_redirect("/crate/foo/1.2.3/builds", "/crate/foo/1.2.3/builds", web)?;
+
+            let resp = web.get("/crate/foo/^3/builds").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn semver_requirements_only_replace_the_version_segment() {
+        wrapper(|env| {
+            let db = env.db();
+            for version in &["0.1.0", "0.2.0"] {
+                db.fake_release().name("latest").version(version).create()?;
+            }
+            let web = env.frontend();
+
+            // The crate name is equal to the requirement, but is left untouched
+            assert_redirect(
+                "/crate/latest/latest/builds",
+                "/crate/latest/0.2.0/builds",
+                web,
+            )?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn semver_requirements_redirect_the_source_page() {
+        wrapper(|env| {
+            let db = env.db();
+            for version in &["1.0.0", "1.1.0"] {
+                db.fake_release()
+                    .name("foo")
+                    .version(version)
+                    .source_file("src/lib.rs", b"fn main() {}")
+                    .create()?;
+            }
+            let web = env.frontend();
+
+            assert_redirect("/crate/foo/^1/source", "/crate/foo/1.1.0/source/", web)?;
+            assert_redirect("/crate/foo/1.0.0/source", "/crate/foo/1.0.0/source/", web)?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn latest_redirects_to_the_newest_version() {
+        wrapper(|env| {
+            let db = env.db();
+            db.fake_release().name("foo").version("0.1.0").create()?;
+            db.fake_release().name("foo").version("0.2.0").create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.3.0")
+                .yanked(true)
+                .create()?;
+            let web = env.frontend();
+
+            assert_redirect("/crate/foo/latest", "/foo/0.2.0/foo/index.html", web)?;
+            assert_redirect("/crate/foo/latest/builds", "/crate/foo/0.2.0/builds", web)?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn latest_redirects_to_the_newest_yanked_version_if_all_are_yanked() {
+        wrapper(|env| {
+            let db = env.db();
+            for version in &["0.1.0", "0.2.0"] {
+                db.fake_release()
+                    .name("foo")
+                    .version(version)
+                    .yanked(true)
+                    .create()?;
+            }
+
+            assert_redirect(
+                "/crate/foo/latest/builds",
+                "/crate/foo/0.2.0/builds",
+                env.frontend(),
+            )?;
+
+            Ok(())
+        });
+    }
+}
@@ -1,6 +1,6 @@
 //! rustdoc handler
 
-use super::crate_details::CrateDetails;
+use super::crate_details::{adjacent_versions, CrateDetails};
 use super::error::Nope;
 use super::file::File;
 use super::metrics;
@@ -8,6 +8,7 @@ use super::page::Page;
 use super::redirect_base;
 use super::{match_version, MatchSemver};
 use crate::db::Pool;
+use crate::storage::{PathNotFoundError, Storage};
 use crate::utils;
 use crate::Config;
 use iron::headers::{CacheControl, CacheDirective, Expires, HttpDate};
@@ -368,6 +369,10 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         format!("/crate/{}/{}", name, latest_version)
     };
 
+    rendering_time.step("find adjacent versions");
+
+    let adjacent = ctry!(adjacent_versions(&conn, &name, &version));
+
     rendering_time.step("serve html");
 
     // The path within this crate version's rustdoc output
@@ -397,15 +402,21 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
     };
 
     // Build the page served to the user while setting options for templating
-    Page::new(content)
+    let mut page = Page::new(content)
         .set_true("show_package_navigation")
         .set_true("package_navigation_documentation_tab")
         .set_true("package_navigation_show_platforms_tab")
         .set_bool("is_latest_version", is_latest_version)
         .set("latest_path", &latest_path)
         .set("latest_version", &latest_version)
-        .set("inner_path", &inner_path)
-        .to_resp("rustdoc")
+        .set("inner_path", &inner_path);
+    if let Some(older) = &adjacent.older {
+        page = page.set("older_version", older);
+    }
+    if let Some(newer) = &adjacent.newer {
+        page = page.set("newer_version", newer);
+    }
+    page.to_resp("rustdoc")
 }
 
 /// Checks whether the given path exists.
@@ -503,9 +514,30 @@ pub fn target_redirect_handler(req: &mut Request) -> IronResult<Response> {
     Ok(resp)
 }
 
+/// Serves the JSON output of rustdoc stored for a release, which is only built when
+/// `DOCSRS_STORE_RUSTDOC_JSON` is enabled.
+pub fn rustdoc_json_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let version = cexpect!(router.find("version"));
+
+    let conn = extension!(req, Pool).get()?;
+    let config = extension!(req, Config);
+    match Storage::new(&conn, &config.storage_backend).get_rustdoc_json(
+        name,
+        version,
+        config.max_file_size,
+    ) {
+        Ok(blob) => Ok(File(blob).serve()),
+        Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => {
+            Err(IronError::new(Nope::ResourceNotFound, status::NotFound))
+        }
+        Err(err) => Err(IronError::new(err.compat(), status::InternalServerError)),
+    }
+}
+
 pub fn badge_handler(req: &mut Request) -> IronResult<Response> {
-    use badge::{Badge, BadgeOptions};
-    use iron::headers::ContentType;
+    use badge::BadgeOptions;
     use params::{Params, Value};
 
     let version = {
@@ -559,16 +591,28 @@ pub fn badge_handler(req: &mut Request) -> IronResult<Response> {
         },
     };
 
-    let mut resp = Response::with((status::Ok, ctry!(Badge::new(options)).to_svg()));
+    badge_response(options)
+}
+
+/// Renders a SVG badge, which isn't cached as its status can change at any time.
+pub(super) fn badge_response(options: badge::BadgeOptions) -> IronResult<Response> {
+    use iron::headers::ContentType;
+
+    let mut resp = Response::with((status::Ok, ctry!(badge::Badge::new(options)).to_svg()));
     resp.headers
         .set(ContentType("image/svg+xml".parse().unwrap()));
+    set_badge_cache_headers(&mut resp);
+    Ok(resp)
+}
+
+/// The status shown by the badges changes on every build, so they must not be cached
+pub(super) fn set_badge_cache_headers(resp: &mut Response) {
     resp.headers.set(Expires(HttpDate(time::now())));
     resp.headers.set(CacheControl(vec![
         CacheDirective::NoCache,
         CacheDirective::NoStore,
         CacheDirective::MustRevalidate,
     ]));
-    Ok(resp)
 }
 
 /// Serves shared web resources used by rustdoc-generated documentation.
@@ -607,6 +651,44 @@ mod test {
     use serde_json::json;
     use std::{collections::BTreeMap, iter::FromIterator};
 
+    #[test]
+    fn rustdoc_json() {
+        wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .create()?;
+            Storage::new(&env.db().conn(), &env.config().storage_backend).store_rustdoc_json(
+                "foo",
+                "0.1.0",
+                &br#"{"format_version":1}"#[..],
+            )?;
+
+            let web = env.frontend();
+            let resp = web.get("/crate/foo/0.1.0/rustdoc.json").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()["content-type"], "application/json");
+            assert_eq!(resp.text()?, r#"{"format_version":1}"#);
+
+            assert_redirect(
+                "/crate/foo/latest/rustdoc.json",
+                "/crate/foo/0.1.0/rustdoc.json",
+                web,
+            )?;
+
+            env.db()
+                .fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .create()?;
+            let resp = web.get("/crate/foo/0.2.0/rustdoc.json").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
     fn try_latest_version_redirect(
         path: &str,
         web: &TestFrontend,
@@ -634,6 +716,46 @@ mod test {
             .and_then(|v| v.ok_or_else(|| failure::format_err!("no redirect found for {}", path)))
     }
 
+    #[test]
+    fn adjacent_versions_navigation() {
+        wrapper(|env| {
+            let db = env.db();
+            for version in &["0.1.0", "0.2.0", "0.10.0"] {
+                db.fake_release().name("foo").version(version).create()?;
+            }
+            db.fake_release()
+                .name("foo")
+                .version("0.3.0")
+                .build_result_successful(false)
+                .create()?;
+
+            let conn = db.conn();
+            let adjacent = adjacent_versions(&conn, "foo", "0.2.0")?;
+            assert_eq!(adjacent.older.as_deref(), Some("0.1.0"));
+            assert_eq!(adjacent.newer.as_deref(), Some("0.10.0"));
+
+            let adjacent = adjacent_versions(&conn, "foo", "0.1.0")?;
+            assert_eq!(adjacent.older, None);
+            assert_eq!(adjacent.newer.as_deref(), Some("0.2.0"));
+
+            let adjacent = adjacent_versions(&conn, "foo", "0.10.0")?;
+            assert_eq!(adjacent.older.as_deref(), Some("0.2.0"));
+            assert_eq!(adjacent.newer, None);
+
+            let web = env.frontend();
+            let data = web.get("/foo/0.2.0/foo/").send()?.text()?;
+            let dom = kuchiki::parse_html().one(data);
+            let links = dom
+                .select("a.adjacent-version")
+                .expect("invalid selector")
+                .map(|elem| elem.attributes.borrow().get("href").unwrap().to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(links, vec!["/foo/0.1.0/", "/foo/0.10.0/"]);
+
+            Ok(())
+        });
+    }
+
     #[test]
     // regression test for https://github.com/rust-lang/docs.rs/issues/552
     fn settings_html() {
@@ -721,6 +843,30 @@ mod test {
         });
     }
 
+    #[test]
+    fn configured_default_target_redirects_to_base() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.default_target = "x86_64-pc-windows-msvc".into();
+            });
+            env.db()
+                .fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html", b"some content")
+                .add_platform("x86_64-unknown-linux-gnu")
+                .create()?;
+
+            let web = env.frontend();
+            let base = "/dummy/0.1.0/dummy/";
+            assert_success(base, web)?;
+            assert_redirect("/dummy/0.1.0/x86_64-pc-windows-msvc/dummy/", base, web)?;
+            assert_success("/dummy/0.1.0/x86_64-unknown-linux-gnu/dummy/", web)?;
+            assert_redirect("/dummy/0.1.0/", base, web)?;
+            Ok(())
+        });
+    }
+
     #[test]
     fn go_to_latest_version() {
         wrapper(|env| {
@@ -1040,7 +1186,7 @@ mod test {
     }
 
     #[test]
-    fn specific_pages_do_not_handle_mismatched_separators() {
+    fn specific_pages_redirect_mismatched_separators() {
         wrapper(|env| {
             let db = env.db();
 
@@ -1067,10 +1213,11 @@ mod test {
                 web,
             )?;
 
-            assert_eq!(
-                web.get("/crate/dummy_mixed_separators").send()?.status(),
-                StatusCode::NOT_FOUND
-            );
+            assert_redirect(
+                "/crate/dummy_mixed_separators",
+                "/dummy_mixed-separators/0.1.0/dummy_mixed_separators/index.html",
+                web,
+            )?;
 
             Ok(())
         })
@@ -1461,9 +1608,12 @@ mod test {
             "owners": [],
             "authors_json": null,
             "dependencies": null,
+            "dependency_names": [],
             "release_time": super::super::duration_to_str(time),
             "build_status": true,
             "last_successful_build": null,
+            "last_build_attempt": null,
+            "last_build_error": null,
             "rustdoc_status": true,
             "repository_url": null,
             "homepage_url": null,
@@ -1487,7 +1637,8 @@ mod test {
             "is_library": true,
             "doc_targets": [],
             "license": null,
-            "documentation_url": null
+            "documentation_url": null,
+            "changelog": null
         });
 
         let mut page = RustdocPage {
@@ -0,0 +1,267 @@
+//! Plumbing needed to gracefully shut down the web server.
+//!
+//! Neither Iron nor hyper 0.10 provide a way to stop a running server: `Listening::close` only
+//! detaches the threads accepting connections, which keep accepting them forever and are respawned
+//! by hyper when they exit. To work around that connections are accepted by a thread of our own,
+//! which stops once the shutdown started and hands them to hyper through a [`ConnectionQueue`],
+//! while [`TrackInFlight`] keeps count of the requests still being processed so that the shutdown
+//! can wait for them to complete.
+
+use hyper::net::{NetworkListener, NetworkStream};
+use iron::error::HttpResult;
+use iron::headers::Connection;
+use iron::prelude::*;
+use iron::{status, Handler, Listening, Protocol};
+use log::{info, warn};
+use std::{
+    io,
+    net::SocketAddr,
+    os::unix::io::AsRawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Starts serving `handler` on `listener`, returning the shared state used to shut it down.
+pub(super) fn listen<H, L>(
+    handler: H,
+    mut listener: L,
+) -> HttpResult<(Listening, Arc<ShutdownState>)>
+where
+    H: Handler,
+    L: NetworkListener + AsRawFd + Send + Sync + 'static,
+{
+    let addr = listener.local_addr()?;
+    let state = Arc::new(ShutdownState::new(listener.clone()));
+
+    // Like when hyper accepts the connections itself, each connection is only accepted once one
+    // of its threads is free to handle it
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let acceptor = {
+        let state = state.clone();
+        thread::Builder::new()
+            .name("web acceptor".into())
+            .spawn(move || accept_connections(listener, sender, &state))?
+    };
+    *state.acceptor.lock().unwrap() = Some(acceptor);
+
+    let listening = Iron::new(TrackInFlight::new(handler, state.clone()))
+        .listen(ConnectionQueue::new(receiver, addr), Protocol::http())?;
+    Ok((listening, state))
+}
+
+/// Accepts the connections on `listener` until the shutdown started.
+fn accept_connections<L: NetworkListener>(
+    mut listener: L,
+    connections: SyncSender<L::Stream>,
+    state: &ShutdownState,
+) {
+    loop {
+        let result = listener.accept();
+        if state.is_shutting_down() {
+            return;
+        }
+        match result {
+            Ok(stream) => {
+                if connections.send(stream).is_err() {
+                    return;
+                }
+            }
+            Err(err) => info!("failed to accept a connection: {}", err),
+        }
+    }
+}
+
+pub(super) struct ShutdownState {
+    /// Clone of the listener, keeping its socket open as long as the state exists
+    listener: Box<dyn AsRawFd + Send + Sync>,
+    acceptor: Mutex<Option<JoinHandle<()>>>,
+    shutting_down: AtomicBool,
+    in_flight: Mutex<usize>,
+    drained: Condvar,
+}
+
+impl ShutdownState {
+    fn new(listener: impl AsRawFd + Send + Sync + 'static) -> Self {
+        Self {
+            listener: Box::new(listener),
+            acceptor: Mutex::new(None),
+            shutting_down: AtomicBool::new(false),
+            in_flight: Mutex::new(0),
+            drained: Condvar::new(),
+        }
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Stops listening for new connections and waits for the in-flight requests to complete,
+    /// returning how many of them were still running when the timeout expired.
+    pub(super) fn shutdown(&self, timeout: Duration) -> usize {
+        self.shutting_down.store(true, Ordering::SeqCst);
+
+        // The socket is shared with the acceptor thread, so it can't be closed from here. Shutting
+        // it down instead makes the kernel refuse new connections and fail the pending accept.
+        //
+        // SAFETY: the descriptor belongs to `self.listener`, which is only dropped with `self`,
+        // so it's still open and can't have been reused for another file.
+        unsafe {
+            libc::shutdown(self.listener.as_raw_fd(), libc::SHUT_RDWR);
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut in_flight = self.in_flight.lock().unwrap();
+        while *in_flight > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            in_flight = self
+                .drained
+                .wait_timeout(in_flight, deadline - now)
+                .unwrap()
+                .0;
+        }
+        let in_flight = *in_flight;
+
+        // The acceptor may be waiting for a thread to hand a connection to, which only happens
+        // once a request completes
+        let acceptor = self.acceptor.lock().unwrap().take();
+        match acceptor {
+            Some(acceptor) if in_flight == 0 => {
+                if acceptor.join().is_err() {
+                    warn!("the acceptor thread panicked");
+                }
+            }
+            _ => {}
+        }
+
+        in_flight
+    }
+}
+
+/// Listener handing out the connections accepted by the acceptor thread to hyper.
+struct ConnectionQueue<S> {
+    connections: Arc<Mutex<Receiver<S>>>,
+    addr: SocketAddr,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl<S> ConnectionQueue<S> {
+    fn new(connections: Receiver<S>, addr: SocketAddr) -> Self {
+        Self {
+            connections: Arc::new(Mutex::new(connections)),
+            addr,
+            read_timeout: None,
+            write_timeout: None,
+        }
+    }
+}
+
+impl<S> Clone for ConnectionQueue<S> {
+    fn clone(&self) -> Self {
+        Self {
+            connections: self.connections.clone(),
+            addr: self.addr,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+        }
+    }
+}
+
+impl<S> NetworkListener for ConnectionQueue<S>
+where
+    S: NetworkStream + Send + Clone,
+{
+    type Stream = S;
+
+    fn accept(&mut self) -> hyper::Result<S> {
+        let received = self.connections.lock().unwrap().recv();
+        match received {
+            Ok(stream) => {
+                stream.set_read_timeout(self.read_timeout)?;
+                stream.set_write_timeout(self.write_timeout)?;
+                Ok(stream)
+            }
+            // hyper retries failed accepts in a loop and respawns its threads when they exit, so
+            // once the acceptor thread stopped the only way to stop them is parking them until
+            // the process exits. They aren't using the socket anymore at this point.
+            Err(_) => loop {
+                thread::park();
+            },
+        }
+    }
+
+    fn local_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn set_read_timeout(&mut self, duration: Option<Duration>) {
+        self.read_timeout = duration;
+    }
+
+    fn set_write_timeout(&mut self, duration: Option<Duration>) {
+        self.write_timeout = duration;
+    }
+}
+
+/// Handler wrapper counting the requests being processed. Once the shutdown started, requests
+/// arriving on kept-alive connections are rejected, and all responses close their connection.
+struct TrackInFlight<H: Handler> {
+    handler: H,
+    state: Arc<ShutdownState>,
+}
+
+impl<H: Handler> TrackInFlight<H> {
+    fn new(handler: H, state: Arc<ShutdownState>) -> Self {
+        Self { handler, state }
+    }
+}
+
+impl<H: Handler> Handler for TrackInFlight<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        if self.state.is_shutting_down() {
+            let mut resp =
+                Response::with((status::ServiceUnavailable, "the server is shutting down"));
+            resp.headers.set(Connection::close());
+            return Ok(resp);
+        }
+
+        let result = {
+            let _guard = InFlightGuard::new(&self.state);
+            self.handler.handle(req)
+        };
+        result.map(|mut resp| {
+            if self.state.is_shutting_down() {
+                resp.headers.set(Connection::close());
+            }
+            resp
+        })
+    }
+}
+
+/// Keeps a request counted as in-flight until it's dropped, even if the handler panics.
+struct InFlightGuard<'a>(&'a ShutdownState);
+
+impl<'a> InFlightGuard<'a> {
+    fn new(state: &'a ShutdownState) -> Self {
+        *state.in_flight.lock().unwrap() += 1;
+        Self(state)
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.0.in_flight.lock().unwrap();
+        *in_flight -= 1;
+        if *in_flight == 0 {
+            self.0.drained.notify_all();
+        }
+    }
+}
@@ -1,55 +1,141 @@
-use crate::{db::Pool, docbuilder::Limits, impl_webpage, web::page::WebPage};
+use crate::{
+    db::{Pool, PoolConnection},
+    docbuilder::Limits,
+    impl_webpage,
+    web::{
+        api::json_response,
+        page::{load_rustc_resource_suffix, WebPage},
+        SERVER_START,
+    },
+    Config,
+};
 use chrono::{DateTime, NaiveDateTime, Utc};
 use iron::{
     headers::ContentType,
     mime::{Mime, SubLevel, TopLevel},
+    response::WriteBody,
     status, IronResult, Request, Response,
 };
 use serde::Serialize;
 use serde_json::Value;
+use std::io::{self, Write};
 
-/// The sitemap
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
-struct SitemapXml {
-    /// The release's names and RFC 3339 timestamp to be displayed on the sitemap
-    releases: Vec<(String, String)>,
+/// Number of rows fetched from the database at a time while streaming the sitemap.
+const SITEMAP_FETCH_SIZE: usize = 1000;
+
+/// Response body streaming the sitemap straight from the database, without loading all the
+/// crates in memory at once.
+struct SitemapBody {
+    conn: PoolConnection,
+}
+
+/// Release listed in the sitemap for a crate.
+struct SitemapEntry {
+    name: String,
+    version: String,
+    semver: Option<semver::Version>,
+    time: DateTime<Utc>,
+}
+
+impl SitemapEntry {
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        // Crate names and versions can't contain characters that need to be escaped
+        writeln!(out, "    <url>")?;
+        writeln!(
+            out,
+            "        <loc>https://docs.rs/{}/{}</loc>",
+            self.name, self.version
+        )?;
+        writeln!(out, "        <lastmod>{}</lastmod>", self.time.format("%+"))?;
+        writeln!(out, "    </url>")
+    }
+}
+
+impl WriteBody for SitemapBody {
+    fn write_body(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        let trans = self.conn.transaction().map_err(to_io_error)?;
+        // Only the latest documented and non-yanked release of every crate is listed. The
+        // database can't compare versions, so the releases of each crate are fetched together
+        // and the latest one is picked here.
+        trans
+            .batch_execute(
+                "DECLARE sitemap CURSOR FOR
+                 SELECT crates.name,
+                        releases.version,
+                        releases.release_time
+                 FROM crates
+                 INNER JOIN releases ON releases.crate_id = crates.id
+                 WHERE releases.rustdoc_status = true AND releases.yanked = false
+                 ORDER BY crates.name",
+            )
+            .map_err(to_io_error)?;
+
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            out,
+            r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+        )?;
+
+        let fetch = format!("FETCH {} FROM sitemap", SITEMAP_FETCH_SIZE);
+        let mut latest: Option<SitemapEntry> = None;
+        loop {
+            let rows = trans.query(&fetch, &[]).map_err(to_io_error)?;
+            if rows.is_empty() {
+                break;
+            }
+
+            for row in &rows {
+                let version: String = row.get(1);
+                let entry = SitemapEntry {
+                    name: row.get(0),
+                    semver: semver::Version::parse(&version).ok(),
+                    version,
+                    time: DateTime::<Utc>::from_utc(row.get::<_, NaiveDateTime>(2), Utc),
+                };
+
+                match &latest {
+                    Some(current) if current.name == entry.name => {
+                        if entry.semver > current.semver {
+                            latest = Some(entry);
+                        }
+                    }
+                    _ => {
+                        if let Some(previous) = latest.replace(entry) {
+                            previous.write(out)?;
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(last) = latest {
+            last.write(out)?;
+        }
+
+        writeln!(out, "</urlset>")
+    }
 }
 
-impl_webpage! {
-    SitemapXml   = "core/sitemap.xml",
-    content_type = ContentType(Mime(TopLevel::Application, SubLevel::Xml, vec![])),
+fn to_io_error(err: postgres::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
 }
 
 pub fn sitemap_handler(req: &mut Request) -> IronResult<Response> {
-    let conn = extension!(req, Pool).get()?;
-    let query = conn
-        .query(
-            "SELECT DISTINCT ON (crates.name)
-                    crates.name,
-                    releases.release_time
-             FROM crates
-             INNER JOIN releases ON releases.crate_id = crates.id
-             WHERE rustdoc_status = true",
-            &[],
-        )
-        .unwrap();
-
-    let releases = query
-        .into_iter()
-        .map(|row| {
-            let time = DateTime::<Utc>::from_utc(row.get::<_, NaiveDateTime>(1), Utc)
-                .format("%+")
-                .to_string();
-
-            (row.get(0), time)
-        })
-        .collect::<Vec<(String, String)>>();
+    let conn = extension!(req, Pool).get_replica()?;
 
-    SitemapXml { releases }.into_response(req)
+    let mut resp = Response::with(status::Ok);
+    resp.body = Some(Box::new(SitemapBody { conn }));
+    resp.headers.set(ContentType(Mime(
+        TopLevel::Application,
+        SubLevel::Xml,
+        vec![],
+    )));
+
+    Ok(resp)
 }
 
-pub fn robots_txt_handler(_: &mut Request) -> IronResult<Response> {
-    let mut resp = Response::with((status::Ok, "Sitemap: https://docs.rs/sitemap.xml"));
+pub fn robots_txt_handler(req: &mut Request) -> IronResult<Response> {
+    let config = extension!(req, Config);
+    let mut resp = Response::with((status::Ok, config.robots_txt.as_str()));
     resp.headers.set(ContentType::plaintext());
 
     Ok(resp)
@@ -65,6 +151,31 @@ struct About {
 
 impl_webpage!(About = "core/about.html");
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct BuildInfo {
+    version: &'static str,
+    commit: &'static str,
+    /// Suffix of the rustdoc resources of the nightly used for the builds, if it's known
+    rustc_resource_suffix: Option<String>,
+    /// Seconds since the web server was started
+    uptime: u64,
+}
+
+/// Returns the metadata of the running deployment, useful when triaging issues.
+pub fn build_info_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = extension!(req, Pool).get()?;
+
+    Ok(json_response(
+        status::Ok,
+        &BuildInfo {
+            version: crate::BUILD_VERSION,
+            commit: crate::GIT_COMMIT,
+            rustc_resource_suffix: load_rustc_resource_suffix(&conn).ok(),
+            uptime: SERVER_START.elapsed().as_secs(),
+        },
+    ))
+}
+
 pub fn about_handler(req: &mut Request) -> IronResult<Response> {
     let conn = extension!(req, Pool).get()?;
     let res = ctry!(conn.query("SELECT value FROM config WHERE name = 'rustc_version'", &[]));
@@ -87,6 +198,7 @@ pub fn about_handler(req: &mut Request) -> IronResult<Response> {
 #[cfg(test)]
 mod tests {
     use crate::test::{assert_success, wrapper};
+    use chrono::{Duration, TimeZone, Utc};
 
     #[test]
     fn sitemap() {
@@ -104,6 +216,76 @@ mod tests {
         })
     }
 
+    #[test]
+    fn sitemap_lists_latest_documented_versions() {
+        wrapper(|env| {
+            let db = env.db();
+            let time = Utc.ymd(2020, 4, 1).and_hms(12, 0, 0);
+            db.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .release_time(time - Duration::days(1))
+                .create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .release_time(time)
+                .create()?;
+            db.fake_release()
+                .name("foo")
+                .version("0.3.0")
+                .release_time(time + Duration::days(1))
+                .build_result_successful(false)
+                .create()?;
+            db.fake_release().name("bar").version("1.0.0").create()?;
+            db.fake_release()
+                .name("failed")
+                .build_result_successful(false)
+                .create()?;
+
+            let resp = env.frontend().get("/sitemap.xml").send()?;
+            assert_eq!(resp.headers()["content-type"], "application/xml");
+            let body = resp.text()?;
+
+            assert!(body.contains("<loc>https://docs.rs/foo/0.2.0</loc>"));
+            assert!(body.contains("<lastmod>2020-04-01T12:00:00+00:00</lastmod>"));
+            assert!(body.contains("<loc>https://docs.rs/bar/1.0.0</loc>"));
+            assert!(!body.contains("https://docs.rs/foo/0.1.0"));
+            assert!(!body.contains("https://docs.rs/foo/0.3.0"));
+            assert!(!body.contains("https://docs.rs/failed/"));
+            assert_eq!(body.matches("<url>").count(), 2);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn sitemap_compares_versions_with_semver() {
+        wrapper(|env| {
+            let db = env.db();
+            let time = Utc.ymd(2020, 4, 1).and_hms(12, 0, 0);
+            db.fake_release()
+                .name("foo")
+                .version("0.10.0")
+                .release_time(time)
+                .create()?;
+            // A fix backported to an older version is released later
+            db.fake_release()
+                .name("foo")
+                .version("0.9.1")
+                .release_time(time + Duration::days(1))
+                .create()?;
+            db.fake_release().name("bar").version("1.0.0").create()?;
+
+            let body = env.frontend().get("/sitemap.xml").send()?.text()?;
+            assert!(body.contains("<loc>https://docs.rs/foo/0.10.0</loc>"));
+            assert!(body.contains("<loc>https://docs.rs/bar/1.0.0</loc>"));
+            assert_eq!(body.matches("<url>").count(), 2);
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn about_page() {
         wrapper(|env| {
@@ -116,7 +298,46 @@ mod tests {
     fn robots_txt() {
         wrapper(|env| {
             let web = env.frontend();
-            assert_success("/robots.txt", web)
+            assert_success("/robots.txt", web)?;
+
+            let body = web.get("/robots.txt").send()?.text()?;
+            assert_eq!(body, env.config().robots_txt);
+            assert!(body.contains("Disallow: /-/"));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn robots_txt_from_config() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.robots_txt = "User-agent: *\nDisallow: /\n".into();
+            });
+
+            let body = env.frontend().get("/robots.txt").send()?.text()?;
+            assert_eq!(body, "User-agent: *\nDisallow: /\n");
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn build_info() {
+        wrapper(|env| {
+            let resp = env.frontend().get("/-/build-info").send()?;
+            assert!(resp.status().is_success());
+
+            let info = resp.json::<serde_json::Value>()?;
+            let info = info.as_object().unwrap();
+            for key in &["version", "commit", "rustc_resource_suffix", "uptime"] {
+                assert!(info.contains_key(*key), "missing {}", key);
+            }
+            assert_eq!(info["version"], crate::BUILD_VERSION);
+            assert_eq!(info["commit"], crate::GIT_COMMIT);
+            assert!(info["uptime"].is_u64());
+
+            Ok(())
         })
     }
 }
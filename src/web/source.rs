@@ -1,15 +1,20 @@
 //! Source code browser
 
+use super::api;
 use super::file::File as DbFile;
+use super::highlight;
 use super::page::Page;
 use super::MetaData;
 use crate::db::Pool;
+use crate::storage::Storage;
 use crate::Config;
 use iron::prelude::*;
+use iron::status;
+use params::Params;
 use postgres::Connection;
 use router::Router;
 use serde::ser::{Serialize, SerializeStruct, Serializer};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::cmp::Ordering;
 use std::collections::HashMap;
 
@@ -181,7 +186,14 @@ impl FileList {
     }
 }
 
+/// Handler for the source browser. Text files are shown with server-side syntax highlighting when
+/// their extension is recognized, and `?raw=1` returns the content of a file as-is.
 pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
+    let raw = match ctry!(req.get::<Params>()).find(&["raw"]) {
+        Some(params::Value::String(raw)) => raw == "1",
+        _ => false,
+    };
+
     let router = extension!(req, Router);
     let name = cexpect!(router.find("name"));
     let version = cexpect!(router.find("version"));
@@ -224,7 +236,7 @@ pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
 
     let (content, is_rust_source) = if let Some(file) = file {
         // serve the file with DatabaseFileHandler if file isn't text and not empty
-        if !file.0.mime.starts_with("text") && !file.is_empty() {
+        if raw || (!file.0.mime.starts_with("text") && !file.is_empty()) {
             return Ok(file.serve());
         } else if file.0.mime.starts_with("text") && !file.is_empty() {
             (
@@ -241,7 +253,6 @@ pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
     let list = FileList::from_path(&conn, &name, &version, &req_path);
     if list.is_none() {
         use super::error::Nope;
-        use iron::status;
         return Err(IronError::new(Nope::NoResults, status::NotFound));
     }
 
@@ -252,14 +263,61 @@ pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
         .set_true("package_source_tab");
 
     if let Some(content) = content {
-        page.set("file_content", &content)
-            .set_bool("file_content_rust_source", is_rust_source)
-            .to_resp("source")
+        let page = match highlight::highlight(&file_path, &content) {
+            Some(html) => page.set("file_content_html", &html),
+            None => page.set_bool("file_content_rust_source", is_rust_source),
+        };
+        page.set("file_content", &content).to_resp("source")
     } else {
         page.to_resp("source")
     }
 }
 
+/// Handler for `/crate/:name/:version/source-list`, returning the files and directories directly
+/// inside a directory of the crate's source as JSON. The directory is set with the `path`
+/// parameter, and is the root of the crate when it's missing.
+pub fn source_list_handler(req: &mut Request) -> IronResult<Response> {
+    let path = match ctry!(req.get::<Params>()).find(&["path"]) {
+        Some(params::Value::String(path)) => path.clone(),
+        _ => String::new(),
+    };
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    if segments
+        .iter()
+        .any(|&segment| segment == "." || segment == "..")
+    {
+        return Ok(api::json_response(
+            status::BadRequest,
+            &json!({ "error": "invalid path" }),
+        ));
+    }
+
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let version = cexpect!(router.find("version"));
+    let mut dir = format!("sources/{}/{}/", name, version);
+    for segment in segments {
+        dir.push_str(segment);
+        dir.push('/');
+    }
+
+    let conn = extension!(req, Pool).get()?;
+    let config = extension!(req, Config);
+    let listing = ctry!(Storage::new(&conn, config.storage_backend()).list_directory(&dir));
+
+    if listing.directories.is_empty() && listing.files.is_empty() {
+        Ok(api::json_response(
+            status::NotFound,
+            &json!({ "error": "directory not found" }),
+        ))
+    } else {
+        Ok(api::json_response(status::Ok, &listing))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -325,4 +383,108 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn highlighted_source() {
+        wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("fake")
+                .version("0.1.0")
+                .source_file("src/lib.rs", b"pub fn answer() -> u8 { 42 }\n")
+                .source_file("README", b"pub fn answer() -> u8 { 42 }\n")
+                .create()?;
+            let web = env.frontend();
+
+            let page = web
+                .get("/crate/fake/0.1.0/source/src/lib.rs")
+                .send()?
+                .text()?;
+            assert!(page.contains("<span style="));
+            assert!(!page.contains(r#"<code class="rust">"#));
+
+            // Files of unknown languages are left to the client-side highlighting
+            let page = web.get("/crate/fake/0.1.0/source/README").send()?.text()?;
+            assert!(!page.contains("<span style="));
+
+            let resp = web
+                .get("/crate/fake/0.1.0/source/src/lib.rs?raw=1")
+                .send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(resp.text()?, "pub fn answer() -> u8 { 42 }\n");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn stored_files_keep_their_mime_type() {
+        wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("fake")
+                .version("0.1.0")
+                .source_file("src/lib.rs", b"pub fn answer() -> u8 { 42 }\n")
+                .add_file("assets/logo.png", b"\x89PNG", "image/png")
+                .add_file("src/data.txt", b"some data", "text/plain")
+                .create()?;
+            let web = env.frontend();
+
+            let resp = web.get("/crate/fake/0.1.0/source/assets/logo.png").send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(resp.bytes()?.as_ref(), b"\x89PNG");
+            crate::test::assert_content_type(
+                "/crate/fake/0.1.0/source/assets/logo.png",
+                "image/png",
+                web,
+            )?;
+
+            let body = web.get_body("/crate/fake/0.1.0/source/src/data.txt?raw=1")?;
+            assert_eq!(body, "some data");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn source_list() {
+        wrapper(|env| {
+            env.db()
+                .fake_release()
+                .name("fake")
+                .version("0.1.0")
+                .source_file("Cargo.toml", b"[package]")
+                .source_file("src/lib.rs", b"mod foo;")
+                .source_file("src/foo/mod.rs", b"")
+                .create()?;
+            let web = env.frontend();
+
+            let listing: Value = web.get_json("/crate/fake/0.1.0/source-list")?;
+            assert_eq!(
+                listing,
+                json!({ "directories": ["src"], "files": ["Cargo.toml"] })
+            );
+            for path in &["src", "/src/", "src//"] {
+                let url = format!("/crate/fake/0.1.0/source-list?path={}", path);
+                let listing: Value = web.get_json(&url)?;
+                assert_eq!(
+                    listing,
+                    json!({ "directories": ["foo"], "files": ["lib.rs"] })
+                );
+            }
+
+            let resp = web
+                .get("/crate/fake/0.1.0/source-list?path=missing")
+                .send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+            for path in &["..", "src/../..", "../../other/1.0.0", "./src"] {
+                let url = format!("/crate/fake/0.1.0/source-list?path={}", path);
+                let resp = web.get(&url).send()?;
+                assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+            }
+
+            Ok(())
+        });
+    }
 }
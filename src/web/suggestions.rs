@@ -0,0 +1,244 @@
+//! "Did you mean" suggestions for requests to crates that don't exist.
+
+use super::routes::build_routes;
+use crate::{db::Pool, docbuilder::crate_exists_in_index, BuildQueue, Config};
+use iron::Request;
+use log::warn;
+use once_cell::sync::Lazy;
+use postgres::Connection;
+use std::collections::HashSet;
+
+/// Maximum number of crates suggested for a missing one
+const MAX_SUGGESTIONS: i64 = 5;
+
+/// The first path segment of the docs.rs pages, which can't be crate names.
+static PAGE_PREFIXES: Lazy<HashSet<String>> = Lazy::new(|| build_routes().page_prefixes());
+
+/// Returns the names of the crates most similar to `name` according to their trigram
+/// similarity, the closest first. Crates less similar than `threshold` (between 0 and 1) are not
+/// returned, and nothing is returned if a crate named `name` exists (ignoring the difference
+/// between dashes and underscores).
+pub(crate) fn similar_crate_names(
+    conn: &Connection,
+    name: &str,
+    threshold: f32,
+    limit: i64,
+) -> Result<Vec<String>, postgres::Error> {
+    let trans = conn.transaction()?;
+    // The `%` operator uses the threshold set here, and unlike a `similarity()` comparison it can
+    // use the trigram index on the crate names.
+    trans.execute(
+        "SELECT set_config('pg_trgm.similarity_threshold', $1, true)",
+        &[&threshold.to_string()],
+    )?;
+    let rows = trans.query(
+        "SELECT name
+         FROM crates
+         WHERE name % $1 AND NOT EXISTS (
+             SELECT 1 FROM crates WHERE normalize_crate_name(name) = normalize_crate_name($1)
+         )
+         ORDER BY similarity(name, $1) DESC, name
+         LIMIT $2",
+        &[&name, &limit],
+    )?;
+
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Returns the crate name a request that wasn't found was looking for, if any: either the first
+/// segment of rustdoc pages or the name in `/crate/:name` pages.
+fn requested_crate_name<'a>(path: &[&'a str]) -> Option<&'a str> {
+    let name = match path {
+        ["crate", name, ..] => *name,
+        [name, ..] if !PAGE_PREFIXES.contains(*name) => *name,
+        _ => return None,
+    };
+
+    let is_valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if is_valid {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// Why a crate requested by a request that wasn't found is missing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum MissingCrate {
+    /// The crate isn't in the registry index, or the index isn't configured
+    Unknown,
+    /// The crate is in the registry index but wasn't documented yet
+    NotDocumented,
+    /// The crate is in the registry index and waiting in the build queue
+    Queued,
+}
+
+/// Looks up the crate requested by a request that wasn't found in the registry index and the
+/// build queue. Failures to read the queue are logged and ignored, like the suggestions.
+pub(super) fn missing_crate(req: &Request) -> MissingCrate {
+    let path = req.url.path();
+    let (name, config) = match (requested_crate_name(&path), req.extensions.get::<Config>()) {
+        (Some(name), Some(config)) => (name, config),
+        _ => return MissingCrate::Unknown,
+    };
+    let in_index = config
+        .registry_index_path
+        .as_ref()
+        .map_or(false, |index| crate_exists_in_index(index, name));
+    if !in_index {
+        return MissingCrate::Unknown;
+    }
+
+    let queued = req
+        .extensions
+        .get::<BuildQueue>()
+        .map_or(Ok(false), |queue| queue.is_queued(name));
+    match queued {
+        Ok(true) => MissingCrate::Queued,
+        Ok(false) => MissingCrate::NotDocumented,
+        Err(err) => {
+            warn!("failed to check whether {} is queued: {}", name, err);
+            MissingCrate::NotDocumented
+        }
+    }
+}
+
+/// Returns whether `path` is the one of a crate itself, `/:name` or `/crate/:name`, the only pages
+/// where a typo in the name is likely enough to be worth suggesting other crates.
+fn is_crate_page(path: &[&str]) -> bool {
+    matches!(path, [_] | [_, ""] | ["crate", _] | ["crate", _, ""])
+}
+
+/// Returns the crates similar to the one requested by a request to a crate page that wasn't
+/// found. Failures are logged and result in no suggestions, as they're not worth replacing the
+/// 404 page with an error.
+pub(super) fn crate_suggestions(req: &Request) -> Vec<String> {
+    let path = req.url.path();
+    if !is_crate_page(&path) {
+        return Vec::new();
+    }
+    let name = match requested_crate_name(&path) {
+        Some(name) => name,
+        None => return Vec::new(),
+    };
+
+    let (config, pool) = match (req.extensions.get::<Config>(), req.extensions.get::<Pool>()) {
+        (Some(config), Some(pool)) => (config, pool),
+        _ => return Vec::new(),
+    };
+    let suggestions = pool
+        .get_replica()
+        .map_err(failure::Error::from)
+        .and_then(|conn| {
+            Ok(similar_crate_names(
+                &conn,
+                name,
+                config.crate_suggestion_threshold,
+                MAX_SUGGESTIONS,
+            )?)
+        });
+
+    suggestions.unwrap_or_else(|err| {
+        warn!("failed to load the crates similar to {}: {}", name, err);
+        Vec::new()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use kuchiki::traits::TendrilSink;
+
+    #[test]
+    fn test_requested_crate_name() {
+        assert_eq!(requested_crate_name(&["serde_"]), Some("serde_"));
+        assert_eq!(
+            requested_crate_name(&["serde_", "1.0.0", "serde"]),
+            Some("serde_")
+        );
+        assert_eq!(
+            requested_crate_name(&["crate", "serde_", "1.0.0"]),
+            Some("serde_")
+        );
+        assert_eq!(requested_crate_name(&["releases", "queue"]), None);
+        assert_eq!(requested_crate_name(&["not a crate"]), None);
+        assert_eq!(requested_crate_name(&[""]), None);
+    }
+
+    #[test]
+    fn test_similar_crate_names() {
+        wrapper(|env| {
+            let db = env.db();
+            for name in &["serde", "serde-json", "serde_derive", "tokio"] {
+                db.fake_release().name(name).create()?;
+            }
+
+            assert_eq!(
+                similar_crate_names(&db.conn(), "serde_", 0.4, 5)?,
+                vec!["serde", "serde-json", "serde_derive"]
+            );
+            assert_eq!(
+                similar_crate_names(&db.conn(), "serde_", 0.5, 5)?,
+                vec!["serde", "serde-json"]
+            );
+            assert_eq!(
+                similar_crate_names(&db.conn(), "serde_", 0.5, 1)?,
+                vec!["serde"]
+            );
+            // serde-json only differs by its separator, so the crate exists
+            assert!(similar_crate_names(&db.conn(), "serde_json", 0.5, 5)?.is_empty());
+            assert!(similar_crate_names(&db.conn(), "http", 0.5, 5)?.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_suggestions_on_404_page() {
+        wrapper(|env| {
+            env.db().fake_release().name("serde").create()?;
+            env.db().fake_release().name("tokio").create()?;
+
+            for url in &["/serde_", "/serde_/", "/crate/serde_"] {
+                let resp = env.frontend().get(url).send()?;
+                assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+                let page = kuchiki::parse_html().one(resp.text()?);
+                let suggestions = page
+                    .select(".crate-suggestions a")
+                    .unwrap()
+                    .map(|link| link.text_contents())
+                    .collect::<Vec<_>>();
+                assert_eq!(suggestions, vec!["serde"], "wrong suggestions for {}", url);
+            }
+
+            Ok(())
+        });
+    }
+    #[test]
+    fn test_no_suggestions_below_crate_pages() {
+        wrapper(|env| {
+            env.db().fake_release().name("serde").create()?;
+
+            for url in &["/serde_/1.0.0/serde/", "/crate/serde_/1.0.0"] {
+                let resp = env.frontend().get(url).send()?;
+                assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+                let page = kuchiki::parse_html().one(resp.text()?);
+                assert_eq!(
+                    page.select(".crate-suggestions a").unwrap().count(),
+                    0,
+                    "unexpected suggestions for {}",
+                    url
+                );
+            }
+
+            Ok(())
+        });
+    }
+}
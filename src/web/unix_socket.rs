@@ -0,0 +1,147 @@
+//! Support for serving the web server over a Unix domain socket, for deployments where a reverse
+//! proxy runs on the same host.
+//!
+//! hyper 0.10 only knows how to listen on TCP sockets, so this module provides the listener and
+//! stream types it needs to work over a [`UnixListener`].
+
+use hyper::net::{NetworkListener, NetworkStream};
+use log::warn;
+use std::{
+    fs, io,
+    io::{Read, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr},
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Prefix of the bind addresses pointing to a Unix domain socket.
+pub(super) const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// Unix sockets don't have an IP address, but hyper and Iron need one for both the listener and
+/// its peers: this unspecified address is reported instead.
+const PLACEHOLDER_ADDR: (Ipv4Addr, u16) = (Ipv4Addr::UNSPECIFIED, 0);
+
+#[derive(Clone)]
+pub(super) struct UnixSocketListener {
+    listener: Arc<UnixListener>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl UnixSocketListener {
+    /// Binds a new socket at `path`, returning the listener and a guard removing the socket file
+    /// once it's dropped.
+    pub(super) fn bind(path: &Path) -> io::Result<(Self, UnixSocketFile)> {
+        let listener = UnixListener::bind(path)?;
+        let file = UnixSocketFile(path.into());
+
+        Ok((
+            Self {
+                listener: Arc::new(listener),
+                read_timeout: None,
+                write_timeout: None,
+            },
+            file,
+        ))
+    }
+}
+
+impl AsRawFd for UnixSocketListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl NetworkListener for UnixSocketListener {
+    type Stream = UnixSocketStream;
+
+    fn accept(&mut self) -> hyper::Result<UnixSocketStream> {
+        let stream = UnixSocketStream(self.listener.accept()?.0);
+        stream.set_read_timeout(self.read_timeout)?;
+        stream.set_write_timeout(self.write_timeout)?;
+        Ok(stream)
+    }
+
+    fn local_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(PLACEHOLDER_ADDR.into())
+    }
+
+    fn set_read_timeout(&mut self, duration: Option<Duration>) {
+        self.read_timeout = duration;
+    }
+
+    fn set_write_timeout(&mut self, duration: Option<Duration>) {
+        self.write_timeout = duration;
+    }
+}
+
+pub(super) struct UnixSocketStream(UnixStream);
+
+impl Clone for UnixSocketStream {
+    fn clone(&self) -> Self {
+        UnixSocketStream(self.0.try_clone().unwrap())
+    }
+}
+
+impl Read for UnixSocketStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for UnixSocketStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl NetworkStream for UnixSocketStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(PLACEHOLDER_ADDR.into())
+    }
+
+    fn set_read_timeout(&self, duration: Option<Duration>) -> io::Result<()> {
+        self.0.set_read_timeout(duration)
+    }
+
+    fn set_write_timeout(&self, duration: Option<Duration>) -> io::Result<()> {
+        self.0.set_write_timeout(duration)
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        match self.0.shutdown(how) {
+            Err(err) if err.kind() != io::ErrorKind::NotConnected => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Removes the socket file when dropped, as binding to an existing path fails.
+pub(super) struct UnixSocketFile(PathBuf);
+
+impl UnixSocketFile {
+    pub(super) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for UnixSocketFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.0) {
+            warn!(
+                "failed to remove the socket file {}: {}",
+                self.0.display(),
+                err
+            );
+        }
+    }
+}
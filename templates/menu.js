@@ -199,3 +199,17 @@
     }
     document.documentElement.addEventListener("keydown", menuKeyDown);
 })();
+// Hide the global alert once it's dismissed, the cookie keeps the server from rendering it again.
+(function() {
+    var dismiss = document.getElementById("dismiss-global-alert");
+    if (!dismiss) {
+        return;
+    }
+    dismiss.addEventListener("click", function(e) {
+        document.cookie = "dismissed_alert=" + dismiss.dataset.alertId +
+            "; path=/; max-age=31536000; samesite=lax";
+        document.getElementById("global-alert").remove();
+        dismiss.parentNode.remove();
+        e.preventDefault();
+    });
+})();